@@ -0,0 +1,357 @@
+//! Instruction to bytes, the reverse of the disassembler
+
+use std::convert::TryFrom;
+
+use crate::disassembler::instruction::{Address, Arg16, Arg8, Cond, Data16, Data8, Instruction, Rel};
+use crate::registers::{Reg16, Reg8};
+
+const DD: u8 = 0xdd;
+const FD: u8 = 0xfd;
+
+/// An 8 bit operand as an opcode field: the register number, the index
+/// prefix it needs and the displacement of `(ix+d)`
+#[derive(Copy, Clone)]
+struct Field {
+    prefix: Option<u8>,
+    code: u8,
+    disp: Option<i8>,
+}
+
+/// The bytes for `instruction`, or None if no opcode takes its operands.
+/// Where the Z80 has several encodings the documented one is used.
+pub fn encode(instruction: &Instruction) -> Option<Vec<u8>> {
+    use self::Instruction::*;
+    let bytes = match instruction {
+        NOP => vec![0x00],
+        HALT => vec![0x76],
+        DI => vec![0xf3],
+        EI => vec![0xfb],
+        EXX => vec![0xd9],
+        DAA => vec![0x27],
+        CPL => vec![0x2f],
+        SCF => vec![0x37],
+        CCF => vec![0x3f],
+        RLCA => vec![0x07],
+        RRCA => vec![0x0f],
+        RLA => vec![0x17],
+        RRA => vec![0x1f],
+        RET => vec![0xc9],
+        NEG => vec![0xed, 0x44],
+        RETN => vec![0xed, 0x45],
+        RETI => vec![0xed, 0x4d],
+        RRD => vec![0xed, 0x67],
+        RLD => vec![0xed, 0x6f],
+        LDI => vec![0xed, 0xa0],
+        CPI => vec![0xed, 0xa1],
+        INI => vec![0xed, 0xa2],
+        OUTI => vec![0xed, 0xa3],
+        LDD => vec![0xed, 0xa8],
+        CPD => vec![0xed, 0xa9],
+        IND => vec![0xed, 0xaa],
+        OUTD => vec![0xed, 0xab],
+        LDIR => vec![0xed, 0xb0],
+        CPIR => vec![0xed, 0xb1],
+        INIR => vec![0xed, 0xb2],
+        OTIR => vec![0xed, 0xb3],
+        LDDR => vec![0xed, 0xb8],
+        CPDR => vec![0xed, 0xb9],
+        INDR => vec![0xed, 0xba],
+        OTDR => vec![0xed, 0xbb],
+        IM(mode) => vec![0xed, *[0x46, 0x56, 0x5e].get(*mode as usize)?],
+
+        LD8(dest, source) => ld8(dest, source)?,
+        LD16(dest, source) => ld16(dest, source)?,
+
+        ADD8(Arg8::Register(Reg8::A), source) => alu(0, source)?,
+        ADC8(Arg8::Register(Reg8::A), source) => alu(1, source)?,
+        SUB8(source) => alu(2, source)?,
+        SBC8(source) => alu(3, source)?,
+        AND(source) => alu(4, source)?,
+        XOR(source) => alu(5, source)?,
+        OR(source) => alu(6, source)?,
+        CP(source) => alu(7, source)?,
+
+        ADD16(Arg16::Register(dest), Arg16::Register(source)) => {
+            let (prefix, p) = pair16(*dest, *source)?;
+            prefixed(prefix, &[0x09 | p << 4])
+        }
+        ADC16(Arg16::Register(Reg16::HL), Arg16::Register(source)) => vec![0xed, 0x4a | plain_pair(*source)? << 4],
+        SBC16(Arg16::Register(Reg16::HL), Arg16::Register(source)) => vec![0xed, 0x42 | plain_pair(*source)? << 4],
+
+        INC8(arg) => single(0x04, arg)?,
+        DEC8(arg) => single(0x05, arg)?,
+        INC16(Arg16::Register(reg)) => {
+            let (prefix, p) = pair(*reg)?;
+            prefixed(prefix, &[0x03 | p << 4])
+        }
+        DEC16(Arg16::Register(reg)) => {
+            let (prefix, p) = pair(*reg)?;
+            prefixed(prefix, &[0x0b | p << 4])
+        }
+
+        EX(Arg16::Register(Reg16::AF), Arg16::Register(Reg16::_AF)) => vec![0x08],
+        EX(Arg16::Register(Reg16::DE), Arg16::Register(Reg16::HL)) => vec![0xeb],
+        EX(Arg16::Memory(Address::SP), Arg16::Register(reg)) => prefixed(index_prefix(*reg)?, &[0xe3]),
+        PUSH(Arg16::Register(reg)) => {
+            let (prefix, p) = stack_pair(*reg)?;
+            prefixed(prefix, &[0xc5 | p << 4])
+        }
+        POP(Arg16::Register(reg)) => {
+            let (prefix, p) = stack_pair(*reg)?;
+            prefixed(prefix, &[0xc1 | p << 4])
+        }
+
+        JP(Address::Direct(Data16(nn))) => with_word(vec![0xc3], *nn),
+        JP(Address::HL) => vec![0xe9],
+        JP(Address::IX) => vec![DD, 0xe9],
+        JP(Address::IY) => vec![FD, 0xe9],
+        JP_COND(cond, Address::Direct(Data16(nn))) => with_word(vec![0xc2 | condition(cond)? << 3], *nn),
+        CALL(Address::Direct(Data16(nn))) => with_word(vec![0xcd], *nn),
+        CALL_COND(cond, Address::Direct(Data16(nn))) => with_word(vec![0xc4 | condition(cond)? << 3], *nn),
+        RET_COND(cond) => vec![0xc0 | condition(cond)? << 3],
+        JR(rel) => vec![0x18, displacement(*rel)?],
+        JR_COND(cond, rel) => {
+            let cc = condition(cond).filter(|cc| *cc < 4)?;
+            vec![0x20 | cc << 3, displacement(*rel)?]
+        }
+        DJNZ(rel) => vec![0x10, displacement(*rel)?],
+        RST(vector) if vector & !0x38 == 0 => vec![0xc7 | vector],
+
+        IN(Arg8::Register(Reg8::A), Arg8::Immediate(Data8(n))) => vec![0xdb, *n],
+        IN(Arg8::Register(reg), Arg8::Register(Reg8::C)) => {
+            let code = if let Reg8::F = reg { 6 } else { plain(*reg)? };
+            vec![0xed, 0x40 | code << 3]
+        }
+        OUT(Arg8::Immediate(Data8(n)), Arg8::Register(Reg8::A)) => vec![0xd3, *n],
+        OUT(Arg8::Register(Reg8::C), Arg8::Register(reg)) => vec![0xed, 0x41 | plain(*reg)? << 3],
+        OUT(Arg8::Register(Reg8::C), Arg8::Immediate(Data8(0))) => vec![0xed, 0x71],
+
+        RLC(arg) => bits(0x00, arg)?,
+        RRC(arg) => bits(0x08, arg)?,
+        RL(arg) => bits(0x10, arg)?,
+        RR(arg) => bits(0x18, arg)?,
+        SLA(arg) => bits(0x20, arg)?,
+        SRA(arg) => bits(0x28, arg)?,
+        SLL(arg) => bits(0x30, arg)?,
+        SRL(arg) => bits(0x38, arg)?,
+        BIT(bit, arg) if *bit < 8 && !matches!(arg, Arg8::Copy(..)) => bits(0x40 | bit << 3, arg)?,
+        RES(bit, arg) if *bit < 8 => bits(0x80 | bit << 3, arg)?,
+        SET(bit, arg) if *bit < 8 => bits(0xc0 | bit << 3, arg)?,
+
+        _ => return None,
+    };
+    Some(bytes)
+}
+
+fn ld8(dest: &Arg8, source: &Arg8) -> Option<Vec<u8>> {
+    use self::Arg8::*;
+    let bytes = match (dest, source) {
+        (Register(Reg8::A), Memory(Address::BC)) => vec![0x0a],
+        (Register(Reg8::A), Memory(Address::DE)) => vec![0x1a],
+        (Register(Reg8::A), Memory(Address::Direct(Data16(nn)))) => with_word(vec![0x3a], *nn),
+        (Memory(Address::BC), Register(Reg8::A)) => vec![0x02],
+        (Memory(Address::DE), Register(Reg8::A)) => vec![0x12],
+        (Memory(Address::Direct(Data16(nn))), Register(Reg8::A)) => with_word(vec![0x32], *nn),
+        (Register(Reg8::I), Register(Reg8::A)) => vec![0xed, 0x47],
+        (Register(Reg8::R), Register(Reg8::A)) => vec![0xed, 0x4f],
+        (Register(Reg8::A), Register(Reg8::I)) => vec![0xed, 0x57],
+        (Register(Reg8::A), Register(Reg8::R)) => vec![0xed, 0x5f],
+        (dest, Immediate(Data8(n))) => {
+            let dest = field(dest)?;
+            let mut bytes = op(dest, 0x06 | dest.code << 3);
+            bytes.push(*n);
+            bytes
+        }
+        (dest, source) => {
+            let (dest, source) = (field(dest)?, field(source)?);
+            // ld (hl),(hl) would be halt
+            if dest.code == 6 && source.code == 6 {
+                return None;
+            }
+            op(combine(dest, source)?, 0x40 | dest.code << 3 | source.code)
+        }
+    };
+    Some(bytes)
+}
+
+fn ld16(dest: &Arg16, source: &Arg16) -> Option<Vec<u8>> {
+    use self::Arg16::*;
+    let bytes = match (dest, source) {
+        (Register(Reg16::SP), Register(reg)) => prefixed(index_prefix(*reg)?, &[0xf9]),
+        (Register(reg), Immediate(Data16(nn))) => {
+            let (prefix, p) = pair(*reg)?;
+            with_word(prefixed(prefix, &[0x01 | p << 4]), *nn)
+        }
+        (Register(reg), Memory(Address::Direct(Data16(nn)))) => match index_prefix(*reg) {
+            Some(prefix) => with_word(prefixed(prefix, &[0x2a]), *nn),
+            None => with_word(vec![0xed, 0x4b | plain_pair(*reg)? << 4], *nn),
+        },
+        (Memory(Address::Direct(Data16(nn))), Register(reg)) => match index_prefix(*reg) {
+            Some(prefix) => with_word(prefixed(prefix, &[0x22]), *nn),
+            None => with_word(vec![0xed, 0x43 | plain_pair(*reg)? << 4], *nn),
+        },
+        _ => return None,
+    };
+    Some(bytes)
+}
+
+/// add, adc, sub, sbc, and, xor, or and cp, by their number in that list
+fn alu(operation: u8, source: &Arg8) -> Option<Vec<u8>> {
+    match source {
+        Arg8::Immediate(Data8(n)) => Some(vec![0xc6 | operation << 3, *n]),
+        _ => {
+            let source = field(source)?;
+            Some(op(source, 0x80 | operation << 3 | source.code))
+        }
+    }
+}
+
+/// inc and dec, which have the register in bits 3-5
+fn single(opcode: u8, arg: &Arg8) -> Option<Vec<u8>> {
+    let arg = field(arg)?;
+    Some(op(arg, opcode | arg.code << 3))
+}
+
+/// The cb page. Indexed operands go through ddcb/fdcb, where the
+/// displacement comes before the opcode and a register can take a copy.
+fn bits(opcode: u8, arg: &Arg8) -> Option<Vec<u8>> {
+    let (arg, copy) = match arg {
+        Arg8::Copy(arg, reg) => (field(arg)?, Some(plain(*reg)?)),
+        arg => (field(arg)?, None),
+    };
+    match (arg.prefix, arg.disp) {
+        (Some(prefix), Some(disp)) => Some(vec![prefix, 0xcb, disp as u8, opcode | copy.unwrap_or(6)]),
+        (None, None) if copy.is_none() => Some(vec![0xcb, opcode | arg.code]),
+        _ => None,
+    }
+}
+
+/// Prefix, opcode and displacement
+fn op(field: Field, opcode: u8) -> Vec<u8> {
+    let mut bytes = prefixed(field.prefix, &[opcode]);
+    if let Some(disp) = field.disp {
+        bytes.push(disp as u8);
+    }
+    bytes
+}
+
+fn prefixed(prefix: Option<u8>, bytes: &[u8]) -> Vec<u8> {
+    prefix.into_iter().chain(bytes.iter().copied()).collect()
+}
+
+fn with_word(mut bytes: Vec<u8>, word: u16) -> Vec<u8> {
+    bytes.extend_from_slice(&word.to_le_bytes());
+    bytes
+}
+
+fn field(arg: &Arg8) -> Option<Field> {
+    let field = |prefix, code, disp| Some(Field { prefix, code, disp });
+    match arg {
+        Arg8::Register(Reg8::IXH) => field(Some(DD), 4, None),
+        Arg8::Register(Reg8::IXL) => field(Some(DD), 5, None),
+        Arg8::Register(Reg8::IYH) => field(Some(FD), 4, None),
+        Arg8::Register(Reg8::IYL) => field(Some(FD), 5, None),
+        Arg8::Register(reg) => field(None, plain(*reg)?, None),
+        Arg8::Memory(Address::HL) => field(None, 6, None),
+        Arg8::Memory(Address::Indexed(reg, disp)) => field(Some(index_prefix(*reg)??), 6, Some(*disp)),
+        _ => None,
+    }
+}
+
+/// The prefix for two operands of one opcode. h and l can not be used with
+/// ixh, ixl, iyh, iyl or an index, they would turn into them.
+fn combine(a: Field, b: Field) -> Option<Field> {
+    let uses_hl = |f: Field| f.prefix.is_none() && (4..=6).contains(&f.code);
+    match (a.prefix, b.prefix) {
+        (None, None) => Some(a),
+        (Some(_), None) if !uses_hl(b) || a.disp.is_some() && b.code != 6 => Some(a),
+        (None, Some(_)) if !uses_hl(a) || b.disp.is_some() && a.code != 6 => Some(b),
+        (Some(x), Some(y)) if x == y && a.disp.is_none() && b.disp.is_none() => Some(a),
+        _ => None,
+    }
+}
+
+fn plain(reg: Reg8) -> Option<u8> {
+    match reg {
+        Reg8::B => Some(0),
+        Reg8::C => Some(1),
+        Reg8::D => Some(2),
+        Reg8::E => Some(3),
+        Reg8::H => Some(4),
+        Reg8::L => Some(5),
+        Reg8::A => Some(7),
+        _ => None,
+    }
+}
+
+/// Some(None) for hl, the prefix for ix and iy, None for anything else
+fn index_prefix(reg: Reg16) -> Option<Option<u8>> {
+    match reg {
+        Reg16::HL => Some(None),
+        Reg16::IX => Some(Some(DD)),
+        Reg16::IY => Some(Some(FD)),
+        _ => None,
+    }
+}
+
+fn plain_pair(reg: Reg16) -> Option<u8> {
+    match reg {
+        Reg16::BC => Some(0),
+        Reg16::DE => Some(1),
+        Reg16::HL => Some(2),
+        Reg16::SP => Some(3),
+        _ => None,
+    }
+}
+
+/// bc, de, hl or sp, where ix and iy take the place of hl
+fn pair(reg: Reg16) -> Option<(Option<u8>, u8)> {
+    match index_prefix(reg) {
+        Some(prefix) => Some((prefix, 2)),
+        None => Some((None, plain_pair(reg)?)),
+    }
+}
+
+/// push and pop have af where the others have sp
+fn stack_pair(reg: Reg16) -> Option<(Option<u8>, u8)> {
+    match reg {
+        Reg16::AF => Some((None, 3)),
+        Reg16::SP => None,
+        reg => pair(reg),
+    }
+}
+
+/// add hl,ss and add ix,pp, where the second operand is ix only in add ix,ix
+fn pair16(dest: Reg16, source: Reg16) -> Option<(Option<u8>, u8)> {
+    let prefix = index_prefix(dest)?;
+    match source {
+        Reg16::HL | Reg16::IX | Reg16::IY => {
+            if index_prefix(source)? == prefix {
+                Some((prefix, 2))
+            } else {
+                None
+            }
+        }
+        source => Some((prefix, plain_pair(source)?)),
+    }
+}
+
+fn condition(cond: &Cond) -> Option<u8> {
+    match cond {
+        Cond::NotZero => Some(0),
+        Cond::Zero => Some(1),
+        Cond::NotCarry => Some(2),
+        Cond::Carry => Some(3),
+        Cond::ParityOdd => Some(4),
+        Cond::ParityEven => Some(5),
+        Cond::Positive => Some(6),
+        Cond::Negative => Some(7),
+        _ => None,
+    }
+}
+
+/// Relative targets count from the start of the instruction, the byte
+/// from the end of it
+fn displacement(rel: Rel) -> Option<u8> {
+    i8::try_from(rel.0 - 2).ok().map(|d| d as u8)
+}
@@ -0,0 +1,155 @@
+//! Operand expressions: numbers, symbols, `$` and C-like operators
+
+pub(super) enum ExprError {
+    Syntax,
+    Undefined(String),
+}
+
+/// Evaluates `text`. `symbol` looks names up and `$` is `pc`.
+pub(super) fn evaluate(text: &str, pc: u16, symbol: &dyn Fn(&str) -> Option<i32>) -> Result<i32, ExprError> {
+    let mut parser = Parser { text: text.as_bytes(), pos: 0, pc, symbol };
+    let value = parser.binary(0)?;
+    parser.skip_space();
+    if parser.pos == parser.text.len() {
+        Ok(value)
+    } else {
+        Err(ExprError::Syntax)
+    }
+}
+
+/// Whether `name` can be a label or an equ
+pub(super) fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Binary operators from the loosest binding
+const LEVELS: [&[&str]; 6] = [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+    pc: u16,
+    symbol: &'a dyn Fn(&str) -> Option<i32>,
+}
+
+impl Parser<'_> {
+    fn binary(&mut self, level: usize) -> Result<i32, ExprError> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut value = self.binary(level + 1)?;
+        'operators: loop {
+            self.skip_space();
+            for operator in LEVELS[level] {
+                if self.text[self.pos..].starts_with(operator.as_bytes()) {
+                    self.pos += operator.len();
+                    let right = self.binary(level + 1)?;
+                    value = match *operator {
+                        "|" => value | right,
+                        "^" => value ^ right,
+                        "&" => value & right,
+                        "<<" => value.wrapping_shl(right as u32),
+                        ">>" => value.wrapping_shr(right as u32),
+                        "+" => value.wrapping_add(right),
+                        "-" => value.wrapping_sub(right),
+                        "*" => value.wrapping_mul(right),
+                        "/" => value.checked_div(right).ok_or(ExprError::Syntax)?,
+                        _ => value.checked_rem(right).ok_or(ExprError::Syntax)?,
+                    };
+                    continue 'operators;
+                }
+            }
+            return Ok(value);
+        }
+    }
+
+    fn unary(&mut self) -> Result<i32, ExprError> {
+        self.skip_space();
+        match self.peek() {
+            Some(b'-') => {
+                self.pos += 1;
+                Ok(self.unary()?.wrapping_neg())
+            }
+            Some(b'+') => {
+                self.pos += 1;
+                self.unary()
+            }
+            Some(b'~') => {
+                self.pos += 1;
+                Ok(!self.unary()?)
+            }
+            Some(b'(') => {
+                self.pos += 1;
+                let value = self.binary(0)?;
+                self.skip_space();
+                if self.peek() != Some(b')') {
+                    return Err(ExprError::Syntax);
+                }
+                self.pos += 1;
+                Ok(value)
+            }
+            Some(b'\'') => match self.text.get(self.pos..self.pos + 3) {
+                Some([b'\'', c, b'\'']) => {
+                    self.pos += 3;
+                    Ok(*c as i32)
+                }
+                _ => Err(ExprError::Syntax),
+            },
+            Some(b'$') if !self.text.get(self.pos + 1).is_some_and(|c| c.is_ascii_hexdigit()) => {
+                self.pos += 1;
+                Ok(self.pc as i32)
+            }
+            Some(b'$') | Some(b'%') => {
+                let radix = if self.peek() == Some(b'$') { 16 } else { 2 };
+                self.pos += 1;
+                let digits = self.word();
+                number(digits, radix)
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let word = self.word().to_ascii_lowercase();
+                if let Some(hex) = word.strip_prefix("0x") {
+                    number(hex, 16)
+                } else if let Some(hex) = word.strip_suffix('h') {
+                    number(hex, 16)
+                } else if let Some(binary) = word.strip_prefix("0b") {
+                    number(binary, 2)
+                } else {
+                    number(&word, 10)
+                }
+            }
+            Some(_) => {
+                let name = self.word().to_string();
+                if !is_identifier(&name) {
+                    return Err(ExprError::Syntax);
+                }
+                (self.symbol)(&name).ok_or(ExprError::Undefined(name))
+            }
+            None => Err(ExprError::Syntax),
+        }
+    }
+
+    /// A run of letters, digits, `_` and `.`
+    fn word(&mut self) -> &str {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == b'_' || c == b'.') {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.text[start..self.pos]).unwrap_or("")
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).copied()
+    }
+
+    fn skip_space(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+}
+
+fn number(digits: &str, radix: u32) -> Result<i32, ExprError> {
+    i32::from_str_radix(digits, radix).map_err(|_| ExprError::Syntax)
+}
@@ -0,0 +1,615 @@
+//! A two pass assembler for Zilog syntax, and the encoding of disassembled
+//! instructions back into bytes.
+//!
+//! Each line is `[label:] [mnemonic [operands]] [; comment]`. Besides the
+//! instructions, including the undocumented ones the disassembler prints,
+//! it knows `org`, `db`/`defb`/`defm`, `dw`/`defw`, `ds`/`defs`,
+//! `name equ value` and `end [entry]`. Numbers are written `$ff`, `0xff`,
+//! `0ffh`, `%1010`, `0b1010`, decimal or `'c'`, and `$` alone is the
+//! address of the current line.
+
+mod encode;
+mod expr;
+
+pub use self::encode::encode;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+use self::expr::{evaluate, is_identifier, ExprError};
+use crate::disassembler::instruction::{Address, Arg16, Arg8, Cond, Data16, Data8, Instruction, Rel};
+use crate::loader::Program;
+use crate::registers::{Reg16, Reg8};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmError {
+    Syntax { line: usize },
+    UnknownMnemonic { line: usize, mnemonic: String },
+    /// No form of the instruction takes these operands
+    InvalidOperands { line: usize },
+    UndefinedSymbol { line: usize, name: String },
+    DuplicateSymbol { line: usize, name: String },
+    /// A value that does not fit its operand, or a relative jump that is too far
+    OutOfRange { line: usize, value: i32 },
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsmError::Syntax { line } => write!(f, "line {}: syntax error", line),
+            AsmError::UnknownMnemonic { line, mnemonic } => write!(f, "line {}: unknown mnemonic {}", line, mnemonic),
+            AsmError::InvalidOperands { line } => write!(f, "line {}: invalid operands", line),
+            AsmError::UndefinedSymbol { line, name } => write!(f, "line {}: undefined symbol {}", line, name),
+            AsmError::DuplicateSymbol { line, name } => write!(f, "line {}: {} is already defined", line, name),
+            AsmError::OutOfRange { line, value } => write!(f, "line {}: {} is out of range", line, value),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Assembles `source` into a program with a segment for each `org`
+pub fn assemble(source: &str) -> Result<Program, AsmError> {
+    let mut assembler = Assembler { symbols: HashMap::new(), last_pass: false, pc: 0, line: 0, program: Program::default() };
+    assembler.pass(source)?;
+    assembler.last_pass = true;
+    assembler.pass(source)?;
+    Ok(assembler.program)
+}
+
+/// The bytes from the lowest address assembled to the highest, with any
+/// gaps between `org`s filled with zeros
+pub fn assemble_bytes(source: &str) -> Result<Vec<u8>, AsmError> {
+    let program = assemble(source)?;
+    let start = program.segments.iter().map(|s| s.address).min().unwrap_or(0);
+    let mut bytes = vec![];
+    for segment in program.segments.iter() {
+        let offset = (segment.address - start) as usize;
+        if bytes.len() < offset + segment.data.len() {
+            bytes.resize(offset + segment.data.len(), 0);
+        }
+        bytes[offset..offset + segment.data.len()].copy_from_slice(&segment.data);
+    }
+    Ok(bytes)
+}
+
+/// An operand before the instruction decides what it can be
+enum Operand {
+    Reg8(Reg8),
+    Reg16(Reg16),
+    /// `(c)` in `in` and `out`
+    Port,
+    /// `(bc)`, `(de)`, `(hl)`, `(sp)`, `(ix)` or `(iy)`
+    Indirect(Reg16),
+    Indexed(Reg16, i32),
+    Memory(i32),
+    Value(i32),
+}
+
+struct Assembler {
+    symbols: HashMap<String, i32>,
+    /// Undefined symbols read as 0 and values are not range checked
+    /// until the last pass, when every label has an address
+    last_pass: bool,
+    pc: u16,
+    line: usize,
+    program: Program,
+}
+
+impl Assembler {
+    fn pass(&mut self, source: &str) -> Result<(), AsmError> {
+        self.pc = 0;
+        for (i, text) in source.lines().enumerate() {
+            self.line = i + 1;
+            if !self.statement(text)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Assembles one line, false after `end`
+    fn statement(&mut self, text: &str) -> Result<bool, AsmError> {
+        let text = strip_comment(text).trim();
+        let (label, rest) = match text.find(':') {
+            Some(i) if is_identifier(&text[..i]) => (Some(&text[..i]), text[i + 1..].trim()),
+            _ => (None, text),
+        };
+        let (mnemonic, operands) = match rest.find(char::is_whitespace) {
+            Some(i) => (&rest[..i], rest[i..].trim()),
+            None => (rest, ""),
+        };
+
+        // `name equ value` and `name = value`
+        let (label, mnemonic, operands) = match operands.split_once(char::is_whitespace) {
+            Some((word, value)) if label.is_none() && word.eq_ignore_ascii_case("equ") => (Some(mnemonic), "equ", value.trim()),
+            _ => match rest.split_once('=') {
+                Some((name, value)) if label.is_none() && is_identifier(name.trim()) => (Some(name.trim()), "equ", value.trim()),
+                _ => (label, mnemonic, operands),
+            },
+        };
+        let mnemonic = mnemonic.to_ascii_lowercase();
+        let operands = split_operands(operands);
+
+        if mnemonic == "equ" {
+            let name = label.ok_or(AsmError::Syntax { line: self.line })?;
+            match (operands.as_slice(), self.last_pass) {
+                ([value], true) => {
+                    let value = self.evaluate(value)?;
+                    self.symbols.insert(name.to_string(), value);
+                }
+                // an equ that uses a later label gets its value in the last pass
+                ([value], false) => {
+                    self.define(name)?;
+                    match evaluate(value, self.pc, &|name| self.symbols.get(name).copied()) {
+                        Ok(value) => self.symbols.insert(name.to_string(), value),
+                        Err(_) => self.symbols.remove(name),
+                    };
+                }
+                _ => return Err(AsmError::Syntax { line: self.line }),
+            }
+            return Ok(true);
+        }
+        if let Some(label) = label {
+            self.define(label)?;
+            self.symbols.insert(label.to_string(), self.pc as i32);
+        }
+
+        match mnemonic.as_str() {
+            "" => {}
+            "org" => {
+                let origin = self.single(&operands)?;
+                // labels before the first pass has seen them would move code around
+                let origin = evaluate(origin, self.pc, &|name| self.symbols.get(name).copied())
+                    .map_err(|err| self.expr_error(err))?;
+                self.pc = self.word(origin)?;
+            }
+            "end" => {
+                if let [entry] = operands.as_slice() {
+                    let entry = self.evaluate(entry)?;
+                    self.program.entry = Some(self.word(entry)? as u32);
+                }
+                return Ok(false);
+            }
+            "db" | "defb" | "defm" => {
+                let mut bytes = vec![];
+                for operand in operands.iter() {
+                    match string(operand) {
+                        Some(text) => bytes.extend_from_slice(text.as_bytes()),
+                        None => {
+                            let value = self.evaluate(operand)?;
+                            bytes.push(self.byte(value)?);
+                        }
+                    }
+                }
+                self.emit(&bytes);
+            }
+            "dw" | "defw" => {
+                let mut bytes = vec![];
+                for operand in operands.iter() {
+                    let value = self.evaluate(operand)?;
+                    bytes.extend_from_slice(&self.word(value)?.to_le_bytes());
+                }
+                self.emit(&bytes);
+            }
+            "ds" | "defs" => {
+                let (count, fill) = match operands.as_slice() {
+                    [count] => (count, 0),
+                    [count, fill] => {
+                        let fill = self.evaluate(fill)?;
+                        (count, self.byte(fill)?)
+                    }
+                    _ => return Err(AsmError::Syntax { line: self.line }),
+                };
+                let count = evaluate(count, self.pc, &|name| self.symbols.get(name).copied())
+                    .map_err(|err| self.expr_error(err))?;
+                let count = usize::try_from(count).map_err(|_| AsmError::OutOfRange { line: self.line, value: count })?;
+                self.emit(&vec![fill; count]);
+            }
+            _ => {
+                let instruction = self.instruction(&mnemonic, &operands)?;
+                let bytes = encode(&instruction).ok_or(AsmError::InvalidOperands { line: self.line })?;
+                self.emit(&bytes);
+            }
+        }
+        Ok(true)
+    }
+
+    fn instruction(&self, mnemonic: &str, operands: &[&str]) -> Result<Instruction, AsmError> {
+        use self::Instruction::*;
+        let invalid = AsmError::InvalidOperands { line: self.line };
+        let implied = match mnemonic {
+            "nop" => Some(NOP),
+            "halt" => Some(HALT),
+            "di" => Some(DI),
+            "ei" => Some(EI),
+            "exx" => Some(EXX),
+            "daa" => Some(DAA),
+            "cpl" => Some(CPL),
+            "scf" => Some(SCF),
+            "ccf" => Some(CCF),
+            "rlca" => Some(RLCA),
+            "rrca" => Some(RRCA),
+            "rla" => Some(RLA),
+            "rra" => Some(RRA),
+            "neg" => Some(NEG),
+            "retn" => Some(RETN),
+            "reti" => Some(RETI),
+            "rrd" => Some(RRD),
+            "rld" => Some(RLD),
+            "ldi" => Some(LDI),
+            "cpi" => Some(CPI),
+            "ini" => Some(INI),
+            "outi" => Some(OUTI),
+            "ldd" => Some(LDD),
+            "cpd" => Some(CPD),
+            "ind" => Some(IND),
+            "outd" => Some(OUTD),
+            "ldir" => Some(LDIR),
+            "cpir" => Some(CPIR),
+            "inir" => Some(INIR),
+            "otir" => Some(OTIR),
+            "lddr" => Some(LDDR),
+            "cpdr" => Some(CPDR),
+            "indr" => Some(INDR),
+            "otdr" => Some(OTDR),
+            _ => None,
+        };
+        if let Some(instruction) = implied {
+            return if operands.is_empty() { Ok(instruction) } else { Err(invalid) };
+        }
+
+        // conditions first, as c would otherwise be the register
+        match (mnemonic, operands) {
+            ("ret", []) => return Ok(RET),
+            ("ret", [cond]) => return Ok(RET_COND(self.condition(cond)?)),
+            ("jp", [cond, target]) => return Ok(JP_COND(self.condition(cond)?, self.address(target)?)),
+            ("call", [cond, target]) => return Ok(CALL_COND(self.condition(cond)?, self.address(target)?)),
+            ("jr", [cond, target]) => return Ok(JR_COND(self.condition(cond)?, self.relative(target)?)),
+            ("call", [target]) => return Ok(CALL(self.address(target)?)),
+            ("jr", [target]) => return Ok(JR(self.relative(target)?)),
+            ("djnz", [target]) => return Ok(DJNZ(self.relative(target)?)),
+            _ => {}
+        }
+
+        let operands = operands.iter().map(|op| self.operand(op)).collect::<Result<Vec<_>, _>>()?;
+        let a = Arg8::Register(Reg8::A);
+        let instruction = match (mnemonic, operands.as_slice()) {
+            ("ld", [d @ Operand::Reg16(_), s]) | ("ld", [d, s @ Operand::Reg16(_)]) => LD16(self.arg16(d)?, self.arg16(s)?),
+            ("ld", [d, s]) => LD8(self.arg8(d)?, self.arg8(s)?),
+
+            ("add", [d @ Operand::Reg16(_), s]) => ADD16(self.arg16(d)?, self.arg16(s)?),
+            ("adc", [d @ Operand::Reg16(_), s]) => ADC16(self.arg16(d)?, self.arg16(s)?),
+            ("sbc", [d @ Operand::Reg16(_), s]) => SBC16(self.arg16(d)?, self.arg16(s)?),
+            ("add", [Operand::Reg8(Reg8::A), s]) | ("add", [s]) => ADD8(a, self.arg8(s)?),
+            ("adc", [Operand::Reg8(Reg8::A), s]) | ("adc", [s]) => ADC8(a, self.arg8(s)?),
+            ("sbc", [Operand::Reg8(Reg8::A), s]) | ("sbc", [s]) => SBC8(self.arg8(s)?),
+            ("sub", [Operand::Reg8(Reg8::A), s]) | ("sub", [s]) => SUB8(self.arg8(s)?),
+            ("and", [Operand::Reg8(Reg8::A), s]) | ("and", [s]) => AND(self.arg8(s)?),
+            ("xor", [Operand::Reg8(Reg8::A), s]) | ("xor", [s]) => XOR(self.arg8(s)?),
+            ("or", [Operand::Reg8(Reg8::A), s]) | ("or", [s]) => OR(self.arg8(s)?),
+            ("cp", [Operand::Reg8(Reg8::A), s]) | ("cp", [s]) => CP(self.arg8(s)?),
+
+            ("inc", [r @ Operand::Reg16(_)]) => INC16(self.arg16(r)?),
+            ("dec", [r @ Operand::Reg16(_)]) => DEC16(self.arg16(r)?),
+            ("inc", [r]) => INC8(self.arg8(r)?),
+            ("dec", [r]) => DEC8(self.arg8(r)?),
+
+            ("ex", [a, b]) => EX(self.arg16(a)?, self.arg16(b)?),
+            ("push", [r]) => PUSH(self.arg16(r)?),
+            ("pop", [r]) => POP(self.arg16(r)?),
+            ("jp", [Operand::Indirect(Reg16::HL)]) => JP(Address::HL),
+            ("jp", [Operand::Indirect(Reg16::IX)]) => JP(Address::IX),
+            ("jp", [Operand::Indirect(Reg16::IY)]) => JP(Address::IY),
+            ("jp", [Operand::Value(target)]) => JP(Address::Direct(Data16(self.word(*target)?))),
+            ("rst", [Operand::Value(vector)]) => RST(self.byte(*vector)?),
+            ("im", [Operand::Value(mode)]) => IM(self.byte(*mode)?),
+
+            ("in", [Operand::Port]) => IN(Arg8::Register(Reg8::F), Arg8::Register(Reg8::C)),
+            ("in", [Operand::Reg8(r), Operand::Port]) => IN(Arg8::Register(*r), Arg8::Register(Reg8::C)),
+            ("in", [Operand::Reg8(Reg8::A), Operand::Memory(port)]) => IN(a, Arg8::Immediate(Data8(self.byte(*port)?))),
+            ("out", [Operand::Port, Operand::Reg8(r)]) => OUT(Arg8::Register(Reg8::C), Arg8::Register(*r)),
+            ("out", [Operand::Port, Operand::Value(0)]) => OUT(Arg8::Register(Reg8::C), Arg8::Immediate(Data8(0))),
+            ("out", [Operand::Memory(port), Operand::Reg8(Reg8::A)]) => OUT(Arg8::Immediate(Data8(self.byte(*port)?)), a),
+
+            ("rlc", ops) | ("rrc", ops) | ("rl", ops) | ("rr", ops) | ("sla", ops) | ("sra", ops) | ("sll", ops)
+            | ("sl1", ops) | ("srl", ops) => {
+                let arg = self.shifted(ops)?;
+                match mnemonic {
+                    "rlc" => RLC(arg),
+                    "rrc" => RRC(arg),
+                    "rl" => RL(arg),
+                    "rr" => RR(arg),
+                    "sla" => SLA(arg),
+                    "sra" => SRA(arg),
+                    "srl" => SRL(arg),
+                    _ => SLL(arg),
+                }
+            }
+            ("bit", [Operand::Value(bit), ops @ ..]) | ("res", [Operand::Value(bit), ops @ ..])
+            | ("set", [Operand::Value(bit), ops @ ..]) => {
+                let bit = u8::try_from(*bit).ok().filter(|bit| *bit < 8).ok_or(invalid)?;
+                let arg = self.shifted(ops)?;
+                match mnemonic {
+                    "bit" => BIT(bit, arg),
+                    "res" => RES(bit, arg),
+                    _ => SET(bit, arg),
+                }
+            }
+
+            ("ld", _) | ("add", _) | ("adc", _) | ("sbc", _) | ("sub", _) | ("and", _) | ("xor", _) | ("or", _)
+            | ("cp", _) | ("inc", _) | ("dec", _) | ("ex", _) | ("push", _) | ("pop", _) | ("jp", _) | ("call", _)
+            | ("jr", _) | ("djnz", _) | ("rst", _) | ("im", _) | ("in", _) | ("out", _) => return Err(invalid),
+            _ => return Err(AsmError::UnknownMnemonic { line: self.line, mnemonic: mnemonic.to_string() }),
+        };
+        Ok(instruction)
+    }
+
+    /// The operand of a cb page instruction, with the register that takes a
+    /// copy in `rlc (ix+5),b`
+    fn shifted(&self, operands: &[Operand]) -> Result<Arg8, AsmError> {
+        match operands {
+            [arg] => self.arg8(arg),
+            [arg, Operand::Reg8(reg)] => Ok(Arg8::Copy(Box::new(self.arg8(arg)?), *reg)),
+            _ => Err(AsmError::InvalidOperands { line: self.line }),
+        }
+    }
+
+    fn operand(&self, text: &str) -> Result<Operand, AsmError> {
+        let lower = text.to_ascii_lowercase();
+        if let Some(reg) = reg8(&lower) {
+            return Ok(Operand::Reg8(reg));
+        }
+        if let Some(reg) = reg16(&lower) {
+            return Ok(Operand::Reg16(reg));
+        }
+        if let Some(inner) = parenthesized(text) {
+            let lower = inner.to_ascii_lowercase();
+            if lower == "c" {
+                return Ok(Operand::Port);
+            }
+            match reg16(&lower) {
+                Some(reg @ Reg16::BC) | Some(reg @ Reg16::DE) | Some(reg @ Reg16::HL) | Some(reg @ Reg16::SP)
+                | Some(reg @ Reg16::IX) | Some(reg @ Reg16::IY) => return Ok(Operand::Indirect(reg)),
+                Some(_) => return Err(AsmError::InvalidOperands { line: self.line }),
+                None => {}
+            }
+            for (name, reg) in [("ix", Reg16::IX), ("iy", Reg16::IY)].iter() {
+                if let Some(offset) = lower.strip_prefix(*name) {
+                    if offset.trim_start().starts_with(['+', '-']) {
+                        let offset = &inner[inner.len() - offset.len()..];
+                        return Ok(Operand::Indexed(*reg, self.evaluate(offset)?));
+                    }
+                }
+            }
+            return Ok(Operand::Memory(self.evaluate(inner)?));
+        }
+        Ok(Operand::Value(self.evaluate(text)?))
+    }
+
+    fn arg8(&self, operand: &Operand) -> Result<Arg8, AsmError> {
+        Ok(match operand {
+            Operand::Reg8(reg) => Arg8::Register(*reg),
+            Operand::Indirect(Reg16::BC) => Arg8::Memory(Address::BC),
+            Operand::Indirect(Reg16::DE) => Arg8::Memory(Address::DE),
+            Operand::Indirect(Reg16::HL) => Arg8::Memory(Address::HL),
+            Operand::Indirect(reg @ Reg16::IX) | Operand::Indirect(reg @ Reg16::IY) => Arg8::Memory(Address::Indexed(*reg, 0)),
+            Operand::Indexed(reg, offset) => Arg8::Memory(Address::Indexed(*reg, self.displacement(*offset)?)),
+            Operand::Memory(address) => Arg8::Memory(Address::Direct(Data16(self.word(*address)?))),
+            Operand::Value(value) => Arg8::Immediate(Data8(self.byte(*value)?)),
+            _ => return Err(AsmError::InvalidOperands { line: self.line }),
+        })
+    }
+
+    fn arg16(&self, operand: &Operand) -> Result<Arg16, AsmError> {
+        Ok(match operand {
+            Operand::Reg16(reg) => Arg16::Register(*reg),
+            Operand::Indirect(Reg16::SP) => Arg16::Memory(Address::SP),
+            Operand::Memory(address) => Arg16::Memory(Address::Direct(Data16(self.word(*address)?))),
+            Operand::Value(value) => Arg16::Immediate(Data16(self.word(*value)?)),
+            _ => return Err(AsmError::InvalidOperands { line: self.line }),
+        })
+    }
+
+    fn address(&self, text: &str) -> Result<Address, AsmError> {
+        let value = self.evaluate(text)?;
+        Ok(Address::Direct(Data16(self.word(value)?)))
+    }
+
+    /// A jr or djnz target, as an offset from this instruction
+    fn relative(&self, text: &str) -> Result<Rel, AsmError> {
+        let target = self.evaluate(text)?;
+        let offset = target - self.pc as i32;
+        if !self.last_pass {
+            Ok(Rel(2))
+        } else if (-126..=129).contains(&offset) {
+            Ok(Rel(offset as i16))
+        } else {
+            Err(AsmError::OutOfRange { line: self.line, value: target })
+        }
+    }
+
+    fn condition(&self, text: &str) -> Result<Cond, AsmError> {
+        Ok(match text.to_ascii_lowercase().as_str() {
+            "nz" => Cond::NotZero,
+            "z" => Cond::Zero,
+            "nc" => Cond::NotCarry,
+            "c" => Cond::Carry,
+            "po" => Cond::ParityOdd,
+            "pe" => Cond::ParityEven,
+            "p" => Cond::Positive,
+            "m" => Cond::Negative,
+            _ => return Err(AsmError::InvalidOperands { line: self.line }),
+        })
+    }
+
+    fn evaluate(&self, text: &str) -> Result<i32, AsmError> {
+        match evaluate(text, self.pc, &|name| self.symbols.get(name).copied()) {
+            Err(ExprError::Undefined(_)) if !self.last_pass => Ok(0),
+            result => result.map_err(|err| self.expr_error(err)),
+        }
+    }
+
+    fn expr_error(&self, err: ExprError) -> AsmError {
+        match err {
+            ExprError::Syntax => AsmError::Syntax { line: self.line },
+            ExprError::Undefined(name) => AsmError::UndefinedSymbol { line: self.line, name },
+        }
+    }
+
+    /// Bytes may be given signed or unsigned
+    fn byte(&self, value: i32) -> Result<u8, AsmError> {
+        self.checked(value, -0x80..=0xff).map(|value| value as u8)
+    }
+
+    fn word(&self, value: i32) -> Result<u16, AsmError> {
+        self.checked(value, -0x8000..=0xffff).map(|value| value as u16)
+    }
+
+    fn displacement(&self, value: i32) -> Result<i8, AsmError> {
+        self.checked(value, -0x80..=0x7f).map(|value| value as i8)
+    }
+
+    fn checked(&self, value: i32, range: std::ops::RangeInclusive<i32>) -> Result<i32, AsmError> {
+        if range.contains(&value) || !self.last_pass {
+            Ok(value)
+        } else {
+            Err(AsmError::OutOfRange { line: self.line, value })
+        }
+    }
+
+    fn single<'s>(&self, operands: &[&'s str]) -> Result<&'s str, AsmError> {
+        match operands {
+            [operand] => Ok(operand),
+            _ => Err(AsmError::Syntax { line: self.line }),
+        }
+    }
+
+    /// Labels and equs may only be defined once
+    fn define(&self, name: &str) -> Result<(), AsmError> {
+        if !self.last_pass && self.symbols.contains_key(name) {
+            return Err(AsmError::DuplicateSymbol { line: self.line, name: name.to_string() });
+        }
+        if reg8(&name.to_ascii_lowercase()).is_some() || reg16(&name.to_ascii_lowercase()).is_some() {
+            return Err(AsmError::Syntax { line: self.line });
+        }
+        Ok(())
+    }
+
+    /// Adds `bytes` at pc. Like pc they wrap around from $ffff to 0.
+    fn emit(&mut self, bytes: &[u8]) {
+        if self.last_pass && !bytes.is_empty() {
+            let (before, after) = bytes.split_at(bytes.len().min(0x10000 - self.pc as usize));
+            self.program.push(self.pc as u32, before);
+            if !after.is_empty() {
+                self.program.push(0, after);
+            }
+        }
+        self.pc = self.pc.wrapping_add(bytes.len() as u16);
+    }
+}
+
+fn reg8(name: &str) -> Option<Reg8> {
+    Some(match name {
+        "a" => Reg8::A,
+        "b" => Reg8::B,
+        "c" => Reg8::C,
+        "d" => Reg8::D,
+        "e" => Reg8::E,
+        "f" => Reg8::F,
+        "h" => Reg8::H,
+        "l" => Reg8::L,
+        "i" => Reg8::I,
+        "r" => Reg8::R,
+        "ixh" => Reg8::IXH,
+        "ixl" => Reg8::IXL,
+        "iyh" => Reg8::IYH,
+        "iyl" => Reg8::IYL,
+        _ => return None,
+    })
+}
+
+fn reg16(name: &str) -> Option<Reg16> {
+    Some(match name {
+        "af" => Reg16::AF,
+        "bc" => Reg16::BC,
+        "de" => Reg16::DE,
+        "hl" => Reg16::HL,
+        "sp" => Reg16::SP,
+        "ix" => Reg16::IX,
+        "iy" => Reg16::IY,
+        "af'" => Reg16::_AF,
+        _ => return None,
+    })
+}
+
+/// The inside of `(...)` when the outer parentheses go together, so that
+/// `(1+2)*3` is a value and not memory
+fn parenthesized(text: &str) -> Option<&str> {
+    let inner = text.strip_prefix('(')?.strip_suffix(')')?;
+    let mut depth = 0;
+    for c in inner.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return None,
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    Some(inner.trim())
+}
+
+/// The text of a quoted string of more than one character; `'c'` is a number
+fn string(operand: &str) -> Option<&str> {
+    let quote = operand.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let text = operand[1..].strip_suffix(quote)?;
+    if quote == '\'' && text.chars().count() == 1 {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (c, quote) {
+            (';', None) => return &line[..i],
+            ('\'', None) if is_shadow(&line[..i]) => {}
+            ('"', None) | ('\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Whether a `'` after `before` is the one in `af'` rather than a quote
+fn is_shadow(before: &str) -> bool {
+    before.len() >= 2 && before[before.len() - 2..].eq_ignore_ascii_case("af")
+}
+
+/// Splits on commas outside quotes and parentheses
+fn split_operands(text: &str) -> Vec<&str> {
+    let mut operands = vec![];
+    let (mut depth, mut quote, mut start) = (0, None, 0);
+    for (i, c) in text.char_indices() {
+        match (c, quote) {
+            ('\'', None) if is_shadow(&text[..i]) => {}
+            ('"', None) | ('\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('(', None) => depth += 1,
+            (')', None) => depth -= 1,
+            (',', None) if depth == 0 => {
+                operands.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if !text.trim().is_empty() {
+        operands.push(text[start..].trim());
+    }
+    operands
+}
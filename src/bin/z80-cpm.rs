@@ -0,0 +1,47 @@
+//! Runs a CP/M `.COM` program with the current directory as drive A.
+//!
+//!     z80-cpm [--dir <path>] <program.com> [args...]
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use z80::cpm::CpmMachine;
+
+fn usage() -> ! {
+    eprintln!("usage: z80-cpm [--dir <path>] <program.com> [args...]");
+    process::exit(2);
+}
+
+/// The program as given, else on the drive, else `NAME.COM` on the drive
+/// the way the CCP finds a bare name
+fn find_program(dir: &Path, program: &str) -> PathBuf {
+    let candidates = [PathBuf::from(program), dir.join(program), dir.join(format!("{}.com", program))];
+    candidates.iter().find(|path| path.is_file()).unwrap_or(&candidates[0]).clone()
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut dir = PathBuf::from(".");
+    let program = loop {
+        match args.next() {
+            Some(arg) if arg == "--dir" => dir = args.next().map(PathBuf::from).unwrap_or_else(|| usage()),
+            Some(arg) => break arg,
+            None => usage(),
+        }
+    };
+    let rest: Vec<String> = args.collect();
+
+    let path = find_program(&dir, &program);
+    let image = std::fs::read(&path).unwrap_or_else(|err| {
+        eprintln!("z80-cpm: {}: {}", path.display(), err);
+        process::exit(1);
+    });
+
+    let mut machine = CpmMachine::new(dir);
+    let rest: Vec<&str> = rest.iter().map(|arg| arg.as_str()).collect();
+    if let Err(err) = machine.load(&image, &rest).and_then(|_| machine.run()) {
+        eprintln!("z80-cpm: {}", err);
+        process::exit(1);
+    }
+}
@@ -1,12 +1,68 @@
+/// One machine cycle, reported to the bus as it begins
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Cycle {
+    /// Op code fetch
+    M1 { addr: u16 },
+    MemRead { addr: u16 },
+    MemWrite { addr: u16 },
+    IoRead { port: u16 },
+    IoWrite { port: u16 },
+    /// Interrupt acknowledge, pc is on the address bus
+    IntAck { addr: u16 },
+    /// `n` T-states of internal operation with `addr` left on the address bus
+    Internal { addr: u16, n: u8 },
+}
+
 pub trait Bus {
     fn memory_read(&self, address: usize) -> u8;
     fn memory_read_word(&self, address: usize) -> u16;
     fn memory_write(&mut self, address: usize, value: u8);
     fn memory_write_word(&mut self, address: usize, value: u16);
 
+    /// Reads an 8-bit port, for buses that only decode A0-A7
+    #[allow(unused_variables)]
+    fn port_read(&mut self, port: u8) -> u8 {
+        0xff
+    }
+
+    /// Writes an 8-bit port, for buses that only decode A0-A7
+    #[allow(unused_variables)]
+    fn port_write(&mut self, port: u8, value: u8) {}
+
+    /// Reads a port with the full 16-bit address the cpu puts on the bus.
+    /// Defaults to `port_read` with the lower byte.
+    fn io_read(&mut self, port: u16) -> u8 {
+        self.port_read(port as u8)
+    }
+
+    /// Writes a port with the full 16-bit address the cpu puts on the bus.
+    /// Defaults to `port_write` with the lower byte.
+    fn io_write(&mut self, port: u16, value: u8) {
+        self.port_write(port as u8, value)
+    }
+
+    /// Byte the interrupting device puts on the data bus when the cpu
+    /// acknowledges INT. In im 0 it is called for every byte of the
+    /// instruction, im 2 uses it as the low byte of the vector address.
+    fn interrupt_acknowledge(&mut self) -> u8 {
+        0xff
+    }
+
+    /// Called as each M-cycle begins, before its `tick`. Returns the wait
+    /// states to add to it, for contended memory and slow devices.
+    #[allow(unused_variables)]
+    fn cycle(&mut self, cycle: Cycle) -> u8 {
+        0
+    }
 
-    fn port_read(&mut self, port: u8) -> u8;
-    fn port_write(&mut self, port: u8, value: u8);
+    /// Level of /WAIT. The cpu samples it at T2 of every memory, I/O and
+    /// interrupt acknowledge M-cycle, after the `tick` for the cycle, and
+    /// adds a wait state with a `tick` of its own for as long as it stays
+    /// asserted.
+    #[allow(unused_variables)]
+    fn wait(&mut self, cycle: Cycle) -> bool {
+        false
+    }
 
     fn tick(&mut self, machine_cycles: u8, t_states: u8);
-}
\ No newline at end of file
+}
@@ -0,0 +1,356 @@
+//! BDOS file functions over the files in a host directory.
+//!
+//! Only names that fit 8.3 are visible. Lookups ignore case and new files
+//! get lower case names.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use super::{CpmMachine, CTRL_Z};
+
+const RECORD: usize = 128;
+/// Records in one fcb extent
+const EXTENT_RECORDS: u32 = 128;
+const NOT_FOUND: u8 = 0xff;
+const END_OF_FILE: u8 = 1;
+const DISK_FULL: u8 = 2;
+const INVALID_FCB: u8 = 9;
+
+/// The 11 name bytes of an fcb for a command line argument like `b:*.com`
+pub(super) fn fcb_name_bytes(arg: &str) -> [u8; 11] {
+    let arg = match arg.find(':') {
+        Some(i) => &arg[i + 1..],
+        None => arg,
+    };
+    let (name, ext) = arg.split_once('.').unwrap_or((arg, ""));
+    let mut bytes = [b' '; 11];
+    pad(&mut bytes[..8], name);
+    pad(&mut bytes[8..], ext);
+    bytes
+}
+
+/// Upper cases `part` into `field`; a `*` fills the rest with `?`
+fn pad(field: &mut [u8], part: &str) {
+    for (i, c) in part.bytes().take(field.len()).enumerate() {
+        if c == b'*' {
+            field[i..].fill(b'?');
+            return;
+        }
+        field[i] = c.to_ascii_uppercase();
+    }
+}
+
+/// Fcb name bytes for a host file name, if it fits 8.3
+fn host_name_bytes(file_name: &str) -> Option<[u8; 11]> {
+    let (name, ext) = file_name.rsplit_once('.').unwrap_or((file_name, ""));
+    let valid = |part: &str| part.bytes().all(|c| c.is_ascii_graphic() && c != b'.' && c != b'*' && c != b'?');
+    if name.is_empty() || name.len() > 8 || ext.len() > 3 || !valid(name) || !valid(ext) {
+        return None;
+    }
+    let mut bytes = [b' '; 11];
+    pad(&mut bytes[..8], name);
+    pad(&mut bytes[8..], ext);
+    Some(bytes)
+}
+
+/// `NAME.TYP`
+fn display(name: &[u8; 11]) -> String {
+    let part = |bytes: &[u8]| String::from_utf8_lossy(bytes).trim_end().to_string();
+    let ext = part(&name[8..]);
+    if ext.is_empty() {
+        part(&name[..8])
+    } else {
+        format!("{}.{}", part(&name[..8]), ext)
+    }
+}
+
+/// `?` matches any character
+fn matches(pattern: &[u8; 11], name: &[u8; 11]) -> bool {
+    pattern.iter().zip(name.iter()).all(|(p, c)| *p == b'?' || p == c)
+}
+
+impl<W: Write> CpmMachine<W> {
+    /// Runs a file function on the fcb at `fcb` and returns the BDOS result
+    pub(super) fn file_function(&mut self, function: u8, fcb: u16) -> u8 {
+        let fcb = fcb as usize;
+        match function {
+            15 => self.open(fcb),
+            16 => self.close(fcb),
+            17 => {
+                self.search = self.directory(&self.name(fcb)).into();
+                self.search_next()
+            }
+            18 => self.search_next(),
+            19 => self.delete(fcb),
+            20 => {
+                let record = self.sequential_record(fcb);
+                let result = self.read_record(fcb, record);
+                if result == 0 {
+                    self.set_sequential_record(fcb, record + 1);
+                }
+                result
+            }
+            21 => {
+                let record = self.sequential_record(fcb);
+                let result = self.write_record(fcb, record);
+                if result == 0 {
+                    self.set_sequential_record(fcb, record + 1);
+                }
+                result
+            }
+            22 => self.make(fcb),
+            23 => self.rename(fcb),
+            // random access leaves the sequential position at the record
+            33 => {
+                let record = self.random_record(fcb);
+                self.set_sequential_record(fcb, record);
+                self.read_record(fcb, record)
+            }
+            34 | 40 => {
+                let record = self.random_record(fcb);
+                self.set_sequential_record(fcb, record);
+                self.write_record(fcb, record)
+            }
+            35 => match self.host_path(&self.name(fcb)).and_then(|path| fs::metadata(path).ok()) {
+                Some(metadata) => {
+                    self.set_random_record(fcb, records(metadata.len()));
+                    0
+                }
+                None => NOT_FOUND,
+            },
+            36 => {
+                let record = self.sequential_record(fcb);
+                self.set_random_record(fcb, record);
+                0
+            }
+            _ => NOT_FOUND,
+        }
+    }
+
+    fn open(&mut self, fcb: usize) -> u8 {
+        // a wildcard opens the first match, whose name goes into the fcb
+        let name = match self.directory(&self.name(fcb)).first() {
+            Some(name) => *name,
+            None => return NOT_FOUND,
+        };
+        for (i, b) in name.iter().enumerate() {
+            self.set_fcb_byte(fcb, 1 + i, *b);
+        }
+        let len = match self.file(fcb) {
+            Some(file) => file.metadata().map(|metadata| metadata.len()).unwrap_or(0),
+            None => return NOT_FOUND,
+        };
+        let extent = self.sequential_record(fcb) / EXTENT_RECORDS;
+        let rc = records(len).saturating_sub(extent * EXTENT_RECORDS).min(EXTENT_RECORDS);
+        self.set_fcb_byte(fcb, 15, rc as u8);
+        0
+    }
+
+    fn close(&mut self, fcb: usize) -> u8 {
+        let name = self.name(fcb);
+        if let Some(file) = self.files.remove(&display(&name)) {
+            if file.sync_all().is_err() {
+                return NOT_FOUND;
+            }
+        }
+        if self.host_path(&name).is_some() {
+            0
+        } else {
+            NOT_FOUND
+        }
+    }
+
+    fn make(&mut self, fcb: usize) -> u8 {
+        let name = self.name(fcb);
+        let path = self.host_path(&name).unwrap_or_else(|| self.dir.join(display(&name).to_lowercase()));
+        match OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path) {
+            Ok(file) => {
+                self.files.insert(display(&name), file);
+                self.set_fcb_byte(fcb, 15, 0);
+                0
+            }
+            Err(_) => NOT_FOUND,
+        }
+    }
+
+    fn delete(&mut self, fcb: usize) -> u8 {
+        let names = self.directory(&self.name(fcb));
+        for name in names.iter() {
+            self.files.remove(&display(name));
+            if let Some(path) = self.host_path(name) {
+                let _ = fs::remove_file(path);
+            }
+        }
+        if names.is_empty() {
+            NOT_FOUND
+        } else {
+            0
+        }
+    }
+
+    /// The new name is in the second half of the fcb
+    fn rename(&mut self, fcb: usize) -> u8 {
+        let from = self.name(fcb);
+        let to = self.name(fcb + 16);
+        let path = match self.host_path(&from) {
+            Some(path) if self.host_path(&to).is_none() => path,
+            _ => return NOT_FOUND,
+        };
+        self.files.remove(&display(&from));
+        match fs::rename(path, self.dir.join(display(&to).to_lowercase())) {
+            Ok(()) => 0,
+            Err(_) => NOT_FOUND,
+        }
+    }
+
+    /// Puts the next directory entry from search first in the dma buffer
+    fn search_next(&mut self) -> u8 {
+        let name = match self.search.pop_front() {
+            Some(name) => name,
+            None => return NOT_FOUND,
+        };
+        let len = self.host_path(&name).and_then(|path| fs::metadata(path).ok()).map_or(0, |metadata| metadata.len());
+        let mut entry = [0xe5; RECORD];
+        entry[..32].fill(0);
+        entry[1..12].copy_from_slice(&name);
+        entry[15] = records(len).min(EXTENT_RECORDS) as u8;
+        self.write_dma(&entry);
+        0
+    }
+
+    fn read_record(&mut self, fcb: usize, record: u32) -> u8 {
+        let mut buffer = [CTRL_Z; RECORD];
+        let read = match self.file(fcb) {
+            Some(file) => {
+                if file.seek(SeekFrom::Start(record as u64 * RECORD as u64)).is_err() {
+                    return END_OF_FILE;
+                }
+                let mut read = 0;
+                while read < RECORD {
+                    match file.read(&mut buffer[read..]) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => read += n,
+                    }
+                }
+                read
+            }
+            None => return INVALID_FCB,
+        };
+        if read == 0 {
+            return END_OF_FILE;
+        }
+        // a partial last record is padded with ^Z
+        self.write_dma(&buffer);
+        0
+    }
+
+    fn write_record(&mut self, fcb: usize, record: u32) -> u8 {
+        let dma = self.dma as usize;
+        let buffer: Vec<u8> = (dma..dma + RECORD).map(|addr| self.memory.bytes[addr & 0xffff]).collect();
+        match self.file(fcb) {
+            Some(file) => {
+                let written = file
+                    .seek(SeekFrom::Start(record as u64 * RECORD as u64))
+                    .and_then(|_| file.write_all(&buffer));
+                if written.is_ok() {
+                    0
+                } else {
+                    DISK_FULL
+                }
+            }
+            None => INVALID_FCB,
+        }
+    }
+
+    fn write_dma(&mut self, data: &[u8]) {
+        for (i, b) in data.iter().enumerate() {
+            self.memory.bytes[(self.dma as usize + i) & 0xffff] = *b;
+        }
+    }
+
+    /// The file an fcb names, opened on first use
+    fn file(&mut self, fcb: usize) -> Option<&mut File> {
+        let name = self.name(fcb);
+        let key = display(&name);
+        if !self.files.contains_key(&key) {
+            let path = self.host_path(&name)?;
+            let file = OpenOptions::new().read(true).write(true).open(&path).or_else(|_| File::open(&path)).ok()?;
+            self.files.insert(key.clone(), file);
+        }
+        self.files.get_mut(&key)
+    }
+
+    /// Name bytes with the attribute bits cleared
+    fn name(&self, fcb: usize) -> [u8; 11] {
+        let mut name = [0; 11];
+        for (i, b) in name.iter_mut().enumerate() {
+            *b = (self.fcb_byte(fcb, 1 + i) & 0x7f).to_ascii_uppercase();
+        }
+        name
+    }
+
+    /// Sorted names in the directory that match `pattern`
+    fn directory(&self, pattern: &[u8; 11]) -> Vec<[u8; 11]> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return vec![],
+        };
+        let mut names: Vec<[u8; 11]> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().map(|t| t.is_file()).unwrap_or(false))
+            .filter_map(|entry| host_name_bytes(&entry.file_name().to_string_lossy()))
+            .filter(|name| matches(pattern, name))
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    /// The host file for an exact name, whatever its case
+    fn host_path(&self, name: &[u8; 11]) -> Option<PathBuf> {
+        fs::read_dir(&self.dir)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .find(|entry| host_name_bytes(&entry.file_name().to_string_lossy()).as_ref() == Some(name))
+            .map(|entry| entry.path())
+    }
+
+    /// Current record, counting across extents
+    fn sequential_record(&self, fcb: usize) -> u32 {
+        let ex = (self.fcb_byte(fcb, 12) & 0x1f) as u32;
+        let s2 = (self.fcb_byte(fcb, 14) & 0x3f) as u32;
+        let cr = self.fcb_byte(fcb, 32) as u32;
+        (s2 * 32 + ex) * EXTENT_RECORDS + cr
+    }
+
+    fn set_sequential_record(&mut self, fcb: usize, record: u32) {
+        self.set_fcb_byte(fcb, 32, (record % EXTENT_RECORDS) as u8);
+        self.set_fcb_byte(fcb, 12, (record / EXTENT_RECORDS % 32) as u8);
+        self.set_fcb_byte(fcb, 14, (record / EXTENT_RECORDS / 32) as u8);
+    }
+
+    fn random_record(&self, fcb: usize) -> u32 {
+        u32::from_le_bytes([self.fcb_byte(fcb, 33), self.fcb_byte(fcb, 34), self.fcb_byte(fcb, 35), 0])
+    }
+
+    fn set_random_record(&mut self, fcb: usize, record: u32) {
+        for (i, b) in record.to_le_bytes()[..3].iter().enumerate() {
+            self.set_fcb_byte(fcb, 33 + i, *b);
+        }
+    }
+
+    /// An fcb can sit at the top of memory, so its bytes wrap like the dma buffer
+    fn fcb_byte(&self, fcb: usize, offset: usize) -> u8 {
+        self.memory.bytes[(fcb + offset) & 0xffff]
+    }
+
+    fn set_fcb_byte(&mut self, fcb: usize, offset: usize, value: u8) {
+        self.memory.bytes[(fcb + offset) & 0xffff] = value;
+    }
+}
+
+/// 128 byte records needed for `len` bytes
+fn records(len: u64) -> u32 {
+    len.div_ceil(RECORD as u64) as u32
+}
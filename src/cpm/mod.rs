@@ -0,0 +1,356 @@
+//! A CP/M 2.2 machine for running `.COM` programs.
+//!
+//! The BDOS and BIOS are not Z80 code. Their entry points hold a `ret`
+//! and calls are handled on the host when pc reaches them. Drive A is a
+//! host directory and the console is any reader and writer, stdin and
+//! stdout for the `z80-cpm` binary.
+
+mod files;
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use crate::bus::Bus;
+use crate::cpu::Z80;
+use crate::loader::Program;
+
+/// Where programs are loaded and start
+pub const TPA: u16 = 0x0100;
+/// BDOS entry, the word at 0x0006 points here and also marks the top of the TPA
+pub const BDOS: u16 = 0xfe06;
+/// BIOS jump table
+pub const BIOS: u16 = 0xff00;
+const BIOS_ENTRIES: u16 = 17;
+const DEFAULT_DMA: u16 = 0x0080;
+const FCB1: u16 = 0x005c;
+const FCB2: u16 = 0x006c;
+const RET: u8 = 0xc9;
+const JP: u8 = 0xc3;
+/// End of file in text files, and what console input reads past the end
+const CTRL_Z: u8 = 0x1a;
+
+#[derive(Debug)]
+pub enum CpmError {
+    Io(io::Error),
+    /// The program does not fit below the BDOS
+    TooLarge(usize),
+    /// A BDOS function this machine does not provide
+    UnsupportedFunction(u8),
+    /// Function 9 was given a string with no `$` anywhere in memory
+    UnterminatedString(u16),
+}
+
+impl fmt::Display for CpmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpmError::Io(err) => write!(f, "{}", err),
+            CpmError::TooLarge(len) => write!(f, "a {} byte program does not fit in the TPA", len),
+            CpmError::UnsupportedFunction(c) => write!(f, "unsupported BDOS function {}", c),
+            CpmError::UnterminatedString(addr) => write!(f, "no $ after the string at {:04x}", addr),
+        }
+    }
+}
+
+impl std::error::Error for CpmError {}
+
+impl From<io::Error> for CpmError {
+    fn from(err: io::Error) -> CpmError {
+        CpmError::Io(err)
+    }
+}
+
+/// 64K of ram; ports read 0xff and writes go nowhere
+pub struct Memory {
+    pub bytes: Vec<u8>,
+}
+
+impl Bus for Memory {
+    fn memory_read(&self, address: usize) -> u8 {
+        self.bytes[address & 0xffff]
+    }
+
+    fn memory_read_word(&self, address: usize) -> u16 {
+        self.memory_read(address) as u16 | ((self.memory_read(address + 1) as u16) << 8)
+    }
+
+    fn memory_write(&mut self, address: usize, value: u8) {
+        self.bytes[address & 0xffff] = value;
+    }
+
+    fn memory_write_word(&mut self, address: usize, value: u16) {
+        self.memory_write(address, value as u8);
+        self.memory_write(address + 1, (value >> 8) as u8);
+    }
+
+    fn tick(&mut self, _: u8, _: u8) {}
+}
+
+/// Console input is read on its own thread so status calls never block
+struct Console<W> {
+    input: Receiver<u8>,
+    peeked: Option<u8>,
+    output: W,
+}
+
+impl<W: Write> Console<W> {
+    fn new(input: impl Read + Send + 'static, output: W) -> Console<W> {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for b in BufReader::new(input).bytes() {
+                match b {
+                    Ok(b) if sender.send(b).is_ok() => {}
+                    _ => break,
+                }
+            }
+        });
+        Console { input: receiver, peeked: None, output }
+    }
+
+    fn ready(&mut self) -> bool {
+        if self.peeked.is_none() {
+            match self.input.try_recv() {
+                Ok(b) => self.peeked = Some(b),
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => {}
+            }
+        }
+        self.peeked.is_some()
+    }
+
+    /// Waits for a key, None once the input has ended. Newlines become
+    /// carriage returns, which is what CP/M programs expect from enter.
+    fn read(&mut self) -> Option<u8> {
+        let b = self.peeked.take().or_else(|| self.input.recv().ok())?;
+        Some(if b == b'\n' { b'\r' } else { b })
+    }
+
+    fn write(&mut self, b: u8) -> io::Result<()> {
+        self.output.write_all(&[b])?;
+        self.output.flush()
+    }
+}
+
+pub struct CpmMachine<W: Write = io::Stdout> {
+    pub cpu: Z80,
+    pub memory: Memory,
+    /// Host directory used as drive A
+    dir: PathBuf,
+    console: Console<W>,
+    dma: u16,
+    /// Files opened or made by the program, by `NAME.TYP`
+    files: HashMap<String, File>,
+    /// Directory entries left for search next
+    search: VecDeque<[u8; 11]>,
+}
+
+impl CpmMachine<io::Stdout> {
+    /// A machine with `dir` as drive A and stdin and stdout as the console
+    pub fn new(dir: impl Into<PathBuf>) -> CpmMachine<io::Stdout> {
+        CpmMachine::with_console(dir, io::stdin(), io::stdout())
+    }
+}
+
+impl<W: Write> CpmMachine<W> {
+    pub fn with_console(dir: impl Into<PathBuf>, input: impl Read + Send + 'static, output: W) -> CpmMachine<W> {
+        let mut memory = Memory { bytes: vec![0; 0x10000] };
+        // warm boot and BDOS vectors
+        memory.bytes[0..3].copy_from_slice(&[JP, (BIOS + 3) as u8, ((BIOS + 3) >> 8) as u8]);
+        memory.bytes[5..8].copy_from_slice(&[JP, BDOS as u8, (BDOS >> 8) as u8]);
+        memory.bytes[BDOS as usize] = RET;
+        for entry in 0..BIOS_ENTRIES {
+            memory.bytes[(BIOS + entry * 3) as usize] = RET;
+        }
+
+        CpmMachine {
+            cpu: Z80::new(),
+            memory,
+            dir: dir.into(),
+            console: Console::new(input, output),
+            dma: DEFAULT_DMA,
+            files: HashMap::new(),
+            search: VecDeque::new(),
+        }
+    }
+
+    pub fn output(&self) -> &W {
+        &self.console.output
+    }
+
+    /// Loads a `.COM` image at 0x0100 and sets up the command tail and the
+    /// default fcbs from `args`, the way the CCP does
+    pub fn load(&mut self, program: &[u8], args: &[&str]) -> Result<(), CpmError> {
+        if program.len() > (BDOS - TPA) as usize {
+            return Err(CpmError::TooLarge(program.len()));
+        }
+        Program::binary(program, TPA)
+            .load(&mut self.cpu, &mut self.memory)
+            .expect("the size was checked against the bdos");
+        // a return from the program goes to the warm boot at 0x0000
+        self.cpu.sp = BDOS - 2;
+        self.memory.memory_write_word(self.cpu.sp as usize, 0);
+
+        for (i, fcb) in [FCB1, FCB2].iter().enumerate() {
+            let name = files::fcb_name_bytes(args.get(i).copied().unwrap_or(""));
+            let fcb = *fcb as usize;
+            self.memory.bytes[fcb] = 0;
+            self.memory.bytes[fcb + 1..fcb + 12].copy_from_slice(&name);
+            self.memory.bytes[fcb + 12..fcb + 16].fill(0);
+        }
+        let tail: Vec<u8> = args.iter().flat_map(|arg| format!(" {}", arg.to_uppercase()).into_bytes()).take(127).collect();
+        let start = DEFAULT_DMA as usize;
+        self.memory.bytes[start] = tail.len() as u8;
+        self.memory.bytes[start + 1..start + 1 + tail.len()].copy_from_slice(&tail);
+        self.dma = DEFAULT_DMA;
+        Ok(())
+    }
+
+    /// Runs until the program warm boots, calls BDOS function 0 or waits
+    /// for console input that has run out
+    pub fn run(&mut self) -> Result<(), CpmError> {
+        loop {
+            let pc = self.cpu.pc;
+            let running = if pc == BDOS {
+                self.bdos()?
+            } else if (BIOS..BIOS + BIOS_ENTRIES * 3).contains(&pc) && (pc - BIOS).is_multiple_of(3) {
+                self.bios((pc - BIOS) / 3)?
+            } else {
+                true
+            };
+            if !running {
+                for (_, file) in self.files.drain() {
+                    file.sync_all()?;
+                }
+                return Ok(());
+            }
+            // the ret at the entry point goes back to the caller
+            self.cpu.step(&mut self.memory);
+        }
+    }
+
+    /// Returns false when the program is done
+    fn bdos(&mut self) -> Result<bool, CpmError> {
+        let function = self.cpu.registers.c;
+        let e = self.cpu.registers.e;
+        let de = (self.cpu.registers.d as u16) << 8 | e as u16;
+        let result = match function {
+            0 => return Ok(false),
+            1 | 3 => match self.console.read() {
+                Some(b) => b as u16,
+                None => return Ok(false),
+            },
+            2 | 4 | 5 => {
+                if function == 2 {
+                    self.console.write(e)?;
+                }
+                0
+            }
+            6 => match e {
+                0xff => {
+                    if self.console.ready() {
+                        self.console.read().unwrap_or(0) as u16
+                    } else {
+                        0
+                    }
+                }
+                0xfe => self.console_status(),
+                _ => {
+                    self.console.write(e)?;
+                    0
+                }
+            },
+            7 | 8 => 0,
+            9 => {
+                let text: Vec<u8> = (0..0x10000).map(|i| self.memory.bytes[(de as usize + i) & 0xffff]).take_while(|c| *c != b'$').collect();
+                if text.len() == 0x10000 {
+                    return Err(CpmError::UnterminatedString(de));
+                }
+                for c in text {
+                    self.console.write(c)?;
+                }
+                0
+            }
+            10 => {
+                if !self.read_line(de) {
+                    return Ok(false);
+                }
+                0
+            }
+            11 => self.console_status(),
+            12 => 0x0022,
+            13 => {
+                self.dma = DEFAULT_DMA;
+                0
+            }
+            14 | 25 | 29 | 32 => 0,
+            24 => 0x0001,
+            26 => {
+                self.dma = de;
+                0
+            }
+            15..=23 | 33..=36 | 40 => self.file_function(function, de) as u16,
+            _ => return Err(CpmError::UnsupportedFunction(function)),
+        };
+        // CP/M 2.2 returns hl, with a copy of l in a and h in b
+        self.cpu.registers.h = (result >> 8) as u8;
+        self.cpu.registers.l = result as u8;
+        self.cpu.registers.b = (result >> 8) as u8;
+        self.cpu.registers.a = result as u8;
+        Ok(true)
+    }
+
+    /// The console entries of the BIOS jump table; boot and warm boot end the program
+    fn bios(&mut self, entry: u16) -> Result<bool, CpmError> {
+        match entry {
+            0 | 1 => return Ok(false),
+            2 => self.cpu.registers.a = self.console_status() as u8,
+            3 => match self.console.read() {
+                Some(b) => self.cpu.registers.a = b,
+                None => return Ok(false),
+            },
+            4 => self.console.write(self.cpu.registers.c)?,
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn console_status(&mut self) -> u16 {
+        if self.console.ready() {
+            0xff
+        } else {
+            0
+        }
+    }
+
+    /// Function 10. The buffer holds its size, then the length read, then
+    /// the line. Returns false if input ended before the line did.
+    fn read_line(&mut self, buffer: u16) -> bool {
+        let buffer = buffer as usize;
+        let max = self.memory.bytes[buffer] as usize;
+        let mut line = Vec::new();
+        loop {
+            let b = match self.console.read() {
+                Some(b) => b,
+                None if line.is_empty() => return false,
+                None => break,
+            };
+            match b {
+                b'\r' => break,
+                0x08 | 0x7f => {
+                    line.pop();
+                }
+                _ if line.len() < max => line.push(b),
+                _ => {}
+            }
+        }
+        // like the dma buffer, a buffer at the top of memory wraps to 0
+        self.memory.bytes[(buffer + 1) & 0xffff] = line.len() as u8;
+        for (i, b) in line.iter().enumerate() {
+            self.memory.bytes[(buffer + 2 + i) & 0xffff] = *b;
+        }
+        true
+    }
+}
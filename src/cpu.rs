@@ -9,10 +9,12 @@ use crate::operations as ops;
 
 use crate::operations::Ops;
 
-use crate::bus::Bus;
+use crate::bus::{Bus, Cycle};
 
 use crate::times;
 
+use crate::state::Z80State;
+
 #[derive(Debug)]
 pub struct ImmByte;
 #[derive(Debug)]
@@ -45,6 +47,15 @@ impl Read16 for u16 {
 
 pub trait Read8: IntoArg8 {
     fn read8(self, cpu: &mut Z80, bus: &mut impl Bus) -> u8;
+
+    /// Read for instructions that change the value in place, memory
+    /// operands keep the address on the bus for an extra T-state
+    fn read8_rmw(self, cpu: &mut Z80, bus: &mut impl Bus) -> u8
+    where
+        Self: Sized,
+    {
+        self.read8(cpu, bus)
+    }
 }
 
 pub trait Write8: IntoArg8 {
@@ -113,40 +124,139 @@ pub struct Mem<T: Read16>(pub T);
 #[derive(Copy, Clone)]
 pub struct RelOffset<T: Read16>(pub T);
 
+/// ix+d or iy+d where d has already been fetched, as on the ddcb/fdcb page
+#[derive(Copy, Clone)]
+pub struct Indexed(pub Reg16, pub i8);
+
+/// Memory operand that also copies what is written into a register,
+/// like the undocumented `rlc (ix+d),b`
+#[derive(Copy, Clone)]
+pub struct CopyTo<T: Read8 + Write8>(pub T, pub Reg8);
+
+/// The silicon variant being emulated, where undocumented behaviour differs
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Model {
+    #[default]
+    ZilogNmos,
+    /// Z84C00; scf and ccf set X and Y like the NMOS parts, but
+    /// `out (c),0` puts $ff on the data bus
+    ZilogCmos,
+    /// scf and ccf take X and Y from a alone
+    NecNmos,
+}
+
 #[derive(Default)]
 pub struct Z80 {
     pub registers: Registers,
+    pub model: Model,
 
     pub interrupt_mode: u8,
     iff1: u8,
     iff2: u8,
     ei_instr: bool,
-
+    /// Set while an im 0 acknowledge takes its instruction from the data bus
+    int_ack: bool,
+
+    /// Input pins, sampled at the end of the last M-cycle of each instruction.
+    /// /WAIT is `Bus::wait`, sampled within each M-cycle.
+    int_line: bool,
+    nmi_pending: bool,
+    busreq_line: bool,
+    reset_line: bool,
+    /// /BUSACK, set once the cpu has let go of the bus
+    busack: bool,
+
+    /// Set while running the nmi handler, until retn
     pub nmi: bool,
 
-    pub debug: bool,
-
     pub sp: u16, // stack pointer
     pub pc: u16, // program counter
     halted: bool,
 
-    pub t_cycles: u32,
-    pub m_cycles: u32,
+    /// Running totals of everything `step` has executed
+    pub t_cycles: u64,
+    pub m_cycles: u64,
+}
+
+/// Passes everything on to the real bus while counting the cycles of one step
+struct CycleCounter<'a, B: Bus> {
+    bus: &'a mut B,
+    m_cycles: u32,
+    t_states: u32,
+}
+
+impl<B: Bus> Bus for CycleCounter<'_, B> {
+    fn memory_read(&self, address: usize) -> u8 {
+        self.bus.memory_read(address)
+    }
+
+    fn memory_read_word(&self, address: usize) -> u16 {
+        self.bus.memory_read_word(address)
+    }
+
+    fn memory_write(&mut self, address: usize, value: u8) {
+        self.bus.memory_write(address, value)
+    }
+
+    fn memory_write_word(&mut self, address: usize, value: u16) {
+        self.bus.memory_write_word(address, value)
+    }
+
+    fn port_read(&mut self, port: u8) -> u8 {
+        self.bus.port_read(port)
+    }
+
+    fn port_write(&mut self, port: u8, value: u8) {
+        self.bus.port_write(port, value)
+    }
+
+    fn io_read(&mut self, port: u16) -> u8 {
+        self.bus.io_read(port)
+    }
+
+    fn io_write(&mut self, port: u16, value: u8) {
+        self.bus.io_write(port, value)
+    }
+
+    fn interrupt_acknowledge(&mut self) -> u8 {
+        self.bus.interrupt_acknowledge()
+    }
+
+    fn cycle(&mut self, cycle: Cycle) -> u8 {
+        self.bus.cycle(cycle)
+    }
+
+    fn wait(&mut self, cycle: Cycle) -> bool {
+        self.bus.wait(cycle)
+    }
+
+    fn tick(&mut self, machine_cycles: u8, t_states: u8) {
+        self.m_cycles += machine_cycles as u32;
+        self.t_states += t_states as u32;
+        self.bus.tick(machine_cycles, t_states)
+    }
 }
 
 impl Z80 {
     pub fn new() -> Z80 {
         Z80 {
             registers: Registers::default(),
+            model: Model::ZilogNmos,
 
             interrupt_mode: 0,
             iff1: 0,
             iff2: 0,
             ei_instr: false,
+            int_ack: false,
+
+            int_line: false,
+            nmi_pending: false,
+            busreq_line: false,
+            reset_line: false,
+            busack: false,
 
             nmi: false,
 
-            debug: true,
             sp: 0xdff0,
             pc: 0,
 
@@ -156,175 +266,446 @@ impl Z80 {
         }
     }
 
-    pub fn step(&mut self, bus: &mut impl Bus, int_flags: u8) -> u32 {
-        self.handle_interrupt(bus, int_flags);
-        self.execute_next_instruction(bus);
+    /// Runs one instruction, or idles a single T-state while /BUSREQ or
+    /// /RESET holds the cpu. Pending interrupts are taken first since the
+    /// lines were sampled at the end of the previous instruction.
+    /// Returns the T-states used, including any interrupt acknowledge and
+    /// wait states.
+    pub fn step(&mut self, bus: &mut impl Bus) -> u32 {
+        let mut counter = CycleCounter { bus, m_cycles: 0, t_states: 0 };
+        if self.reset_line || self.busreq_line {
+            self.busack = self.busreq_line;
+            counter.tick(0, 1);
+        } else {
+            self.handle_interrupt(&mut counter);
+            self.execute_next_instruction(&mut counter);
+        }
 
-        0
+        self.m_cycles += counter.m_cycles as u64;
+        self.t_cycles += counter.t_states as u64;
+        counter.t_states
     }
 
-    pub fn nmi(&mut self, bus: &mut impl Bus) {
-        self.nmi = true;
-        self.iff2 = self.iff1;
-        self.iff1 = 0;
-        let pc = self.pc;
-        self.push_word(bus, pc);
-        self.pc = 0x0066;
-        self.registers.r = (self.registers.r & 128) + 1;
+    /// Takes a pending nmi or INT without running the instruction after it,
+    /// so a debugger can stop at the start of the handler. Returns the
+    /// T-states used, 0 if nothing was taken.
+    pub(crate) fn accept_interrupt(&mut self, bus: &mut impl Bus) -> u32 {
+        if self.reset_line || self.busreq_line {
+            return 0;
+        }
+        let mut counter = CycleCounter { bus, m_cycles: 0, t_states: 0 };
+        self.handle_interrupt(&mut counter);
+
+        self.m_cycles += counter.m_cycles as u64;
+        self.t_cycles += counter.t_states as u64;
+        counter.t_states
     }
 
-    pub fn reset_interrupt(&mut self, bus: &mut impl Bus) {
-        if self.iff1 == 0 || self.ei_instr {
-            return;
+    /// Steps until at least `t_states` have been used and returns how far the
+    /// last instruction ran past the budget, to be taken off the next one
+    pub fn run_for(&mut self, bus: &mut impl Bus, t_states: u32) -> u32 {
+        let mut spent = 0;
+        while spent < t_states {
+            spent += self.step(bus);
+        }
+        spent - t_states
+    }
+
+    /// Steps until `done` returns true, checking it before every instruction.
+    /// Returns the T-states used.
+    pub fn run_until(&mut self, bus: &mut impl Bus, mut done: impl FnMut(&Z80) -> bool) -> u32 {
+        let mut spent = 0;
+        while !done(self) {
+            spent += self.step(bus);
+        }
+        spent
+    }
+
+    /// Level of /INT, the device keeps it asserted until it is acknowledged
+    pub fn set_int_line(&mut self, asserted: bool) {
+        self.int_line = asserted;
+    }
+
+    /// Falling edge on /NMI, latched until the next instruction boundary
+    pub fn trigger_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    /// Level of /BUSREQ. The cpu lets go of the bus at the next step, so a
+    /// request is granted at the end of the current instruction rather than
+    /// at the end of its current M-cycle.
+    pub fn set_busreq_line(&mut self, asserted: bool) {
+        self.busreq_line = asserted;
+        if !asserted {
+            self.busack = false;
+        }
+    }
+
+    /// /BUSACK, true while another device owns the bus
+    pub fn busack(&self) -> bool {
+        self.busack
+    }
+
+    /// Level of /RESET. The cpu is reset when it is asserted and stays
+    /// idle until it is released, then starts again from address 0.
+    pub fn set_reset_line(&mut self, asserted: bool) {
+        if asserted {
+            self.reset();
         }
+        self.reset_line = asserted;
+    }
+
+    /// What /RESET does; other registers keep their values
+    fn reset(&mut self) {
+        self.pc = 0;
+        self.registers.i = 0;
+        self.registers.r = 0;
+        self.registers.wz = 0;
+        self.interrupt_mode = 0;
         self.iff1 = 0;
+        self.iff2 = 0;
+        self.ei_instr = false;
         self.halted = false;
-        let pc = self.pc;
-        self.push_word(bus, pc);
-        self.pc = 0x66;
+        self.nmi_pending = false;
+        self.nmi = false;
+    }
+
+    /// Copies out the full cpu state, to be put back with `restore`
+    pub fn snapshot(&self) -> Z80State {
+        let r = &self.registers;
+        Z80State {
+            af: make_u16(r.f, r.a),
+            bc: make_u16(r.c, r.b),
+            de: make_u16(r.e, r.d),
+            hl: make_u16(r.l, r.h),
+            alt_af: make_u16(r._f, r._a),
+            alt_bc: make_u16(r._c, r._b),
+            alt_de: make_u16(r._e, r._d),
+            alt_hl: make_u16(r._l, r._h),
+            ix: r.ix,
+            iy: r.iy,
+            sp: self.sp,
+            pc: self.pc,
+            i: r.i,
+            r: r.r,
+            wz: r.wz,
+            q: r.q,
+            iff1: self.iff1 != 0,
+            iff2: self.iff2 != 0,
+            interrupt_mode: self.interrupt_mode,
+            halted: self.halted,
+            ei_delay: self.ei_instr,
+            nmi_pending: self.nmi_pending,
+            nmi: self.nmi,
+        }
     }
 
-    pub fn handle_interrupt(&mut self, bus: &mut impl Bus, int_flags: u8) {
-        if self.iff1 == 0 || self.ei_instr {
+    /// Puts back a state from `snapshot`. The input lines, the model and
+    /// the cycle totals are left alone.
+    pub fn restore(&mut self, state: &Z80State) {
+        let r = &mut self.registers;
+        r.a = (state.af >> 8) as u8;
+        r.f = state.af as u8;
+        r.b = (state.bc >> 8) as u8;
+        r.c = state.bc as u8;
+        r.d = (state.de >> 8) as u8;
+        r.e = state.de as u8;
+        r.h = (state.hl >> 8) as u8;
+        r.l = state.hl as u8;
+        r._a = (state.alt_af >> 8) as u8;
+        r._f = state.alt_af as u8;
+        r._b = (state.alt_bc >> 8) as u8;
+        r._c = state.alt_bc as u8;
+        r._d = (state.alt_de >> 8) as u8;
+        r._e = state.alt_de as u8;
+        r._h = (state.alt_hl >> 8) as u8;
+        r._l = state.alt_hl as u8;
+        r.ix = state.ix;
+        r.iy = state.iy;
+        r.i = state.i;
+        r.r = state.r;
+        r.wz = state.wz;
+        r.q = state.q;
+        self.sp = state.sp;
+        self.pc = state.pc;
+        self.iff1 = state.iff1 as u8;
+        self.iff2 = state.iff2 as u8;
+        self.interrupt_mode = state.interrupt_mode;
+        self.halted = state.halted;
+        self.ei_instr = state.ei_delay;
+        self.nmi_pending = state.nmi_pending;
+        self.nmi = state.nmi;
+    }
+
+    /// Takes a latched nmi, or INT if it is asserted and interrupts are enabled
+    pub fn handle_interrupt(&mut self, bus: &mut impl Bus) {
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            self.accept_nmi(bus);
             return;
         }
-
-        if int_flags != 0 && self.iff1 > 0 && self.interrupt_mode == 1 {
-            self.halted = false;
-            let pc = self.pc;
-            self.push_word(bus, pc);
-            if self.iff1 == 2 {
-                let i = Reg8::I.read8(self, bus);
-                let addr = (i as u16) << 8;
-                let addr_lo = (i + 1) as u16;
-                Reg16::PC.write16(self, bus, addr | addr_lo);
-            } else {
-                Reg16::PC.write16(self, bus, 0x38);
-            }
-            self.iff1 = 0;
-            self.iff2 = 0;
+        if !self.int_line || self.iff1 == 0 || self.ei_instr {
+            return;
         }
+        self.interrupt(bus);
+    }
+
+    /// Nmi acknowledge, a 5 T-state fetch that is ignored followed by rst 66h
+    fn accept_nmi(&mut self, bus: &mut impl Bus) {
+        self.nmi = true;
+        self.halted = false;
+        self.iff2 = self.iff1;
+        self.iff1 = 0;
+        self.inc_r();
+        self.begin_cycle(bus, Cycle::M1 { addr: self.pc }, 1, times::OCF);
+        self.internal(bus, self.ir(), 1);
+        let pc = self.pc;
+        self.push_word(bus, pc);
+        self.pc = 0x0066;
+        self.registers.wz = self.pc;
+        self.registers.q = 0;
     }
 
     pub fn execute_next_instruction(&mut self, bus: &mut impl Bus) -> u8 {
         self.ei_instr = false;
+        self.registers.flags_written = false;
 
         let instr = if !self.halted {
             self.read_instruction(bus)
         } else {
-            bus.tick(1, times::OCF);
+            // halt keeps fetching nops until an interrupt
+            self.begin_cycle(bus, Cycle::M1 { addr: self.pc }, 1, times::OCF);
+            self.inc_r();
             0
         };
 
-        ops::decode((self, bus), instr);
+        ops::decode((&mut *self, bus), instr);
+        self.registers.latch_q();
         instr
     }
 
-    fn test_bit(&mut self, bit: u8, val: u8) {
+    /// X/Y for scf and ccf; Zilog parts mix in the flags if the previous instruction left them alone
+    fn scf_ccf_xy(&self) -> u8 {
+        match self.model {
+            Model::ZilogNmos | Model::ZilogCmos => (self.registers.q ^ self.registers.f) | self.registers.a,
+            Model::NecNmos => self.registers.a,
+        }
+    }
+
+    fn test_bit(&mut self, bit: u8, val: u8, xy: u8) {
         let res = val & (1 << bit);
         self.registers.set_flag(Zero, res == 0);
         self.registers.set_flag(Parity, res == 0);
         self.registers.set_flag(Sign, bit == 7 && res != 0);
         self.registers.set_flag(HalfCarry, true);
         self.registers.set_flag(Subtract, false);
-        // self.registers.set_xy(res);
+        self.registers.set_xy(xy);
     }
 
-    fn otir(&mut self, bus: &mut impl Bus) -> u8 {
-        self.outi(bus);
-        if Reg8::B.read8(self, bus) != 0 {
-            self.pc -= 2;
-            21
+    /// ini/ind; the port is addressed with b before it is decremented
+    fn block_in(&mut self, bus: &mut impl Bus, increment: bool) {
+        self.internal(bus, self.ir(), 1); // ocf #2 takes 5 tstates
+        let bc = Reg16::BC.read16(self, bus);
+        let val = self.io_in(bus, bc);
+        let hl = Reg16::HL.read16(self, bus);
+        self.mem_write(bus, hl, val, times::MW);
+
+        let (bc, hl, c) = if increment {
+            (bc.wrapping_add(1), hl.wrapping_add(1), self.registers.c.wrapping_add(1))
         } else {
-            16
-        }
+            (bc.wrapping_sub(1), hl.wrapping_sub(1), self.registers.c.wrapping_sub(1))
+        };
+        self.registers.wz = bc;
+        Reg16::HL.write16(self, bus, hl);
+        self.registers.b = self.registers.b.wrapping_sub(1);
+        self.block_io_flags(val, val as u16 + c as u16);
     }
 
-    fn outi(&mut self, bus: &mut impl Bus) {
-        bus.tick(0, 1); // ocf 2 takes 5 tstates
+    /// outi/outd; the port is addressed with b after it is decremented
+    fn block_out(&mut self, bus: &mut impl Bus, increment: bool) {
+        self.internal(bus, self.ir(), 1); // ocf #2 takes 5 tstates
         let hl = Reg16::HL.read16(self, bus);
-        let io_val = bus.memory_read(hl as usize);
-        bus.tick(1, times::MR);
-
-        self.inc16(bus, Reg16::HL);
-        let b = Reg8::B.read8(self, bus);
-        Reg8::B.write8(self, bus, b.wrapping_sub(1));
+        let val = self.mem_read(bus, hl, times::MR);
+        self.registers.b = self.registers.b.wrapping_sub(1);
         let bc = Reg16::BC.read16(self, bus);
-        self.outp(bus, bc, io_val);
+        self.io_out(bus, bc, val);
 
-        let l = Reg8::L.read8(self, bus);
-
-        self.registers.set_flag(Zero, b == 1);
-        self.registers.set_flag(Subtract, true);
-        let carry = l as u16 + b as u16 > 255;
-        self.registers.set_flag(Carry, carry);
-        self.registers.set_flag(HalfCarry, carry);
-        // bus.tick(0, 1);
-        // bus.tick(1, times::MR);
+        let (bc, hl) = if increment {
+            (bc.wrapping_add(1), hl.wrapping_add(1))
+        } else {
+            (bc.wrapping_sub(1), hl.wrapping_sub(1))
+        };
+        self.registers.wz = bc;
+        Reg16::HL.write16(self, bus, hl);
+        let l = self.registers.l;
+        self.block_io_flags(val, val as u16 + l as u16);
+    }
+
+    /// Flags shared by ini/ind/outi/outd, `k` is the transferred byte plus c+-1 or l
+    fn block_io_flags(&mut self, val: u8, k: u16) {
+        let b = self.registers.b;
+        self.registers.set_flag(Sign, b & 0x80 == 0x80);
+        self.registers.set_flag(Zero, b == 0);
+        self.registers.set_flag(Subtract, val & 0x80 == 0x80);
+        self.registers.set_flag(HalfCarry, k > 0xff);
+        self.registers.set_flag(Carry, k > 0xff);
+        let p = (k as u8 & 0x07) ^ b;
+        self.registers.set_flag(Parity, p.count_ones().is_multiple_of(2));
+        self.registers.set_xy(b);
+    }
+
+    /// Repeat step of inir/indr/otir/otdr, `addr` is what stays on the bus:
+    /// hl for the inputs and bc for the outputs. While rewinding pc the cpu
+    /// also alters x/y, h and p as described by David Banks in 2018.
+    fn block_io_repeat(&mut self, bus: &mut impl Bus, addr: u16) {
+        let b = self.registers.b;
+        if b == 0 {
+            return;
+        }
+        self.repeat_block();
+        self.internal(bus, addr, times::IO);
+        self.registers.set_xy((self.pc >> 8) as u8);
+
+        let even = |v: u8| (v & 0x07).count_ones().is_multiple_of(2);
+        let mut parity = self.registers.get_flag(Parity);
+        if self.registers.get_flag(Carry) {
+            let half = if self.registers.get_flag(Subtract) {
+                parity ^= !even(b.wrapping_sub(1));
+                b & 0x0f == 0x00
+            } else {
+                parity ^= !even(b.wrapping_add(1));
+                b & 0x0f == 0x0f
+            };
+            self.registers.set_flag(HalfCarry, half);
+        } else {
+            parity ^= !even(b);
+        }
+        self.registers.set_flag(Parity, parity);
     }
 
     fn jp_cond<C: Source<bool>>(&mut self, bus: &mut impl Bus, cond: C) {
         let cond = cond.read(self, bus);
         let addr = self.read_u16(bus);
+        self.registers.wz = addr;
         if cond {
             self.pc = addr;
         }
     }
 
     fn call_cond<C: Source<bool>>(&mut self, bus: &mut impl Bus, cond: C) {
-        bus.tick(0, 1);
         let cond = cond.read(self, bus);
         let addr = ImmWord.read16(self, bus);
+        self.registers.wz = addr;
         if cond {
+            self.internal(bus, self.pc.wrapping_sub(1), 1);
             self.call(bus, addr);
         }
     }
 
+    /// out (c),r; b is put on the upper half of the address bus
     fn write_port<P: Read8, V: Read8>(&mut self, bus: &mut impl Bus, port: P, val: V) {
-        let port = port.read8(self, bus);
+        let port = make_u16(port.read8(self, bus), self.registers.b);
         let val = val.read8(self, bus);
-        // println!("out {:x},{:x}", port, val);
-        bus.port_write(port, val);
-        bus.tick(1, times::PW);
+        self.io_out(bus, port, val);
+        self.registers.wz = port.wrapping_add(1);
     }
 
-    fn read_port<D: Write8, P: Read8>(&mut self, bus: &mut impl Bus, reg: D, port: P) {
-        let port = port.read8(self, bus);
-        let val = bus.port_read(port);
-        bus.tick(1, times::PR);
-        reg.write8(self, bus, val);
+    /// in r,(c); b is put on the upper half of the address bus
+    fn read_port<P: Read8>(&mut self, bus: &mut impl Bus, port: P) -> u8 {
+        let port = make_u16(port.read8(self, bus), self.registers.b);
+        let val = self.io_in(bus, port);
+        self.registers.wz = port.wrapping_add(1);
         self.registers.set_flag(Sign, val & 0x80 == 0x80);
         self.registers.set_flag(Zero, val == 0);
         self.registers.set_flag(HalfCarry, false);
-        self.registers.set_flag(Parity, val.count_ones() % 2 == 0);
+        self.registers.set_flag(Parity, val.count_ones().is_multiple_of(2));
         self.registers.set_flag(Subtract, false);
-        // println!("in {:x},{:x}", port, val);
+        self.registers.set_xy(val);
+        val
     }
 
     pub fn read_instruction(&mut self, bus: &mut impl Bus) -> u8 {
-        bus.tick(1, times::OCF);
-        let val = bus.memory_read(self.pc as usize);
-        self.pc += 1;
-        val
+        self.begin_cycle(bus, Cycle::M1 { addr: self.pc }, 1, times::OCF);
+        self.inc_r();
+        self.fetch(bus)
     }
 
     pub fn read_u8(&mut self, bus: &mut impl Bus) -> u8 {
-        bus.tick(1, times::OD);
-        let val = bus.memory_read(self.pc as usize);
-        self.pc += 1;
-        val
+        self.begin_cycle(bus, Cycle::MemRead { addr: self.pc }, 1, times::OD);
+        self.fetch(bus)
     }
 
     pub fn read_u16(&mut self, bus: &mut impl Bus) -> u16 {
-        let lo = bus.memory_read(self.pc as usize);
-        self.pc += 1;
-        bus.tick(1, times::ODL);
-        let hi = bus.memory_read(self.pc as usize);
-        self.pc += 1;
-        bus.tick(1, times::ODH);
+        self.begin_cycle(bus, Cycle::MemRead { addr: self.pc }, 1, times::ODL);
+        let lo = self.fetch(bus);
+        self.begin_cycle(bus, Cycle::MemRead { addr: self.pc }, 1, times::ODH);
+        let hi = self.fetch(bus);
         make_u16(lo, hi)
     }
 
+    /// Reports an M-cycle to the bus and ticks it along with any wait states.
+    /// Cycles that drive the bus then add one more for every T-state /WAIT
+    /// is held.
+    fn begin_cycle(&mut self, bus: &mut impl Bus, cycle: Cycle, machine_cycles: u8, t_states: u8) {
+        let wait = bus.cycle(cycle);
+        bus.tick(machine_cycles, t_states.saturating_add(wait));
+        if let Cycle::Internal { .. } = cycle {
+            return;
+        }
+        while bus.wait(cycle) {
+            bus.tick(0, 1);
+        }
+    }
+
+    /// Memory read M-cycle
+    pub(crate) fn mem_read(&mut self, bus: &mut impl Bus, addr: u16, t_states: u8) -> u8 {
+        self.begin_cycle(bus, Cycle::MemRead { addr }, 1, t_states);
+        bus.memory_read(addr as usize)
+    }
+
+    /// Memory write M-cycle
+    pub(crate) fn mem_write(&mut self, bus: &mut impl Bus, addr: u16, val: u8, t_states: u8) {
+        self.begin_cycle(bus, Cycle::MemWrite { addr }, 1, t_states);
+        bus.memory_write(addr as usize, val);
+    }
+
+    /// Port read M-cycle
+    fn io_in(&mut self, bus: &mut impl Bus, port: u16) -> u8 {
+        self.begin_cycle(bus, Cycle::IoRead { port }, 1, times::PR);
+        bus.io_read(port)
+    }
+
+    /// Port write M-cycle
+    fn io_out(&mut self, bus: &mut impl Bus, port: u16, val: u8) {
+        self.begin_cycle(bus, Cycle::IoWrite { port }, 1, times::PW);
+        bus.io_write(port, val);
+    }
+
+    /// `n` T-states of internal operation with `addr` on the address bus.
+    /// Three or more make up an M-cycle of their own, one or two stretch
+    /// the previous one.
+    pub(crate) fn internal(&mut self, bus: &mut impl Bus, addr: u16, n: u8) {
+        let machine_cycles = if n >= 3 { 1 } else { 0 };
+        self.begin_cycle(bus, Cycle::Internal { addr, n }, machine_cycles, n);
+    }
+
+    /// Internal cycles spent adding the displacement to ix/iy, with the last
+    /// byte fetched still on the address bus
+    pub(crate) fn displacement_delay(&mut self, bus: &mut impl Bus, n: u8) {
+        self.internal(bus, self.pc.wrapping_sub(1), n);
+    }
+
+    /// The seven tstates the 16-bit alu needs for add/adc/sbc hl,rr
+    fn add16_delay(&mut self, bus: &mut impl Bus) {
+        self.internal(bus, self.ir(), 4);
+        self.internal(bus, self.ir(), 3);
+    }
+
+    /// I and R, which the cpu puts on the address bus to refresh memory
+    pub(crate) fn ir(&self) -> u16 {
+        make_u16(self.registers.r, self.registers.i)
+    }
+
     pub fn read_address(&mut self, bus: &mut impl Bus) -> usize {
         self.read_u16(bus) as usize
     }
@@ -332,7 +713,7 @@ impl Z80 {
     fn rra(&mut self, bus: &mut impl Bus) {
         let val = Reg8::A.read8(self, bus);
         let carry = if self.registers.get_flag(Carry) { 1 } else { 0 };
-        let res = (val >> 1 | carry << 7) & 0xff;
+        let res = val >> 1 | carry << 7;
         self.registers.set_flag(Carry, val & 1 == 1);
         self.registers.set_flag(Subtract, false);
         self.registers.set_flag(HalfCarry, false);
@@ -355,13 +736,15 @@ impl Z80 {
         let pc = self.pc;
         self.push_word(bus, pc);
         self.pc = addr;
+        self.registers.wz = addr;
     }
 
     fn ldi(&mut self, bus: &mut impl Bus) {
         let de = Reg16::DE.read16(self, bus);
         let hl = Reg16::HL.read16(self, bus);
-        let hl_val = bus.memory_read(hl as usize);
-        bus.memory_write(de as usize, hl_val);
+        let hl_val = self.mem_read(bus, hl, times::MR);
+        self.mem_write(bus, de, hl_val, times::MW);
+        self.internal(bus, de, 2);
         self.inc16(bus, Reg16::DE);
         self.inc16(bus, Reg16::HL);
         self.dec16(bus, Reg16::BC);
@@ -380,8 +763,9 @@ impl Z80 {
     fn ldd(&mut self, bus: &mut impl Bus) {
         let de = Reg16::DE.read16(self, bus);
         let hl = Reg16::HL.read16(self, bus);
-        let hl_val = bus.memory_read(hl as usize);
-        bus.memory_write(de as usize, hl_val);
+        let hl_val = self.mem_read(bus, hl, times::MR);
+        self.mem_write(bus, de, hl_val, times::MW);
+        self.internal(bus, de, 2);
         self.dec16(bus, Reg16::DE);
         self.dec16(bus, Reg16::HL);
         self.dec16(bus, Reg16::BC);
@@ -399,7 +783,8 @@ impl Z80 {
     fn cpi(&mut self, bus: &mut impl Bus) {
         let a = Reg8::A.read8(self, bus);
         let hl = Reg16::HL.read16(self, bus);
-        let hl_mem = bus.memory_read(hl as usize);
+        let hl_mem = self.mem_read(bus, hl, times::MR);
+        self.internal(bus, hl, times::IO);
         let v = a.wrapping_sub(hl_mem);
         self.inc16(bus, Reg16::HL);
         self.dec16(bus, Reg16::BC);
@@ -410,6 +795,7 @@ impl Z80 {
         let bc = Reg16::BC.read16(self, bus);
         self.registers.set_flag(Parity, bc != 0);
         self.registers.set_flag(Subtract, true);
+        self.registers.wz = self.registers.wz.wrapping_add(1);
 
         let n = v - if self.registers.get_flag(HalfCarry) {
             1
@@ -421,102 +807,129 @@ impl Z80 {
     }
 
     fn ldir(&mut self, bus: &mut impl Bus) {
+        let de = Reg16::DE.read16(self, bus);
         self.ldi(bus);
         self.registers.set_flag(Parity, false);
         if Reg16::BC.read16(self, bus) != 0 {
-            self.pc -= 2;
+            self.repeat_block();
+            self.internal(bus, de, times::IO);
         }
     }
 
     fn lddr(&mut self, bus: &mut impl Bus) {
+        let de = Reg16::DE.read16(self, bus);
         self.ldd(bus);
         self.registers.set_flag(Parity, false);
         if Reg16::BC.read16(self, bus) != 0 {
-            self.pc -= 2;
+            self.repeat_block();
+            self.internal(bus, de, times::IO);
         }
     }
 
     fn cpir(&mut self, bus: &mut impl Bus) {
+        let hl = Reg16::HL.read16(self, bus);
         self.cpi(bus);
         if Reg16::BC.read16(self, bus) != 0 && !self.registers.get_flag(Zero) {
-            self.pc -= 2;
+            self.repeat_block();
+            self.internal(bus, hl, times::IO);
         }
     }
 
+    /// Rewinds pc to the start of a repeating block instruction
+    pub fn repeat_block(&mut self) {
+        self.pc = self.pc.wrapping_sub(2);
+        self.registers.wz = self.pc.wrapping_add(1);
+    }
+
     pub fn common_rot_flags(&mut self) {
         self.registers.set_flag(HalfCarry, false);
         self.registers.set_flag(Subtract, false);
     }
 
-    fn outp(&mut self, bus: &mut impl Bus, port: u16, val: u8) {
-        bus.port_write(port as u8, val);
-    }
-
     fn push_word(&mut self, bus: &mut impl Bus, word: u16) {
         let lo = (word & 0xff) as u8;
         let hi = (word >> 8) as u8;
 
-        self.sp -= 1;
-        bus.memory_write(self.sp as usize, hi);
-        bus.tick(1, times::SWH);
+        self.sp = self.sp.wrapping_sub(1);
+        self.mem_write(bus, self.sp, hi, times::SWH);
 
-        self.sp -= 1;
-        bus.memory_write(self.sp as usize, lo);
-        bus.tick(1, times::SWL);
+        self.sp = self.sp.wrapping_sub(1);
+        self.mem_write(bus, self.sp, lo, times::SWL);
     }
 
-    fn pop_byte(&mut self, bus: &mut impl Bus) -> u8 {
+    fn pop_byte(&mut self, bus: &mut impl Bus, t_states: u8) -> u8 {
         let sp = self.sp;
-        let val = bus.memory_read(sp as usize);
+        let val = self.mem_read(bus, sp, t_states);
         self.sp = sp.wrapping_add(1);
         val
     }
 
     pub fn pop_word(&mut self, bus: &mut impl Bus) -> u16 {
-        let lo = self.pop_byte(bus);
-        bus.tick(1, times::SRL);
-
-        let hi = self.pop_byte(bus);
-        bus.tick(1, times::SRH);
+        let lo = self.pop_byte(bus, times::SRL);
+        let hi = self.pop_byte(bus, times::SRH);
         make_u16(lo, hi)
     }
 
     pub fn szp_flags(&mut self, val: u8) {
-        let v = val & 0xff;
         self.registers.set_flag(Sign, val & 0x80 == 0x80);
-        self.registers.set_flag(Zero, v == 0);
-        self.registers.set_flag(Parity, v.count_ones() % 2 == 0);
+        self.registers.set_flag(Zero, val == 0);
+        self.registers.set_flag(Parity, val.count_ones().is_multiple_of(2));
     }
 
+    /// Runs the INT acknowledge cycle for the current interrupt mode
     pub fn interrupt(&mut self, bus: &mut impl Bus) {
-        if self.iff1 != 0 {
-            if self.halted {
-                self.pc += 1;
-                self.halted = false;
+        if self.iff1 == 0 {
+            return;
+        }
+        self.halted = false;
+        self.iff1 = 0;
+        self.iff2 = 0;
+        self.inc_r();
+        self.begin_cycle(bus, Cycle::IntAck { addr: self.pc }, 1, times::INTA);
+
+        match self.interrupt_mode {
+            0 => {
+                // the device supplies the instruction, pc only moves if it jumps
+                self.int_ack = true;
+                let op = bus.interrupt_acknowledge();
+                ops::decode((&mut *self, bus), op);
+                self.int_ack = false;
             }
-            // cycles = 7
-            let r = (Reg8::R.read8(self, bus) + 1) & 0x7f;
-            Reg8::R.write8(self, bus, r);
-            self.iff1 = 0;
-            self.iff2 = 0;
-
-            let pc = self.pc;
-            self.push_word(bus, pc);
-
-            match self.interrupt_mode {
-                0 | 1 => {
-                    self.pc = 0x0038;
-                }
-                2 => {
-                    let i = Reg8::I.read8(self, bus);
-                    let inttemp = ((i as u16) << 8) | 0xff;
-                    self.pc = bus.memory_read_word(inttemp as usize);
-                }
-                _ => {
-                    panic!("Unknown interrupt mode");
-                }
+            1 => {
+                self.internal(bus, self.ir(), 1);
+                let pc = self.pc;
+                self.push_word(bus, pc);
+                self.pc = 0x0038;
             }
+            _ => {
+                self.internal(bus, self.ir(), 1);
+                let vector = bus.interrupt_acknowledge() & 0xfe;
+                let pc = self.pc;
+                self.push_word(bus, pc);
+                let addr = make_u16(vector, self.registers.i);
+                let lo = self.mem_read(bus, addr, times::MRL);
+                let hi = self.mem_read(bus, addr.wrapping_add(1), times::MRH);
+                self.pc = make_u16(lo, hi);
+            }
+        }
+        self.registers.wz = self.pc;
+        self.registers.q = 0;
+    }
+
+    /// The lower seven bits of r count op code fetches
+    fn inc_r(&mut self) {
+        let r = self.registers.r;
+        self.registers.r = (r & 0x80) | (r.wrapping_add(1) & 0x7f);
+    }
+
+    /// Next instruction byte, from memory or from the data bus during an im 0 acknowledge
+    fn fetch(&mut self, bus: &mut impl Bus) -> u8 {
+        if self.int_ack {
+            return bus.interrupt_acknowledge();
         }
+        let val = bus.memory_read(self.pc as usize);
+        self.pc = self.pc.wrapping_add(1);
+        val
     }
 }
 
@@ -544,9 +957,11 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
     fn ccf(self) {
         let (cpu, _) = self;
         let carry = cpu.registers.get_flag(Carry);
+        let xy = cpu.scf_ccf_xy();
         cpu.registers.set_flag(HalfCarry, carry);
         cpu.registers.set_flag(Subtract, false);
         cpu.registers.set_flag(Carry, !carry);
+        cpu.registers.set_xy(xy);
     }
 
     fn cpl(self) {
@@ -556,36 +971,32 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
         Reg8::A.write8(cpu, bus, res);
         cpu.registers.set_flag(HalfCarry, true);
         cpu.registers.set_flag(Subtract, true);
+        cpu.registers.set_xy(res);
     }
 
     fn daa(self) {
         let (cpu, bus) = self;
-        let mut a = Reg8::A.read8(cpu, bus) as i16;
+        let a = Reg8::A.read8(cpu, bus);
         let n = cpu.registers.get_flag(Subtract);
         let c = cpu.registers.get_flag(Carry);
         let h = cpu.registers.get_flag(HalfCarry);
+        let lo = a & 0x0f;
 
-        if c || a & 0xff > 0x99 {
-            if n {
-                a -= 0x60
-            } else {
-                a += 0x60
-            }
-            cpu.registers.set_flag(Carry, true);
+        let mut diff = 0;
+        if h || lo > 9 {
+            diff |= 0x06;
         }
-        if h || a & 0xf > 0x9 {
-            if n {
-                a -= 0x6
-            } else {
-                a += 0x6
-            }
+        if c || a > 0x99 {
+            diff |= 0x60;
         }
 
-        let old_a = Reg8::A.read8(cpu, bus) as i16;
-        cpu.registers
-            .set_flag(HalfCarry, (old_a ^ a) & 0b1_0000 == 0b1_0000);
-        cpu.szp_flags(a as u8);
-        Reg8::A.write8(cpu, bus, a as u8);
+        let res = if n { a.wrapping_sub(diff) } else { a.wrapping_add(diff) };
+
+        cpu.registers.set_flag(Carry, c || a > 0x99);
+        cpu.registers.set_flag(HalfCarry, if n { h && lo < 6 } else { lo > 9 });
+        cpu.szp_flags(res);
+        cpu.registers.set_xy(res);
+        Reg8::A.write8(cpu, bus, res);
     }
 
     fn dec8<R: Write8 + Read8 + Copy>(self, reg: R) {
@@ -631,6 +1042,7 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
     fn ld8_int<D: Write8, S: Read8>(self, dest: D, source: S) {
         let (cpu, bus) = self;
 
+        cpu.internal(bus, cpu.ir(), 1);
         let val = source.read8(cpu, bus);
 
         dest.write8(cpu, bus, val);
@@ -642,6 +1054,16 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
 
         let addr = dest.read_address(cpu, bus);
         let val = source.read8(cpu, bus);
+        cpu.displacement_delay(bus, times::IO);
+        Mem(addr).write8(cpu, bus, val);
+    }
+
+    fn ld8_address_dest_imm<D: ReadAddress>(self, dest: D) {
+        let (cpu, bus) = self;
+
+        let addr = dest.read_address(cpu, bus);
+        let val = ImmByte.read8(cpu, bus);
+        cpu.displacement_delay(bus, 2);
         Mem(addr).write8(cpu, bus, val);
     }
 
@@ -649,6 +1071,7 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
         let (cpu, bus) = self;
 
         let addr = source.read_address(cpu, bus);
+        cpu.displacement_delay(bus, times::IO);
         let val = Mem(addr).read8(cpu, bus);
         dest.write8(cpu, bus, val);
     }
@@ -659,18 +1082,32 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
         dest.write16(cpu, bus, val);
     }
 
+    fn ld16_sp<S: Read16>(self, source: S) {
+        let (cpu, bus) = self;
+        let val = source.read16(cpu, bus);
+        Reg16::SP.write16(cpu, bus, val);
+        cpu.internal(bus, cpu.ir(), 2);
+    }
+
     fn in8<D: Write8, S: Read8>(self, dest: D, source: S) {
         let (cpu, bus) = self;
 
-        cpu.read_port(bus, dest, source)
+        let val = cpu.read_port(bus, source);
+        dest.write8(cpu, bus, val);
+    }
+
+    fn in8_flags<S: Read8>(self, source: S) {
+        let (cpu, bus) = self;
+        cpu.read_port(bus, source);
     }
 
     fn in8_noflags<D: Write8, S: Read8>(self, _dest: D, _source: S) {
         let (cpu, bus) = self;
 
         let addr = cpu.read_u8(bus) as u16 | ((Reg8::A.read8(cpu, bus) as u16) << 8);
-        let port_val = bus.port_read(addr as u8);
+        let port_val = cpu.io_in(bus, addr);
         Reg8::A.write8(cpu, bus, port_val);
+        cpu.registers.wz = addr.wrapping_add(1);
     }
 
     fn inc8<R: Write8 + Read8 + Copy>(self, reg: R) {
@@ -681,12 +1118,14 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
     fn inc8_memory<R: ReadAddress>(self, reg: R) {
         let (cpu, bus) = self;
         let addr = reg.read_address(cpu, bus);
+        cpu.displacement_delay(bus, times::IO);
         ops::inc_u8(cpu, bus, Mem(addr));
     }
 
     fn dec8_memory<R: ReadAddress>(self, reg: R) {
         let (cpu, bus) = self;
         let addr = reg.read_address(cpu, bus);
+        cpu.displacement_delay(bus, times::IO);
         ops::dec_u8(cpu, bus, Mem(addr));
     }
 
@@ -695,23 +1134,28 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
 
         let v = reg.read16(cpu, bus);
         reg.write16(cpu, bus, v.wrapping_add(1));
-        bus.tick(0, 2);
+        cpu.internal(bus, cpu.ir(), 2);
     }
 
     fn dec16<R: Write16 + Read16 + Copy>(self, reg: R) {
         let (cpu, bus) = self;
 
         cpu.dec16(bus, reg);
-        bus.tick(0, 2);
+        cpu.internal(bus, cpu.ir(), 2);
     }
 
     fn jp<A: Read16>(self, addr: A) {
         let (cpu, bus) = self;
         let addr = addr.read16(cpu, bus);
-
+        cpu.registers.wz = addr;
         cpu.pc = addr;
     }
 
+    fn jp_reg<A: Read16>(self, reg: A) {
+        let (cpu, bus) = self;
+        cpu.pc = reg.read16(cpu, bus);
+    }
+
     fn jp_cond<C: ReadCond, A: Read16>(self, condition: C, _: A) {
         let (cpu, bus) = self;
         let cond = condition.read_cond(cpu);
@@ -725,36 +1169,35 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
         let temp = cpu.read_u8(bus) as i8 as i32;
 
         if cond {
+            cpu.displacement_delay(bus, times::IO);
             cpu.pc = (cpu.pc as i32 + temp) as u16;
-            cpu.registers.xy_int = (cpu.pc >> 8) as u8;
-            bus.tick(1, times::IO);
+            cpu.registers.wz = cpu.pc;
         }
     }
 
     fn djnz(self) {
         let (cpu, bus) = self;
+        cpu.internal(bus, cpu.ir(), 1); // ocf takes 5 tstates
         let b = Reg8::B.read8(cpu, bus);
         let b = b.wrapping_sub(1); //((B.read(self, bus) as i32 - 1) & 0xff) as u8;
         Reg8::B.write8(cpu, bus, b);
 
-        if b != 0 {
-            (cpu, bus).jr(true);
-        } else {
-            cpu.pc += 1; // @todo Not nice!!!
-        }
+        (cpu, bus).jr(b != 0);
     }
 
     fn ret_cond<C: ReadCond>(self, condition: C) {
         let (cpu, bus) = self;
-        bus.tick(0, 1); // @todo for some reason  ocf is 5 don't know why
+        cpu.internal(bus, cpu.ir(), 1); // ocf takes 5 tstates
         if condition.read_cond(cpu) {
             cpu.pc = cpu.pop_word(bus);
+            cpu.registers.wz = cpu.pc;
         }
     }
 
     fn ret(self) {
         let (cpu, bus) = self;
         cpu.pc = cpu.pop_word(bus);
+        cpu.registers.wz = cpu.pc;
     }
 
     fn halt(self) {
@@ -772,32 +1215,26 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
         cpu.write_port(bus, dest, source)
     }
 
+    fn out8_zero<D: Read8>(self, dest: D) {
+        let (cpu, bus) = self;
+        let val = match cpu.model {
+            Model::ZilogCmos => 0xff,
+            Model::ZilogNmos | Model::NecNmos => 0,
+        };
+        cpu.write_port(bus, dest, val)
+    }
+
     fn out8_noflags<D: Read8, S: Read8>(self, _dest: D, _source: S) {
         let (cpu, bus) = self;
         let port = cpu.read_u8(bus);
         let a = Reg8::A.read8(cpu, bus);
-        bus.port_write(port, a);
+        cpu.io_out(bus, make_u16(port, a), a);
+        cpu.registers.wz = (a as u16) << 8 | port.wrapping_add(1) as u16;
     }
 
     fn outi(self) {
         let (cpu, bus) = self;
-        bus.tick(0, 1); // ocf #2 takes 5 tstates for outi
-
-        let hl = Reg16::HL.read16(cpu, bus);
-        let byte = bus.memory_read(hl as usize);
-        bus.tick(1, times::MR);
-        cpu.write_port(bus, Reg8::C, byte);
-
-        ops::dec_u8(cpu, bus, Reg8::B);
-
-        cpu.inc16(bus, Reg16::HL);
-
-        let l = Reg8::L.read8(cpu, bus);
-        let carry = l as u16 + byte as u16 > 255;
-        cpu.registers.set_flag(Carry, carry);
-        cpu.registers.set_flag(HalfCarry, carry);
-
-        cpu.registers.set_flag(Subtract, byte & 0x80 == 0x80);
+        cpu.block_out(bus, true);
     }
 
     fn rla(self) {
@@ -816,7 +1253,7 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
     fn rlca(self) {
         let (cpu, bus) = self;
         let val = Reg8::A.read8(cpu, bus);
-        let res = val << 1 | val >> 7;
+        let res = val.rotate_left(1);
         cpu.registers.set_flag(Carry, val >> 7 == 1);
         cpu.registers.set_flag(Subtract, false);
         cpu.registers.set_flag(HalfCarry, false);
@@ -832,7 +1269,7 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
     fn rrca(self) {
         let (cpu, bus) = self;
         let val = Reg8::A.read8(cpu, bus);
-        let res = (val >> 1 | val << 7) & 0xff;
+        let res = val.rotate_right(1);
         cpu.registers.set_flag(Carry, val & 0b1 == 1);
         cpu.registers.set_flag(Subtract, false);
         cpu.registers.set_flag(HalfCarry, false);
@@ -854,6 +1291,7 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
         let (cpu, bus) = self;
         let pc = cpu.pop_word(bus);
         cpu.pc = pc;
+        cpu.registers.wz = pc;
         cpu.iff1 = cpu.iff2;
         cpu.nmi = false;
     }
@@ -863,6 +1301,8 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
 
         let destval = dest.read16(cpu, bus) as u32;
         let val = source.read16(cpu, bus) as u32;
+        cpu.add16_delay(bus);
+        cpu.registers.wz = (destval as u16).wrapping_add(1);
         let carry1 = if cpu.registers.get_flag(Carry) { 1 } else { 0 };
         let lo = ops::raw_sub(cpu, destval as u8, val as u8, carry1);
         let carry = if cpu.registers.get_flag(Carry) { 1 } else { 0 };
@@ -875,9 +1315,11 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
 
     fn scf(self) {
         let (cpu, _) = self;
+        let xy = cpu.scf_ccf_xy();
         cpu.registers.set_flag(HalfCarry, false);
         cpu.registers.set_flag(Subtract, false);
         cpu.registers.set_flag(Carry, true);
+        cpu.registers.set_xy(xy);
     }
 
     fn xor<R: Read8>(self, reg: R) {
@@ -890,6 +1332,8 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
 
         let val = source.read16(cpu, bus) as u32;
         let destval = dest.read16(cpu, bus) as u32;
+        cpu.add16_delay(bus);
+        cpu.registers.wz = (destval as u16).wrapping_add(1);
         let carry = if cpu.registers.get_flag(Carry) { 1 } else { 0 };
         let lo = ops::raw_addc(cpu, destval as u8, val as u8, carry);
         let carry = if cpu.registers.get_flag(Carry) { 1 } else { 0 };
@@ -898,7 +1342,7 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
         Reg16::HL.write16(cpu, bus, res);
         cpu.registers.set_flag(Zero, res == 0);
 
-        dest.write16(cpu, bus, res as u16);
+        dest.write16(cpu, bus, res);
     }
 
     fn cpi(self) {
@@ -916,9 +1360,11 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
     fn cpdr(self) {
         let (cpu, bus) = self;
 
+        let hl = Reg16::HL.read16(cpu, bus);
         ops::cpd(cpu, bus);
         if Reg16::BC.read16(cpu, bus) != 0 && !cpu.registers.get_flag(Zero) {
-            cpu.pc -= 2;
+            cpu.repeat_block();
+            cpu.internal(bus, hl, times::IO);
         }
     }
 
@@ -933,7 +1379,9 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
 
     fn otir(self) {
         let (cpu, bus) = self;
-        cpu.otir(bus);
+        cpu.block_out(bus, true);
+        let bc = Reg16::BC.read16(cpu, bus);
+        cpu.block_io_repeat(bus, bc);
     }
     fn ldir(self) {
         let (cpu, bus) = self;
@@ -942,38 +1390,11 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
 
     fn outd(self) {
         let (cpu, bus) = self;
-        let hl = Reg16::HL.read16(cpu, bus);
-        let byte = bus.memory_read(hl as usize);
-        cpu.write_port(bus, Reg8::C, byte);
-
-        ops::dec_u8(cpu, bus, Reg8::B);
-
-        cpu.dec16(bus, Reg16::HL);
-
-        let l = Reg8::L.read8(cpu, bus);
-        let carry = l as u16 + byte as u16 > 255;
-        cpu.registers.set_flag(Carry, carry);
-        cpu.registers.set_flag(HalfCarry, carry);
-
-        cpu.registers.set_flag(Subtract, byte & 0x80 == 0x80);
+        cpu.block_out(bus, false);
     }
     fn ini(self) {
         let (cpu, bus) = self;
-
-        let bc = Reg16::BC.read16(cpu, bus);
-        let hl = Reg16::HL.read16(cpu, bus);
-        let ini = bus.port_read(bc as u8);
-        bus.port_write(hl as u8, ini);
-        let b = Reg8::B.read8(cpu, bus);
-        ops::dec_u8(cpu, bus, Reg8::B);
-        cpu.inc16(bus, Reg16::HL);
-        //                cpu.set_hl(hl + 1);
-
-        let ini2 = ini.wrapping_add(Reg8::C.read8(cpu, bus)).wrapping_add(1);
-        cpu.registers.set_flag(Subtract, ini & 0x80 != 0);
-        cpu.registers.set_flag(HalfCarry, ini2 < ini);
-        cpu.registers.set_flag(Carry, ini2 < ini);
-        cpu.registers.set_flag(Parity, (ini2 & 0x07) ^ b != 0);
+        cpu.block_in(bus, true);
     }
 
     fn im(self, im: u8) {
@@ -984,15 +1405,16 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
     fn rrd(self) {
         let (cpu, bus) = self;
 
-        let addr = Reg16::HL.read16(cpu, bus) as usize;
-        let v = bus.memory_read(addr);
+        let addr = Reg16::HL.read16(cpu, bus);
+        let v = cpu.mem_read(bus, addr, times::MR);
+        cpu.internal(bus, addr, 4);
         let a = Reg8::A.read8(cpu, bus);
         let ah = a & 0xf0;
         let al = a & 0x0f;
         let a = ah | (v & 0x0f);
         Reg8::A.write8(cpu, bus, a);
-        bus.memory_write(addr, (v >> 4 | al << 4) & 0xff);
-        // wz = addr + 1
+        cpu.mem_write(bus, addr, v >> 4 | al << 4, times::MW);
+        cpu.registers.wz = addr.wrapping_add(1);
         cpu.szp_flags(a);
         cpu.registers.set_xy(a);
         cpu.common_rot_flags();
@@ -1001,16 +1423,17 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
     fn rld(self) {
         let (cpu, bus) = self;
         // cpu.rld(bus);
-        let addr = Reg16::HL.read16(cpu, bus) as usize;
-        let v = bus.memory_read(addr);
+        let addr = Reg16::HL.read16(cpu, bus);
+        let v = cpu.mem_read(bus, addr, times::MR);
+        cpu.internal(bus, addr, 4);
         let a = Reg8::A.read8(cpu, bus);
         let ah = a & 0xf0;
         let al = a & 0x0f;
 
         let a = ah | (v >> 4 & 0x0f);
         Reg8::A.write8(cpu, bus, a);
-        bus.memory_write(addr, (v << 4 | al) & 0xff);
-        // wz = addr + 1
+        cpu.mem_write(bus, addr, v << 4 | al, times::MW);
+        cpu.registers.wz = addr.wrapping_add(1);
         cpu.szp_flags(a);
         cpu.registers.set_xy(a);
         cpu.common_rot_flags();
@@ -1020,6 +1443,7 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
         let (cpu, bus) = self;
         let pc = cpu.pop_word(bus);
         cpu.pc = pc;
+        cpu.registers.wz = pc;
         cpu.iff1 = 1;
         cpu.iff2 = 1;
     }
@@ -1029,33 +1453,29 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
         cpu.ldi(bus);
     }
     fn otdr(self) {
-        panic!("otdr?")
+        let (cpu, bus) = self;
+        cpu.block_out(bus, false);
+        let bc = Reg16::BC.read16(cpu, bus);
+        cpu.block_io_repeat(bus, bc);
     }
 
     fn inir(self) {
-        panic!("inir?")
+        let (cpu, bus) = self;
+        let hl = Reg16::HL.read16(cpu, bus);
+        cpu.block_in(bus, true);
+        cpu.block_io_repeat(bus, hl);
     }
 
     fn ind(self) {
         let (cpu, bus) = self;
-        let c_val = Reg8::C.read8(cpu, bus);
-
-        let port_val = bus.port_read(c_val);
-
-        let hl_val = Reg16::HL.read16(cpu, bus);
-        bus.memory_write(hl_val.into(), port_val);
-
-        let b_val = Reg8::B.read8(cpu, bus);
-
-        Reg8::B.write8(cpu, bus, b_val.wrapping_sub(1));
-        Reg16::HL.write16(cpu, bus, hl_val.wrapping_sub(1));
-
-        Flag::Subtract.write(&mut cpu.registers, true);
-        Flag::Zero.write(&mut cpu.registers, b_val == 1);
+        cpu.block_in(bus, false);
     }
 
     fn indr(self) {
-        panic!("indr?");
+        let (cpu, bus) = self;
+        let hl = Reg16::HL.read16(cpu, bus);
+        cpu.block_in(bus, false);
+        cpu.block_io_repeat(bus, hl);
     }
     fn cb_op(self) {
         let (cpu, bus) = self;
@@ -1085,11 +1505,11 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
 
     fn dd_fd_cb_op(self, ireg: Reg16) {
         let (cpu, bus) = self;
-        let address = RelOffset(ireg).read16(cpu, bus);
-        let op = cpu.read_instruction(bus);
-        // let op = bus.memory_read(cpu.pc as usize);
-        // cpu.pc += 1;
-        // bus.tick(0, times::OCF);
+        let address = Indexed(ireg, cpu.read_u8(bus) as i8);
+        cpu.registers.wz = address.read16(cpu, bus);
+        // the opcode is read as data rather than fetched, then the address is added
+        let op = cpu.read_u8(bus);
+        cpu.displacement_delay(bus, 2);
 
         ops::decode_dd_fd_cb((cpu, bus), address, op)
     }
@@ -1109,19 +1529,26 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
 
     fn bit<S: Read8>(self, bit: u8, source: S) {
         let (cpu, bus) = self;
-        let val = source.read8(cpu, bus);
-        cpu.test_bit(bit, val);
+        let val = source.read8_rmw(cpu, bus);
+        cpu.test_bit(bit, val, val);
+    }
+
+    fn bit_memory<S: Read8>(self, bit: u8, source: S) {
+        let (cpu, bus) = self;
+        let val = source.read8_rmw(cpu, bus);
+        let xy = (cpu.registers.wz >> 8) as u8;
+        cpu.test_bit(bit, val, xy);
     }
 
     fn set<S: Read8 + Write8 + Copy>(self, bit: u8, source: S) {
         let (cpu, bus) = self;
-        let val: u8 = source.read8(cpu, bus);
+        let val: u8 = source.read8_rmw(cpu, bus);
         source.write8(cpu, bus, val | (1 << bit));
     }
 
     fn res<S: Read8 + Write8 + Copy>(self, bit: u8, source: S) {
         let (cpu, bus) = self;
-        let val: u8 = source.read8(cpu, bus);
+        let val: u8 = source.read8_rmw(cpu, bus);
         source.write8(cpu, bus, val & !(1 << bit));
     }
 
@@ -1187,9 +1614,11 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
 
     fn rst(self, byte: u8) {
         let (cpu, bus) = self;
+        cpu.internal(bus, cpu.ir(), 1); // ocf takes 5 tstates
         let pc = cpu.pc;
         cpu.push_word(bus, pc);
         cpu.pc = byte as u16;
+        cpu.registers.wz = cpu.pc;
     }
 
     fn pop<T: Write16>(self, target: T) {
@@ -1201,6 +1630,7 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
 
     fn push<S: Read16>(self, source: S) {
         let (cpu, bus) = self;
+        cpu.internal(bus, cpu.ir(), 1); // ocf takes 5 tstates
         let val = source.read16(cpu, bus);
         cpu.push_word(bus, val);
     }
@@ -1210,6 +1640,8 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
 
         let val = source.read16(cpu, bus) as u32;
         let destval = dest.read16(cpu, bus) as u32;
+        cpu.add16_delay(bus);
+        cpu.registers.wz = (destval as u16).wrapping_add(1);
 
         let res = val + destval;
 
@@ -1220,7 +1652,5 @@ impl<'a, B: Bus> Ops for (&'a mut Z80, &'a mut B) {
         cpu.registers.set_xy((res >> 8) as u8);
 
         dest.write16(cpu, bus, res as u16);
-
-        cpu.registers.xy_int = cpu.registers.h;
     }
 }
@@ -0,0 +1,262 @@
+//! Break conditions over registers and memory, like `a == $3f && (hl) != 0`
+
+use std::fmt;
+
+use crate::bus::Bus;
+use crate::cpu::Z80;
+use crate::state::Z80State;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ConditionError {
+    /// Byte offset into the condition
+    pub position: usize,
+}
+
+impl fmt::Display for ConditionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid condition at position {}", self.position)
+    }
+}
+
+impl std::error::Error for ConditionError {}
+
+/// An expression that breaks when it is not 0.
+///
+/// It is made of register names, including `af'` and the other shadow
+/// pairs, numbers written `$ff`, `0xff`, `0ffh`, `%1010` or decimal, and
+/// `(x)`, the byte at address `x`. Operators bind like in C, from `||`,
+/// `&&`, `|`, `^`, `&`, `==` `!=`, `<` `<=` `>` `>=` to `+` `-`, with
+/// unary `!`, `~` and `-`. Parentheses always read memory, they do not
+/// group.
+#[derive(Debug, Clone)]
+pub struct Condition {
+    text: String,
+    expr: Expr,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition, ConditionError> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens: &tokens, pos: 0, end: text.len() };
+        let expr = parser.binary(0)?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(Condition { text: text.to_string(), expr }),
+            Some((_, position)) => Err(ConditionError { position: *position }),
+        }
+    }
+
+    /// Whether the condition holds. Memory is read with `memory_read`.
+    pub fn evaluate(&self, cpu: &Z80, bus: &impl Bus) -> bool {
+        self.expr.value(&cpu.snapshot(), &|address| bus.memory_read(address as usize)) != 0
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+type Register = fn(&Z80State) -> u32;
+
+const REGISTERS: [(&str, Register); 26] = [
+    ("a", |s| (s.af >> 8) as u32),
+    ("f", |s| (s.af & 0xff) as u32),
+    ("b", |s| (s.bc >> 8) as u32),
+    ("c", |s| (s.bc & 0xff) as u32),
+    ("d", |s| (s.de >> 8) as u32),
+    ("e", |s| (s.de & 0xff) as u32),
+    ("h", |s| (s.hl >> 8) as u32),
+    ("l", |s| (s.hl & 0xff) as u32),
+    ("i", |s| s.i as u32),
+    ("r", |s| s.r as u32),
+    ("ixh", |s| (s.ix >> 8) as u32),
+    ("ixl", |s| (s.ix & 0xff) as u32),
+    ("iyh", |s| (s.iy >> 8) as u32),
+    ("iyl", |s| (s.iy & 0xff) as u32),
+    ("af", |s| s.af as u32),
+    ("bc", |s| s.bc as u32),
+    ("de", |s| s.de as u32),
+    ("hl", |s| s.hl as u32),
+    ("ix", |s| s.ix as u32),
+    ("iy", |s| s.iy as u32),
+    ("sp", |s| s.sp as u32),
+    ("pc", |s| s.pc as u32),
+    ("af'", |s| s.alt_af as u32),
+    ("bc'", |s| s.alt_bc as u32),
+    ("de'", |s| s.alt_de as u32),
+    ("hl'", |s| s.alt_hl as u32),
+];
+
+/// Binary operators from the loosest binding
+const LEVELS: [&[&str]; 8] = [&["||"], &["&&"], &["|"], &["^"], &["&"], &["==", "!="], &["<=", ">=", "<", ">"], &["+", "-"]];
+
+/// Longer operators first, so `&&` is not read as two `&`
+const OPERATORS: [&str; 16] = ["||", "&&", "==", "!=", "<=", ">=", "|", "^", "&", "<", ">", "+", "-", "!", "~", "("];
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(u32),
+    Register(Register),
+    Memory(Box<Expr>),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn value(&self, state: &Z80State, memory: &dyn Fn(u16) -> u8) -> u32 {
+        match self {
+            Expr::Number(n) => *n,
+            Expr::Register(read) => read(state),
+            Expr::Memory(address) => memory(address.value(state, memory) as u16) as u32,
+            Expr::Unary(operator, operand) => {
+                let value = operand.value(state, memory);
+                match *operator {
+                    "!" => (value == 0) as u32,
+                    "~" => !value,
+                    _ => value.wrapping_neg(),
+                }
+            }
+            Expr::Binary(operator, left, right) => {
+                let (left, right) = (left.value(state, memory), right.value(state, memory));
+                match *operator {
+                    "||" => (left != 0 || right != 0) as u32,
+                    "&&" => (left != 0 && right != 0) as u32,
+                    "|" => left | right,
+                    "^" => left ^ right,
+                    "&" => left & right,
+                    "==" => (left == right) as u32,
+                    "!=" => (left != right) as u32,
+                    "<=" => (left <= right) as u32,
+                    ">=" => (left >= right) as u32,
+                    "<" => (left < right) as u32,
+                    ">" => (left > right) as u32,
+                    "+" => left.wrapping_add(right),
+                    _ => left.wrapping_sub(right),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(u32),
+    Name(String),
+    Operator(&'static str),
+    Close,
+}
+
+/// Tokens with their byte offsets
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, ConditionError> {
+    let bytes = text.as_bytes();
+    let mut tokens = vec![];
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let c = bytes[pos];
+        let start = pos;
+        if c.is_ascii_whitespace() {
+            pos += 1;
+            continue;
+        }
+        let word_end = |from: usize| {
+            let mut end = from;
+            while end < bytes.len() && (bytes[end].is_ascii_alphanumeric() || bytes[end] == b'_') {
+                end += 1;
+            }
+            end
+        };
+        let token = if c == b')' {
+            pos += 1;
+            Token::Close
+        } else if let Some(operator) = OPERATORS.iter().find(|op| text[pos..].starts_with(*op)) {
+            pos += operator.len();
+            Token::Operator(operator)
+        } else if c == b'$' || c == b'%' || c.is_ascii_digit() {
+            let from = if c.is_ascii_digit() { pos } else { pos + 1 };
+            pos = word_end(from);
+            let word = text[from..pos].to_ascii_lowercase();
+            number(c, &word).ok_or(ConditionError { position: start })?
+        } else if c.is_ascii_alphabetic() {
+            pos = word_end(pos);
+            if bytes.get(pos) == Some(&b'\'') {
+                pos += 1;
+            }
+            Token::Name(text[start..pos].to_ascii_lowercase())
+        } else {
+            return Err(ConditionError { position: start });
+        };
+        tokens.push((token, start));
+    }
+    Ok(tokens)
+}
+
+fn number(first: u8, word: &str) -> Option<Token> {
+    let value = match first {
+        b'$' => u32::from_str_radix(word, 16),
+        b'%' => u32::from_str_radix(word, 2),
+        _ => {
+            if let Some(hex) = word.strip_prefix("0x") {
+                u32::from_str_radix(hex, 16)
+            } else if let Some(hex) = word.strip_suffix('h') {
+                u32::from_str_radix(hex, 16)
+            } else {
+                word.parse()
+            }
+        }
+    };
+    value.ok().map(Token::Number)
+}
+
+struct Parser<'a> {
+    tokens: &'a [(Token, usize)],
+    pos: usize,
+    /// Reported for a condition that ends too early
+    end: usize,
+}
+
+impl Parser<'_> {
+    fn binary(&mut self, level: usize) -> Result<Expr, ConditionError> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut expr = self.binary(level + 1)?;
+        while let Some((Token::Operator(operator), _)) = self.tokens.get(self.pos) {
+            if !LEVELS[level].contains(operator) {
+                break;
+            }
+            self.pos += 1;
+            let right = self.binary(level + 1)?;
+            expr = Expr::Binary(operator, Box::new(expr), Box::new(right));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ConditionError> {
+        let (token, position) = match self.tokens.get(self.pos) {
+            Some((token, position)) => (token, *position),
+            None => return Err(ConditionError { position: self.end }),
+        };
+        self.pos += 1;
+        match token {
+            Token::Number(n) => Ok(Expr::Number(*n)),
+            Token::Name(name) => match REGISTERS.iter().find(|(register, _)| register == name) {
+                Some((_, read)) => Ok(Expr::Register(*read)),
+                None => Err(ConditionError { position }),
+            },
+            Token::Operator("(") => {
+                let address = self.binary(0)?;
+                match self.tokens.get(self.pos) {
+                    Some((Token::Close, _)) => {
+                        self.pos += 1;
+                        Ok(Expr::Memory(Box::new(address)))
+                    }
+                    Some((_, position)) => Err(ConditionError { position: *position }),
+                    None => Err(ConditionError { position: self.end }),
+                }
+            }
+            Token::Operator(operator @ ("!" | "~" | "-")) => Ok(Expr::Unary(operator, Box::new(self.unary()?))),
+            _ => Err(ConditionError { position }),
+        }
+    }
+}
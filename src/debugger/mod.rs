@@ -0,0 +1,274 @@
+//! Breakpoints, watchpoints and break conditions, checked around
+//! `Z80::step`.
+//!
+//! Watched memory and port accesses are seen as they reach the bus, but
+//! the cpu is only stopped once the instruction that made them is done, so
+//! it is always left at an instruction boundary. A repeating block
+//! instruction like ldir stops after the iteration that made the access,
+//! with pc back on the ldir and bc, de and hl ready for the next one.
+
+mod condition;
+
+pub use self::condition::{Condition, ConditionError};
+
+use std::cell::Cell;
+use std::collections::BTreeSet;
+use std::ops::RangeInclusive;
+
+use crate::bus::{Bus, Cycle};
+use crate::cpu::Z80;
+
+/// What a watchpoint looks for, and what a hit was
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    /// Either; hits are always reported as `Read` or `Write`
+    ReadWrite,
+}
+
+impl Access {
+    fn covers(self, access: Access) -> bool {
+        self == Access::ReadWrite || self == access
+    }
+}
+
+/// Why `run_until_break` stopped
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Break {
+    /// pc reached a breakpoint, the instruction there has not run
+    Breakpoint(u16),
+    /// The first watched memory access of the last instruction
+    Watchpoint { address: u16, access: Access, value: u8 },
+    /// The first watched port access of the last instruction, with the full
+    /// 16-bit port address
+    Port { port: u16, access: Access, value: u8 },
+    /// INT was acknowledged and pc is at the start of the handler
+    InterruptAcknowledge,
+    /// The condition with this index, counting in the order they were added,
+    /// holds before the instruction at pc
+    Condition(usize),
+}
+
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<(RangeInclusive<u16>, Access)>,
+    ports: Vec<(RangeInclusive<u8>, Access)>,
+    conditions: Vec<Condition>,
+    /// Stop when the cpu acknowledges INT
+    pub break_on_interrupt: bool,
+    /// pc of the last stop at a breakpoint or condition, cleared by a step
+    stopped_at: Cell<Option<u16>>,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger::default()
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    /// Returns false if there was no breakpoint at `address`
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    /// Watches memory accesses in `range`. Op code fetches are not reads,
+    /// the operand bytes read after them are.
+    pub fn add_watchpoint(&mut self, range: RangeInclusive<u16>, access: Access) {
+        self.watchpoints.push((range, access));
+    }
+
+    /// Removes a watchpoint added with the same range and access
+    pub fn remove_watchpoint(&mut self, range: RangeInclusive<u16>, access: Access) -> bool {
+        let before = self.watchpoints.len();
+        self.watchpoints.retain(|watchpoint| *watchpoint != (range.clone(), access));
+        self.watchpoints.len() != before
+    }
+
+    /// Watches ports whose lower byte is in `range`, like `port_read` and
+    /// `port_write` see them
+    pub fn add_port_break(&mut self, range: RangeInclusive<u8>, access: Access) {
+        self.ports.push((range, access));
+    }
+
+    pub fn remove_port_break(&mut self, range: RangeInclusive<u8>, access: Access) -> bool {
+        let before = self.ports.len();
+        self.ports.retain(|port| *port != (range.clone(), access));
+        self.ports.len() != before
+    }
+
+    /// Parses and adds a condition, see `Condition`. Returns the index
+    /// `Break::Condition` reports it with.
+    pub fn add_condition(&mut self, condition: &str) -> Result<usize, ConditionError> {
+        self.conditions.push(Condition::parse(condition)?);
+        Ok(self.conditions.len() - 1)
+    }
+
+    pub fn conditions(&self) -> &[Condition] {
+        &self.conditions
+    }
+
+    /// Runs one instruction, or takes an interrupt, and reports the first
+    /// watched access or acknowledge it made. Taking an interrupt leaves pc
+    /// at the start of the handler, so it can be checked before it runs.
+    pub fn step<B: Bus>(&self, cpu: &mut Z80, bus: &mut B) -> Option<Break> {
+        self.stopped_at.set(None);
+        let mut watcher = Watcher { bus, debugger: self, fetch: false, hit: Cell::new(None) };
+        if cpu.accept_interrupt(&mut watcher) == 0 {
+            cpu.step(&mut watcher);
+        }
+        watcher.hit.get()
+    }
+
+    /// Breakpoints and conditions for the instruction at pc
+    pub fn check(&self, cpu: &Z80, bus: &impl Bus) -> Option<Break> {
+        if self.breakpoints.contains(&cpu.pc) {
+            return Some(Break::Breakpoint(cpu.pc));
+        }
+        self.conditions.iter().position(|condition| condition.evaluate(cpu, bus)).map(Break::Condition)
+    }
+
+    /// Steps until something breaks. Breakpoints and conditions at pc are
+    /// checked first, unless the last stop was for one of them at this pc,
+    /// so calling it again after a stop carries on.
+    pub fn run_until_break(&self, cpu: &mut Z80, bus: &mut impl Bus) -> Break {
+        if let Some(reason) = self.check_resume(cpu, bus) {
+            return reason;
+        }
+        loop {
+            if let Some(reason) = self.step_checked(cpu, bus) {
+                return reason;
+            }
+        }
+    }
+
+    /// `check` before running on from pc, skipped when the last stop was a
+    /// breakpoint or condition here
+    pub(crate) fn check_resume(&self, cpu: &Z80, bus: &impl Bus) -> Option<Break> {
+        if self.stopped_at.get() == Some(cpu.pc) {
+            return None;
+        }
+        self.check(cpu, bus).map(|reason| self.stopped(cpu, reason))
+    }
+
+    /// `step`, then `check` for the next instruction
+    pub(crate) fn step_checked(&self, cpu: &mut Z80, bus: &mut impl Bus) -> Option<Break> {
+        self.step(cpu, bus).or_else(|| self.check(cpu, bus)).map(|reason| self.stopped(cpu, reason))
+    }
+
+    fn stopped(&self, cpu: &Z80, reason: Break) -> Break {
+        if let Break::Breakpoint(_) | Break::Condition(_) = reason {
+            self.stopped_at.set(Some(cpu.pc));
+        }
+        reason
+    }
+
+    fn watched(&self, address: u16, access: Access) -> bool {
+        self.watchpoints.iter().any(|(range, watched)| range.contains(&address) && watched.covers(access))
+    }
+
+    fn port_watched(&self, port: u16, access: Access) -> bool {
+        self.ports.iter().any(|(range, watched)| range.contains(&(port as u8)) && watched.covers(access))
+    }
+}
+
+/// Passes everything on to the real bus while looking out for watched accesses
+struct Watcher<'a, B: Bus> {
+    bus: &'a mut B,
+    debugger: &'a Debugger,
+    /// Set during an op code fetch
+    fetch: bool,
+    hit: Cell<Option<Break>>,
+}
+
+impl<B: Bus> Watcher<'_, B> {
+    fn record(&self, hit: Break) {
+        if self.hit.get().is_none() {
+            self.hit.set(Some(hit));
+        }
+    }
+
+    fn memory(&self, address: usize, access: Access, value: u8) {
+        let address = address as u16;
+        if self.debugger.watched(address, access) {
+            self.record(Break::Watchpoint { address, access, value });
+        }
+    }
+
+    fn port(&self, port: u16, access: Access, value: u8) {
+        if self.debugger.port_watched(port, access) {
+            self.record(Break::Port { port, access, value });
+        }
+    }
+}
+
+impl<B: Bus> Bus for Watcher<'_, B> {
+    fn memory_read(&self, address: usize) -> u8 {
+        let value = self.bus.memory_read(address);
+        if !self.fetch {
+            self.memory(address, Access::Read, value);
+        }
+        value
+    }
+
+    fn memory_read_word(&self, address: usize) -> u16 {
+        let value = self.bus.memory_read_word(address);
+        self.memory(address, Access::Read, value as u8);
+        self.memory(address.wrapping_add(1), Access::Read, (value >> 8) as u8);
+        value
+    }
+
+    fn memory_write(&mut self, address: usize, value: u8) {
+        self.memory(address, Access::Write, value);
+        self.bus.memory_write(address, value)
+    }
+
+    fn memory_write_word(&mut self, address: usize, value: u16) {
+        self.memory(address, Access::Write, value as u8);
+        self.memory(address.wrapping_add(1), Access::Write, (value >> 8) as u8);
+        self.bus.memory_write_word(address, value)
+    }
+
+    fn port_read(&mut self, port: u8) -> u8 {
+        self.bus.port_read(port)
+    }
+
+    fn port_write(&mut self, port: u8, value: u8) {
+        self.bus.port_write(port, value)
+    }
+
+    fn io_read(&mut self, port: u16) -> u8 {
+        let value = self.bus.io_read(port);
+        self.port(port, Access::Read, value);
+        value
+    }
+
+    fn io_write(&mut self, port: u16, value: u8) {
+        self.port(port, Access::Write, value);
+        self.bus.io_write(port, value)
+    }
+
+    fn interrupt_acknowledge(&mut self) -> u8 {
+        self.bus.interrupt_acknowledge()
+    }
+
+    fn cycle(&mut self, cycle: Cycle) -> u8 {
+        self.fetch = matches!(cycle, Cycle::M1 { .. });
+        if self.debugger.break_on_interrupt && matches!(cycle, Cycle::IntAck { .. }) {
+            self.record(Break::InterruptAcknowledge);
+        }
+        self.bus.cycle(cycle)
+    }
+
+    fn wait(&mut self, cycle: Cycle) -> bool {
+        self.bus.wait(cycle)
+    }
+
+    fn tick(&mut self, machine_cycles: u8, t_states: u8) {
+        self.bus.tick(machine_cycles, t_states)
+    }
+}
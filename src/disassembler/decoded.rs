@@ -0,0 +1,124 @@
+use std::fmt;
+
+use crate::bus::Bus;
+use crate::cpu::Z80;
+use crate::disassembler::instruction::Instruction;
+use crate::disassembler::syntax::Syntax;
+use crate::disassembler::Disassembler;
+
+#[derive(Debug)]
+pub struct DecodedInstruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub length: usize,
+    pub instruction: Instruction,
+    pub t_states: TStates,
+}
+
+/// T-states an instruction takes, not counting wait states
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TStates {
+    Fixed(u32),
+    /// Conditional jumps, calls and returns, djnz and the repeating block instructions
+    Conditional { taken: u32, not_taken: u32 },
+}
+
+impl fmt::Display for TStates {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            TStates::Fixed(t) => write!(f, "{}", t),
+            TStates::Conditional { taken, not_taken } => write!(f, "{}/{}", taken, not_taken),
+        }
+    }
+}
+
+impl DecodedInstruction {
+    /// The listing line `Display` gives, with the instruction in `syntax`
+    pub fn format(&self, syntax: &Syntax) -> String {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("{:04x}  {:<12} {}", self.address, bytes.join(" "), syntax.format(&self.instruction, self.address))
+    }
+}
+
+impl fmt::Display for DecodedInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.format(&Syntax::default()))
+    }
+}
+
+/// Instructions one after the other, see `Disassembler::iter`
+pub struct Instructions<'d, 'a> {
+    disassembler: &'d Disassembler<'a>,
+    next: u32,
+    end: u32,
+}
+
+impl<'d, 'a> Instructions<'d, 'a> {
+    pub(crate) fn new(disassembler: &'d Disassembler<'a>, start: u32, end: u32) -> Instructions<'d, 'a> {
+        Instructions { disassembler, next: start, end }
+    }
+}
+
+impl Iterator for Instructions<'_, '_> {
+    type Item = DecodedInstruction;
+
+    fn next(&mut self) -> Option<DecodedInstruction> {
+        if self.next >= self.end {
+            return None;
+        }
+        let decoded = self.disassembler.disassemble(self.next as u16);
+        self.next += decoded.length as u32;
+        Some(decoded)
+    }
+}
+
+/// Only the instruction's own bytes, everything else reads as zero and
+/// writes are dropped
+struct Scratch<'a> {
+    address: u16,
+    bytes: &'a [u8],
+}
+
+impl Bus for Scratch<'_> {
+    fn memory_read(&self, address: usize) -> u8 {
+        let offset = (address as u16).wrapping_sub(self.address) as usize;
+        self.bytes.get(offset).copied().unwrap_or(0)
+    }
+
+    fn memory_read_word(&self, address: usize) -> u16 {
+        self.memory_read(address) as u16 | ((self.memory_read(address + 1) as u16) << 8)
+    }
+
+    fn memory_write(&mut self, _: usize, _: u8) {}
+
+    fn memory_write_word(&mut self, _: usize, _: u16) {}
+
+    fn tick(&mut self, _: u8, _: u8) {}
+}
+
+/// Times an instruction by running it on a scratch cpu, so the counts are
+/// the ones `Z80::step` gives. It runs twice with the flags and counters set
+/// so that whatever condition it has goes one way and then the other.
+pub(crate) fn t_states(address: u16, bytes: &[u8]) -> TStates {
+    // f = 0 takes nz, nc, po and p, 0xff takes the rest. b = 1 and bc =
+    // 0x0101 end djnz and the b counted block instructions but repeat the
+    // bc counted ones, bc = 1 does the opposite. a never matches memory.
+    let run = |f: u8, b: u8, c: u8| {
+        let mut cpu = Z80::new();
+        cpu.pc = address;
+        cpu.registers.a = 0xff;
+        cpu.registers.f = f;
+        cpu.registers.b = b;
+        cpu.registers.c = c;
+        cpu.registers.h = 0x80;
+        cpu.registers.d = 0x80;
+        cpu.step(&mut Scratch { address, bytes })
+    };
+    let first = run(0x00, 0x01, 0x01);
+    let second = run(0xff, 0x00, 0x01);
+    if first == second {
+        TStates::Fixed(first)
+    } else {
+        TStates::Conditional { taken: first.max(second), not_taken: first.min(second) }
+    }
+}
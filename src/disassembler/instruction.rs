@@ -1,5 +1,7 @@
 use std::fmt;
 
+use crate::disassembler::syntax::Syntax;
+
 
 
 
@@ -12,7 +14,7 @@ pub struct Data8(pub u8);
 
 impl fmt::Display for Data8 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "${:02x}", self.0)
+        f.write_str(&Syntax::default().byte(self.0))
     }
 }
 
@@ -20,7 +22,21 @@ impl fmt::Display for Data8 {
 pub struct Data16(pub u16);
 impl fmt::Display for Data16 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-       write!(f, "${:04x}", self.0)
+        f.write_str(&Syntax::default().word(self.0))
+    }
+}
+
+/// Relative jump target, as an offset from the start of the instruction
+#[derive(Debug, Copy, Clone)]
+pub struct Rel(pub i16);
+
+impl fmt::Display for Rel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 < 0 {
+            write!(f, "$-{}", self.0.unsigned_abs())
+        } else {
+            write!(f, "$+{}", self.0)
+        }
     }
 }
 
@@ -30,20 +46,19 @@ pub enum Address {
     BC,
     DE,
     HL,
+    SP,
+    IX,
+    IY,
     ZeroPage(Data8),
     RelOffset(Data16, Data8),
+    Indexed(Reg16, i8),
 }
 
 
 
 impl fmt::Display for Address {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        use self::Address::*;
-        match *self {
-            Direct(ref addr) => write!(f, "{}", addr),
-            ZeroPage(ref addr) => write!(f, "{}", addr),
-            _ => write!(f, "{:?}", *self),
-        }
+        f.write_str(&Syntax::default().address(self))
     }
 }
 
@@ -53,6 +68,8 @@ pub enum Cond {
     NotZero,
     Carry,
     NotCarry,
+    ParityOdd,
+    ParityEven,
     Positive,
     Negative,
 
@@ -62,14 +79,16 @@ pub enum Cond {
 
 impl fmt::Display for Cond {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
+        match *self {
             
-            &Cond::Zero => write!(f, "z"),
-            &Cond::NotZero => write!(f, "nz"),
-            &Cond::Carry => write!(f, "c"),
-            &Cond::NotCarry => write!(f, "nc"),
-            &Cond::Positive => write!(f, "p"),
-            &Cond::Negative => write!(f, "m"),
+            Cond::Zero => write!(f, "z"),
+            Cond::NotZero => write!(f, "nz"),
+            Cond::Carry => write!(f, "c"),
+            Cond::NotCarry => write!(f, "nc"),
+            Cond::ParityOdd => write!(f, "po"),
+            Cond::ParityEven => write!(f, "pe"),
+            Cond::Positive => write!(f, "p"),
+            Cond::Negative => write!(f, "m"),
             _ => write!(f, ""),
         }
     }
@@ -80,15 +99,12 @@ pub enum Arg8 {
     Register(Reg8),
     Immediate(Data8),
     Memory(Address),
+    /// Result also copied to a register, only the ddcb/fdcb page has these
+    Copy(Box<Arg8>, Reg8),
 }
 impl fmt::Display for Arg8 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        use self::Arg8::*;
-        match *self {
-            Register(reg) => write!(f, "{}", reg),
-            Immediate(ref imm) => write!(f, "{}", imm), 
-            Memory(ref addr) => write!(f, "({})", addr), 
-        }
+        f.write_str(&Syntax::default().arg8(self))
     }
 }
 
@@ -100,12 +116,7 @@ pub enum Arg16 {
 }
 impl fmt::Display for Arg16 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        use self::Arg16::*;
-        match *self {
-            Register(reg) => write!(f, "{:?}", reg),
-            Immediate(ref imm) => write!(f, "{}", imm), 
-            Memory(ref addr) => write!(f, "({})", addr), 
-        }
+        f.write_str(&Syntax::default().arg16(self))
     }
 }
 
@@ -140,15 +151,14 @@ pub enum Instruction {
     DEC8(Arg8),
     DEC16(Arg16),
     DI,
-    DJNZ(Arg8),
+    DJNZ(Rel),
     EI,
     EX(Arg16, Arg16),
     EXX,
     HALT,
-    IM,
+    IM(u8),
     IN(Arg8, Arg8),
     INC8(Arg8),
-    INC8_MEMORY(Address),
     INC16(Arg16),
     IND,
     INDR,
@@ -156,8 +166,8 @@ pub enum Instruction {
     INIR,
     JP(Address),
     JP_COND(Cond, Address),
-    JR(Address),
-    JR_COND(Cond, u8),
+    JR(Rel),
+    JR_COND(Cond, Rel),
     LD8(Arg8, Arg8),
     LD16(Arg16, Arg16),
     LDD,
@@ -202,57 +212,10 @@ pub enum Instruction {
     XOR(Arg8),
 }
 
+/// Relative jumps show as `$+n`; `Syntax::format` can give the address
 impl fmt::Display for Instruction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
-            Instruction::ADD8(ref d, ref s) => write!(f, "add {},{}", d, s),
-            Instruction::ADC8(ref d, ref s) => write!(f, "adc {},{}", d, s),
-            Instruction::ADD16(ref d, ref s) => write!(f, "add {},{}", d, s),
-            Instruction::ADC16(ref d, ref s) => write!(f, "adc {},{}", d, s),
-            Instruction::AND(ref val) => write!(f, "and {}", val),
-            Instruction::BIT(ref b, ref r) => write!(f, "bit {},{}", b, r),
-            Instruction::CP(ref val) => write!(f, "cp {}", val),
-            Instruction::CALL(ref addr) => write!(f, "call {}", addr),
-            Instruction::CALL_COND(ref cond, ref addr) => write!(f, "call {},{}", cond, addr),
-            
-            Instruction::DEC8(ref reg) => write!(f, "dec {}", reg),
-            Instruction::DEC16(ref reg) => write!(f, "dec {}", reg),
-
-            Instruction::DJNZ(ref val) => write!(f, "djnz {}", val),
-            Instruction::EX(ref a, ref b) => write!(f, "ex {},{}", a, b),
-            Instruction::IN(ref d, ref s) => write!(f, "in {},{}", d, s),
-            Instruction::INC8(ref reg) => write!(f, "inc {}", reg),
-            Instruction::INC8_MEMORY(ref addr) => write!(f, "inc {}", addr),
-            Instruction::INC16(ref reg) => write!(f, "inc {}", reg),
-            Instruction::JP(ref addr) => write!(f, "jp {}", addr),
-            Instruction::JP_COND(ref cond, ref addr) => write!(f, "jp {},{}", cond, addr),
-            Instruction::JR(ref addr) => write!(f, "jr {}", addr),
-            Instruction::JR_COND(ref cond, addr) => write!(f, "jr {},{}", cond, addr as i8),
-            Instruction::LD8(ref d, ref s) => write!(f, "ld {},{}", d, s),
-            Instruction::LD16(ref d, ref s) => write!(f, "ld {},{}", d, s),
-            Instruction::OR(ref val) => write!(f, "or {}", val),
-            Instruction::OUT(ref port, ref val) => write!(f, "out ({}),{}", port, val),
-            Instruction::POP(ref val) => write!(f, "pop {}", val),
-            Instruction::PUSH(ref val) => write!(f, "push {}", val),
-            Instruction::RES(ref b, ref r) => write!(f, "res {},{}", b, r),
-            Instruction::RET => write!(f, "ret"),
-            Instruction::RET_COND(ref cond) => write!(f, "ret {}", cond),
-            Instruction::RL(ref reg) => write!(f, "rl {}", reg),
-            Instruction::RLC(ref reg) => write!(f, "rlc {}", reg),
-            Instruction::RR(ref reg) => write!(f, "rr {}", reg),
-            Instruction::RRC(ref reg) => write!(f, "rrc {}", reg),
-            Instruction::RST(ref byte) => write!(f, "rst {:02x}", byte),
-            Instruction::SBC8(ref s) => write!(f, "sbc a,{}", s),
-            Instruction::SBC16(ref d, ref s) => write!(f, "sbc {},{}", d, s),
-            Instruction::SET(ref b, ref r) => write!(f, "set {},{}", b, r),
-            Instruction::SLA(ref reg) => write!(f, "sla {}", reg),
-            Instruction::SLL(ref reg) => write!(f, "sll {}", reg),
-            Instruction::SRA(ref reg) => write!(f, "sra {}", reg),
-            Instruction::SRL(ref reg) => write!(f, "srl {}", reg),
-            Instruction::SUB8(ref reg) => write!(f, "sub {}", reg),
-            Instruction::XOR(ref reg) => write!(f, "xor {}", reg),
-            _ => write!(f, "{}", format!("{:?}", *self).to_lowercase()),
-        }
+        f.write_str(&Syntax::default().format(self, 0))
     }
 }
 
@@ -0,0 +1,54 @@
+use crate::bus::Bus;
+
+/// Memory the disassembler reads from. Reading must not have side effects,
+/// so it can be a live machine's memory or a file.
+pub trait Memory {
+    fn peek(&self, address: u16) -> u8;
+}
+
+impl<T: Memory + ?Sized> Memory for &T {
+    fn peek(&self, address: u16) -> u8 {
+        (**self).peek(address)
+    }
+}
+
+/// Starts at address 0; anything past the end reads as 0
+impl Memory for [u8] {
+    fn peek(&self, address: u16) -> u8 {
+        self.get(address as usize).copied().unwrap_or(0)
+    }
+}
+
+impl Memory for Vec<u8> {
+    fn peek(&self, address: u16) -> u8 {
+        self.as_slice().peek(address)
+    }
+}
+
+/// Bytes loaded at `base`, like a rom file. Anything outside reads as 0.
+#[derive(Debug, Copy, Clone)]
+pub struct Image<'a> {
+    pub base: u16,
+    pub bytes: &'a [u8],
+}
+
+impl<'a> Image<'a> {
+    pub fn new(base: u16, bytes: &'a [u8]) -> Image<'a> {
+        Image { base, bytes }
+    }
+}
+
+impl Memory for Image<'_> {
+    fn peek(&self, address: u16) -> u8 {
+        self.bytes.peek(address.wrapping_sub(self.base))
+    }
+}
+
+/// A borrowed bus, read with `Bus::memory_read`. Ports are never touched.
+pub struct BusMemory<'a, B: Bus>(pub &'a B);
+
+impl<B: Bus> Memory for BusMemory<'_, B> {
+    fn peek(&self, address: u16) -> u8 {
+        self.0.memory_read(address as usize)
+    }
+}
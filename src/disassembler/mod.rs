@@ -1,35 +1,102 @@
 //
+pub mod decoded;
 pub mod instruction;
+pub mod memory;
+pub mod recursive;
+pub mod syntax;
 pub mod traits;
 
-use crate::bus::Bus;
-pub struct Disassembler {
-    pub bus: Box<dyn Bus>,
-    pub pc: u16
+pub use self::decoded::{DecodedInstruction, Instructions, TStates};
+pub use self::memory::{BusMemory, Image, Memory};
+pub use self::recursive::{parse_symbols, Listing, StaticDisassembler, SymbolError};
+pub use self::syntax::{Case, Hex, Mnemonics, Syntax};
+
+use std::cell::Cell;
+use std::ops::{Bound, RangeBounds};
+
+pub struct Disassembler<'a> {
+    pub memory: Box<dyn Memory + 'a>,
+    pub pc: u16,
+    /// Start of the instruction being decoded
+    start: Cell<u16>,
+    /// Address of the next byte to decode, operands are read in the order they are encoded
+    next: Cell<u16>,
 }
 
-impl Disassembler {
+impl<'a> Disassembler<'a> {
+    pub fn new(memory: impl Memory + 'a, pc: u16) -> Disassembler<'a> {
+        Disassembler { memory: Box::new(memory), pc, start: Cell::new(pc), next: Cell::new(pc.wrapping_add(1)) }
+    }
+
+    /// Decodes the instruction at pc, prefixes and all
+    pub fn instruction(&self) -> Instruction {
+        self.decode_at(self.pc)
+    }
+
+    /// Decodes the instruction at `pc` along with its bytes and timing
+    pub fn disassemble(&self, pc: u16) -> DecodedInstruction {
+        let instruction = self.decode_at(pc);
+        let length = self.next.get().wrapping_sub(pc) as usize;
+        let bytes: Vec<u8> = (0..length).map(|i| self.memory.peek(pc.wrapping_add(i as u16))).collect();
+        DecodedInstruction {
+            address: pc,
+            t_states: decoded::t_states(pc, &bytes),
+            bytes,
+            length,
+            instruction,
+        }
+    }
+
+    /// Linear disassembly of every instruction that starts in `range`
+    pub fn iter(&self, range: impl RangeBounds<u16>) -> Instructions<'_, 'a> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start as u32,
+            Bound::Excluded(&start) => start as u32 + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end as u32 + 1,
+            Bound::Excluded(&end) => end as u32,
+            Bound::Unbounded => 0x10000,
+        };
+        Instructions::new(self, start, end)
+    }
+
+    fn decode_at(&self, pc: u16) -> Instruction {
+        self.start.set(pc);
+        self.next.set(pc);
+        decode(self, self.next_byte())
+    }
+
     pub fn next_byte(&self) -> u8 {
-        self.bus.memory_read((self.pc + 1) as usize)
+        let addr = self.next.get();
+        self.next.set(addr.wrapping_add(1));
+        self.memory.peek(addr)
     }
 
     pub fn next_word(&self) -> u16 {
-        let lo = self.bus.memory_read((self.pc + 1) as usize);
-        let hi = self.bus.memory_read((self.pc + 2) as usize);
+        let lo = self.next_byte();
+        let hi = self.next_byte();
         lo as u16 | ((hi as u16) << 8)
     }
+
+    /// A relative jump whose displacement is the next byte, measured from
+    /// the start of the instruction
+    fn relative(&self) -> Rel {
+        let d = self.next_byte() as i8;
+        Rel(self.next.get().wrapping_sub(self.start.get()) as i16 + d as i16)
+    }
 }
 
-use crate::cpu::{Write8, Write16, Read8, Read16, ReadCond, ImmByte};
+use crate::cpu::{ImmByte, Write8, Write16, Read8, Read16, ReadCond, Indexed};
 use crate::operations::Ops;
-use crate::operations::{decode_cb, decode_dd, decode_ed, decode_fd, decode_dd_fd_cb};
-use crate::registers::Reg16;
+use crate::operations::{decode, decode_cb, decode_dd, decode_ed, decode_fd, decode_dd_fd_cb};
+use crate::registers::{Reg8, Reg16};
 use crate::registers::ReadAddress;
-use self::instruction::Instruction;
-use self::traits::IntoArg8;
+use self::instruction::{Cond, Instruction, Rel};
 
 #[allow(unused)]
-impl<'a> Ops for &'a Disassembler {
+impl Ops for &Disassembler<'_> {
     type R = Instruction;
     fn and<R: Read8>(self, reg: R) -> Self::R {
         Instruction::AND(reg.into_arg8(self))
@@ -80,14 +147,11 @@ impl<'a> Ops for &'a Disassembler {
         Instruction::IN(dest.into_arg8(self), source.into_arg8(self))
     }
     fn in8_noflags<D: Write8, S: Read8>(self, dest: D, source: S) -> Self::R { self.in8(dest, source) }
+    fn in8_flags<S: Read8>(self, source: S) -> Self::R { self.in8(Reg8::F, source) }
 
     fn inc8<R: Write8 + Read8 + Copy>(self, reg: R) -> Self::R { Instruction::INC8(reg.into_arg8(self))}
-    fn inc8_memory<R: ReadAddress>(self, reg: R) -> Self::R {
-
-//        Instruction::INC8_MEMORY(reg.read_address(self).into_address(self))
-        Instruction::NOP
-    }
-    fn dec8_memory<R: ReadAddress>(self, reg: R) -> Self::R{         Instruction::NOP     }
+    fn inc8_memory<R: ReadAddress>(self, reg: R) -> Self::R { Instruction::INC8(reg.into_arg8(self)) }
+    fn dec8_memory<R: ReadAddress>(self, reg: R) -> Self::R { Instruction::DEC8(reg.into_arg8(self)) }
     fn inc16<R: Write16 + Read16 + Copy>(self, reg: R) -> Self::R{
         Instruction::INC16(reg.into_arg16(self))
     }
@@ -96,14 +160,21 @@ impl<'a> Ops for &'a Disassembler {
         Instruction::JP(addr.into_address(self))
     }
 
+    fn jp_reg<A: Read16>(self, reg: A) -> Self::R {
+        self.jp(reg)
+    }
+
     fn jp_cond<C: ReadCond, A: Read16>(self, condition: C, addr: A) -> Self::R{
         Instruction::JP_COND(condition.into_cond(self), addr.into_address(self))
     }
 
     fn jr<C: ReadCond>(self, condition: C) -> Self::R{
-        Instruction::JR_COND(condition.into_cond(self), self.next_byte())
+        match condition.into_cond(self) {
+            Cond::True => Instruction::JR(self.relative()),
+            cond => Instruction::JR_COND(cond, self.relative()),
+        }
     }
-    fn djnz(self) -> Self::R{ Instruction::DJNZ(ImmByte.into_arg8(self)) }
+    fn djnz(self) -> Self::R{ Instruction::DJNZ(self.relative()) }
     fn ret(self) -> Self::R {
         Instruction::RET    
     }
@@ -115,17 +186,23 @@ impl<'a> Ops for &'a Disassembler {
         Instruction::LD8(dest.into_arg8(self), source.into_arg8(self))
     }
     fn ld8_address_dest<D: ReadAddress, S: Read8>(self, dest: D, source: S) -> Self::R{
-        // Instruction::LD8(dest.into_arg8(&self), source.into_arg8(&self))     
-        Instruction::NOP
+        // the displacement comes before an immediate source
+        Instruction::LD8(dest.into_arg8(self), source.into_arg8(self))
+    }
+
+    fn ld8_address_dest_imm<D: ReadAddress>(self, dest: D) -> Self::R{
+        self.ld8_address_dest(dest, ImmByte)
     }
 
     fn ld8_address_source<D: Write8, S: ReadAddress>(self, dest: D, source: S) -> Self::R{
-        // Instruction::LD8(dest.into_arg8(&self), source.into_arg8(&self))     
-        Instruction::NOP
+        Instruction::LD8(dest.into_arg8(self), source.into_arg8(self))
     }
     fn ld16<D: Write16, S: Read16>(self, dest: D, source: S) -> Self::R{
         Instruction::LD16(dest.into_arg16(self), source.into_arg16(self))
     }
+    fn ld16_sp<S: Read16>(self, source: S) -> Self::R{
+        self.ld16(Reg16::SP, source)
+    }
 
     fn nop(self) -> Self::R { Instruction::NOP }
 
@@ -137,6 +214,8 @@ impl<'a> Ops for &'a Disassembler {
         Instruction::OUT(dest.into_arg8(self), source.into_arg8(self))
     }
 
+    fn out8_zero<D: Read8>(self, dest: D) -> Self::R { self.out8(dest, 0) }
+
     fn or<R: Read8>(self, reg: R) -> Self::R {
         Instruction::OR(reg.into_arg8(self))
     }
@@ -181,7 +260,7 @@ impl<'a> Ops for &'a Disassembler {
     fn otir(self) -> Self::R { Instruction::OTIR }
     fn ldd(self) -> Self::R { Instruction::LDD }
     fn ini(self) -> Self::R { Instruction::INI }
-    fn im(self, im: u8) -> Self::R { Instruction::IM }
+    fn im(self, im: u8) -> Self::R { Instruction::IM(im) }
     fn rrd(self) -> Self::R { Instruction::RRD }
     fn rld(self) -> Self::R { Instruction::RLD }
     fn reti(self) -> Self::R { Instruction::RETI }
@@ -218,6 +297,9 @@ impl<'a> Ops for &'a Disassembler {
     fn bit<S: Read8>(self, bit: u8, source: S) -> Self::R {
         Instruction::BIT(bit, source.into_arg8(self))
     }
+    fn bit_memory<S: Read8>(self, bit: u8, source: S) -> Self::R {
+        self.bit(bit, source)
+    }
     fn rl<S: Read8 + Write8 + Copy>(self, source: S) -> Self::R {
         Instruction::RL(source.into_arg8(self))
     }
@@ -236,11 +318,11 @@ impl<'a> Ops for &'a Disassembler {
     fn dd_op(self) -> Self::R{     decode_dd(self, self.next_byte()) }
     fn ed_op(self) -> Self::R{         decode_ed(self, self.next_byte())  }
     fn fd_op(self) -> Self::R{         decode_fd(self, self.next_byte()) }
-    fn dd_fd_cb_op(self, _: Reg16) -> Self::R {
-
-        let _ = self.next_byte();
+    fn dd_fd_cb_op(self, ireg: Reg16) -> Self::R {
+        // dd cb d op, the displacement comes before the opcode
+        let offset = self.next_byte() as i8;
         let op = self.next_byte();
 
-        decode_dd_fd_cb(self, 0, op)
+        decode_dd_fd_cb(self, Indexed(ireg, offset), op)
     }
 }
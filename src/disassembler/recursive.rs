@@ -0,0 +1,256 @@
+//! Recursive descent disassembly. Code is found by following every jump,
+//! call, rst and djnz from the entry points; whatever is never reached is
+//! taken to be data. The result can be written out as an `.asm` file that
+//! assembles back to the same bytes.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::disassembler::decoded::DecodedInstruction;
+use crate::disassembler::instruction::{Address, Data16, Instruction, Rel};
+use crate::disassembler::syntax::Syntax;
+use crate::disassembler::{Disassembler, Memory};
+
+/// Bytes per `db` line
+const DATA_PER_LINE: usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SymbolError {
+    pub line: usize,
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: expected `name equ value` or `name = value`", self.line)
+    }
+}
+
+impl std::error::Error for SymbolError {}
+
+/// Reads `name equ value`, `name: equ value` or `name = value` lines.
+/// Values are `$ffff`, `0xffff`, `0ffffh` or decimal and `;` starts a comment.
+pub fn parse_symbols(text: &str) -> Result<BTreeMap<u16, String>, SymbolError> {
+    let mut symbols = BTreeMap::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = SymbolError { line: i + 1 };
+        let (name, value) = match line.split_once('=') {
+            Some((name, value)) => (name, value),
+            None => {
+                let mut words = line.split_whitespace();
+                match (words.next(), words.next(), words.next(), words.next()) {
+                    (Some(name), Some(equ), Some(value), None) if equ.eq_ignore_ascii_case("equ") => (name, value),
+                    _ => return Err(error),
+                }
+            }
+        };
+        let name = name.trim().trim_end_matches(':');
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(error);
+        }
+        let value = parse_number(value.trim()).ok_or(error)?;
+        symbols.insert(value, name.to_string());
+    }
+    Ok(symbols)
+}
+
+fn parse_number(value: &str) -> Option<u16> {
+    let lower = value.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix('$').or_else(|| lower.strip_prefix("0x")) {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(hex) = lower.strip_suffix('h') {
+        u16::from_str_radix(hex, 16).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+pub struct StaticDisassembler<'a> {
+    disassembler: Disassembler<'a>,
+    range: RangeInclusive<u16>,
+    entries: Vec<u16>,
+    /// Names that replace the generated `L_xxxx` labels
+    pub symbols: BTreeMap<u16, String>,
+}
+
+impl<'a> StaticDisassembler<'a> {
+    /// Disassembles the part of `memory` in `range`; targets outside it are
+    /// named but not followed
+    pub fn new(memory: impl Memory + 'a, range: RangeInclusive<u16>) -> StaticDisassembler<'a> {
+        StaticDisassembler {
+            disassembler: Disassembler::new(memory, *range.start()),
+            range,
+            entries: vec![],
+            symbols: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_entry(&mut self, address: u16) {
+        self.entries.push(address);
+    }
+
+    pub fn run(&self) -> Listing {
+        let mut instructions: BTreeMap<u16, DecodedInstruction> = BTreeMap::new();
+        let mut targets = vec![];
+        let mut work = self.entries.clone();
+
+        while let Some(mut address) = work.pop() {
+            // a target inside an instruction is decoded again from there
+            while self.range.contains(&address) && !instructions.contains_key(&address) {
+                let decoded = self.disassembler.disassemble(address);
+                let (target, continues) = flow(&decoded);
+                if let Some(target) = target {
+                    targets.push(target);
+                    work.push(target);
+                }
+                let next = address.wrapping_add(decoded.length as u16);
+                instructions.insert(address, decoded);
+                if !continues || next < address {
+                    break;
+                }
+                address = next;
+            }
+        }
+
+        let mut labels: BTreeMap<u16, String> =
+            targets.into_iter().chain(self.entries.iter().copied()).map(|t| (t, format!("L_{:04x}", t))).collect();
+        for (address, name) in self.symbols.iter() {
+            labels.insert(*address, name.clone());
+        }
+        let bytes = (0..self.len()).map(|i| self.disassembler.memory.peek(self.range.start().wrapping_add(i as u16))).collect();
+        Listing { start: *self.range.start(), bytes, instructions, labels, syntax: Syntax::default() }
+    }
+
+    fn len(&self) -> usize {
+        (*self.range.end() as usize + 1).saturating_sub(*self.range.start() as usize)
+    }
+}
+
+/// Where an instruction can go: the target it names and whether it can
+/// also carry on to the next instruction
+fn flow(decoded: &DecodedInstruction) -> (Option<u16>, bool) {
+    let relative = |rel: Rel| decoded.address.wrapping_add(rel.0 as u16);
+    match decoded.instruction {
+        Instruction::JP(Address::Direct(Data16(target))) => (Some(target), false),
+        Instruction::JP(_) | Instruction::RET | Instruction::RETI | Instruction::RETN => (None, false),
+        Instruction::JR(rel) => (Some(relative(rel)), false),
+        Instruction::JP_COND(_, Address::Direct(Data16(target)))
+        | Instruction::CALL(Address::Direct(Data16(target)))
+        | Instruction::CALL_COND(_, Address::Direct(Data16(target))) => (Some(target), true),
+        Instruction::JR_COND(_, rel) | Instruction::DJNZ(rel) => (Some(relative(rel)), true),
+        Instruction::RST(vector) => (Some(vector as u16), true),
+        _ => (None, true),
+    }
+}
+
+pub struct Listing {
+    start: u16,
+    bytes: Vec<u8>,
+    /// Decoded code, by address
+    pub instructions: BTreeMap<u16, DecodedInstruction>,
+    /// Branch targets and symbols
+    pub labels: BTreeMap<u16, String>,
+    /// How `write_asm` writes instructions, numbers and directives
+    pub syntax: Syntax,
+}
+
+impl Listing {
+    pub fn is_code(&self, address: u16) -> bool {
+        self.instructions.range(..=address).next_back().is_some_and(|(start, decoded)| {
+            (address - start) < decoded.length as u16
+        })
+    }
+
+    pub fn label(&self, address: u16) -> Option<&str> {
+        self.labels.get(&address).map(|name| name.as_str())
+    }
+
+    /// Writes an `.asm` file. Labels that do not start a line, because they
+    /// are outside the listing or inside an instruction, become `equ`s.
+    pub fn write_asm(&self, out: &mut impl Write) -> io::Result<()> {
+        let end = self.start as usize + self.bytes.len();
+        for (address, name) in self.labels.iter() {
+            let inside = (*address as usize) >= self.start as usize && (*address as usize) < end;
+            if !inside || (self.is_code(*address) && !self.instructions.contains_key(address)) {
+                writeln!(out, "{} {} {}", name, self.syntax.text("equ"), self.syntax.word(*address))?;
+            }
+        }
+        writeln!(out, "\t{} {}", self.syntax.text("org"), self.syntax.word(self.start))?;
+
+        let mut address = self.start as usize;
+        while address < end {
+            if let Some(name) = self.label(address as u16) {
+                writeln!(out, "{}:", name)?;
+            }
+            if let Some(decoded) = self.instructions.get(&(address as u16)) {
+                // code entered in the middle of an instruction, or running
+                // past the end, keeps only the bytes up to there
+                let next = self.instructions.range(address as u16..).nth(1).map_or(end, |(start, _)| (*start as usize).min(end));
+                let len = decoded.length.min(next - address);
+                if len == decoded.length && canonical(decoded) {
+                    writeln!(out, "\t{}", self.render(decoded))?;
+                } else {
+                    writeln!(out, "\t{}\t; {}", self.data(&decoded.bytes[..len]), self.render(decoded))?;
+                }
+                address += len;
+                continue;
+            }
+            // data runs until the next label, code or full line
+            let mut len = 1;
+            while len < DATA_PER_LINE
+                && address + len < end
+                && !self.is_code((address + len) as u16)
+                && !self.labels.contains_key(&((address + len) as u16))
+            {
+                len += 1;
+            }
+            let offset = address - self.start as usize;
+            writeln!(out, "\t{}", self.data(&self.bytes[offset..offset + len]))?;
+            address += len;
+        }
+        Ok(())
+    }
+
+    /// The instruction with branch targets replaced by their labels
+    fn render(&self, decoded: &DecodedInstruction) -> String {
+        self.syntax.format_labelled(&decoded.instruction, decoded.address, &|target| self.label(target).map(str::to_string))
+    }
+
+    /// A `db` line
+    fn data(&self, bytes: &[u8]) -> String {
+        let bytes: Vec<String> = bytes.iter().map(|b| self.syntax.byte(*b)).collect();
+        format!("{} {}", self.syntax.text("db"), bytes.join(","))
+    }
+}
+
+/// Whether assembling the mnemonic gives back the same bytes. Unused
+/// prefixes, the `bit n,(ix+d)` aliases and the ed page duplicates of neg,
+/// retn, im and `ld (nn),hl` do not.
+fn canonical(decoded: &DecodedInstruction) -> bool {
+    let bytes = &decoded.bytes;
+    match bytes[0] {
+        0xdd | 0xfd => {
+            let text = decoded.instruction.to_string();
+            let index = if bytes[0] == 0xdd { "ix" } else { "iy" };
+            let bit_alias = bytes[1] == 0xcb && (0x40..0x80).contains(&bytes[3]) && bytes[3] & 0x07 != 0x06;
+            !matches!(bytes[1], 0xdd | 0xfd | 0xed) && text.contains(index) && !bit_alias
+        }
+        0xed => {
+            let duplicate = matches!(
+                bytes[1],
+                0x4c | 0x54 | 0x5c | 0x64 | 0x6c | 0x74 | 0x7c
+                    | 0x55 | 0x5d | 0x65 | 0x6d | 0x75 | 0x7d
+                    | 0x4e | 0x66 | 0x6e | 0x76 | 0x7e
+                    | 0x63 | 0x6b
+            );
+            let undefined = matches!(decoded.instruction, Instruction::NOP);
+            !duplicate && !undefined
+        }
+        _ => true,
+    }
+}
@@ -0,0 +1,381 @@
+//! How instructions are written out. The default is what `Display` gives:
+//! lower case Zilog mnemonics, `$` hex, signed displacements and relative
+//! jumps as `$+n`.
+
+use crate::disassembler::instruction::{Address, Arg16, Arg8, Cond, Data16, Data8, Instruction, Rel};
+use crate::registers::{Reg16, Reg8};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Case {
+    Lower,
+    Upper,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Hex {
+    /// `$1f`
+    Dollar,
+    /// `0x1f`
+    Prefix,
+    /// `1fh`, with a leading 0 when it would start with a letter
+    Suffix,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mnemonics {
+    Zilog,
+    /// Intel 8080 mnemonics. Instructions the 8080 does not have keep their
+    /// Zilog form.
+    Intel8080,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Syntax {
+    pub case: Case,
+    pub hex: Hex,
+    /// `(ix-5)` rather than `(ix+$fb)`; signed displacements are decimal
+    pub signed_displacement: bool,
+    /// jr and djnz targets as addresses rather than `$+n`
+    pub absolute_relative: bool,
+    pub mnemonics: Mnemonics,
+}
+
+impl Default for Syntax {
+    fn default() -> Syntax {
+        Syntax {
+            case: Case::Lower,
+            hex: Hex::Dollar,
+            signed_displacement: true,
+            absolute_relative: false,
+            mnemonics: Mnemonics::Zilog,
+        }
+    }
+}
+
+impl Syntax {
+    /// The instruction at `address`, which is only needed for absolute
+    /// relative targets
+    pub fn format(&self, instruction: &Instruction, address: u16) -> String {
+        self.format_labelled(instruction, address, &|_| None)
+    }
+
+    /// Like `format`, with jump and call targets that `label` names written
+    /// as the name
+    pub fn format_labelled(&self, instruction: &Instruction, address: u16, label: &dyn Fn(u16) -> Option<String>) -> String {
+        let formatter = Formatter { syntax: self, address, label };
+        match self.mnemonics {
+            Mnemonics::Intel8080 => formatter.intel(instruction).unwrap_or_else(|| formatter.zilog(instruction)),
+            Mnemonics::Zilog => formatter.zilog(instruction),
+        }
+    }
+
+    pub fn byte(&self, value: u8) -> String {
+        self.hex(format!("{:02x}", value))
+    }
+
+    pub fn word(&self, value: u16) -> String {
+        self.hex(format!("{:04x}", value))
+    }
+
+    /// Mnemonics, registers and directives in the configured case
+    pub fn text(&self, text: &str) -> String {
+        match self.case {
+            Case::Lower => text.to_string(),
+            Case::Upper => text.to_uppercase(),
+        }
+    }
+
+    pub(crate) fn arg8(&self, arg: &Arg8) -> String {
+        Formatter { syntax: self, address: 0, label: &|_| None }.arg8(arg)
+    }
+
+    pub(crate) fn arg16(&self, arg: &Arg16) -> String {
+        Formatter { syntax: self, address: 0, label: &|_| None }.arg16(arg)
+    }
+
+    pub(crate) fn address(&self, address: &Address) -> String {
+        Formatter { syntax: self, address: 0, label: &|_| None }.address(address)
+    }
+
+    fn hex(&self, digits: String) -> String {
+        let digits = self.text(&digits);
+        match self.hex {
+            Hex::Dollar => format!("${}", digits),
+            Hex::Prefix => format!("0x{}", digits),
+            Hex::Suffix if digits.starts_with(|c: char| c.is_ascii_alphabetic()) => format!("0{}{}", digits, self.text("h")),
+            Hex::Suffix => format!("{}{}", digits, self.text("h")),
+        }
+    }
+}
+
+struct Formatter<'a> {
+    syntax: &'a Syntax,
+    address: u16,
+    label: &'a dyn Fn(u16) -> Option<String>,
+}
+
+impl Formatter<'_> {
+    fn zilog(&self, instruction: &Instruction) -> String {
+        use self::Instruction::*;
+        let one = |m: &str, a: String| format!("{} {}", self.text(m), a);
+        let two = |m: &str, a: String, b: String| format!("{} {},{}", self.text(m), a, b);
+        match instruction {
+            ADD8(d, s) => two("add", self.arg8(d), self.arg8(s)),
+            ADC8(d, s) => two("adc", self.arg8(d), self.arg8(s)),
+            ADD16(d, s) => two("add", self.arg16(d), self.arg16(s)),
+            ADC16(d, s) => two("adc", self.arg16(d), self.arg16(s)),
+            SBC16(d, s) => two("sbc", self.arg16(d), self.arg16(s)),
+            SUB8(s) => one("sub", self.arg8(s)),
+            SBC8(s) => two("sbc", self.text("a"), self.arg8(s)),
+            AND(s) => one("and", self.arg8(s)),
+            XOR(s) => one("xor", self.arg8(s)),
+            OR(s) => one("or", self.arg8(s)),
+            CP(s) => one("cp", self.arg8(s)),
+            INC8(r) => one("inc", self.arg8(r)),
+            DEC8(r) => one("dec", self.arg8(r)),
+            INC16(r) => one("inc", self.arg16(r)),
+            DEC16(r) => one("dec", self.arg16(r)),
+            LD8(d, s) => two("ld", self.arg8(d), self.arg8(s)),
+            LD16(d, s) => two("ld", self.arg16(d), self.arg16(s)),
+            EX(a, b) => two("ex", self.arg16(a), self.arg16(b)),
+            PUSH(r) => one("push", self.arg16(r)),
+            POP(r) => one("pop", self.arg16(r)),
+            BIT(b, r) => two("bit", b.to_string(), self.arg8(r)),
+            RES(b, r) => two("res", b.to_string(), self.arg8(r)),
+            SET(b, r) => two("set", b.to_string(), self.arg8(r)),
+            RLC(r) => one("rlc", self.arg8(r)),
+            RRC(r) => one("rrc", self.arg8(r)),
+            RL(r) => one("rl", self.arg8(r)),
+            RR(r) => one("rr", self.arg8(r)),
+            SLA(r) => one("sla", self.arg8(r)),
+            SRA(r) => one("sra", self.arg8(r)),
+            SLL(r) => one("sll", self.arg8(r)),
+            SRL(r) => one("srl", self.arg8(r)),
+            // the port is either (c) or an immediate
+            IN(d, s) => two("in", self.arg8(d), format!("({})", self.arg8(s))),
+            OUT(p, v) => two("out", format!("({})", self.arg8(p)), self.arg8(v)),
+            IM(mode) => one("im", mode.to_string()),
+            RST(vector) => one("rst", self.syntax.byte(*vector)),
+            JP(Address::Direct(Data16(target))) => one("jp", self.target(*target)),
+            JP(reg) => one("jp", format!("({})", self.address(reg))),
+            JP_COND(c, Address::Direct(Data16(target))) => two("jp", self.cond(c), self.target(*target)),
+            JP_COND(c, a) => two("jp", self.cond(c), self.address(a)),
+            CALL(Address::Direct(Data16(target))) => one("call", self.target(*target)),
+            CALL(a) => one("call", self.address(a)),
+            CALL_COND(c, Address::Direct(Data16(target))) => two("call", self.cond(c), self.target(*target)),
+            CALL_COND(c, a) => two("call", self.cond(c), self.address(a)),
+            RET_COND(c) => one("ret", self.cond(c)),
+            JR(rel) => one("jr", self.relative(*rel)),
+            JR_COND(c, rel) => two("jr", self.cond(c), self.relative(*rel)),
+            DJNZ(rel) => one("djnz", self.relative(*rel)),
+            CCF => self.text("ccf"),
+            CPD => self.text("cpd"),
+            CPDR => self.text("cpdr"),
+            CPI => self.text("cpi"),
+            CPIR => self.text("cpir"),
+            CPL => self.text("cpl"),
+            DAA => self.text("daa"),
+            DI => self.text("di"),
+            EI => self.text("ei"),
+            EXX => self.text("exx"),
+            HALT => self.text("halt"),
+            IND => self.text("ind"),
+            INDR => self.text("indr"),
+            INI => self.text("ini"),
+            INIR => self.text("inir"),
+            LDD => self.text("ldd"),
+            LDDR => self.text("lddr"),
+            LDI => self.text("ldi"),
+            LDIR => self.text("ldir"),
+            NEG => self.text("neg"),
+            NOP => self.text("nop"),
+            OTDR => self.text("otdr"),
+            OTIR => self.text("otir"),
+            OUTD => self.text("outd"),
+            OUTI => self.text("outi"),
+            RET => self.text("ret"),
+            RETI => self.text("reti"),
+            RETN => self.text("retn"),
+            RLA => self.text("rla"),
+            RLCA => self.text("rlca"),
+            RLD => self.text("rld"),
+            RRA => self.text("rra"),
+            RRCA => self.text("rrca"),
+            RRD => self.text("rrd"),
+            SCF => self.text("scf"),
+        }
+    }
+
+    /// The 8080 form, if there is one
+    fn intel(&self, instruction: &Instruction) -> Option<String> {
+        use self::Instruction::*;
+        let one = |m: &str, a: String| Some(format!("{} {}", self.text(m), a));
+        let two = |m: &str, a: String, b: String| Some(format!("{} {},{}", self.text(m), a, b));
+        let text = |m: &str| Some(self.text(m));
+        // alu instructions are `add r` or `adi n`
+        let alu = |register: &str, immediate: &str, s: &Arg8| match s {
+            Arg8::Immediate(Data8(n)) => one(immediate, self.syntax.byte(*n)),
+            s => one(register, self.intel_reg8(s)?),
+        };
+        let direct = |a: &Address| match a {
+            Address::Direct(Data16(target)) => Some(self.target(*target)),
+            _ => None,
+        };
+        match instruction {
+            NOP => text("nop"),
+            HALT => text("hlt"),
+            DI => text("di"),
+            EI => text("ei"),
+            DAA => text("daa"),
+            CPL => text("cma"),
+            SCF => text("stc"),
+            CCF => text("cmc"),
+            RLCA => text("rlc"),
+            RRCA => text("rrc"),
+            RLA => text("ral"),
+            RRA => text("rar"),
+            RET => text("ret"),
+
+            LD8(Arg8::Register(Reg8::A), Arg8::Memory(Address::BC)) => one("ldax", self.text("b")),
+            LD8(Arg8::Register(Reg8::A), Arg8::Memory(Address::DE)) => one("ldax", self.text("d")),
+            LD8(Arg8::Memory(Address::BC), Arg8::Register(Reg8::A)) => one("stax", self.text("b")),
+            LD8(Arg8::Memory(Address::DE), Arg8::Register(Reg8::A)) => one("stax", self.text("d")),
+            LD8(Arg8::Register(Reg8::A), Arg8::Memory(Address::Direct(Data16(nn)))) => one("lda", self.syntax.word(*nn)),
+            LD8(Arg8::Memory(Address::Direct(Data16(nn))), Arg8::Register(Reg8::A)) => one("sta", self.syntax.word(*nn)),
+            LD8(d, Arg8::Immediate(Data8(n))) => two("mvi", self.intel_reg8(d)?, self.syntax.byte(*n)),
+            LD8(d, s) => two("mov", self.intel_reg8(d)?, self.intel_reg8(s)?),
+            LD16(Arg16::Register(Reg16::SP), Arg16::Register(Reg16::HL)) => text("sphl"),
+            LD16(Arg16::Register(r), Arg16::Immediate(Data16(nn))) => two("lxi", self.intel_pair(*r)?, self.syntax.word(*nn)),
+            LD16(Arg16::Register(Reg16::HL), Arg16::Memory(Address::Direct(Data16(nn)))) => one("lhld", self.syntax.word(*nn)),
+            LD16(Arg16::Memory(Address::Direct(Data16(nn))), Arg16::Register(Reg16::HL)) => one("shld", self.syntax.word(*nn)),
+
+            ADD8(Arg8::Register(Reg8::A), s) => alu("add", "adi", s),
+            ADC8(Arg8::Register(Reg8::A), s) => alu("adc", "aci", s),
+            SUB8(s) => alu("sub", "sui", s),
+            SBC8(s) => alu("sbb", "sbi", s),
+            AND(s) => alu("ana", "ani", s),
+            XOR(s) => alu("xra", "xri", s),
+            OR(s) => alu("ora", "ori", s),
+            CP(s) => alu("cmp", "cpi", s),
+            INC8(r) => one("inr", self.intel_reg8(r)?),
+            DEC8(r) => one("dcr", self.intel_reg8(r)?),
+            INC16(Arg16::Register(r)) => one("inx", self.intel_pair(*r)?),
+            DEC16(Arg16::Register(r)) => one("dcx", self.intel_pair(*r)?),
+            ADD16(Arg16::Register(Reg16::HL), Arg16::Register(r)) => one("dad", self.intel_pair(*r)?),
+
+            EX(Arg16::Register(Reg16::DE), Arg16::Register(Reg16::HL)) => text("xchg"),
+            EX(Arg16::Memory(Address::SP), Arg16::Register(Reg16::HL)) => text("xthl"),
+            PUSH(Arg16::Register(Reg16::AF)) => one("push", self.text("psw")),
+            POP(Arg16::Register(Reg16::AF)) => one("pop", self.text("psw")),
+            PUSH(Arg16::Register(r)) if !matches!(r, Reg16::SP) => one("push", self.intel_pair(*r)?),
+            POP(Arg16::Register(r)) if !matches!(r, Reg16::SP) => one("pop", self.intel_pair(*r)?),
+
+            JP(Address::HL) => text("pchl"),
+            JP(a) => one("jmp", direct(a)?),
+            JP_COND(c, a) => one(&format!("j{}", self.intel_cond(c)?), direct(a)?),
+            CALL(a) => one("call", direct(a)?),
+            CALL_COND(c, a) => one(&format!("c{}", self.intel_cond(c)?), direct(a)?),
+            RET_COND(c) => text(&format!("r{}", self.intel_cond(c)?)),
+            RST(vector) => one("rst", (vector / 8).to_string()),
+            IN(Arg8::Register(Reg8::A), Arg8::Immediate(Data8(n))) => one("in", self.syntax.byte(*n)),
+            OUT(Arg8::Immediate(Data8(n)), Arg8::Register(Reg8::A)) => one("out", self.syntax.byte(*n)),
+            _ => None,
+        }
+    }
+
+    fn text(&self, text: &str) -> String {
+        self.syntax.text(text)
+    }
+
+    fn arg8(&self, arg: &Arg8) -> String {
+        match arg {
+            Arg8::Register(reg) => self.text(&reg.to_string()),
+            Arg8::Immediate(Data8(n)) => self.syntax.byte(*n),
+            Arg8::Memory(address) => format!("({})", self.address(address)),
+            Arg8::Copy(arg, reg) => format!("{},{}", self.arg8(arg), self.text(&reg.to_string())),
+        }
+    }
+
+    fn arg16(&self, arg: &Arg16) -> String {
+        match arg {
+            Arg16::Register(reg) => self.text(&reg.to_string()),
+            Arg16::Immediate(Data16(nn)) => self.syntax.word(*nn),
+            Arg16::Memory(address) => format!("({})", self.address(address)),
+        }
+    }
+
+    fn address(&self, address: &Address) -> String {
+        match address {
+            Address::Direct(Data16(nn)) => self.syntax.word(*nn),
+            Address::BC => self.text("bc"),
+            Address::DE => self.text("de"),
+            Address::HL => self.text("hl"),
+            Address::SP => self.text("sp"),
+            Address::IX => self.text("ix"),
+            Address::IY => self.text("iy"),
+            Address::ZeroPage(Data8(n)) => self.syntax.byte(*n),
+            Address::RelOffset(Data16(base), Data8(offset)) => format!("{}+{}", self.syntax.word(*base), self.syntax.byte(*offset)),
+            Address::Indexed(reg, offset) => {
+                let reg = self.text(if let Reg16::IY = reg { "iy" } else { "ix" });
+                if !self.syntax.signed_displacement {
+                    format!("{}+{}", reg, self.syntax.byte(*offset as u8))
+                } else if *offset < 0 {
+                    format!("{}-{}", reg, offset.unsigned_abs())
+                } else {
+                    format!("{}+{}", reg, offset)
+                }
+            }
+        }
+    }
+
+    fn cond(&self, cond: &Cond) -> String {
+        self.text(&cond.to_string())
+    }
+
+    fn target(&self, target: u16) -> String {
+        (self.label)(target).unwrap_or_else(|| self.syntax.word(target))
+    }
+
+    fn relative(&self, rel: Rel) -> String {
+        let target = self.address.wrapping_add(rel.0 as u16);
+        match (self.label)(target) {
+            Some(label) => label,
+            None if self.syntax.absolute_relative => self.syntax.word(target),
+            None if rel.0 < 0 => format!("$-{}", rel.0.unsigned_abs()),
+            None => format!("$+{}", rel.0),
+        }
+    }
+
+    /// b, c, d, e, h, l, m for (hl) or a
+    fn intel_reg8(&self, arg: &Arg8) -> Option<String> {
+        match arg {
+            Arg8::Register(reg @ Reg8::A)
+            | Arg8::Register(reg @ Reg8::B)
+            | Arg8::Register(reg @ Reg8::C)
+            | Arg8::Register(reg @ Reg8::D)
+            | Arg8::Register(reg @ Reg8::E)
+            | Arg8::Register(reg @ Reg8::H)
+            | Arg8::Register(reg @ Reg8::L) => Some(self.text(&reg.to_string())),
+            Arg8::Memory(Address::HL) => Some(self.text("m")),
+            _ => None,
+        }
+    }
+
+    /// b, d, h or sp
+    fn intel_pair(&self, reg: Reg16) -> Option<String> {
+        match reg {
+            Reg16::BC => Some(self.text("b")),
+            Reg16::DE => Some(self.text("d")),
+            Reg16::HL => Some(self.text("h")),
+            Reg16::SP => Some(self.text("sp")),
+            _ => None,
+        }
+    }
+
+    fn intel_cond(&self, cond: &Cond) -> Option<String> {
+        match cond {
+            Cond::True | Cond::False => None,
+            cond => Some(cond.to_string()),
+        }
+    }
+}
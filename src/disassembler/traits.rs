@@ -2,7 +2,7 @@ use crate::disassembler::instruction::{Address, Data8, Data16, Cond, Arg8, Arg16
 use crate::disassembler::Disassembler;
 
 use crate::registers::{Reg8, Reg16};
-use crate::cpu::{Not, ImmByte, ImmWord, Mem, RelOffset};
+use crate::cpu::{Not, ImmByte, ImmWord, Mem, RelOffset, Indexed, CopyTo, Read8, Write8};
 use crate::flags::Flag;
 
 pub trait IntoArg8 {
@@ -57,9 +57,9 @@ impl IntoArg8 for Mem<ImmWord> {
 }
 
 impl IntoArg8 for Mem<RelOffset<Reg16>> {
-    fn into_arg8(self, _disassembler: &Disassembler) -> Arg8 {
-        let address = Address::BC; // @todo not correct
-        Arg8::Memory(address)
+    fn into_arg8(self, disassembler: &Disassembler) -> Arg8 {
+        let Mem(offset) = self;
+        Arg8::Memory(offset.into_address(disassembler))
     }
 }
 
@@ -72,6 +72,32 @@ impl IntoArg8 for Mem<RelOffset<u16>> {
     }
 }
 
+impl IntoArg8 for Mem<Indexed> {
+    fn into_arg8(self, disassembler: &Disassembler) -> Arg8 {
+        let Mem(indexed) = self;
+        Arg8::Memory(indexed.into_address(disassembler))
+    }
+}
+
+impl<T: Read8 + Write8> IntoArg8 for CopyTo<T> {
+    fn into_arg8(self, disassembler: &Disassembler) -> Arg8 {
+        let CopyTo(mem, reg) = self;
+        Arg8::Copy(Box::new(mem.into_arg8(disassembler)), reg)
+    }
+}
+
+impl IntoAddress for Indexed {
+    fn into_address(self, _disassembler: &Disassembler) -> Address {
+        let Indexed(reg, offset) = self;
+        Address::Indexed(reg, offset)
+    }
+}
+
+impl IntoArg16 for Indexed {
+    fn into_arg16(self, disassembler: &Disassembler) -> Arg16 {
+        Arg16::Memory(self.into_address(disassembler))
+    }
+}
 
 impl IntoAddress for Reg16 {
     fn into_address(self, _disassembler: &Disassembler) -> Address {
@@ -79,6 +105,9 @@ impl IntoAddress for Reg16 {
             Reg16::BC => Address::BC,
             Reg16::DE => Address::DE,
             Reg16::HL => Address::HL,
+            Reg16::SP => Address::SP,
+            Reg16::IX => Address::IX,
+            Reg16::IY => Address::IY,
             _ => panic!("invalid address register: {:?}", self)
         }
     }
@@ -114,20 +143,16 @@ impl IntoAddress for Mem<Reg16> {
 }
 
 impl IntoAddress for Mem<RelOffset<Reg16>> {
-    fn into_address(self, _disassembler: &Disassembler) -> Address {
-        panic!("should not be called, i think")
-        // let Mem(RelOffset(reg)) = self;
-        // let offset = disassembler.next_byte();
-        // let address = Address::RelOffset(Data16(addr), Data8(offset)); // @todo not correct
-        // Arg8::Memory(address)
+    fn into_address(self, disassembler: &Disassembler) -> Address {
+        let Mem(offset) = self;
+        offset.into_address(disassembler)
     }
 }
 
 impl IntoAddress for RelOffset<Reg16> {
     fn into_address(self, disassembler: &Disassembler) -> Address {
-        let RelOffset(_reg) = self;
-        let offset = disassembler.next_byte();
-        Address::RelOffset(Data16(1), Data8(offset)) // @todo not correct, should show reg
+        let RelOffset(reg) = self;
+        Address::Indexed(reg, disassembler.next_byte() as i8)
     }
 }
 
@@ -209,7 +234,8 @@ impl IntoCond for Flag {
         match self {
             Flag::Carry => Cond::Carry,
             Flag::Zero => Cond::Zero,
-            Flag::Subtract => Cond::Negative,
+            Flag::Parity => Cond::ParityEven,
+            Flag::Sign => Cond::Negative,
             _ => unreachable!("invalid cond"),
         }
     }
@@ -221,7 +247,8 @@ impl IntoCond for Not<Flag> {
         match flag {
             Flag::Carry => Cond::NotCarry,
             Flag::Zero => Cond::NotZero,
-            Flag::Subtract => Cond::Positive,
+            Flag::Parity => Cond::ParityOdd,
+            Flag::Sign => Cond::Positive,
             _ => unreachable!("invalid cond"),
         }
     }
@@ -27,6 +27,7 @@ impl Flag {
     }
 
     pub fn write(self, registers: &mut Registers, val: bool) {
+        registers.flags_written = true;
         let flags = registers.f;
         match self {
             Flag::Sign => registers.f = if val { set_bit(flags, 7) } else { reset_bit(flags, 7)},
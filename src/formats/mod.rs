@@ -0,0 +1,138 @@
+//! ZX Spectrum snapshot files, `.sna` and `.z80`
+
+pub mod sna;
+pub mod z80;
+
+use std::fmt;
+
+use crate::cpu::Z80;
+use crate::state::Z80State;
+
+pub const PAGE_SIZE: usize = 0x4000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Machine {
+    Spectrum48,
+    Spectrum128,
+}
+
+impl Machine {
+    pub fn ram_size(self) -> usize {
+        match self {
+            Machine::Spectrum48 => 3 * PAGE_SIZE,
+            Machine::Spectrum128 => 8 * PAGE_SIZE,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The file ends before its header or memory does
+    Truncated,
+    /// Not a size any `.sna` can have
+    BadLength(usize),
+    /// `.z80` hardware mode that is neither a 48K nor a 128K
+    UnsupportedHardware(u8),
+    /// A compressed `.z80` block that does not expand to a page
+    BadBlock,
+    /// The 48K `.sna` keeps pc on the stack, which has to be in ram
+    StackInRom,
+    /// Version 1 `.z80` files only hold a 48K
+    NotSpectrum48,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::BadLength(len) => write!(f, "{} bytes is not a valid .sna size", len),
+            SnapshotError::UnsupportedHardware(mode) => write!(f, "unsupported .z80 hardware mode {}", mode),
+            SnapshotError::BadBlock => write!(f, "compressed block does not expand to 16K"),
+            SnapshotError::StackInRom => write!(f, "stack pointer is in rom"),
+            SnapshotError::NotSpectrum48 => write!(f, "version 1 .z80 files only hold a 48K"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+/// A Spectrum as stored in a snapshot file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub machine: Machine,
+    pub state: Z80State,
+    /// 48K: the ram from 0x4000 up. 128K: the eight banks in order.
+    pub ram: Vec<u8>,
+    pub border: u8,
+    /// Last write to port 0x7ffd, 128K only
+    pub port_7ffd: u8,
+}
+
+impl Snapshot {
+    /// A machine with cleared ram and the cpu state of `cpu`
+    pub fn new(machine: Machine, cpu: &Z80) -> Snapshot {
+        Snapshot {
+            machine,
+            state: cpu.snapshot(),
+            ram: vec![0; machine.ram_size()],
+            border: 0,
+            port_7ffd: 0,
+        }
+    }
+
+    /// Puts the snapshot's registers into `cpu`
+    pub fn restore(&self, cpu: &mut Z80) {
+        cpu.restore(&self.state);
+    }
+
+    /// 128K ram bank `n`
+    pub fn bank(&self, n: usize) -> &[u8] {
+        &self.ram[n * PAGE_SIZE..(n + 1) * PAGE_SIZE]
+    }
+
+    pub fn bank_mut(&mut self, n: usize) -> &mut [u8] {
+        &mut self.ram[n * PAGE_SIZE..(n + 1) * PAGE_SIZE]
+    }
+
+    /// The bank a 128K has paged in at 0xc000
+    pub fn paged_bank(&self) -> usize {
+        (self.port_7ffd & 0x07) as usize
+    }
+
+    /// The 64K the cpu sees, with `rom` (up to 16K of it) at the bottom
+    pub fn memory(&self, rom: &[u8]) -> Vec<u8> {
+        let mut memory = vec![0; 0x10000];
+        let len = rom.len().min(PAGE_SIZE);
+        memory[..len].copy_from_slice(&rom[..len]);
+        match self.machine {
+            Machine::Spectrum48 => memory[PAGE_SIZE..].copy_from_slice(&self.ram),
+            Machine::Spectrum128 => {
+                for (slot, bank) in [5, 2, self.paged_bank()].iter().enumerate() {
+                    let start = (slot + 1) * PAGE_SIZE;
+                    memory[start..start + PAGE_SIZE].copy_from_slice(self.bank(*bank));
+                }
+            }
+        }
+        memory
+    }
+
+    /// Byte at a cpu address in 0x4000-0xffff
+    fn peek(&self, addr: u16) -> Result<u8, SnapshotError> {
+        let index = (addr as usize).checked_sub(PAGE_SIZE).ok_or(SnapshotError::StackInRom)?;
+        Ok(self.ram[index])
+    }
+
+    fn poke(&mut self, addr: u16, val: u8) -> Result<(), SnapshotError> {
+        let index = (addr as usize).checked_sub(PAGE_SIZE).ok_or(SnapshotError::StackInRom)?;
+        self.ram[index] = val;
+        Ok(())
+    }
+}
+
+fn word(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn put_word(bytes: &mut Vec<u8>, val: u16) {
+    bytes.extend_from_slice(&val.to_le_bytes());
+}
@@ -0,0 +1,130 @@
+//! `.sna`, a 27 byte register header followed by the ram.
+//!
+//! The 48K version keeps pc on the stack. The 128K version stores it
+//! after the first 48K, followed by port 0x7ffd and the remaining banks.
+
+use super::{put_word, word, Machine, Snapshot, SnapshotError, PAGE_SIZE};
+use crate::state::Z80State;
+
+const HEADER: usize = 27;
+pub const LEN_48K: usize = HEADER + 3 * PAGE_SIZE;
+/// 128K with a bank other than 2 or 5 paged in
+pub const LEN_128K: usize = LEN_48K + 4 + 5 * PAGE_SIZE;
+/// 128K with bank 2 or 5 paged in, which is then stored twice
+pub const LEN_128K_DUPLICATE: usize = LEN_48K + 4 + 6 * PAGE_SIZE;
+
+pub fn load(bytes: &[u8]) -> Result<Snapshot, SnapshotError> {
+    let machine = match bytes.len() {
+        LEN_48K => Machine::Spectrum48,
+        LEN_128K | LEN_128K_DUPLICATE => Machine::Spectrum128,
+        len if len < HEADER => return Err(SnapshotError::Truncated),
+        len => return Err(SnapshotError::BadLength(len)),
+    };
+
+    let iff = bytes[19] & 0x04 != 0;
+    let state = Z80State {
+        i: bytes[0],
+        alt_hl: word(bytes, 1),
+        alt_de: word(bytes, 3),
+        alt_bc: word(bytes, 5),
+        alt_af: word(bytes, 7),
+        hl: word(bytes, 9),
+        de: word(bytes, 11),
+        bc: word(bytes, 13),
+        iy: word(bytes, 15),
+        ix: word(bytes, 17),
+        iff1: iff,
+        iff2: iff,
+        r: bytes[20],
+        af: word(bytes, 21),
+        sp: word(bytes, 23),
+        interrupt_mode: bytes[25] & 0x03,
+        ..Z80State::default()
+    };
+    let mut snapshot = Snapshot {
+        machine,
+        state,
+        ram: vec![0; machine.ram_size()],
+        border: bytes[26] & 0x07,
+        port_7ffd: 0,
+    };
+    let ram = &bytes[HEADER..LEN_48K];
+
+    match machine {
+        Machine::Spectrum48 => {
+            snapshot.ram.copy_from_slice(ram);
+            // retn
+            let sp = snapshot.state.sp;
+            let lo = snapshot.peek(sp)?;
+            let hi = snapshot.peek(sp.wrapping_add(1))?;
+            snapshot.state.pc = u16::from_le_bytes([lo, hi]);
+            snapshot.state.sp = sp.wrapping_add(2);
+        }
+        Machine::Spectrum128 => {
+            let extra = &bytes[LEN_48K..];
+            snapshot.state.pc = word(extra, 0);
+            snapshot.port_7ffd = extra[2];
+            let paged = snapshot.paged_bank();
+            for (i, bank) in [5, 2, paged].iter().enumerate() {
+                snapshot.bank_mut(*bank).copy_from_slice(&ram[i * PAGE_SIZE..(i + 1) * PAGE_SIZE]);
+            }
+            let rest = remaining_banks(paged);
+            if extra.len() != 4 + rest.len() * PAGE_SIZE {
+                return Err(SnapshotError::BadLength(bytes.len()));
+            }
+            for (i, bank) in rest.iter().enumerate() {
+                let start = 4 + i * PAGE_SIZE;
+                snapshot.bank_mut(*bank).copy_from_slice(&extra[start..start + PAGE_SIZE]);
+            }
+        }
+    }
+    Ok(snapshot)
+}
+
+/// Saves `snapshot`; a 48K gets pc pushed on its stack, the way the format expects
+pub fn save(snapshot: &Snapshot) -> Result<Vec<u8>, SnapshotError> {
+    let mut snapshot = snapshot.clone();
+    if snapshot.machine == Machine::Spectrum48 {
+        let sp = snapshot.state.sp.wrapping_sub(2);
+        let [lo, hi] = snapshot.state.pc.to_le_bytes();
+        snapshot.poke(sp, lo)?;
+        snapshot.poke(sp.wrapping_add(1), hi)?;
+        snapshot.state.sp = sp;
+    }
+
+    let state = &snapshot.state;
+    let mut bytes = Vec::with_capacity(LEN_128K_DUPLICATE);
+    bytes.push(state.i);
+    for word in [state.alt_hl, state.alt_de, state.alt_bc, state.alt_af, state.hl, state.de, state.bc, state.iy, state.ix].iter() {
+        put_word(&mut bytes, *word);
+    }
+    bytes.push(if state.iff2 { 0x04 } else { 0 });
+    bytes.push(state.r);
+    put_word(&mut bytes, state.af);
+    put_word(&mut bytes, state.sp);
+    bytes.push(state.interrupt_mode);
+    bytes.push(snapshot.border);
+
+    match snapshot.machine {
+        Machine::Spectrum48 => bytes.extend_from_slice(&snapshot.ram),
+        Machine::Spectrum128 => {
+            let paged = snapshot.paged_bank();
+            for bank in [5, 2, paged].iter() {
+                bytes.extend_from_slice(snapshot.bank(*bank));
+            }
+            put_word(&mut bytes, state.pc);
+            bytes.push(snapshot.port_7ffd);
+            // tr-dos rom not paged
+            bytes.push(0);
+            for bank in remaining_banks(paged) {
+                bytes.extend_from_slice(snapshot.bank(bank));
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+/// Banks stored after the first 48K, in ascending order
+fn remaining_banks(paged: usize) -> Vec<usize> {
+    (0..8).filter(|&bank| bank != 5 && bank != 2 && bank != paged).collect()
+}
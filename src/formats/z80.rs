@@ -0,0 +1,252 @@
+//! `.z80`, as written by Gerton Lunter's Z80 emulator and most since.
+//!
+//! Version 1 has a 30 byte header and a 48K image. Versions 2 and 3 set pc
+//! in that header to 0 and add an extended header, followed by one block
+//! per 16K page. Memory may be compressed with `ED ED nn bb` runs.
+
+use super::{put_word, word, Machine, Snapshot, SnapshotError, PAGE_SIZE};
+use crate::state::Z80State;
+
+const HEADER: usize = 30;
+const V2_EXTRA: usize = 23;
+const V3_EXTRA: usize = 54;
+/// Ends a compressed version 1 image
+const V1_END_MARKER: [u8; 4] = [0x00, 0xed, 0xed, 0x00];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Version {
+    V1,
+    V2,
+    V3,
+}
+
+pub fn load(bytes: &[u8]) -> Result<Snapshot, SnapshotError> {
+    if bytes.len() < HEADER {
+        return Err(SnapshotError::Truncated);
+    }
+    // some old files store 255 here, which means 1
+    let flags = if bytes[12] == 0xff { 1 } else { bytes[12] };
+    let state = Z80State {
+        af: u16::from_be_bytes([bytes[0], bytes[1]]),
+        bc: word(bytes, 2),
+        hl: word(bytes, 4),
+        pc: word(bytes, 6),
+        sp: word(bytes, 8),
+        i: bytes[10],
+        r: (bytes[11] & 0x7f) | ((flags & 0x01) << 7),
+        de: word(bytes, 13),
+        alt_bc: word(bytes, 15),
+        alt_de: word(bytes, 17),
+        alt_hl: word(bytes, 19),
+        alt_af: u16::from_be_bytes([bytes[21], bytes[22]]),
+        iy: word(bytes, 23),
+        ix: word(bytes, 25),
+        iff1: bytes[27] != 0,
+        iff2: bytes[28] != 0,
+        interrupt_mode: bytes[29] & 0x03,
+        ..Z80State::default()
+    };
+    let border = (flags >> 1) & 0x07;
+
+    if state.pc != 0 {
+        let mut snapshot = Snapshot { machine: Machine::Spectrum48, state, ram: vec![], border, port_7ffd: 0 };
+        let data = &bytes[HEADER..];
+        snapshot.ram = if flags & 0x20 != 0 {
+            decompress(data, 3 * PAGE_SIZE)?
+        } else {
+            data.get(..3 * PAGE_SIZE).ok_or(SnapshotError::Truncated)?.to_vec()
+        };
+        return Ok(snapshot);
+    }
+
+    let extra = word(bytes.get(HEADER..HEADER + 2).ok_or(SnapshotError::Truncated)?, 0) as usize;
+    let header = bytes.get(HEADER + 2..HEADER + 2 + extra).ok_or(SnapshotError::Truncated)?;
+    if header.len() < 4 {
+        return Err(SnapshotError::Truncated);
+    }
+    let mode = header[2];
+    let machine = match (extra == V2_EXTRA, mode) {
+        // 48K, with interface 1 or an mgt
+        (_, 0) | (_, 1) | (false, 3) => Machine::Spectrum48,
+        // 128K, with interface 1 or an mgt
+        (true, 3) | (true, 4) | (false, 4..=6) => Machine::Spectrum128,
+        _ => return Err(SnapshotError::UnsupportedHardware(mode)),
+    };
+    let mut snapshot = Snapshot {
+        machine,
+        state: Z80State { pc: word(header, 0), ..state },
+        ram: vec![0; machine.ram_size()],
+        border,
+        port_7ffd: if machine == Machine::Spectrum128 { header[3] } else { 0 },
+    };
+
+    let mut data = &bytes[HEADER + 2 + extra..];
+    while !data.is_empty() {
+        if data.len() < 3 {
+            return Err(SnapshotError::Truncated);
+        }
+        let len = word(data, 0);
+        let page = data[2];
+        data = &data[3..];
+        let (page_data, len) = if len == 0xffff {
+            (data.get(..PAGE_SIZE).ok_or(SnapshotError::Truncated)?.to_vec(), PAGE_SIZE)
+        } else {
+            let block = data.get(..len as usize).ok_or(SnapshotError::Truncated)?;
+            (decompress_block(block)?, len as usize)
+        };
+        data = &data[len..];
+
+        if let Some(offset) = page_offset(machine, page) {
+            snapshot.ram[offset..offset + PAGE_SIZE].copy_from_slice(&page_data);
+        }
+    }
+    Ok(snapshot)
+}
+
+pub fn save(snapshot: &Snapshot, version: Version) -> Result<Vec<u8>, SnapshotError> {
+    let state = &snapshot.state;
+    if version == Version::V1 && snapshot.machine != Machine::Spectrum48 {
+        return Err(SnapshotError::NotSpectrum48);
+    }
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&state.af.to_be_bytes());
+    put_word(&mut bytes, state.bc);
+    put_word(&mut bytes, state.hl);
+    put_word(&mut bytes, if version == Version::V1 { state.pc } else { 0 });
+    put_word(&mut bytes, state.sp);
+    bytes.push(state.i);
+    bytes.push(state.r & 0x7f);
+    let compressed = if version == Version::V1 { 0x20 } else { 0 };
+    bytes.push((state.r >> 7) | ((snapshot.border & 0x07) << 1) | compressed);
+    put_word(&mut bytes, state.de);
+    put_word(&mut bytes, state.alt_bc);
+    put_word(&mut bytes, state.alt_de);
+    put_word(&mut bytes, state.alt_hl);
+    bytes.extend_from_slice(&state.alt_af.to_be_bytes());
+    put_word(&mut bytes, state.iy);
+    put_word(&mut bytes, state.ix);
+    bytes.push(state.iff1 as u8);
+    bytes.push(state.iff2 as u8);
+    bytes.push(state.interrupt_mode & 0x03);
+
+    if version == Version::V1 {
+        bytes.extend(compress(&snapshot.ram));
+        bytes.extend_from_slice(&V1_END_MARKER);
+        return Ok(bytes);
+    }
+
+    let extra = if version == Version::V2 { V2_EXTRA } else { V3_EXTRA };
+    let mode = match (snapshot.machine, version) {
+        (Machine::Spectrum48, _) => 0,
+        (Machine::Spectrum128, Version::V2) => 3,
+        (Machine::Spectrum128, _) => 4,
+    };
+    put_word(&mut bytes, extra as u16);
+    put_word(&mut bytes, state.pc);
+    bytes.push(mode);
+    bytes.push(snapshot.port_7ffd);
+    // interface 1, emulation flags, sound chip registers and, for version
+    // 3, the T-state counter and peripherals are all left at zero
+    bytes.resize(HEADER + 2 + extra, 0);
+
+    let pages: Vec<u8> = match snapshot.machine {
+        Machine::Spectrum48 => vec![4, 5, 8],
+        Machine::Spectrum128 => (3..11).collect(),
+    };
+    for page in pages {
+        let offset = page_offset(snapshot.machine, page).unwrap_or(0);
+        let data = &snapshot.ram[offset..offset + PAGE_SIZE];
+        let block = compress(data);
+        // version 3 can store a page that does not compress as it is
+        if version == Version::V3 && block.len() >= PAGE_SIZE {
+            put_word(&mut bytes, 0xffff);
+            bytes.push(page);
+            bytes.extend_from_slice(data);
+        } else {
+            put_word(&mut bytes, block.len() as u16);
+            bytes.push(page);
+            bytes.extend(block);
+        }
+    }
+    Ok(bytes)
+}
+
+/// Where a page number goes in `Snapshot::ram`; rom pages have nowhere to go
+fn page_offset(machine: Machine, page: u8) -> Option<usize> {
+    match (machine, page) {
+        (Machine::Spectrum48, 8) => Some(0),
+        (Machine::Spectrum48, 4) => Some(PAGE_SIZE),
+        (Machine::Spectrum48, 5) => Some(2 * PAGE_SIZE),
+        (Machine::Spectrum128, 3..=10) => Some((page as usize - 3) * PAGE_SIZE),
+        _ => None,
+    }
+}
+
+/// Runs of five or more equal bytes, and of two or more `ED`s, become
+/// `ED ED nn bb`. The byte after a single `ED` is never part of a run.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let b = data[i];
+        let run = data[i..].iter().take(255).take_while(|&&x| x == b).count();
+        if run >= 5 || (b == 0xed && run >= 2) {
+            out.extend_from_slice(&[0xed, 0xed, run as u8, b]);
+            i += run;
+        } else if b == 0xed {
+            out.push(b);
+            i += 1;
+            if i < data.len() {
+                out.push(data[i]);
+                i += 1;
+            }
+        } else {
+            out.push(b);
+            i += 1;
+        }
+    }
+    out
+}
+
+/// Expands `data` until `len` bytes have come out
+pub fn decompress(data: &[u8], len: usize) -> Result<Vec<u8>, SnapshotError> {
+    let mut out = Vec::with_capacity(len);
+    let mut i = 0;
+    while out.len() < len {
+        match data.get(i..i + 2) {
+            Some([0xed, 0xed]) => {
+                let run = data.get(i + 2..i + 4).ok_or(SnapshotError::Truncated)?;
+                out.extend(std::iter::repeat_n(run[1], run[0] as usize));
+                i += 4;
+            }
+            _ => {
+                out.push(*data.get(i).ok_or(SnapshotError::Truncated)?);
+                i += 1;
+            }
+        }
+    }
+    if out.len() != len {
+        return Err(SnapshotError::BadBlock);
+    }
+    Ok(out)
+}
+
+/// A version 2 or 3 page, which has to expand to exactly 16K
+fn decompress_block(block: &[u8]) -> Result<Vec<u8>, SnapshotError> {
+    let mut out = Vec::with_capacity(PAGE_SIZE);
+    let mut i = 0;
+    while i < block.len() {
+        if block[i..].starts_with(&[0xed, 0xed]) && i + 4 <= block.len() {
+            out.extend(std::iter::repeat_n(block[i + 3], block[i + 2] as usize));
+            i += 4;
+        } else {
+            out.push(block[i]);
+            i += 1;
+        }
+    }
+    if out.len() != PAGE_SIZE {
+        return Err(SnapshotError::BadBlock);
+    }
+    Ok(out)
+}
@@ -0,0 +1,311 @@
+//! A GDB Remote Serial Protocol stub, so guest code can be debugged with
+//! gdb or a front end for it.
+//!
+//! Registers are sent in the order of gdb's z80 target: af, bc, de, hl,
+//! sp, pc, ix, iy, af', bc', de', hl' and ir, 16 bits each. Breakpoints and
+//! watchpoints are those of `Debugger`, so a watchpoint stops the cpu at
+//! the end of the instruction that hit it.
+
+mod packet;
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+
+use self::packet::{hex, number, parse_hex, read_packet, write_packet, Incoming};
+use crate::bus::Bus;
+use crate::cpu::Z80;
+use crate::debugger::{Access, Break, Debugger};
+use crate::state::Z80State;
+
+/// Instructions run between checks for Ctrl-C
+const POLL_INTERVAL: u32 = 1024;
+
+/// Most bytes an `m` packet reads, as two hex digits each they fill the
+/// PacketSize of 0x1000 sent in reply to qSupported
+const MAX_MEMORY_READ: u32 = 0x800;
+
+const TARGET_XML: &str = "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+                          <target version=\"1.0\"><architecture>z80</architecture></target>";
+
+/// A stream gdb is connected with
+pub trait Connection: Read + Write {
+    /// Whether gdb sent Ctrl-C to stop the running target, checked without
+    /// blocking. Anything else that arrives is dropped.
+    fn interrupted(&mut self) -> io::Result<bool>;
+}
+
+impl Connection for TcpStream {
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.set_nonblocking(true)?;
+        let interrupted = poll(self);
+        self.set_nonblocking(false)?;
+        interrupted
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.set_nonblocking(true)?;
+        let interrupted = poll(self);
+        self.set_nonblocking(false)?;
+        interrupted
+    }
+}
+
+/// Reads what a non-blocking stream has waiting
+fn poll(stream: &mut impl Read) -> io::Result<bool> {
+    let mut byte = [0];
+    loop {
+        match stream.read(&mut byte) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(_) if byte[0] == 0x03 => return Ok(true),
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct GdbStub {
+    /// Breakpoints and watchpoints set by gdb end up here, others can be
+    /// added directly
+    pub debugger: Debugger,
+    no_ack: bool,
+}
+
+impl GdbStub {
+    pub fn new() -> GdbStub {
+        GdbStub::default()
+    }
+
+    /// Waits for gdb to connect to `address`, like `localhost:1234`, and
+    /// serves it
+    pub fn listen_tcp(&mut self, address: impl ToSocketAddrs, cpu: &mut Z80, bus: &mut impl Bus) -> io::Result<()> {
+        let (stream, _) = TcpListener::bind(address)?.accept()?;
+        stream.set_nodelay(true)?;
+        self.serve(stream, cpu, bus)
+    }
+
+    /// Waits for gdb to connect to the socket at `path` and serves it
+    #[cfg(unix)]
+    pub fn listen_unix(&mut self, path: impl AsRef<Path>, cpu: &mut Z80, bus: &mut impl Bus) -> io::Result<()> {
+        let (stream, _) = UnixListener::bind(path)?.accept()?;
+        self.serve(stream, cpu, bus)
+    }
+
+    /// Answers packets until gdb detaches, kills the target or disconnects.
+    /// The cpu only runs while gdb has it continue or step.
+    pub fn serve(&mut self, mut stream: impl Connection, cpu: &mut Z80, bus: &mut impl Bus) -> io::Result<()> {
+        self.no_ack = false;
+        match self.session(&mut stream, cpu, bus) {
+            Err(err) if disconnected(&err) => Ok(()),
+            result => result,
+        }
+    }
+
+    fn session(&mut self, stream: &mut impl Connection, cpu: &mut Z80, bus: &mut impl Bus) -> io::Result<()> {
+        loop {
+            let packet = match read_packet(stream, self.no_ack)? {
+                Incoming::Packet(packet) => packet,
+                Incoming::Interrupt => continue,
+                Incoming::Closed => return Ok(()),
+            };
+            let reply = match packet.as_bytes().first() {
+                Some(b'k') => return Ok(()),
+                Some(b'D') => {
+                    write_packet(stream, "OK")?;
+                    return Ok(());
+                }
+                Some(b'c') => {
+                    if let Some(address) = number(&packet[1..]) {
+                        cpu.pc = address as u16;
+                    }
+                    self.resume(stream, cpu, bus)?
+                }
+                Some(b's') => {
+                    if let Some(address) = number(&packet[1..]) {
+                        cpu.pc = address as u16;
+                    }
+                    stop_reply(self.debugger.step(cpu, bus))
+                }
+                _ => self.command(&packet, cpu, bus).unwrap_or_else(|| "E01".to_string()),
+            };
+            write_packet(stream, &reply)?;
+            if packet == "QStartNoAckMode" {
+                self.no_ack = true;
+            }
+        }
+    }
+
+    /// Runs until something breaks or gdb sends Ctrl-C
+    fn resume(&mut self, stream: &mut impl Connection, cpu: &mut Z80, bus: &mut impl Bus) -> io::Result<String> {
+        if let Some(reason) = self.debugger.check_resume(cpu, bus) {
+            return Ok(stop_reply(Some(reason)));
+        }
+        let mut count = 0u32;
+        loop {
+            if let Some(reason) = self.debugger.step_checked(cpu, bus) {
+                return Ok(stop_reply(Some(reason)));
+            }
+            count = count.wrapping_add(1);
+            if count.is_multiple_of(POLL_INTERVAL) && stream.interrupted()? {
+                return Ok("S02".to_string());
+            }
+        }
+    }
+
+    /// Everything that does not run the cpu. None is an error reply and an
+    /// empty reply tells gdb the packet is not supported.
+    fn command(&mut self, packet: &str, cpu: &mut Z80, bus: &mut impl Bus) -> Option<String> {
+        let ok = || Some("OK".to_string());
+        let (command, args) = (packet.get(..1).unwrap_or(""), packet.get(1..).unwrap_or(""));
+        match command {
+            "?" => Some("S05".to_string()),
+            "g" => Some(registers(&cpu.snapshot()).iter().map(|r| hex(&r.to_le_bytes())).collect()),
+            "G" => {
+                let bytes = parse_hex(args)?;
+                let mut state = cpu.snapshot();
+                for (n, value) in bytes.chunks_exact(2).enumerate() {
+                    set_register(&mut state, n, u16::from_le_bytes([value[0], value[1]]))?;
+                }
+                cpu.restore(&state);
+                ok()
+            }
+            "p" => Some(hex(&registers(&cpu.snapshot()).get(number(args)? as usize)?.to_le_bytes())),
+            "P" => {
+                let (n, value) = args.split_once('=')?;
+                let value = parse_hex(value)?;
+                let mut state = cpu.snapshot();
+                set_register(&mut state, number(n)? as usize, u16::from_le_bytes([*value.first()?, *value.get(1)?]))?;
+                cpu.restore(&state);
+                ok()
+            }
+            "m" => {
+                let (address, length) = args.split_once(',')?;
+                let (address, length) = (number(address)?, number(length)?);
+                if length > MAX_MEMORY_READ {
+                    return None;
+                }
+                let bytes: Vec<u8> = (0..length).map(|i| bus.memory_read(address.wrapping_add(i) as u16 as usize)).collect();
+                Some(hex(&bytes))
+            }
+            "M" => {
+                let (location, data) = args.split_once(':')?;
+                let address = number(location.split(',').next()?)?;
+                for (i, byte) in parse_hex(data)?.into_iter().enumerate() {
+                    bus.memory_write(address.wrapping_add(i as u32) as u16 as usize, byte);
+                }
+                ok()
+            }
+            "Z" | "z" => {
+                let mut fields = args.split(',');
+                let (kind, address, length) = (fields.next()?, number(fields.next()?)? as u16, number(fields.next()?)?);
+                let range = address..=address.saturating_add((length.clamp(1, 0x10000) - 1) as u16);
+                let access = match kind {
+                    "0" | "1" => None,
+                    "2" => Some(Access::Write),
+                    "3" => Some(Access::Read),
+                    "4" => Some(Access::ReadWrite),
+                    _ => return Some(String::new()),
+                };
+                match (command, access) {
+                    ("Z", None) => self.debugger.add_breakpoint(address),
+                    ("Z", Some(access)) => self.debugger.add_watchpoint(range, access),
+                    (_, None) => {
+                        self.debugger.remove_breakpoint(address);
+                    }
+                    (_, Some(access)) => {
+                        self.debugger.remove_watchpoint(range, access);
+                    }
+                }
+                ok()
+            }
+            "H" | "T" => ok(),
+            _ => self.query(packet),
+        }
+    }
+
+    fn query(&mut self, packet: &str) -> Option<String> {
+        if packet.starts_with("qSupported") {
+            return Some("PacketSize=1000;QStartNoAckMode+;qXfer:features:read+".to_string());
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, length) = range.split_once(',')?;
+            let rest = TARGET_XML.get(number(offset)? as usize..).unwrap_or("");
+            let length = number(length)? as usize;
+            return Some(if rest.len() > length { format!("m{}", &rest[..length]) } else { format!("l{}", rest) });
+        }
+        Some(
+            match packet {
+                "QStartNoAckMode" => "OK",
+                "qAttached" => "1",
+                "qC" => "QC1",
+                "qfThreadInfo" => "m1",
+                "qsThreadInfo" => "l",
+                _ => "",
+            }
+            .to_string(),
+        )
+    }
+}
+
+/// gdb went away, possibly without waiting for the last acknowledgement
+fn disconnected(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::UnexpectedEof | io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset)
+}
+
+fn stop_reply(reason: Option<Break>) -> String {
+    match reason {
+        Some(Break::Watchpoint { address, access: Access::Write, .. }) => format!("T05watch:{:x};", address),
+        Some(Break::Watchpoint { address, .. }) => format!("T05rwatch:{:x};", address),
+        _ => "S05".to_string(),
+    }
+}
+
+/// gdb's z80 registers
+fn registers(state: &Z80State) -> [u16; 13] {
+    [
+        state.af,
+        state.bc,
+        state.de,
+        state.hl,
+        state.sp,
+        state.pc,
+        state.ix,
+        state.iy,
+        state.alt_af,
+        state.alt_bc,
+        state.alt_de,
+        state.alt_hl,
+        (state.i as u16) << 8 | state.r as u16,
+    ]
+}
+
+fn set_register(state: &mut Z80State, n: usize, value: u16) -> Option<()> {
+    match n {
+        0 => state.af = value,
+        1 => state.bc = value,
+        2 => state.de = value,
+        3 => state.hl = value,
+        4 => state.sp = value,
+        5 => state.pc = value,
+        6 => state.ix = value,
+        7 => state.iy = value,
+        8 => state.alt_af = value,
+        9 => state.alt_bc = value,
+        10 => state.alt_de = value,
+        11 => state.alt_hl = value,
+        12 => {
+            state.i = (value >> 8) as u8;
+            state.r = value as u8;
+        }
+        _ => return None,
+    }
+    Some(())
+}
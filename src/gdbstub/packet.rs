@@ -0,0 +1,74 @@
+//! Framing of RSP packets, `$data#checksum`, and the hex they are made of
+
+use std::io::{self, Read, Write};
+
+/// What arrives from gdb
+pub(super) enum Incoming {
+    Packet(String),
+    /// Ctrl-C, sent on its own while the target runs
+    Interrupt,
+    Closed,
+}
+
+/// Reads the next packet, skipping acknowledgements and acknowledging it
+/// unless `no_ack` is set. Packets with a bad checksum are asked for again.
+pub(super) fn read_packet(stream: &mut (impl Read + Write), no_ack: bool) -> io::Result<Incoming> {
+    loop {
+        match read_byte(stream)? {
+            None => return Ok(Incoming::Closed),
+            Some(0x03) => return Ok(Incoming::Interrupt),
+            Some(b'$') => {}
+            Some(_) => continue,
+        }
+        let mut data = vec![];
+        loop {
+            match read_byte(stream)? {
+                None => return Ok(Incoming::Closed),
+                Some(b'#') => break,
+                Some(byte) => data.push(byte),
+            }
+        }
+        let mut sum = [0; 2];
+        stream.read_exact(&mut sum)?;
+        let valid = std::str::from_utf8(&sum).ok().and_then(|sum| u8::from_str_radix(sum, 16).ok()) == Some(checksum(&data));
+        if !no_ack {
+            stream.write_all(if valid { b"+" } else { b"-" })?;
+        }
+        if valid {
+            return Ok(Incoming::Packet(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+}
+
+pub(super) fn write_packet(stream: &mut impl Write, data: &str) -> io::Result<()> {
+    write!(stream, "${}#{:02x}", data, checksum(data.as_bytes()))?;
+    stream.flush()
+}
+
+fn read_byte(stream: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match stream.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum: u8, byte| sum.wrapping_add(*byte))
+}
+
+pub(super) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(super) fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// A hex number, as addresses, lengths and register numbers are sent
+pub(super) fn number(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
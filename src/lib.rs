@@ -3,8 +3,17 @@ pub mod flags;
 pub mod registers;
 pub mod operations;
 pub mod disassembler;
+pub mod assembler;
+pub mod debugger;
+pub mod gdbstub;
+pub mod tracer;
 pub mod bus;
 pub mod cpu;
+pub mod state;
+pub mod loader;
+pub mod cpm;
+#[cfg(feature = "formats")]
+pub mod formats;
 mod util;
 
 mod times;
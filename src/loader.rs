@@ -0,0 +1,218 @@
+//! Program images from Intel HEX, Motorola S-records or raw binaries
+
+use std::fmt;
+
+use crate::bus::Bus;
+use crate::cpu::Z80;
+
+/// Bytes to be written from `address` up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Program {
+    pub segments: Vec<Segment>,
+    /// Start address from the file, if it has one
+    pub entry: Option<u32>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LoadError {
+    /// The record does not start with `:` or `S`
+    MissingStartCode { line: usize },
+    /// Odd number of digits or something that is not a hex digit
+    InvalidHex { line: usize },
+    /// The byte count does not match the length of the record
+    BadLength { line: usize },
+    Checksum { line: usize, expected: u8, found: u8 },
+    UnknownRecordType { line: usize, record_type: u8 },
+    /// An S5/S6 count that does not match the number of data records
+    RecordCount { line: usize, expected: u32, found: u32 },
+    /// Data or the entry point above $ffff, which the cpu cannot address
+    AddressOutOfRange { address: u32 },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            LoadError::MissingStartCode { line } => write!(f, "line {}: missing start code", line),
+            LoadError::InvalidHex { line } => write!(f, "line {}: invalid hex", line),
+            LoadError::BadLength { line } => write!(f, "line {}: byte count does not match the record", line),
+            LoadError::Checksum { line, expected, found } => {
+                write!(f, "line {}: checksum is {:02x}, expected {:02x}", line, found, expected)
+            }
+            LoadError::UnknownRecordType { line, record_type } => {
+                write!(f, "line {}: unknown record type {}", line, record_type)
+            }
+            LoadError::RecordCount { line, expected, found } => {
+                write!(f, "line {}: {} data records, the count says {}", line, found, expected)
+            }
+            LoadError::AddressOutOfRange { address } => write!(f, "address {:x} is above $ffff", address),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl Program {
+    /// A raw binary loaded at `address`, which is also where it starts
+    pub fn binary(data: &[u8], address: u16) -> Program {
+        Program {
+            segments: vec![Segment { address: address as u32, data: data.to_vec() }],
+            entry: Some(address as u32),
+        }
+    }
+
+    /// Parses Intel HEX, with 16-bit, segment (02/03) or linear (04/05) addressing
+    pub fn from_ihex(text: &str) -> Result<Program, LoadError> {
+        let mut program = Program::default();
+        let mut base = 0u32;
+
+        for (line, record) in records(text) {
+            let bytes = record
+                .strip_prefix(':')
+                .ok_or(LoadError::MissingStartCode { line })
+                .and_then(|hex| parse_hex(hex, line))?;
+            if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+                return Err(LoadError::BadLength { line });
+            }
+            // all bytes including the checksum add up to 0
+            let (body, checksum) = bytes.split_at(bytes.len() - 1);
+            let expected = body.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)).wrapping_neg();
+            if expected != checksum[0] {
+                return Err(LoadError::Checksum { line, expected, found: checksum[0] });
+            }
+
+            let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+            let data = &bytes[4..bytes.len() - 1];
+            let value = data.iter().fold(0u32, |acc, b| acc << 8 | *b as u32);
+            match bytes[3] {
+                0x00 => program.push(base.wrapping_add(offset), data),
+                0x01 => break,
+                0x02 => base = value << 4,
+                0x03 => program.entry = Some(((value >> 16) << 4) + (value & 0xffff)),
+                0x04 => base = value << 16,
+                0x05 => program.entry = Some(value),
+                record_type => return Err(LoadError::UnknownRecordType { line, record_type }),
+            }
+        }
+        Ok(program)
+    }
+
+    /// Parses Motorola S19, S28 or S37 records
+    pub fn from_srec(text: &str) -> Result<Program, LoadError> {
+        let mut program = Program::default();
+        let mut data_records = 0;
+
+        for (line, record) in records(text) {
+            let mut chars = record.chars();
+            if chars.next() != Some('S') {
+                return Err(LoadError::MissingStartCode { line });
+            }
+            let record_type = chars
+                .next()
+                .and_then(|c| c.to_digit(10))
+                .ok_or(LoadError::InvalidHex { line })? as u8;
+            let bytes = parse_hex(&record[2..], line)?;
+            if bytes.len() < 2 || bytes.len() != bytes[0] as usize + 1 {
+                return Err(LoadError::BadLength { line });
+            }
+            // ones' complement of the sum of count, address and data
+            let (body, checksum) = bytes.split_at(bytes.len() - 1);
+            let expected = !body.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+            if expected != checksum[0] {
+                return Err(LoadError::Checksum { line, expected, found: checksum[0] });
+            }
+
+            let address_len = match record_type {
+                0 | 1 | 5 | 9 => 2,
+                2 | 6 | 8 => 3,
+                3 | 7 => 4,
+                _ => return Err(LoadError::UnknownRecordType { line, record_type }),
+            };
+            if body.len() < 1 + address_len {
+                return Err(LoadError::BadLength { line });
+            }
+            let address = body[1..=address_len].iter().fold(0u32, |acc, b| acc << 8 | *b as u32);
+            let data = &body[1 + address_len..];
+            match record_type {
+                0 => {}
+                1..=3 => {
+                    program.push(address, data);
+                    data_records += 1;
+                }
+                5 | 6 => {
+                    if address != data_records {
+                        return Err(LoadError::RecordCount { line, expected: address, found: data_records });
+                    }
+                }
+                _ => {
+                    program.entry = Some(address);
+                    break;
+                }
+            }
+        }
+        Ok(program)
+    }
+
+    /// Writes every segment into `bus`. Nothing is written if any byte
+    /// would land above $ffff.
+    pub fn write_to(&self, bus: &mut impl Bus) -> Result<(), LoadError> {
+        for segment in self.segments.iter() {
+            let end = segment.address as u64 + segment.data.len() as u64;
+            if !segment.data.is_empty() && end > 0x10000 {
+                return Err(LoadError::AddressOutOfRange { address: segment.address.max(0x10000) });
+            }
+        }
+        for segment in self.segments.iter() {
+            for (offset, b) in segment.data.iter().enumerate() {
+                bus.memory_write(segment.address as usize + offset, *b);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the program into `bus` and points pc at the entry, if there is one
+    pub fn load(&self, cpu: &mut Z80, bus: &mut impl Bus) -> Result<(), LoadError> {
+        if let Some(address) = self.entry.filter(|entry| *entry > 0xffff) {
+            return Err(LoadError::AddressOutOfRange { address });
+        }
+        self.write_to(bus)?;
+        if let Some(entry) = self.entry {
+            cpu.pc = entry as u16;
+        }
+        Ok(())
+    }
+
+    /// Adds data, joining it to the previous segment if it follows on
+    pub(crate) fn push(&mut self, address: u32, data: &[u8]) {
+        if let Some(last) = self.segments.last_mut() {
+            if last.address.wrapping_add(last.data.len() as u32) == address {
+                last.data.extend_from_slice(data);
+                return;
+            }
+        }
+        self.segments.push(Segment { address, data: data.to_vec() });
+    }
+}
+
+/// Non-empty lines with their 1-based line numbers
+fn records(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+}
+
+fn parse_hex(hex: &str, line: usize) -> Result<Vec<u8>, LoadError> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(LoadError::InvalidHex { line });
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| LoadError::InvalidHex { line }))
+        .collect()
+}
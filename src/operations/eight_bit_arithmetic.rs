@@ -5,7 +5,7 @@ use crate::bus::Bus;
 use crate::registers::{Reg8};
 
 pub fn inc_u8<R: Read8 + Write8 + Copy, B: Bus>(z80: &mut Z80, bus: &mut B, reg: R) {
-    let val = reg.read8(z80, bus);
+    let val = reg.read8_rmw(z80, bus);
     let res = val.wrapping_add(1);
 
     z80.registers.set_flag(Sign, res & 0x80 == 0x80);
@@ -19,7 +19,7 @@ pub fn inc_u8<R: Read8 + Write8 + Copy, B: Bus>(z80: &mut Z80, bus: &mut B, reg:
 }
 
 pub fn dec_u8<R: Write8 + Read8 + Copy, B: Bus>(z80: &mut Z80, bus: &mut B, reg: R) {
-    let val = reg.read8(z80, bus);
+    let val = reg.read8_rmw(z80, bus);
     let res = val.wrapping_sub(1);
 
     z80.registers.set_flag(Sign, res & 0x80 == 0x80);
@@ -39,7 +39,7 @@ pub fn add<D: Write8 + Read8 + Copy, S: Read8, B: Bus>(z80: &mut Z80, bus: &mut
     let destval = dest.read8(z80, bus);
     let res = raw_add(z80, destval, val);
 
-    dest.write8(z80, bus, res as u8);
+    dest.write8(z80, bus, res);
 }
 
 pub fn raw_add(z80: &mut Z80, dest: u8, source:u8) -> u8 {
@@ -53,7 +53,7 @@ pub fn raw_addc(z80: &mut Z80, dest: u8, val: u8, carry: u8) -> u8 {
     let res = dest as u16 + val as u16 + carry as u16;
 
 
-    flags_add(z80, dest, val, res as u16);
+    flags_add(z80, dest, val, res);
     res as u8
 }
 
@@ -66,21 +66,20 @@ pub fn adc<D: Write8 + Read8 + Copy, S: Read8, B: Bus>(z80: &mut Z80, bus: &mut
     let res = raw_addc(z80, destval, val, carry);
 
 
-    dest.write8(z80, bus, res as u8);
+    dest.write8(z80, bus, res);
 }
 
 pub fn sub<S: Read8, B: Bus>(z80: &mut Z80, bus: &mut B, source: S) {
     let a = Reg8::A.read8(z80, bus);
     let val = source.read8(z80, bus);
     let res = raw_sub(z80, a, val, 0);
-    Reg8::A.write8(z80, bus, res as u8);
+    Reg8::A.write8(z80, bus, res);
 }
 
 pub fn raw_sub(z80: &mut Z80, dest: u8, val: u8, carry: u8) -> u8 {
     
     let res = (dest as i32 - val as i32 - carry as i32) as u16;
 
-// println!("{:X} = {:X}-{:X}-{}", res as u8, dest, val, carry);
     flags_sub(z80, dest as u16, val as u16, res);
     res as u8
 }
@@ -90,7 +89,7 @@ pub fn sbc<S: Read8, B: Bus>(z80: &mut Z80, bus: &mut B, source: S) {
     let val = source.read8(z80, bus);
     let carry = if z80.registers.get_flag(Carry) {1} else {0};
     let res = raw_sub(z80, a, val, carry);
-    Reg8::A.write8(z80, bus, res as u8);
+    Reg8::A.write8(z80, bus, res);
 }
 
 pub fn flags_add(z80: &mut Z80, acc: u8, add: u8, res: u16) {
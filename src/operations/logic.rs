@@ -27,7 +27,7 @@ pub fn and<S: Read8, B: Bus>(z80: &mut Z80, bus: &mut B, reg: S) {
     z80.registers.set_flag(HalfCarry, true);
     z80.registers.set_flag(Subtract, false);
     z80.registers.set_flag(Carry, false);
-    z80.registers.set_xy(res as u8);
+    z80.registers.set_xy(res);
     Reg8::A.write8(z80, bus, res);
 }
 
@@ -3,6 +3,7 @@ use crate::cpu::{Z80, Read8, Read16};
 use crate::flags::Flag::*;
 use crate::bus::Bus;
 use crate::registers::*;
+use crate::times;
 
 
 
@@ -10,7 +11,8 @@ use crate::registers::*;
 pub fn cpd(cpu: &mut Z80, bus: &mut impl Bus) {
     let a = Reg8::A.read8(cpu, bus);
     let hl = Reg16::HL.read16(cpu, bus);
-    let hl_mem = bus.memory_read(hl as usize);
+    let hl_mem = cpu.mem_read(bus, hl, times::MR);
+    cpu.internal(bus, hl, times::IO);
     let v = a.wrapping_sub(hl_mem);
     cpu.dec16(bus, Reg16::HL);
     cpu.dec16(bus, Reg16::BC);
@@ -21,6 +23,7 @@ pub fn cpd(cpu: &mut Z80, bus: &mut impl Bus) {
     let bc = Reg16::BC.read16(cpu, bus);
     cpu.registers.set_flag(Parity, bc != 0);
     cpu.registers.set_flag(Subtract, true);
+    cpu.registers.wz = cpu.registers.wz.wrapping_sub(1);
 
     let n = v - if cpu.registers.get_flag(HalfCarry) { 1 } else { 0 };
     cpu.registers.set_flag(Y, n & 0b10 != 0);
@@ -16,7 +16,7 @@ use crate::registers::Reg8::*;
 use crate::registers::Reg16::*;
 use crate::registers::Reg16;
 use crate::registers::ReadAddress;
-use crate::cpu::{ImmByte, ImmWord, Mem, RelOffset};
+use crate::cpu::{ImmByte, ImmWord, Mem, RelOffset, Indexed, CopyTo};
 
 
 pub trait Ops {
@@ -47,6 +47,8 @@ pub trait Ops {
 
     fn in8<D: Write8, S: Read8>(self, dest: D, source: S) -> Self::R;
     fn in8_noflags<D: Write8, S: Read8>(self, dest: D, source: S) -> Self::R;
+    /// in (c); only updates the flags, the value read is dropped
+    fn in8_flags<S: Read8>(self, source: S) -> Self::R;
 
     fn inc8<R: Write8 + Read8 + Copy>(self, reg: R) -> Self::R;
     fn inc8_memory<R: ReadAddress>(self, reg: R) -> Self::R;
@@ -54,6 +56,8 @@ pub trait Ops {
     fn inc16<R: Write16 + Read16 + Copy>(self, reg: R) -> Self::R;
 
     fn jp<A: Read16>(self, addr: A) -> Self::R;
+    /// jp (hl), jp (ix) and jp (iy); unlike jp nn this leaves wz alone
+    fn jp_reg<A: Read16>(self, reg: A) -> Self::R;
 
     fn jp_cond<C: ReadCond, A: Read16>(self, condition: C, addr: A) -> Self::R;
 
@@ -64,12 +68,18 @@ pub trait Ops {
     fn ld8<D: Write8, S: Read8>(self, dest: D, source: S) -> Self::R;
     fn ld8_int<D: Write8, S: Read8>(self, dest: D, source: S) -> Self::R;
     fn ld8_address_dest<D: ReadAddress, S: Read8>(self, dest: D, source: S) -> Self::R;
+    /// ld (ix+d),n; the displacement is added while n is fetched
+    fn ld8_address_dest_imm<D: ReadAddress>(self, dest: D) -> Self::R;
     fn ld8_address_source<D: Write8, S: ReadAddress>(self, dest: D, source: S) -> Self::R;
     fn ld16<D: Write16, S: Read16>(self, dest: D, source: S) -> Self::R;
+    /// ld sp,hl, ld sp,ix and ld sp,iy, two T-states longer than the fetch
+    fn ld16_sp<S: Read16>(self, source: S) -> Self::R;
 
     fn nop(self) -> Self::R;
     fn out8<D: Read8, S: Read8>(self, dest: D, source: S) -> Self::R;
     fn out8_noflags<D: Read8, S: Read8>(self, dest: D, source: S) -> Self::R;
+    /// out (c),0; cmos parts put 0xff on the data bus instead
+    fn out8_zero<D: Read8>(self, dest: D) -> Self::R;
 
     fn or<R: Read8>(self, reg: R) -> Self::R;
 
@@ -130,6 +140,7 @@ pub trait Ops {
     fn rlc<S: Read8 + Write8 + Copy>(self, source: S) -> Self::R;
     fn rrc<S: Read8 + Write8 + Copy>(self, source: S) -> Self::R;
     fn bit<S: Read8>(self, bit: u8, source: S) -> Self::R;
+    fn bit_memory<S: Read8>(self, bit: u8, source: S) -> Self::R;
     fn rl<S: Read8 + Write8 + Copy>(self, source: S) -> Self::R;
     fn res<S: Read8 + Write8 + Copy>(self, bit: u8, source: S) -> Self::R;
     fn rr<S: Read8 + Write8 + Copy>(self, source: S) -> Self::R;
@@ -407,7 +418,7 @@ pub fn decode<O: Ops>(ops: O, op: u8) -> O::R {
         0xe6 => ops.and(ImmByte),
         0xe7 => ops.rst(0x20),
         0xe8 => ops.ret_cond(Parity),
-        0xe9 => ops.jp(HL),
+        0xe9 => ops.jp_reg(HL),
         0xea => ops.jp_cond(Parity, ImmWord),
         0xeb => ops.ex(DE, HL),
         0xec => ops.call_cond(Parity, ImmWord),
@@ -424,14 +435,13 @@ pub fn decode<O: Ops>(ops: O, op: u8) -> O::R {
         0xf6 => ops.or(ImmByte),
         0xf7 => ops.rst(0x30),
         0xf8 => ops.ret_cond(Sign),
-        0xf9 => ops.ld16(SP, HL),
+        0xf9 => ops.ld16_sp(HL),
         0xfa => ops.jp_cond(Sign, ImmWord),
         0xfb => ops.ei(),
         0xfc => ops.call_cond(Sign, ImmWord),
         0xfd => ops.fd_op(),
         0xfe => ops.cp(ImmByte),
         0xff => ops.rst(0x38),
-        _ => unreachable!("Unreachable {:02X}", op),
     }
 }
 
@@ -473,7 +483,7 @@ fn decode_fd_dd<O: Ops>(ops: O, ireg: Reg16, op: u8) -> O::R {
 
         0x34 => ops.inc8_memory(Mem(RelOffset(ireg))),
         0x35 => ops.dec8_memory(Mem(RelOffset(ireg))),
-        0x36 => ops.ld8_address_dest(Mem(RelOffset(ireg)), ImmByte),
+        0x36 => ops.ld8_address_dest_imm(Mem(RelOffset(ireg))),
 
         0x39 => ops.add16(ireg, SP),
 
@@ -563,41 +573,46 @@ fn decode_fd_dd<O: Ops>(ops: O, ireg: Reg16, op: u8) -> O::R {
         0xe1 => ops.pop(ireg),
         0xe3 => ops.ex(Mem(SP), ireg),
         0xe5 => ops.push(ireg),
-        0xe9 => ops.jp(ireg),
-        0xf9 => ops.ld16(SP, ireg),
+        0xe9 => ops.jp_reg(ireg),
+        0xf9 => ops.ld16_sp(ireg),
         
         _ => decode(ops, op), //unreachable!("nope {:X}", op)
     }
 }
 
-pub fn decode_dd_fd_cb<O: Ops>(ops: O, address: u16, op: u8) -> O::R {
-    match op & 0b1100_0111 {
-        0x06 => {
-            match op {
-                0x06 => ops.rlc(Mem(address)),
-                0x0e => ops.rrc(Mem(address)),
-                0x16 => ops.rl(Mem(address)),
-                0x1e => ops.rr(Mem(address)),
-                0x26 => ops.sla(Mem(address)),
-                0x2e => ops.sra(Mem(address)),
-                0x36 => ops.sll(Mem(address)),
-                0x3e => ops.srl(Mem(address)),
-                _ => unreachable!()
-            }
-        },
+/// Every ddcb/fdcb opcode works on (ix+d); all but bit also copy the
+/// result into the register named by the low three bits, unless that is (hl)
+pub fn decode_dd_fd_cb<O: Ops>(ops: O, address: Indexed, op: u8) -> O::R {
+    let mem = Mem(address);
+    let reg = match op & 0b111 {
+        0 => Some(B),
+        1 => Some(C),
+        2 => Some(D),
+        3 => Some(E),
+        4 => Some(H),
+        5 => Some(L),
+        7 => Some(A),
+        _ => None,
+    };
+    let bit = (op >> 3) & 0b111;
 
-        0x46 => ops.bit((op & 0b0011_1000) >> 3, Mem(address)),
-                        
-        0x86 => ops.res((op & 0b0011_1000) >> 3, Mem(address)),
-        0xc6 => ops.set((op & 0b0011_1000) >> 3, Mem(address)),
-        _ => ops.nop(), //unreachable!()
+    match op >> 3 {
+        0b000 => match reg { Some(r) => ops.rlc(CopyTo(mem, r)), _ => ops.rlc(mem) },
+        0b001 => match reg { Some(r) => ops.rrc(CopyTo(mem, r)), _ => ops.rrc(mem) },
+        0b010 => match reg { Some(r) => ops.rl(CopyTo(mem, r)), _ => ops.rl(mem) },
+        0b011 => match reg { Some(r) => ops.rr(CopyTo(mem, r)), _ => ops.rr(mem) },
+        0b100 => match reg { Some(r) => ops.sla(CopyTo(mem, r)), _ => ops.sla(mem) },
+        0b101 => match reg { Some(r) => ops.sra(CopyTo(mem, r)), _ => ops.sra(mem) },
+        0b110 => match reg { Some(r) => ops.sll(CopyTo(mem, r)), _ => ops.sll(mem) },
+        0b111 => match reg { Some(r) => ops.srl(CopyTo(mem, r)), _ => ops.srl(mem) },
+
+        0b1000..=0b1111 => ops.bit_memory(bit, mem),
+        0b1_0000..=0b1_0111 => match reg { Some(r) => ops.res(bit, CopyTo(mem, r)), _ => ops.res(bit, mem) },
+        _ => match reg { Some(r) => ops.set(bit, CopyTo(mem, r)), _ => ops.set(bit, mem) },
     }
 }
 
 pub fn decode_ed<O: Ops>(ops: O, op: u8) -> O::R {
-    if op != 0x4d && op != 0xb0 {
-        // println!(" {:02x}", op);
-    }
     match op {
         0x40 => ops.in8(B, C),
         0x41 => ops.out8(C, B),
@@ -611,16 +626,16 @@ pub fn decode_ed<O: Ops>(ops: O, op: u8) -> O::R {
         0x49 => ops.out8(C, C),
         0x4a => ops.adc16(HL, BC),
         0x4b => ops.ld16(BC, Mem(ImmWord)),
-        0x4c => ops.neg(), // @todo undoc
+        0x4c => ops.neg(), // Undocumented
         0x4d => ops.reti(),
-        // 0x4e
+        0x4e => ops.im(0), // Undocumented, im 0/1 behaves as im 0
         0x4f => ops.ld8(R, A),
 
         0x50 => ops.in8(D, C),
         0x51 => ops.out8(C, D),
         0x52 => ops.sbc16(HL, DE),
         0x53 => ops.ld16(Mem(ImmWord), DE),
-        0x54 => ops.neg(), // @todo undoc
+        0x54 => ops.neg(), // Undocumented
         0x55 => ops.retn(),
         0x56 => ops.im(1),
         0x57 => ops.ld8_int(A, I),
@@ -628,7 +643,7 @@ pub fn decode_ed<O: Ops>(ops: O, op: u8) -> O::R {
         0x59 => ops.out8(C, E),
         0x5a => ops.adc16(HL, DE),
         0x5b => ops.ld16(DE, Mem(ImmWord)),
-        0x5c => ops.neg(), // @todo undoc
+        0x5c => ops.neg(), // Undocumented
         0x5d => ops.retn(),
         0x5e => ops.im(2),
         0x5f => ops.ld8_int(A, R),
@@ -636,36 +651,37 @@ pub fn decode_ed<O: Ops>(ops: O, op: u8) -> O::R {
         0x60 => ops.in8(H, C),
         0x61 => ops.out8(C, H),
         0x62 => ops.sbc16(HL, HL),
-        0x63 => ops.ld16(Mem(ImmWord), HL), // @todo undoc
-        0x64 => ops.neg(),                // @todo undoc
+        0x63 => ops.ld16(Mem(ImmWord), HL), // Undocumented
+        0x64 => ops.neg(),                  // Undocumented
         0x65 => ops.retn(),
         0x66 => ops.im(0),
         0x67 => ops.rrd(),
         0x68 => ops.in8(L, C),
         0x69 => ops.out8(C, L),
         0x6a => ops.adc16(HL, HL),
-        0x6b => ops.ld16(HL, Mem(ImmWord)), // @todo undoc
-        0x6c => ops.neg(),                // @todo undoc
+        0x6b => ops.ld16(HL, Mem(ImmWord)), // Undocumented
+        0x6c => ops.neg(),                  // Undocumented
         0x6d => ops.retn(),
-        //        0x6e ,
+        0x6e => ops.im(0), // Undocumented, im 0/1 behaves as im 0
         0x6f => ops.rld(),
 
-        //        0x70
-        //        0x71
-        0x71 => ops.out8(C, 0),
+        0x70 => ops.in8_flags(C),  // Undocumented
+        0x71 => ops.out8_zero(C), // Undocumented
         0x72 => ops.sbc16(HL, SP),
         0x73 => ops.ld16(Mem(ImmWord), SP),
-        //        0x74
+        0x74 => ops.neg(), // Undocumented
         0x75 => ops.retn(),
         0x76 => ops.im(1),
+        0x77 => ops.nop(), // Undocumented
 
         0x78 => ops.in8(A, C),
         0x79 => ops.out8(C, A),
         0x7a => ops.adc16(HL, SP),
         0x7b => ops.ld16(SP, Mem(ImmWord)),
-        0x7c => ops.neg(), //@todo undoc
+        0x7c => ops.neg(), // Undocumented
         0x7d => ops.retn(),
         0x7e => ops.im(2),
+        0x7f => ops.nop(), // Undocumented
 
         0xa0 => ops.ldi(),
         0xa1 => ops.cpi(),
@@ -686,7 +702,8 @@ pub fn decode_ed<O: Ops>(ops: O, op: u8) -> O::R {
         0xb9 => ops.cpdr(),
         0xba => ops.indr(),
         0xbb => ops.otdr(),
-        _ => unreachable!("no! {:02x}", op),
+        // The rest of the ed page does nothing but take 8 tstates
+        _ => ops.nop(),
     }
 }
 
@@ -714,7 +731,7 @@ pub fn decode_cb<O: Ops>(ops: O, op: u8) -> O::R {
 
         0b1000..=0b1111 => {
             let bit = (op >> 3) & 0b111;
-              match reg { Some(r) => ops.bit(bit,r), _ => ops.bit(bit,Mem(HL))}
+              match reg { Some(r) => ops.bit(bit,r), _ => ops.bit_memory(bit,Mem(HL))}
         }
 
         // reset bit
@@ -4,7 +4,7 @@ use crate::bus::Bus;
 
 
 pub fn rlc<R: Read8 + Write8 + Copy, B: Bus>(z80: &mut Z80, bus: &mut B, reg: R) {
-    let val = reg.read8(z80, bus);
+    let val = reg.read8_rmw(z80, bus);
 
     let res = val.rotate_left(1);
 
@@ -18,7 +18,7 @@ pub fn rlc<R: Read8 + Write8 + Copy, B: Bus>(z80: &mut Z80, bus: &mut B, reg: R)
 }
 
 pub fn rl<R: Read8 + Write8 + Copy, B: Bus>(z80: &mut Z80, bus: &mut B, reg: R) {
-    let val = reg.read8(z80, bus);
+    let val = reg.read8_rmw(z80, bus);
     let mut res = val << 1;
     if z80.registers.get_flag(Carry) {
         res |= 1;
@@ -37,7 +37,7 @@ pub fn rl<R: Read8 + Write8 + Copy, B: Bus>(z80: &mut Z80, bus: &mut B, reg: R)
 pub fn rr<R: Write8 + Read8 + Copy, B: Bus>(z80: &mut Z80, bus: &mut B, r: R) {
     let c = if z80.registers.get_flag(Carry) { 1 } else { 0 };
 
-    let val = r.read8(z80, bus);
+    let val = r.read8_rmw(z80, bus);
 
     let co = val & 0x01;
 
@@ -51,7 +51,7 @@ pub fn rr<R: Write8 + Read8 + Copy, B: Bus>(z80: &mut Z80, bus: &mut B, r: R) {
 }
 
 pub fn rrc<R: Read8 + Write8 + Copy, B: Bus>(z80: &mut Z80, bus: &mut B, reg: R) {
-    let val = reg.read8(z80, bus);
+    let val = reg.read8_rmw(z80, bus);
     let res = val.rotate_right(1);
 
     z80.registers.set_flag(Carry, val & 0x1 == 1);
@@ -63,7 +63,7 @@ pub fn rrc<R: Read8 + Write8 + Copy, B: Bus>(z80: &mut Z80, bus: &mut B, reg: R)
 }
 
 pub fn sla<R: Read8 + Write8 + Copy, B: Bus>(z80: &mut Z80, bus: &mut B, reg: R) {
-    let val = reg.read8(z80, bus);
+    let val = reg.read8_rmw(z80, bus);
     let r = val << 1;
 
     z80.registers.set_flag(HalfCarry, false);
@@ -75,7 +75,7 @@ pub fn sla<R: Read8 + Write8 + Copy, B: Bus>(z80: &mut Z80, bus: &mut B, reg: R)
 }
 
 pub fn sra<R: Read8 + Write8 + Copy, B: Bus>(z80: &mut Z80, bus: &mut B, reg: R) {
-    let val = reg.read8(z80, bus);
+    let val = reg.read8_rmw(z80, bus);
     let r = ((val as i8) >> 1) as u8 ;//| (val & 0x80);
 
     //        let r = if z80.flags.c { r | 0x80 } else { r };
@@ -90,7 +90,7 @@ pub fn sra<R: Read8 + Write8 + Copy, B: Bus>(z80: &mut Z80, bus: &mut B, reg: R)
 }
 
 pub fn srl<R: Read8 + Write8 + Copy, B: Bus>(z80: &mut Z80, bus: &mut B, reg: R) {
-    let val = reg.read8(z80, bus);
+    let val = reg.read8_rmw(z80, bus);
     let r = val >> 1;
 
     z80.registers.set_flag(HalfCarry,false);
@@ -102,7 +102,7 @@ pub fn srl<R: Read8 + Write8 + Copy, B: Bus>(z80: &mut Z80, bus: &mut B, reg: R)
 }
 
 pub fn sll<R: Read8 + Write8 + Copy, B: Bus>(z80: &mut Z80, bus: &mut B, reg: R) {
-    let val = reg.read8(z80, bus);
+    let val = reg.read8_rmw(z80, bus);
     let r = (val << 1) | 1;
 
     z80.registers.set_flag(HalfCarry, false);
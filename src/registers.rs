@@ -1,5 +1,6 @@
-use crate::cpu::{Z80, ImmByte, ImmWord, RelOffset, Read8, Read16, Write8, Write16};
+use crate::cpu::{Z80, ImmByte, ImmWord, RelOffset, Indexed, CopyTo, Read8, Read16, Write8, Write16};
 use crate::cpu::Mem;
+use crate::disassembler::traits::IntoArg8;
 use crate::flags::Flag;
 use crate::bus::Bus;
 use crate::util::make_u16;
@@ -93,6 +94,28 @@ pub enum Reg16 {
     _HL,
 }
 
+impl fmt::Display for Reg16 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::Reg16::*;
+        match *self {
+            AF => write!(f, "af"),
+            BC => write!(f, "bc"),
+            DE => write!(f, "de"),
+            HL => write!(f, "hl"),
+
+            SP => write!(f, "sp"),
+            PC => write!(f, "pc"),
+            IX => write!(f, "ix"),
+            IY => write!(f, "iy"),
+
+            _AF => write!(f, "af'"),
+            _BC => write!(f, "bc'"),
+            _DE => write!(f, "de'"),
+            _HL => write!(f, "hl'"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Registers {
     pub a: u8,
@@ -110,16 +133,20 @@ pub struct Registers {
     pub ix: u16,
     pub iy: u16,
 
-    _a: u8,
-    _b: u8,
-    _c: u8,
-    _d: u8,
-    _e: u8,
-    _f: u8,
-    _h: u8,
-    _l: u8,
-
-    pub xy_int: u8,
+    pub(crate) _a: u8,
+    pub(crate) _b: u8,
+    pub(crate) _c: u8,
+    pub(crate) _d: u8,
+    pub(crate) _e: u8,
+    pub(crate) _f: u8,
+    pub(crate) _h: u8,
+    pub(crate) _l: u8,
+
+    /// Internal MEMPTR register, the source of X/Y for `bit n,(hl)`
+    pub wz: u16,
+    /// Copy of F if the last instruction modified the flags, otherwise 0
+    pub q: u8,
+    pub(crate) flags_written: bool,
 }
 
 impl Default for Registers {
@@ -148,8 +175,9 @@ impl Default for Registers {
             _h: 0,
             _l: 0,
 
-            xy_int: 0,
-
+            wz: 0,
+            q: 0,
+            flags_written: false,
         }
     }
 
@@ -169,6 +197,11 @@ impl Registers {
         Flag::X.write(self, val & 0b0000_1000 != 0);
     }
 
+    /// Updates Q at the end of an instruction
+    pub fn latch_q(&mut self) {
+        self.q = if self.flags_written { self.f } else { 0 };
+    }
+
 
     fn alt_swap8(&mut self, register: Reg8) {
         match register {
@@ -233,7 +266,7 @@ impl Read8 for Reg8 {
 
 
 impl Write8 for Reg8 {
-    fn write8(self, cpu: &mut Z80, _: &mut impl Bus, val: u8) {
+    fn write8(self, cpu: &mut Z80, bus: &mut impl Bus, val: u8) {
         use self::Reg8::*;
         match self {
             A => cpu.registers.a = val,
@@ -244,8 +277,9 @@ impl Write8 for Reg8 {
             F => cpu.registers.f = val,
             H => cpu.registers.h = val,
             L => cpu.registers.l = val,
-            R => cpu.registers.r = val,
-            I => cpu.registers.i = val,
+            // only ld i,a and ld r,a write these, with an extra tstate
+            R => { cpu.internal(bus, cpu.ir(), 1); cpu.registers.r = val }
+            I => { cpu.internal(bus, cpu.ir(), 1); cpu.registers.i = val }
 
             IXH => cpu.registers.ix = make_u16(cpu.registers.ix as u8, val),
             IXL => cpu.registers.ix = make_u16(val, (cpu.registers.ix >> 8) as u8),
@@ -331,42 +365,36 @@ impl Read16 for ImmWord {
 impl Write8 for Mem<ImmWord> {
     fn write8(self, cpu: &mut Z80, bus: &mut impl Bus, val: u8) {
         let Mem(imm) = self;
-        // cpu.t_cycles += 3;
-        // cpu.m_cycles += 1;
         let addr = imm.read16(cpu, bus);
-        bus.memory_write(addr as usize, val);
-        bus.tick(1, times::MW);
+        cpu.mem_write(bus, addr, val, times::MW);
+        cpu.registers.wz = (val as u16) << 8 | (addr.wrapping_add(1) & 0xff);
     }
 }
 
 impl Write16 for Mem<ImmWord> {
     fn write16(self, cpu: &mut Z80, bus: &mut impl Bus, val: u16) {
         let Mem(imm) = self;
-        // cpu.t_cycles += 3;
-        // cpu.m_cycles += 1;
-        // cpu.t_cycles += 3;
-        // cpu.m_cycles += 1;
         let addr = imm.read16(cpu, bus);
         let lo = val as u8;
         let hi = (val >> 8) as u8;
-        bus.memory_write(addr as usize, lo);
-        bus.tick(1, times::MWL);
-        bus.memory_write(addr as usize + 1, hi);
-        bus.tick(1, times::MWH);
+        cpu.mem_write(bus, addr, lo, times::MWL);
+        cpu.mem_write(bus, addr.wrapping_add(1), hi, times::MWH);
+        cpu.registers.wz = addr.wrapping_add(1);
     }
 }
 
 impl Write16 for Mem<Reg16> {
     fn write16(self, cpu: &mut Z80, bus: &mut impl Bus, val: u16) {
         let Mem(imm) = self;
-        
+
+        // only ex (sp),rr writes a word through a register, high byte first
         let addr = imm.read16(cpu, bus);
         let lo = val as u8;
         let hi = (val >> 8) as u8;
-        bus.memory_write(addr as usize, lo);
-        bus.tick(1, times::MWL);
-        bus.memory_write(addr as usize + 1, hi);
-        bus.tick(1, times::MWH);
+        cpu.internal(bus, addr.wrapping_add(1), 1);
+        cpu.mem_write(bus, addr.wrapping_add(1), hi, times::MWH);
+        cpu.mem_write(bus, addr, lo, times::MWL);
+        cpu.internal(bus, addr, 2);
     }
 }
 
@@ -374,9 +402,7 @@ impl Write8 for Mem<u16> {
     fn write8(self, cpu: &mut Z80, bus: &mut impl Bus, val: u8) {
         let Mem(imm) = self;
         let addr = imm.read16(cpu, bus);
-        bus.memory_write(addr as usize, val);
-        bus.tick(1, times::MW);
-        
+        cpu.mem_write(bus, addr, val, times::MW);
     }
 }
 
@@ -384,10 +410,9 @@ impl Read16 for Mem<ImmWord> {
     fn read16(self, cpu: &mut Z80, bus: &mut impl Bus) -> u16 {
         let Mem(imm) = self;
         let addr = imm.read16(cpu, bus);
-        let lo = bus.memory_read(addr as usize);
-        bus.tick(1, times::MRL);
-        let hi = bus.memory_read(addr as usize + 1);
-        bus.tick(1, times::MRH);
+        let lo = cpu.mem_read(bus, addr, times::MRL);
+        let hi = cpu.mem_read(bus, addr.wrapping_add(1), times::MRH);
+        cpu.registers.wz = addr.wrapping_add(1);
         make_u16(lo, hi)
     }
 }
@@ -396,11 +421,12 @@ impl Read16 for Mem<Reg16>{
     fn read16(self, cpu: &mut Z80, bus: &mut impl Bus) -> u16 {
         let Mem(reg) = self;
         let addr = reg.read16(cpu, bus);
-        let lo = bus.memory_read(addr as usize);
-        bus.tick(1, times::MRL);
-        let hi = bus.memory_read(addr as usize + 1);
-        bus.tick(1, times::MRH);
-        make_u16(lo, hi)
+        let lo = cpu.mem_read(bus, addr, times::MRL);
+        let hi = cpu.mem_read(bus, addr.wrapping_add(1), times::MRH);
+        // only ex (sp),rr reads a word through a register, and it leaves the word in wz
+        let val = make_u16(lo, hi);
+        cpu.registers.wz = val;
+        val
     }
 }
 
@@ -408,11 +434,11 @@ impl Read16 for Mem<Reg16>{
 impl Write8 for Mem<Reg16> {
     fn write8(self, cpu: &mut Z80, bus: &mut impl Bus, val: u8) {
         let Mem(imm) = self;
-        // cpu.t_cycles += 3;
-        // cpu.m_cycles += 1;
         let addr = imm.read16(cpu, bus);
-        bus.memory_write(addr as usize, val);
-        bus.tick(1, times::MW);
+        cpu.mem_write(bus, addr, val, times::MW);
+        if let Reg16::BC | Reg16::DE = imm {
+            cpu.registers.wz = (val as u16) << 8 | (addr.wrapping_add(1) & 0xff);
+        }
     }
 }
 
@@ -420,29 +446,91 @@ impl Read8 for Mem<Reg16> {
     fn read8(self, cpu: &mut Z80, bus: &mut impl Bus) -> u8 {
         let Mem(reg) = self;
         let addr = reg.read16(cpu, bus);
-        bus.tick(1, times::MR);
-        bus.memory_read(addr as usize)
+        if let Reg16::BC | Reg16::DE = reg {
+            cpu.registers.wz = addr.wrapping_add(1);
+        }
+        cpu.mem_read(bus, addr, times::MR)
+    }
+
+    fn read8_rmw(self, cpu: &mut Z80, bus: &mut impl Bus) -> u8 {
+        let Mem(reg) = self;
+        let addr = reg.read16(cpu, bus);
+        let val = cpu.mem_read(bus, addr, times::MR);
+        cpu.internal(bus, addr, 1);
+        val
     }
 }
 
-//
 impl Read16 for RelOffset<u16> {
     fn read16(self, cpu: &mut Z80, bus: &mut impl Bus) -> u16 {
         let RelOffset(reg) = self;
         let offset = cpu.read_u8(bus) as i8 as i32;
         let val = reg.read16(cpu, bus) as i32;
-        bus.tick(1, times::IO);
         (val + offset) as u16
     }
 }
 
+/// The displacement is fetched here, adding it to ix/iy takes internal
+/// cycles that depend on the instruction
 impl Read16 for RelOffset<Reg16> {
     fn read16(self, cpu: &mut Z80, bus: &mut impl Bus) -> u16 {
         let RelOffset(reg) = self;
         let offset = cpu.read_u8(bus) as i8 as i32;
         let val = reg.read16(cpu, bus) as i32;
-        bus.tick(1, times::IO);
-        (val + offset) as u16
+        let addr = (val + offset) as u16;
+        cpu.registers.wz = addr;
+        addr
+    }
+}
+
+impl Read16 for Indexed {
+    fn read16(self, cpu: &mut Z80, bus: &mut impl Bus) -> u16 {
+        let Indexed(reg, offset) = self;
+        reg.read16(cpu, bus).wrapping_add(offset as u16)
+    }
+}
+
+impl Read8 for Mem<Indexed> {
+    fn read8(self, cpu: &mut Z80, bus: &mut impl Bus) -> u8 {
+        let Mem(indexed) = self;
+        let addr = indexed.read16(cpu, bus);
+        cpu.mem_read(bus, addr, times::MR)
+    }
+
+    fn read8_rmw(self, cpu: &mut Z80, bus: &mut impl Bus) -> u8 {
+        let Mem(indexed) = self;
+        let addr = indexed.read16(cpu, bus);
+        let val = cpu.mem_read(bus, addr, times::MR);
+        cpu.internal(bus, addr, 1);
+        val
+    }
+}
+
+impl Write8 for Mem<Indexed> {
+    fn write8(self, cpu: &mut Z80, bus: &mut impl Bus, val: u8) {
+        let Mem(indexed) = self;
+        let addr = indexed.read16(cpu, bus);
+        cpu.mem_write(bus, addr, val, times::MW);
+    }
+}
+
+impl<T: Read8 + Write8> Read8 for CopyTo<T> {
+    fn read8(self, cpu: &mut Z80, bus: &mut impl Bus) -> u8 {
+        let CopyTo(mem, _) = self;
+        mem.read8(cpu, bus)
+    }
+
+    fn read8_rmw(self, cpu: &mut Z80, bus: &mut impl Bus) -> u8 {
+        let CopyTo(mem, _) = self;
+        mem.read8_rmw(cpu, bus)
+    }
+}
+
+impl<T: Read8 + Write8> Write8 for CopyTo<T> {
+    fn write8(self, cpu: &mut Z80, bus: &mut impl Bus, val: u8) {
+        let CopyTo(mem, reg) = self;
+        mem.write8(cpu, bus, val);
+        reg.write8(cpu, bus, val);
     }
 }
 
@@ -450,12 +538,12 @@ impl Write8 for Mem<RelOffset<Reg16>> {
     fn write8(self, cpu: &mut Z80, bus: &mut impl Bus, val: u8) {
         let Mem(imm) = self;
         let addr = imm.read16(cpu, bus);
-        bus.tick(1, times::MW);
-        bus.memory_write(addr as usize, val)
+        cpu.displacement_delay(bus, times::IO);
+        cpu.mem_write(bus, addr, val, times::MW);
     }
 }
 
-pub trait ReadAddress {
+pub trait ReadAddress: IntoArg8 {
     fn read_address(self, cpu: &mut Z80, bus: &mut impl Bus) -> u16;
 }
 
@@ -471,8 +559,8 @@ impl Read8 for Mem<RelOffset<Reg16>> {
     fn read8(self, cpu: &mut Z80, bus: &mut impl Bus) -> u8 {
         let Mem(reg) = self;
         let addr = reg.read16(cpu, bus);
-        bus.tick(1, times::MR);
-        bus.memory_read(addr as usize)
+        cpu.displacement_delay(bus, times::IO);
+        cpu.mem_read(bus, addr, times::MR)
     }
 }
 
@@ -480,8 +568,8 @@ impl Read8 for Mem<RelOffset<u16>> {
     fn read8(self, cpu: &mut Z80, bus: &mut impl Bus) -> u8 {
         let Mem(reg) = self;
         let addr = reg.read16(cpu, bus);
-        bus.tick(1, times::MR);
-        bus.memory_read(addr as usize)
+        cpu.displacement_delay(bus, times::IO);
+        cpu.mem_read(bus, addr, times::MR)
     }
 }
 
@@ -490,8 +578,8 @@ impl Read8 for Mem<ImmWord> {
 
         let Mem(val) = self;
         let addr = val.read16(cpu, bus);
-        bus.tick(1, times::MR);
-        bus.memory_read(addr as usize)
+        cpu.registers.wz = addr.wrapping_add(1);
+        cpu.mem_read(bus, addr, times::MR)
     }
 }
 
@@ -499,7 +587,14 @@ impl Read8 for Mem<u16> {
     fn read8(self, cpu: &mut Z80, bus: &mut impl Bus) -> u8 {
         let Mem(val) = self;
         let addr = val.read16(cpu, bus);
-        bus.tick(1, times::MR);
-        bus.memory_read(addr as usize)
+        cpu.mem_read(bus, addr, times::MR)
+    }
+
+    fn read8_rmw(self, cpu: &mut Z80, bus: &mut impl Bus) -> u8 {
+        let Mem(val) = self;
+        let addr = val.read16(cpu, bus);
+        let val = cpu.mem_read(bus, addr, times::MR);
+        cpu.internal(bus, addr, 1);
+        val
     }
 }
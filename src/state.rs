@@ -0,0 +1,137 @@
+use std::fmt;
+
+/// Everything the cpu needs to carry on from an instruction boundary,
+/// see `Z80::snapshot` and `Z80::restore`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Z80State {
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+
+    pub alt_af: u16,
+    pub alt_bc: u16,
+    pub alt_de: u16,
+    pub alt_hl: u16,
+
+    pub ix: u16,
+    pub iy: u16,
+    pub sp: u16,
+    pub pc: u16,
+    pub i: u8,
+    pub r: u8,
+
+    /// MEMPTR
+    pub wz: u16,
+    /// F if the last instruction changed the flags, otherwise 0
+    pub q: u8,
+
+    pub iff1: bool,
+    pub iff2: bool,
+    pub interrupt_mode: u8,
+    pub halted: bool,
+    /// The last instruction was ei or di, INT is not taken before the next one
+    pub ei_delay: bool,
+    /// An nmi edge that has not been acknowledged yet
+    pub nmi_pending: bool,
+    /// Running the nmi handler, until retn
+    pub nmi: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// Does not start with `MAGIC`
+    BadMagic,
+    UnsupportedVersion(u8),
+    /// Shorter than the version says it should be
+    Truncated,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            StateError::BadMagic => write!(f, "not a z80 state"),
+            StateError::UnsupportedVersion(v) => write!(f, "unsupported z80 state version {}", v),
+            StateError::Truncated => write!(f, "z80 state is truncated"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl Z80State {
+    pub const MAGIC: [u8; 4] = *b"Z80S";
+    /// Bumped whenever the layout changes, older versions keep loading
+    pub const VERSION: u8 = 1;
+    /// Encoded size of version 1
+    pub const LEN: usize = 36;
+
+    /// Magic and version, then the 16-bit registers little endian in field
+    /// order, i, r, q, the interrupt mode and a byte of flags
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::LEN);
+        bytes.extend_from_slice(&Self::MAGIC);
+        bytes.push(Self::VERSION);
+        let words = [
+            self.af, self.bc, self.de, self.hl,
+            self.alt_af, self.alt_bc, self.alt_de, self.alt_hl,
+            self.ix, self.iy, self.sp, self.pc, self.wz,
+        ];
+        for word in words.iter() {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes.push(self.i);
+        bytes.push(self.r);
+        bytes.push(self.q);
+        bytes.push(self.interrupt_mode);
+
+        let flags = [self.iff1, self.iff2, self.halted, self.ei_delay, self.nmi_pending, self.nmi];
+        let flags = flags.iter().enumerate().fold(0, |acc, (bit, &set)| acc | ((set as u8) << bit));
+        bytes.push(flags);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Z80State, StateError> {
+        if bytes.len() < 5 {
+            return Err(if Self::MAGIC.starts_with(bytes) { StateError::Truncated } else { StateError::BadMagic });
+        }
+        if bytes[..4] != Self::MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        if bytes[4] != Self::VERSION {
+            return Err(StateError::UnsupportedVersion(bytes[4]));
+        }
+        if bytes.len() < Self::LEN {
+            return Err(StateError::Truncated);
+        }
+
+        let word = |n: usize| u16::from_le_bytes([bytes[5 + n * 2], bytes[6 + n * 2]]);
+        let flags = bytes[35];
+        let flag = |bit: u8| flags & (1 << bit) != 0;
+        Ok(Z80State {
+            af: word(0),
+            bc: word(1),
+            de: word(2),
+            hl: word(3),
+            alt_af: word(4),
+            alt_bc: word(5),
+            alt_de: word(6),
+            alt_hl: word(7),
+            ix: word(8),
+            iy: word(9),
+            sp: word(10),
+            pc: word(11),
+            wz: word(12),
+            i: bytes[31],
+            r: bytes[32],
+            q: bytes[33],
+            interrupt_mode: bytes[34],
+            iff1: flag(0),
+            iff2: flag(1),
+            halted: flag(2),
+            ei_delay: flag(3),
+            nmi_pending: flag(4),
+            nmi: flag(5),
+        })
+    }
+}
@@ -0,0 +1,256 @@
+//! A text trace of every instruction, to compare runs against each other
+//! or against other emulators with `diff`.
+//!
+//! Each line is one instruction, with the registers as they were before it
+//! ran and the accesses it made:
+//!
+//! ```text
+//! 0005  77           ld (hl),a              AF=4100 BC=0000 DE=0000 HL=0200 IX=0000 IY=0000 SP=dff0 AF'=0000 BC'=0000 DE'=0000 HL'=0000 IR=008e F=-------- T=17 wr 0200=41
+//! 0006  86           add a,(hl)             AF=4100 BC=0000 DE=0000 HL=0200 IX=0000 IY=0000 SP=dff0 AF'=0000 BC'=0000 DE'=0000 HL'=0000 IR=008f F=-------- T=24 rd 0200=41
+//! 0007  01 fe 12     ld bc,$12fe            AF=8284 BC=0000 DE=0000 HL=0200 IX=0000 IY=0000 SP=dff0 AF'=0000 BC'=0000 DE'=0000 HL'=0000 IR=0090 F=S----P-- T=31
+//! ```
+//!
+//! The first 40 columns are the disassembly line of `DecodedInstruction`.
+//! Flags are `SZYHXPNC`, with `-` for a flag that is clear, and `T` is
+//! `Z80::t_cycles`. Accesses are `rd` and `wr` for memory and `in` and
+//! `out` for ports, with the address and the byte in hex. Reads of the
+//! instruction's own bytes are left out. An accepted interrupt gets a line
+//! of its own with `int` or `nmi` in place of the instruction, and steps
+//! while the cpu is halted show `(halted)`.
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::ops::Range;
+
+use crate::bus::{Bus, Cycle};
+use crate::cpu::Z80;
+use crate::disassembler::{BusMemory, Disassembler};
+use crate::state::Z80State;
+
+/// When tracing starts or stops, checked before each instruction
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    /// pc is at the address
+    Pc(u16),
+    /// `Z80::t_cycles` has reached the count
+    Cycle(u64),
+}
+
+impl Trigger {
+    fn hit(self, cpu: &Z80) -> bool {
+        match self {
+            Trigger::Pc(pc) => cpu.pc == pc,
+            Trigger::Cycle(t) => cpu.t_cycles >= t,
+        }
+    }
+}
+
+pub struct Tracer<W: Write> {
+    out: W,
+    start: Option<Trigger>,
+    stop: Option<Trigger>,
+    active: bool,
+}
+
+impl<W: Write> Tracer<W> {
+    /// Traces everything until a start or stop trigger is set
+    pub fn new(out: W) -> Tracer<W> {
+        Tracer { out, start: None, stop: None, active: true }
+    }
+
+    /// Waits for `trigger` before writing anything. A pc trigger starts
+    /// tracing again whenever it is hit after a stop, a cycle count only once.
+    pub fn start_at(&mut self, trigger: Trigger) {
+        self.start = Some(trigger);
+        self.active = false;
+    }
+
+    /// Stops writing when `trigger` is hit; that instruction is not traced
+    pub fn stop_at(&mut self, trigger: Trigger) {
+        self.stop = Some(trigger);
+    }
+
+    /// Whether the next instruction will be traced, as far as is known
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /// Like `Z80::step`, writing a line for the instruction and any
+    /// interrupt taken before it
+    pub fn step(&mut self, cpu: &mut Z80, bus: &mut impl Bus) -> io::Result<u32> {
+        if !self.active && self.start.is_some_and(|trigger| trigger.hit(cpu)) {
+            self.active = true;
+            if let Some(Trigger::Cycle(_)) = self.start {
+                self.start = None;
+            }
+        }
+        if self.active && self.stop.is_some_and(|trigger| trigger.hit(cpu)) {
+            self.active = false;
+        }
+        if !self.active {
+            return Ok(cpu.step(bus));
+        }
+
+        let state = cpu.snapshot();
+        let t_cycles = cpu.t_cycles;
+        let mut recorder = Recorder::new(bus, 0..0);
+        let mut spent = cpu.accept_interrupt(&mut recorder);
+        if spent > 0 {
+            let kind = if recorder.acknowledged { "int" } else { "nmi" };
+            self.line(&format!("{:04x}  {:<12} {}", state.pc, "", kind), &state, t_cycles, &recorder.accesses.into_inner())?;
+        }
+
+        let state = cpu.snapshot();
+        let t_cycles = cpu.t_cycles;
+        let (text, own) = if state.halted {
+            (format!("{:04x}  {:<12} (halted)", state.pc, ""), 0..0)
+        } else {
+            let decoded = Disassembler::new(BusMemory(&*bus), state.pc).disassemble(state.pc);
+            (decoded.to_string(), state.pc as u32..state.pc as u32 + decoded.length as u32)
+        };
+        let mut recorder = Recorder::new(bus, own);
+        spent += cpu.step(&mut recorder);
+        if recorder.fetched {
+            self.line(&text, &state, t_cycles, &recorder.accesses.into_inner())?;
+        }
+        Ok(spent)
+    }
+
+    /// Steps until at least `t_states` have been used, see `Z80::run_for`
+    pub fn run_for(&mut self, cpu: &mut Z80, bus: &mut impl Bus, t_states: u32) -> io::Result<u32> {
+        let mut spent = 0;
+        while spent < t_states {
+            spent += self.step(cpu, bus)?;
+        }
+        Ok(spent - t_states)
+    }
+
+    fn line(&mut self, instruction: &str, state: &Z80State, t_cycles: u64, accesses: &[String]) -> io::Result<()> {
+        write!(
+            self.out,
+            "{:<40}  AF={:04x} BC={:04x} DE={:04x} HL={:04x} IX={:04x} IY={:04x} SP={:04x} \
+             AF'={:04x} BC'={:04x} DE'={:04x} HL'={:04x} IR={:02x}{:02x} F={} T={}",
+            instruction,
+            state.af,
+            state.bc,
+            state.de,
+            state.hl,
+            state.ix,
+            state.iy,
+            state.sp,
+            state.alt_af,
+            state.alt_bc,
+            state.alt_de,
+            state.alt_hl,
+            state.i,
+            state.r,
+            flags(state.af as u8),
+            t_cycles
+        )?;
+        for access in accesses {
+            write!(self.out, " {}", access)?;
+        }
+        writeln!(self.out)
+    }
+}
+
+fn flags(f: u8) -> String {
+    "SZYHXPNC".chars().enumerate().map(|(i, name)| if f & (0x80 >> i) != 0 { name } else { '-' }).collect()
+}
+
+/// Passes everything on to the real bus while writing down the accesses
+struct Recorder<'a, B: Bus> {
+    bus: &'a mut B,
+    /// Addresses of the instruction itself, as u32 so it can wrap past $ffff
+    own: Range<u32>,
+    accesses: RefCell<Vec<String>>,
+    /// An op code was fetched, so an instruction ran
+    fetched: bool,
+    acknowledged: bool,
+}
+
+impl<'a, B: Bus> Recorder<'a, B> {
+    fn new(bus: &'a mut B, own: Range<u32>) -> Recorder<'a, B> {
+        Recorder { bus, own, accesses: RefCell::new(vec![]), fetched: false, acknowledged: false }
+    }
+
+    fn record(&self, kind: &str, address: u16, value: u8) {
+        self.accesses.borrow_mut().push(format!("{} {:04x}={:02x}", kind, address, value));
+    }
+
+    fn own(&self, address: usize) -> bool {
+        self.own.contains(&(address as u32)) || self.own.contains(&(address as u32 + 0x10000))
+    }
+}
+
+impl<B: Bus> Bus for Recorder<'_, B> {
+    fn memory_read(&self, address: usize) -> u8 {
+        let value = self.bus.memory_read(address);
+        if !self.own(address) {
+            self.record("rd", address as u16, value);
+        }
+        value
+    }
+
+    fn memory_read_word(&self, address: usize) -> u16 {
+        let value = self.bus.memory_read_word(address);
+        self.record("rd", address as u16, value as u8);
+        self.record("rd", (address as u16).wrapping_add(1), (value >> 8) as u8);
+        value
+    }
+
+    fn memory_write(&mut self, address: usize, value: u8) {
+        self.record("wr", address as u16, value);
+        self.bus.memory_write(address, value)
+    }
+
+    fn memory_write_word(&mut self, address: usize, value: u16) {
+        self.record("wr", address as u16, value as u8);
+        self.record("wr", (address as u16).wrapping_add(1), (value >> 8) as u8);
+        self.bus.memory_write_word(address, value)
+    }
+
+    fn port_read(&mut self, port: u8) -> u8 {
+        self.bus.port_read(port)
+    }
+
+    fn port_write(&mut self, port: u8, value: u8) {
+        self.bus.port_write(port, value)
+    }
+
+    fn io_read(&mut self, port: u16) -> u8 {
+        let value = self.bus.io_read(port);
+        self.record("in", port, value);
+        value
+    }
+
+    fn io_write(&mut self, port: u16, value: u8) {
+        self.record("out", port, value);
+        self.bus.io_write(port, value)
+    }
+
+    fn interrupt_acknowledge(&mut self) -> u8 {
+        self.bus.interrupt_acknowledge()
+    }
+
+    fn cycle(&mut self, cycle: Cycle) -> u8 {
+        match cycle {
+            Cycle::M1 { .. } => self.fetched = true,
+            Cycle::IntAck { .. } => self.acknowledged = true,
+            _ => {}
+        }
+        self.bus.cycle(cycle)
+    }
+
+    fn wait(&mut self, cycle: Cycle) -> bool {
+        self.bus.wait(cycle)
+    }
+
+    fn tick(&mut self, machine_cycles: u8, t_states: u8) {
+        self.bus.tick(machine_cycles, t_states)
+    }
+}
@@ -0,0 +1,247 @@
+#[cfg(test)]
+mod test_all_opcodes {
+    use z80::bus::Bus;
+    use z80::cpu::Z80;
+
+    struct TestBus {
+        memory: Vec<u8>,
+        pub m_cycles: u32,
+        pub t_states: u32,
+    }
+
+    impl TestBus {
+        fn new() -> TestBus {
+            TestBus {
+                memory: vec![0; 0x10000],
+                m_cycles: 0,
+                t_states: 0,
+            }
+        }
+    }
+
+    impl Bus for TestBus {
+        fn memory_read(&self, address: usize) -> u8 {
+            self.memory[address & 0xffff]
+        }
+
+        fn memory_read_word(&self, address: usize) -> u16 {
+            self.memory_read(address) as u16 | ((self.memory_read(address + 1) as u16) << 8)
+        }
+
+        fn memory_write(&mut self, address: usize, value: u8) {
+            self.memory[address & 0xffff] = value;
+        }
+
+        fn memory_write_word(&mut self, address: usize, value: u16) {
+            self.memory_write(address, value as u8);
+            self.memory_write(address + 1, (value >> 8) as u8);
+        }
+
+        fn tick(&mut self, machine_cycles: u8, t_states: u8) {
+            self.m_cycles += machine_cycles as u32;
+            self.t_states += t_states as u32;
+        }
+    }
+
+    /// Every opcode reachable through each prefix, ddcb/fdcb with a displacement
+    fn all_sequences() -> Vec<Vec<u8>> {
+        let mut sequences = vec![];
+        for op in 0..=0xff {
+            sequences.push(vec![op]);
+            sequences.push(vec![0xcb, op]);
+            sequences.push(vec![0xdd, op]);
+            sequences.push(vec![0xed, op]);
+            sequences.push(vec![0xfd, op]);
+            sequences.push(vec![0xdd, 0xcb, 0x80, op]);
+            sequences.push(vec![0xfd, 0xcb, 0x7f, op]);
+        }
+        sequences
+    }
+
+    fn run(seq: &[u8], pc: u16, fill: u8) {
+        let mut bus = TestBus::new();
+        let mut cpu = Z80::new();
+        bus.memory.iter_mut().for_each(|b| *b = fill);
+        for (i, b) in seq.iter().enumerate() {
+            bus.memory_write(pc.wrapping_add(i as u16) as usize, *b);
+        }
+        cpu.pc = pc;
+        cpu.sp = pc;
+        cpu.registers.b = fill;
+        cpu.registers.c = fill;
+        cpu.registers.h = fill;
+        cpu.registers.l = fill;
+        cpu.registers.ix = pc;
+        cpu.registers.iy = pc;
+        cpu.step(&mut bus);
+    }
+
+    #[test]
+    fn test_all_opcodes_execute() {
+        for seq in all_sequences() {
+            run(&seq, 0x0000, 0x00);
+            run(&seq, 0xfffe, 0xff);
+        }
+    }
+
+    #[test]
+    fn test_ed_undefined_is_8_tstate_nop() {
+        for op in [0x00, 0x3f, 0x77, 0x7f, 0x80, 0xa4, 0xbf, 0xc0, 0xff] {
+            let mut bus = TestBus::new();
+            let mut cpu = Z80::new();
+            bus.memory_write(0, 0xed);
+            bus.memory_write(1, op);
+            cpu.registers.a = 0x12;
+            cpu.registers.f = 0x34;
+
+            cpu.step(&mut bus);
+            assert_eq!(2, cpu.pc);
+            assert_eq!(0x12, cpu.registers.a);
+            assert_eq!(0x34, cpu.registers.f);
+            assert_eq!(2, bus.m_cycles);
+            assert_eq!(8, bus.t_states);
+        }
+    }
+
+    #[test]
+    fn test_ed_im_aliases() {
+        for (op, mode) in [(0x4e, 0), (0x66, 0), (0x6e, 0), (0x76, 1), (0x7e, 2)] {
+            let mut bus = TestBus::new();
+            let mut cpu = Z80::new();
+            bus.memory_write(0, 0xed);
+            bus.memory_write(1, op);
+            cpu.interrupt_mode = 2 - mode;
+
+            cpu.step(&mut bus);
+            assert_eq!(mode, cpu.interrupt_mode);
+        }
+    }
+
+    #[test]
+    fn test_ed_neg_aliases() {
+        for op in [0x44, 0x4c, 0x54, 0x5c, 0x64, 0x6c, 0x74, 0x7c] {
+            let mut bus = TestBus::new();
+            let mut cpu = Z80::new();
+            bus.memory_write(0, 0xed);
+            bus.memory_write(1, op);
+            cpu.registers.a = 0x01;
+
+            cpu.step(&mut bus);
+            assert_eq!(0xff, cpu.registers.a);
+        }
+    }
+
+    /// Documented T-states of the unprefixed page with all flags reset and b=1,
+    /// so nz/nc/po/p are taken and djnz falls through. Prefixes are 0.
+    const TIMINGS: [u32; 256] = [
+        4, 10, 7, 6, 4, 4, 7, 4, 4, 11, 7, 6, 4, 4, 7, 4,
+        8, 10, 7, 6, 4, 4, 7, 4, 12, 11, 7, 6, 4, 4, 7, 4,
+        12, 10, 16, 6, 4, 4, 7, 4, 7, 11, 16, 6, 4, 4, 7, 4,
+        12, 10, 13, 6, 11, 11, 10, 4, 7, 11, 13, 6, 4, 4, 7, 4,
+        4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4,
+        4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4,
+        4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4,
+        7, 7, 7, 7, 7, 7, 4, 7, 4, 4, 4, 4, 4, 4, 7, 4,
+        4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4,
+        4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4,
+        4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4,
+        4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4,
+        11, 10, 10, 10, 17, 11, 7, 11, 5, 10, 10, 0, 10, 17, 7, 11,
+        11, 10, 10, 11, 17, 11, 7, 11, 5, 4, 10, 11, 10, 0, 7, 11,
+        11, 10, 10, 19, 17, 11, 7, 11, 5, 4, 10, 4, 10, 0, 7, 11,
+        11, 10, 10, 4, 17, 11, 7, 11, 5, 6, 10, 4, 10, 0, 7, 11,
+    ];
+
+    /// Runs one instruction from address 0 and returns its T-states
+    fn time(seq: &[u8], bc: u16) -> u32 {
+        let mut bus = TestBus::new();
+        let mut cpu = Z80::new();
+        for (i, b) in seq.iter().enumerate() {
+            bus.memory_write(i, *b);
+        }
+        cpu.sp = 0x8000;
+        cpu.registers.a = 0xff;
+        cpu.registers.f = 0;
+        cpu.registers.b = (bc >> 8) as u8;
+        cpu.registers.c = bc as u8;
+        cpu.registers.h = 0x40;
+        cpu.registers.ix = 0x4000;
+        cpu.registers.iy = 0x4000;
+        let t_states = cpu.step(&mut bus);
+        assert_eq!(t_states, bus.t_states);
+        t_states
+    }
+
+    #[test]
+    fn test_unprefixed_timings() {
+        for op in 0..=0xffu8 {
+            let expected = TIMINGS[op as usize];
+            if expected != 0 {
+                assert_eq!(expected, time(&[op], 0x0100), "op {:02x}", op);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cb_timings() {
+        for op in 0..=0xffu8 {
+            let expected = match (op >> 6, op & 7) {
+                (1, 6) => 12,
+                (_, 6) => 15,
+                _ => 8,
+            };
+            assert_eq!(expected, time(&[0xcb, op], 0x0100), "cb {:02x}", op);
+        }
+    }
+
+    #[test]
+    fn test_ed_timings() {
+        for op in 0..=0xffu8 {
+            let expected = match op {
+                0x47 | 0x4f | 0x57 | 0x5f => 9,
+                0x67 | 0x6f => 18,
+                0x77 | 0x7f => 8,
+                0x40..=0x7f => [12, 12, 15, 20, 8, 14, 8][op as usize & 7],
+                0xa0..=0xa3 | 0xa8..=0xab | 0xb0..=0xb3 | 0xb8..=0xbb => 16,
+                _ => 8,
+            };
+            // block i/o counts with b, the rest with bc; neither repeats
+            let bc = if op & 0xa2 == 0xa2 { 0x0100 } else { 0x0001 };
+            assert_eq!(expected, time(&[0xed, op], bc), "ed {:02x}", op);
+        }
+    }
+
+    #[test]
+    fn test_ed_repeat_timings() {
+        for op in [0xb0, 0xb1, 0xb2, 0xb3, 0xb8, 0xb9, 0xba, 0xbb] {
+            assert_eq!(21, time(&[0xed, op], 0x0202), "ed {:02x}", op);
+        }
+    }
+
+    #[test]
+    fn test_dd_fd_timings() {
+        for prefix in [0xdd, 0xfd] {
+            for op in 0..=0xffu8 {
+                let expected = match op {
+                    0xcb | 0xdd | 0xed | 0xfd => continue,
+                    0x34 | 0x35 => 23,
+                    0x36 => 19,
+                    0x76 => 8,
+                    0x46 | 0x4e | 0x56 | 0x5e | 0x66 | 0x6e | 0x7e => 19,
+                    0x70..=0x77 => 19,
+                    0x86 | 0x8e | 0x96 | 0x9e | 0xa6 | 0xae | 0xb6 | 0xbe => 19,
+                    _ => 4 + TIMINGS[op as usize],
+                };
+                assert_eq!(expected, time(&[prefix, op], 0x0100), "{:02x} {:02x}", prefix, op);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ddcb_timings() {
+        for op in 0..=0xffu8 {
+            let expected = if op >> 6 == 1 { 20 } else { 23 };
+            assert_eq!(expected, time(&[0xdd, 0xcb, 0x05, op], 0x0100), "ddcb {:02x}", op);
+        }
+    }
+}
@@ -0,0 +1,169 @@
+
+
+#[cfg(test)]
+mod test_assembler {
+    use z80::assembler::{assemble, assemble_bytes, encode, AsmError};
+    use z80::bus::Bus;
+    use z80::cpu::Z80;
+    use z80::disassembler::Disassembler;
+    use z80::loader::Segment;
+
+    struct TestBus {
+        memory: Vec<u8>,
+    }
+
+    impl Bus for TestBus {
+        fn memory_read(&self, address: usize) -> u8 {
+            self.memory[address]
+        }
+
+        fn memory_read_word(&self, address: usize) -> u16 {
+            self.memory[address] as u16 | ((self.memory[address + 1] as u16) << 8)
+        }
+
+        fn memory_write(&mut self, address: usize, value: u8) {
+            self.memory[address] = value;
+        }
+
+        fn memory_write_word(&mut self, address: usize, value: u16) {
+            self.memory[address] = value as u8;
+            self.memory[address + 1] = (value >> 8) as u8;
+        }
+
+        #[allow(unused_variables)]
+        fn tick(&mut self, machine_cycles: u8, t_states: u8) {}
+    }
+
+    fn disassembler(mut prg: Vec<u8>) -> Disassembler<'static> {
+        prg.resize(0x10000, 0);
+        Disassembler::new(prg, 0)
+    }
+
+    fn golden() -> impl Iterator<Item = (Vec<u8>, &'static str)> {
+        include_str!("data/disassembly.txt").lines().map(|line| {
+            let (bytes, text) = line.split_at(13);
+            (bytes.split_whitespace().map(|b| u8::from_str_radix(b, 16).unwrap()).collect(), text)
+        })
+    }
+
+    /// Encoding what was decoded gives bytes that decode the same, and the
+    /// same bytes whenever there is no prefix to be redundant
+    #[test]
+    fn test_encode_round_trip() {
+        for (prg, text) in golden() {
+            let instruction = disassembler(prg.clone()).disassemble(0).instruction;
+            let bytes = encode(&instruction).unwrap_or_else(|| panic!("{}", text));
+            assert_eq!(text, format!("{}", disassembler(bytes.clone()).disassemble(0).instruction));
+            if ![0xdd, 0xed, 0xfd].contains(&prg[0]) {
+                assert_eq!(prg, bytes, "{}", text);
+            }
+        }
+        assert_eq!(Some(vec![0xdd, 0xcb, 0x05, 0x00]), encode(&disassembler(vec![0xdd, 0xcb, 0x05, 0x00]).instruction()));
+        assert_eq!(Some(vec![0xed, 0x44]), encode(&disassembler(vec![0xed, 0x7c]).instruction()));
+    }
+
+    /// Every line of the disassembler's output assembles to something that
+    /// disassembles to the same line
+    #[test]
+    fn test_assemble_disassembly() {
+        for (_, text) in golden() {
+            let bytes = assemble_bytes(text).unwrap_or_else(|err| panic!("{}: {}", text, err));
+            assert_eq!(text, format!("{}", disassembler(bytes).instruction()));
+        }
+    }
+
+    #[test]
+    fn test_program() {
+        let source = "
+            ; prints through the bdos
+            bdos    equ 5
+            count:  equ done - message
+                    org $100
+            start:  ld de,message
+                    ld c,9
+                    call bdos
+                    ld b,count*2
+            loop:   djnz loop
+                    jr nz,start
+                    ld (ix+offset),'!'
+                    ret
+            message: db \"Hi; there\",'$'
+            done:
+                    dw start, $ + 2
+                    ds 3,$ff
+            offset  = -2
+                    end start
+                    nop
+        ";
+        let program = assemble(source).unwrap();
+        assert_eq!(Some(0x100), program.entry);
+        assert_eq!(
+            vec![Segment {
+                address: 0x100,
+                data: vec![
+                    0x11, 0x13, 0x01, // ld de,message
+                    0x0e, 0x09, // ld c,9
+                    0xcd, 0x05, 0x00, // call bdos
+                    0x06, 0x14, // ld b,count*2
+                    0x10, 0xfe, // djnz loop
+                    0x20, 0xf2, // jr nz,start
+                    0xdd, 0x36, 0xfe, 0x21, // ld (ix-2),'!'
+                    0xc9, // ret
+                    b'H', b'i', b';', b' ', b't', b'h', b'e', b'r', b'e', b'$',
+                    0x00, 0x01, 0x1f, 0x01, // dw
+                    0xff, 0xff, 0xff, // ds
+                ],
+            }],
+            program.segments
+        );
+    }
+
+    #[test]
+    fn test_org_gaps() {
+        assert_eq!(vec![0x3e, 0x12, 0x00, 0x00, 0xc9], assemble_bytes("ld a,$12\norg 4\nret").unwrap());
+        assert_eq!(vec![0x08, 0xe3, 0xdd, 0x7e, 0x00], assemble_bytes("ex af,af'\nex (sp),hl\nld a,(ix)").unwrap());
+        assert_eq!(vec![0x3e, 0x09], assemble_bytes("ld a,(1+2)*3").unwrap());
+
+        // code running past $ffff carries on at 0, like pc
+        let program = assemble("org $fffe\nld hl,$1234\nnop").unwrap();
+        assert_eq!(
+            vec![Segment { address: 0xfffe, data: vec![0x21, 0x34] }, Segment { address: 0, data: vec![0x12, 0x00] }],
+            program.segments
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |source| assemble(source).unwrap_err();
+        assert_eq!(AsmError::UnknownMnemonic { line: 2, mnemonic: "mov".to_string() }, error("nop\nmov a,b"));
+        assert_eq!(AsmError::InvalidOperands { line: 1 }, error("ld (hl),(hl)"));
+        assert_eq!(AsmError::InvalidOperands { line: 1 }, error("ld ixh,h"));
+        assert_eq!(AsmError::InvalidOperands { line: 1 }, error("jr po,0"));
+        assert_eq!(AsmError::UndefinedSymbol { line: 1, name: "nowhere".to_string() }, error("jp nowhere"));
+        assert_eq!(AsmError::DuplicateSymbol { line: 2, name: "a1".to_string() }, error("a1: nop\na1: nop"));
+        assert_eq!(AsmError::OutOfRange { line: 1, value: 0x100 }, error("jr $100"));
+        assert_eq!(AsmError::OutOfRange { line: 1, value: 256 }, error("ld a,256"));
+        assert_eq!(AsmError::Syntax { line: 1 }, error("ld a,1+"));
+    }
+
+    #[test]
+    fn test_run_assembled() {
+        let mut memory = assemble_bytes(
+            "
+                ld hl,0
+                ld b,10
+            add:
+                inc hl
+                djnz add
+                halt
+            ",
+        )
+        .unwrap();
+        memory.resize(0x10000, 0);
+        let mut bus = TestBus { memory };
+        let mut cpu = Z80::new();
+        // the halt is at 8
+        cpu.run_until(&mut bus, |cpu| cpu.pc == 8);
+        assert_eq!(10, cpu.registers.l);
+    }
+}
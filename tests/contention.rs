@@ -0,0 +1,325 @@
+#[cfg(test)]
+mod test_contention {
+    use z80::bus::{Bus, Cycle};
+    use z80::cpu::Z80;
+
+    /// Records every cycle as (address, T-states), internal cycles one
+    /// T-state at a time, like the breakdowns in the Spectrum contention tables
+    struct RecordingBus {
+        memory: Vec<u8>,
+        cycles: Vec<(u16, u8)>,
+    }
+
+    impl Bus for RecordingBus {
+        fn memory_read(&self, address: usize) -> u8 {
+            self.memory[address]
+        }
+
+        fn memory_read_word(&self, address: usize) -> u16 {
+            self.memory[address] as u16 | ((self.memory[address + 1] as u16) << 8)
+        }
+
+        fn memory_write(&mut self, address: usize, value: u8) {
+            self.memory[address] = value;
+        }
+
+        fn memory_write_word(&mut self, address: usize, value: u16) {
+            self.memory[address] = value as u8;
+            self.memory[address + 1] = (value >> 8) as u8;
+        }
+
+        fn cycle(&mut self, cycle: Cycle) -> u8 {
+            match cycle {
+                Cycle::M1 { addr } => self.cycles.push((addr, 4)),
+                Cycle::MemRead { addr } | Cycle::MemWrite { addr } => self.cycles.push((addr, 3)),
+                Cycle::IoRead { port } | Cycle::IoWrite { port } => self.cycles.push((port, 4)),
+                Cycle::IntAck { addr } => self.cycles.push((addr, 6)),
+                Cycle::Internal { addr, n } => {
+                    for _ in 0..n {
+                        self.cycles.push((addr, 1));
+                    }
+                }
+            }
+            0
+        }
+
+        fn tick(&mut self, _: u8, _: u8) {}
+    }
+
+    const PC: u16 = 0x8000;
+    const SP: u16 = 0x9000;
+    const HL: u16 = 0x4000;
+    const DE: u16 = 0x5000;
+    const IX: u16 = 0x6000;
+    /// I is 0x3f, R is one after an unprefixed op code fetch and two after a prefixed one
+    const IR1: u16 = 0x3f01;
+    const IR2: u16 = 0x3f02;
+
+    fn breakdown(prg: &[u8]) -> Vec<(u16, u8)> {
+        let mut bus = RecordingBus { memory: vec![0; 0x10000], cycles: vec![] };
+        for (i, b) in prg.iter().enumerate() {
+            bus.memory[PC as usize + i] = *b;
+        }
+        let mut cpu = Z80::new();
+        cpu.pc = PC;
+        cpu.sp = SP;
+        cpu.registers.h = (HL >> 8) as u8;
+        cpu.registers.l = HL as u8;
+        cpu.registers.d = (DE >> 8) as u8;
+        cpu.registers.e = DE as u8;
+        cpu.registers.b = 0x02;
+        cpu.registers.c = 0x02;
+        cpu.registers.a = 0x12;
+        cpu.registers.f = 0;
+        cpu.registers.ix = IX;
+        cpu.registers.i = 0x3f;
+        cpu.registers.r = 0;
+        cpu.step(&mut bus);
+        bus.cycles
+    }
+
+    fn times(addr: u16, n: usize) -> Vec<(u16, u8)> {
+        vec![(addr, 1); n]
+    }
+
+    #[test]
+    fn test_breakdown_register_pairs() {
+        // inc bc: pc:4,IR:1 x 2
+        assert_eq!(vec![(PC, 4), (IR1, 1), (IR1, 1)], breakdown(&[0x03]));
+        // add hl,bc: pc:4,IR:1 x 7
+        assert_eq!([vec![(PC, 4)], times(IR1, 7)].concat(), breakdown(&[0x09]));
+        // ld sp,hl: pc:4,IR:1 x 2
+        assert_eq!(vec![(PC, 4), (IR1, 1), (IR1, 1)], breakdown(&[0xf9]));
+        // sbc hl,bc: pc:4,pc+1:4,IR:1 x 7
+        assert_eq!([vec![(PC, 4), (PC + 1, 4)], times(IR2, 7)].concat(), breakdown(&[0xed, 0x42]));
+        // ld a,i: pc:4,pc+1:4,IR:1
+        assert_eq!(vec![(PC, 4), (PC + 1, 4), (IR2, 1)], breakdown(&[0xed, 0x57]));
+    }
+
+    #[test]
+    fn test_breakdown_indexed() {
+        // ld a,(ix+5): pc:4,pc+1:4,pc+2:3,pc+2:1 x 5,ix+n:3
+        assert_eq!(
+            [vec![(PC, 4), (PC + 1, 4), (PC + 2, 3)], times(PC + 2, 5), vec![(IX + 5, 3)]].concat(),
+            breakdown(&[0xdd, 0x7e, 0x05])
+        );
+        // ld (ix+5),n: pc:4,pc+1:4,pc+2:3,pc+3:3,pc+3:1 x 2,ix+n:3
+        assert_eq!(
+            [vec![(PC, 4), (PC + 1, 4), (PC + 2, 3), (PC + 3, 3)], times(PC + 3, 2), vec![(IX + 5, 3)]].concat(),
+            breakdown(&[0xdd, 0x36, 0x05, 0x12])
+        );
+        // inc (ix+5): pc:4,pc+1:4,pc+2:3,pc+2:1 x 5,ix+n:3,ix+n:1,ix+n(write):3
+        assert_eq!(
+            [
+                vec![(PC, 4), (PC + 1, 4), (PC + 2, 3)],
+                times(PC + 2, 5),
+                vec![(IX + 5, 3), (IX + 5, 1), (IX + 5, 3)]
+            ]
+            .concat(),
+            breakdown(&[0xdd, 0x34, 0x05])
+        );
+        // rlc (ix+5): pc:4,pc+1:4,pc+2:3,pc+3:3,pc+3:1 x 2,ix+n:3,ix+n:1,ix+n(write):3
+        assert_eq!(
+            [
+                vec![(PC, 4), (PC + 1, 4), (PC + 2, 3), (PC + 3, 3)],
+                times(PC + 3, 2),
+                vec![(IX + 5, 3), (IX + 5, 1), (IX + 5, 3)]
+            ]
+            .concat(),
+            breakdown(&[0xdd, 0xcb, 0x05, 0x06])
+        );
+    }
+
+    #[test]
+    fn test_breakdown_memory() {
+        // inc (hl): pc:4,hl:3,hl:1,hl(write):3
+        assert_eq!(vec![(PC, 4), (HL, 3), (HL, 1), (HL, 3)], breakdown(&[0x34]));
+        // bit 0,(hl): pc:4,pc+1:4,hl:3,hl:1
+        assert_eq!(vec![(PC, 4), (PC + 1, 4), (HL, 3), (HL, 1)], breakdown(&[0xcb, 0x46]));
+        // rld: pc:4,pc+1:4,hl:3,hl:1 x 4,hl(write):3
+        assert_eq!(
+            [vec![(PC, 4), (PC + 1, 4), (HL, 3)], times(HL, 4), vec![(HL, 3)]].concat(),
+            breakdown(&[0xed, 0x6f])
+        );
+        // ldir: pc:4,pc+1:4,hl:3,de:3,de:1 x 2,de:1 x 5
+        assert_eq!(
+            [vec![(PC, 4), (PC + 1, 4), (HL, 3), (DE, 3)], times(DE, 7)].concat(),
+            breakdown(&[0xed, 0xb0])
+        );
+        // cpir: pc:4,pc+1:4,hl:3,hl:1 x 5,hl:1 x 5
+        assert_eq!(
+            [vec![(PC, 4), (PC + 1, 4), (HL, 3)], times(HL, 10)].concat(),
+            breakdown(&[0xed, 0xb1])
+        );
+    }
+
+    #[test]
+    fn test_breakdown_stack_and_jumps() {
+        // push bc: pc:4,IR:1,sp-1:3,sp-2:3
+        assert_eq!(vec![(PC, 4), (IR1, 1), (SP - 1, 3), (SP - 2, 3)], breakdown(&[0xc5]));
+        // ex (sp),hl: pc:4,sp:3,sp+1:3,sp+1:1,sp+1(write):3,sp(write):3,sp(write):1 x 2
+        assert_eq!(
+            vec![(PC, 4), (SP, 3), (SP + 1, 3), (SP + 1, 1), (SP + 1, 3), (SP, 3), (SP, 1), (SP, 1)],
+            breakdown(&[0xe3])
+        );
+        // call nn: pc:4,pc+1:3,pc+2:3,pc+2:1,sp-1:3,sp-2:3
+        assert_eq!(
+            vec![(PC, 4), (PC + 1, 3), (PC + 2, 3), (PC + 2, 1), (SP - 1, 3), (SP - 2, 3)],
+            breakdown(&[0xcd, 0x00, 0x00])
+        );
+        // ret nz: pc:4,IR:1,sp:3,sp+1:3
+        assert_eq!(vec![(PC, 4), (IR1, 1), (SP, 3), (SP + 1, 3)], breakdown(&[0xc0]));
+        // jr: pc:4,pc+1:3,pc+1:1 x 5
+        assert_eq!([vec![(PC, 4), (PC + 1, 3)], times(PC + 1, 5)].concat(), breakdown(&[0x18, 0x00]));
+        // djnz taken: pc:4,IR:1,pc+1:3,pc+1:1 x 5
+        assert_eq!(
+            [vec![(PC, 4), (IR1, 1), (PC + 1, 3)], times(PC + 1, 5)].concat(),
+            breakdown(&[0x10, 0x00])
+        );
+    }
+
+    #[test]
+    fn test_breakdown_io() {
+        // out (n),a: pc:4,pc+1:3,IO
+        assert_eq!(vec![(PC, 4), (PC + 1, 3), (0x12fe, 4)], breakdown(&[0xd3, 0xfe]));
+        // outi: pc:4,pc+1:4,IR:1,hl:3,IO with b already decremented
+        assert_eq!(
+            vec![(PC, 4), (PC + 1, 4), (IR2, 1), (HL, 3), (0x0102, 4)],
+            breakdown(&[0xed, 0xa3])
+        );
+        // inir: pc:4,pc+1:4,IR:1,IO,hl:3,hl:1 x 5
+        assert_eq!(
+            [vec![(PC, 4), (PC + 1, 4), (IR2, 1), (0x0202, 4), (HL, 3)], times(HL, 5)].concat(),
+            breakdown(&[0xed, 0xb2])
+        );
+    }
+
+    /// 48K Spectrum: 0x4000-0x7fff is contended while the ULA draws the
+    /// screen, 128 T-states of every 224 T-state line starting at 14335
+    struct SpectrumBus {
+        memory: Vec<u8>,
+        t: u32,
+    }
+
+    impl SpectrumBus {
+        fn delay(&self, t: u32) -> u8 {
+            if !(14335..14335 + 192 * 224).contains(&t) || (t - 14335) % 224 >= 128 {
+                return 0;
+            }
+            [6, 5, 4, 3, 2, 1, 0, 0][((t - 14335) % 8) as usize]
+        }
+
+        fn contended(addr: u16) -> bool {
+            (0x4000..0x8000).contains(&addr)
+        }
+
+        /// Delay for `pattern` of (contended, T-states) starting now
+        fn delay_pattern(&self, pattern: &[(bool, u32)]) -> u8 {
+            let mut t = self.t;
+            let mut wait = 0;
+            for &(contended, n) in pattern {
+                if contended {
+                    let d = self.delay(t);
+                    wait += d;
+                    t += d as u32;
+                }
+                t += n;
+            }
+            wait
+        }
+    }
+
+    impl Bus for SpectrumBus {
+        fn memory_read(&self, address: usize) -> u8 {
+            self.memory[address]
+        }
+
+        fn memory_read_word(&self, address: usize) -> u16 {
+            self.memory[address] as u16 | ((self.memory[address + 1] as u16) << 8)
+        }
+
+        fn memory_write(&mut self, address: usize, value: u8) {
+            self.memory[address] = value;
+        }
+
+        fn memory_write_word(&mut self, address: usize, value: u16) {
+            self.memory[address] = value as u8;
+            self.memory[address + 1] = (value >> 8) as u8;
+        }
+
+        fn cycle(&mut self, cycle: Cycle) -> u8 {
+            match cycle {
+                Cycle::M1 { addr } | Cycle::MemRead { addr } | Cycle::MemWrite { addr } => {
+                    if Self::contended(addr) { self.delay(self.t) } else { 0 }
+                }
+                Cycle::Internal { addr, n } => {
+                    let contended = Self::contended(addr);
+                    self.delay_pattern(&vec![(contended, 1); n as usize])
+                }
+                Cycle::IoRead { port } | Cycle::IoWrite { port } => {
+                    let high = Self::contended(port);
+                    match (high, port & 1 == 0) {
+                        (true, true) => self.delay_pattern(&[(true, 1), (true, 3)]),
+                        (true, false) => self.delay_pattern(&[(true, 1); 4]),
+                        (false, true) => self.delay_pattern(&[(false, 1), (true, 3)]),
+                        (false, false) => 0,
+                    }
+                }
+                Cycle::IntAck { .. } => 0,
+            }
+        }
+
+        fn tick(&mut self, _: u8, t_states: u8) {
+            self.t += t_states as u32;
+        }
+    }
+
+    fn run_at(t: u32, pc: u16, prg: &[u8], setup: impl FnOnce(&mut Z80)) -> u32 {
+        let mut bus = SpectrumBus { memory: vec![0; 0x10000], t };
+        for (i, b) in prg.iter().enumerate() {
+            bus.memory[pc as usize + i] = *b;
+        }
+        let mut cpu = Z80::new();
+        cpu.pc = pc;
+        setup(&mut cpu);
+        let t_states = cpu.step(&mut bus);
+        assert_eq!(t + t_states, bus.t);
+        t_states
+    }
+
+    #[test]
+    fn test_contended_fetch() {
+        // nop from uncontended memory
+        assert_eq!(4, run_at(14335, 0x8000, &[0x00], |_| {}));
+        // from contended memory at the start of the screen, and later in the pattern
+        assert_eq!(10, run_at(14335, 0x4000, &[0x00], |_| {}));
+        assert_eq!(4, run_at(14341, 0x4000, &[0x00], |_| {}));
+        // in the right border
+        assert_eq!(4, run_at(14335 + 128, 0x4000, &[0x00], |_| {}));
+        // before the screen
+        assert_eq!(4, run_at(14000, 0x4000, &[0x00], |_| {}));
+    }
+
+    #[test]
+    fn test_contended_read() {
+        // ld a,(0x4000): the read starts at 14345, 6 into the pattern
+        assert_eq!(13 + 4, run_at(14335, 0x8000, &[0x3a, 0x00, 0x40], |_| {}));
+    }
+
+    #[test]
+    fn test_contended_ir() {
+        // inc bc with I pointing at contended memory: IR:1 at 14335 waits 6
+        assert_eq!(6 + 6, run_at(14331, 0x8000, &[0x03], |cpu| cpu.registers.i = 0x40));
+        assert_eq!(6, run_at(14331, 0x8000, &[0x03], |cpu| cpu.registers.i = 0x80));
+    }
+
+    #[test]
+    fn test_contended_io() {
+        // out (0xfe),a: N:1, C:3 with the C at 14343
+        assert_eq!(11 + 6, run_at(14335, 0x8000, &[0xd3, 0xfe], |cpu| cpu.registers.a = 0));
+        // in a,(0xff) with a=0x40: C:1 x 4 from 14342
+        assert_eq!(11 + 12, run_at(14335, 0x8000, &[0xdb, 0xff], |cpu| cpu.registers.a = 0x40));
+        // out (0xff),a with an uncontended address: N:4
+        assert_eq!(11, run_at(14335, 0x8000, &[0xd3, 0xff], |cpu| cpu.registers.a = 0));
+    }
+}
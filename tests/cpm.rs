@@ -0,0 +1,250 @@
+#[cfg(test)]
+mod test_cpm {
+    use std::fs;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::process::{Command, Stdio};
+
+    use z80::cpm::{CpmError, CpmMachine};
+
+    const FCB: u16 = 0x300;
+    const DMA: usize = 0x400;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("z80-cpm-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn machine(dir: &PathBuf, input: &[u8]) -> CpmMachine<Vec<u8>> {
+        CpmMachine::with_console(dir, Cursor::new(input.to_vec()), Vec::new())
+    }
+
+    /// Runs a program that sets the dma to 0x400, calls BDOS and returns a.
+    /// It ends with function 0, so an fcb that wraps over the warm boot
+    /// vector does not matter.
+    fn bdos(machine: &mut CpmMachine<Vec<u8>>, c: u8, de: u16) -> u8 {
+        let program = [
+            0x0e, 0x1a, 0x11, 0x00, 0x04, 0xcd, 0x05, 0x00, // ld c,26; ld de,0x400; call 5
+            0x0e, c, 0x11, de as u8, (de >> 8) as u8, 0xcd, 0x05, 0x00, // ld c,c; ld de,de; call 5
+            0x32, 0x00, 0x02, 0x0e, 0x00, 0xc3, 0x05, 0x00, // ld (0x200),a; ld c,0; jp 5
+        ];
+        let saved = machine.memory.bytes[DMA..DMA + 128].to_vec();
+        machine.load(&program, &[]).unwrap();
+        machine.memory.bytes[DMA..DMA + 128].copy_from_slice(&saved);
+        machine.run().unwrap();
+        machine.memory.bytes[0x200]
+    }
+
+    fn set_fcb(machine: &mut CpmMachine<Vec<u8>>, at: u16, name: &[u8; 11]) {
+        let at = at as usize;
+        machine.memory.bytes[at..at + 36].fill(0);
+        machine.memory.bytes[at + 1..at + 12].copy_from_slice(name);
+    }
+
+    #[test]
+    fn test_console_output() {
+        let dir = PathBuf::from(".");
+        let mut machine = machine(&dir, b"");
+        // ld de,0x110; ld c,9; call 5; ld e,'!'; ld c,2; call 5; ret
+        let mut program = vec![0x11, 0x10, 0x01, 0x0e, 0x09, 0xcd, 0x05, 0x00, 0x1e, 0x21, 0x0e, 0x02, 0xcd, 0x05, 0x00, 0xc9];
+        program.extend_from_slice(b"hello$");
+        machine.load(&program, &[]).unwrap();
+        machine.run().unwrap();
+        assert_eq!(b"hello!", machine.output().as_slice());
+    }
+
+    #[test]
+    fn test_read_line() {
+        let dir = PathBuf::from(".");
+        let mut machine = machine(&dir, b"abc\x08d\nrest");
+        let mut program = vec![
+            0x11, 0x20, 0x01, 0x0e, 0x0a, 0xcd, 0x05, 0x00, // ld de,0x120; ld c,10; call 5
+            0x21, 0x21, 0x01, 0x5e, 0x16, 0x00, 0x23, 0x19, // ld hl,0x121; ld e,(hl); ld d,0; inc hl; add hl,de
+            0x36, 0x24, 0x11, 0x22, 0x01, 0x0e, 0x09, 0xcd, // ld (hl),'$'; ld de,0x122; ld c,9; call 5
+            0x05, 0x00, 0xc3, 0x00, 0x00, // jp 0
+        ];
+        program.resize(0x20, 0);
+        program.push(10);
+        machine.load(&program, &[]).unwrap();
+        machine.run().unwrap();
+        assert_eq!(b"abd", machine.output().as_slice());
+        assert_eq!(3, machine.memory.bytes[0x121]);
+    }
+
+    #[test]
+    fn test_stops_when_input_ends() {
+        let dir = PathBuf::from(".");
+        let mut machine = machine(&dir, b"ab\n");
+        // ld c,1; call 5; ld e,a; ld c,2; call 5; jr 0x100
+        let program = [0x0e, 0x01, 0xcd, 0x05, 0x00, 0x5f, 0x0e, 0x02, 0xcd, 0x05, 0x00, 0x18, 0xf3];
+        machine.load(&program, &[]).unwrap();
+        machine.run().unwrap();
+        assert_eq!(b"ab\r", machine.output().as_slice());
+    }
+
+    #[test]
+    fn test_command_line() {
+        let dir = PathBuf::from(".");
+        let mut machine = machine(&dir, b"");
+        machine.load(&[0xc9], &["foo.txt", "b:*.com"]).unwrap();
+        assert_eq!(b"FOO     TXT", &machine.memory.bytes[0x5d..0x68]);
+        assert_eq!(b"????????COM", &machine.memory.bytes[0x6d..0x78]);
+        assert_eq!(16, machine.memory.bytes[0x80]);
+        assert_eq!(b" FOO.TXT B:*.COM", &machine.memory.bytes[0x81..0x91]);
+        assert_eq!(0x100, machine.cpu.pc);
+
+        assert!(matches!(machine.load(&vec![0; 0xff00], &[]), Err(CpmError::TooLarge(0xff00))));
+        machine.load(&[0x0e, 99, 0xcd, 0x05, 0x00], &[]).unwrap();
+        assert!(matches!(machine.run(), Err(CpmError::UnsupportedFunction(99))));
+    }
+
+    /// Function 9 with no `$` in memory is an error rather than a hang
+    #[test]
+    fn test_unterminated_string() {
+        let dir = PathBuf::from(".");
+        let mut machine = machine(&dir, b"");
+        machine.memory.bytes.iter_mut().filter(|b| **b == b'$').for_each(|b| *b = 0);
+        // ld de,$8000; ld c,9; call 5
+        machine.load(&[0x11, 0x00, 0x80, 0x0e, 0x09, 0xcd, 0x05, 0x00], &[]).unwrap();
+        assert!(matches!(machine.run(), Err(CpmError::UnterminatedString(0x8000))));
+        assert!(machine.output().is_empty());
+    }
+
+    #[test]
+    fn test_read_file() {
+        let dir = temp_dir("read");
+        let data: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        fs::write(dir.join("Data.txt"), &data).unwrap();
+        let mut machine = machine(&dir, b"");
+
+        set_fcb(&mut machine, FCB, b"MISSING    ");
+        assert_eq!(0xff, bdos(&mut machine, 15, FCB));
+
+        set_fcb(&mut machine, FCB, b"DATA    TXT");
+        assert_eq!(0, bdos(&mut machine, 15, FCB));
+        assert_eq!(2, machine.memory.bytes[FCB as usize + 15]);
+        assert_eq!(0, bdos(&mut machine, 20, FCB));
+        assert_eq!(data[..128], machine.memory.bytes[DMA..DMA + 128]);
+        assert_eq!(0, bdos(&mut machine, 20, FCB));
+        assert_eq!(data[128..], machine.memory.bytes[DMA..DMA + 72]);
+        assert_eq!([0x1a; 56], machine.memory.bytes[DMA + 72..DMA + 128]);
+        assert_eq!(1, bdos(&mut machine, 20, FCB));
+        assert_eq!(2, machine.memory.bytes[FCB as usize + 32]);
+
+        // random read of record 0 also moves the sequential position
+        assert_eq!(0, bdos(&mut machine, 33, FCB));
+        assert_eq!(data[..128], machine.memory.bytes[DMA..DMA + 128]);
+        assert_eq!(0, machine.memory.bytes[FCB as usize + 32]);
+
+        assert_eq!(0, bdos(&mut machine, 35, FCB));
+        assert_eq!([2, 0, 0], machine.memory.bytes[FCB as usize + 33..FCB as usize + 36]);
+        assert_eq!(0, bdos(&mut machine, 16, FCB));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_file() {
+        let dir = temp_dir("write");
+        let mut machine = machine(&dir, b"");
+
+        set_fcb(&mut machine, FCB, b"NEW     DAT");
+        assert_eq!(0, bdos(&mut machine, 22, FCB));
+        machine.memory.bytes[DMA..DMA + 128].fill(0x55);
+        assert_eq!(0, bdos(&mut machine, 21, FCB));
+        machine.memory.bytes[FCB as usize + 33] = 3;
+        machine.memory.bytes[DMA..DMA + 128].fill(0xaa);
+        assert_eq!(0, bdos(&mut machine, 34, FCB));
+        assert_eq!(0, bdos(&mut machine, 16, FCB));
+
+        let written = fs::read(dir.join("new.dat")).unwrap();
+        assert_eq!(512, written.len());
+        assert_eq!([0x55; 128], written[..128]);
+        assert_eq!([0xaa; 128], written[384..]);
+
+        set_fcb(&mut machine, FCB, b"NEW     DAT");
+        machine.memory.bytes[FCB as usize + 17..FCB as usize + 28].copy_from_slice(b"OLD     DAT");
+        assert_eq!(0, bdos(&mut machine, 23, FCB));
+        assert!(dir.join("old.dat").exists());
+        assert!(!dir.join("new.dat").exists());
+
+        set_fcb(&mut machine, FCB, b"OLD     DAT");
+        assert_eq!(0, bdos(&mut machine, 19, FCB));
+        assert_eq!(0xff, bdos(&mut machine, 19, FCB));
+        assert!(!dir.join("old.dat").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_search() {
+        let dir = temp_dir("search");
+        for name in ["b.com", "A.COM", "c.txt", "toolongname.com"].iter() {
+            fs::write(dir.join(name), b"x").unwrap();
+        }
+        let mut machine = machine(&dir, b"");
+
+        set_fcb(&mut machine, FCB, b"????????COM");
+        assert_eq!(0, bdos(&mut machine, 17, FCB));
+        assert_eq!(b"A       COM", &machine.memory.bytes[DMA + 1..DMA + 12]);
+        assert_eq!(1, machine.memory.bytes[DMA + 15]);
+        assert_eq!(0, bdos(&mut machine, 18, FCB));
+        assert_eq!(b"B       COM", &machine.memory.bytes[DMA + 1..DMA + 12]);
+        assert_eq!(0xff, bdos(&mut machine, 18, FCB));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// An fcb or line buffer at the top of memory wraps to 0 like the dma
+    #[test]
+    fn test_wrapping_addresses() {
+        let dir = temp_dir("wrap");
+        let data: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        fs::write(dir.join("data.txt"), &data).unwrap();
+        let mut machine = machine(&dir, b"");
+
+        let fcb = 0xffe0;
+        machine.memory.bytes[fcb..].fill(0);
+        machine.memory.bytes[fcb + 1..fcb + 12].copy_from_slice(b"DATA    TXT");
+        machine.memory.bytes[0..4].fill(0);
+        assert_eq!(0, bdos(&mut machine, 15, fcb as u16));
+        assert_eq!(2, machine.memory.bytes[fcb + 15]);
+        assert_eq!(0, bdos(&mut machine, 20, fcb as u16));
+        assert_eq!(data[..128], machine.memory.bytes[DMA..DMA + 128]);
+        // cr is at 0x10000 and the random record at 0x10001
+        assert_eq!(1, machine.memory.bytes[0]);
+        assert_eq!(0, bdos(&mut machine, 36, fcb as u16));
+        assert_eq!([1, 0, 0], machine.memory.bytes[1..4]);
+        assert_eq!(0, bdos(&mut machine, 33, fcb as u16));
+        assert_eq!(data[128..], machine.memory.bytes[DMA..DMA + 72]);
+        assert_eq!(0, bdos(&mut machine, 35, fcb as u16));
+        assert_eq!([2, 0, 0], machine.memory.bytes[1..4]);
+        assert_eq!(0, bdos(&mut machine, 16, fcb as u16));
+
+        let mut machine = self::machine(&dir, b"hi\n");
+        machine.memory.bytes[0xffff] = 10;
+        assert_eq!(0, bdos(&mut machine, 10, 0xffff));
+        assert_eq!([2, b'h', b'i'], machine.memory.bytes[0..3]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// `z80-cpm --dir roms zexall.com` as in the readme, and the bare name
+    #[test]
+    fn test_binary_finds_program_on_drive() {
+        let dir = temp_dir("binary");
+        // ld de,0x109; ld c,9; call 5; ret; "hi$"
+        fs::write(dir.join("hello.com"), b"\x11\x09\x01\x0e\x09\xcd\x05\x00\xc9hi$").unwrap();
+        for program in ["hello.com", "hello"].iter() {
+            let output = Command::new(env!("CARGO_BIN_EXE_z80-cpm"))
+                .arg("--dir")
+                .arg(&dir)
+                .arg(program)
+                .current_dir(std::env::temp_dir())
+                .stdin(Stdio::null())
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{}: {}", program, String::from_utf8_lossy(&output.stderr));
+            assert_eq!(b"hi", output.stdout.as_slice());
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
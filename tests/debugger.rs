@@ -0,0 +1,245 @@
+
+
+#[cfg(test)]
+mod test_debugger {
+    use z80::assembler::assemble_bytes;
+    use z80::bus::Bus;
+    use z80::cpu::Z80;
+    use z80::debugger::{Access, Break, Condition, ConditionError, Debugger};
+
+    struct TestBus {
+        memory: Vec<u8>,
+        pub out: Vec<(u16, u8)>,
+    }
+
+    impl TestBus {
+        fn new(source: &str) -> TestBus {
+            let mut memory = assemble_bytes(source).unwrap();
+            memory.resize(0x10000, 0);
+            TestBus { memory, out: vec![] }
+        }
+    }
+
+    impl Bus for TestBus {
+        fn memory_read(&self, address: usize) -> u8 {
+            self.memory[address]
+        }
+
+        fn memory_read_word(&self, address: usize) -> u16 {
+            self.memory[address] as u16 | ((self.memory[address + 1] as u16) << 8)
+        }
+
+        fn memory_write(&mut self, address: usize, value: u8) {
+            self.memory[address] = value;
+        }
+
+        fn memory_write_word(&mut self, address: usize, value: u16) {
+            self.memory[address] = value as u8;
+            self.memory[address + 1] = (value >> 8) as u8;
+        }
+
+        fn io_read(&mut self, port: u16) -> u8 {
+            (port >> 8) as u8
+        }
+
+        fn io_write(&mut self, port: u16, value: u8) {
+            self.out.push((port, value));
+        }
+
+        #[allow(unused_variables)]
+        fn tick(&mut self, machine_cycles: u8, t_states: u8) {}
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut bus = TestBus::new(
+            "
+                ld b,3
+            loop:
+                inc a       ; 2
+                djnz loop
+                halt        ; 5
+            ",
+        );
+        let mut cpu = Z80::new();
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(2);
+        debugger.add_breakpoint(5);
+        assert_eq!(Break::Breakpoint(2), debugger.run_until_break(&mut cpu, &mut bus));
+        assert_eq!(0, cpu.registers.a);
+        // carrying on runs the instruction at the breakpoint
+        assert_eq!(Break::Breakpoint(2), debugger.run_until_break(&mut cpu, &mut bus));
+        assert_eq!(1, cpu.registers.a);
+        assert!(debugger.remove_breakpoint(2));
+        assert!(!debugger.remove_breakpoint(2));
+        assert_eq!(Break::Breakpoint(5), debugger.run_until_break(&mut cpu, &mut bus));
+        assert_eq!(3, cpu.registers.a);
+    }
+
+    /// ldir stops after the iteration that touched the watched byte, ready
+    /// to carry on with the next one
+    #[test]
+    fn test_watchpoint_in_ldir() {
+        let mut bus = TestBus::new(
+            "
+                ld hl,$100
+                ld de,$200
+                ld bc,16
+                ldir        ; 9
+                halt        ; 11
+            ",
+        );
+        for i in 0..16 {
+            bus.memory[0x100 + i] = 0x40 + i as u8;
+        }
+        let mut cpu = Z80::new();
+        let mut debugger = Debugger::new();
+        debugger.add_watchpoint(0x205..=0x206, Access::Write);
+        debugger.add_watchpoint(0x10a..=0x10a, Access::ReadWrite);
+        debugger.add_breakpoint(11);
+
+        assert_eq!(Break::Watchpoint { address: 0x205, access: Access::Write, value: 0x45 }, debugger.run_until_break(&mut cpu, &mut bus));
+        assert_eq!(9, cpu.pc);
+        let state = cpu.snapshot();
+        assert_eq!((10, 0x106, 0x206), (state.bc, state.hl, state.de));
+        assert_eq!(0, bus.memory[0x206]);
+
+        assert_eq!(Break::Watchpoint { address: 0x206, access: Access::Write, value: 0x46 }, debugger.run_until_break(&mut cpu, &mut bus));
+        assert_eq!(Break::Watchpoint { address: 0x10a, access: Access::Read, value: 0x4a }, debugger.run_until_break(&mut cpu, &mut bus));
+        assert_eq!(Break::Breakpoint(11), debugger.run_until_break(&mut cpu, &mut bus));
+        assert_eq!(&bus.memory[0x100..0x110], &bus.memory[0x200..0x210]);
+
+        // op code fetches are not reads, prefixed or not
+        assert!(debugger.remove_watchpoint(0x10a..=0x10a, Access::ReadWrite));
+        assert!(debugger.remove_watchpoint(0x205..=0x206, Access::Write));
+        debugger.add_watchpoint(0..=0, Access::Read);
+        debugger.add_watchpoint(9..=10, Access::Read);
+        cpu.pc = 0;
+        assert_eq!(Break::Breakpoint(11), debugger.run_until_break(&mut cpu, &mut bus));
+    }
+
+    #[test]
+    fn test_ports() {
+        let mut bus = TestBus::new(
+            "
+                ld a,$12
+                out ($fe),a
+                ld bc,$34fe
+                in d,(c)
+                halt
+            ",
+        );
+        let mut cpu = Z80::new();
+        let mut debugger = Debugger::new();
+        debugger.add_port_break(0xfe..=0xfe, Access::ReadWrite);
+        assert_eq!(Break::Port { port: 0x12fe, access: Access::Write, value: 0x12 }, debugger.run_until_break(&mut cpu, &mut bus));
+        assert_eq!(vec![(0x12fe, 0x12)], bus.out);
+        assert_eq!(Break::Port { port: 0x34fe, access: Access::Read, value: 0x34 }, debugger.run_until_break(&mut cpu, &mut bus));
+        assert_eq!(0x34, cpu.registers.d);
+        assert_eq!(9, cpu.pc);
+    }
+
+    #[test]
+    fn test_interrupt_acknowledge() {
+        let mut bus = TestBus::new(
+            "
+                im 1
+                ei
+            loop:
+                jr loop
+                org $38
+                reti
+            ",
+        );
+        let mut cpu = Z80::new();
+        let mut debugger = Debugger::new();
+        debugger.break_on_interrupt = true;
+        debugger.add_breakpoint(0x1000);
+        cpu.run_until(&mut bus, |cpu| cpu.pc == 3);
+        cpu.set_int_line(true);
+        assert_eq!(Break::InterruptAcknowledge, debugger.run_until_break(&mut cpu, &mut bus));
+        assert_eq!(0x38, cpu.pc);
+        assert_eq!(3, bus.memory_read_word(cpu.sp as usize));
+    }
+
+    /// Taking the interrupt is a step of its own, so a breakpoint at the
+    /// start of the handler is seen before it runs
+    #[test]
+    fn test_breakpoint_on_handler() {
+        let mut bus = TestBus::new(
+            "
+                im 1
+                ei
+            loop:
+                jr loop
+                org $38
+                reti
+            ",
+        );
+        let mut cpu = Z80::new();
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x38);
+        cpu.run_until(&mut bus, |cpu| cpu.pc == 3);
+        cpu.set_int_line(true);
+        assert_eq!(Break::Breakpoint(0x38), debugger.run_until_break(&mut cpu, &mut bus));
+        assert_eq!(0x38, cpu.pc);
+    }
+
+    /// After a stop that was not for a breakpoint, one at the pc it stopped
+    /// at is still reported
+    #[test]
+    fn test_breakpoint_after_watchpoint() {
+        let mut bus = TestBus::new(
+            "
+                ld ($200),a
+                nop         ; 3
+                nop
+                halt        ; 5
+            ",
+        );
+        let mut cpu = Z80::new();
+        let mut debugger = Debugger::new();
+        debugger.add_watchpoint(0x200..=0x200, Access::Write);
+        debugger.add_breakpoint(3);
+        debugger.add_breakpoint(5);
+        assert_eq!(Break::Watchpoint { address: 0x200, access: Access::Write, value: 0 }, debugger.run_until_break(&mut cpu, &mut bus));
+        assert_eq!(Break::Breakpoint(3), debugger.run_until_break(&mut cpu, &mut bus));
+        assert_eq!(Break::Breakpoint(5), debugger.run_until_break(&mut cpu, &mut bus));
+    }
+
+    #[test]
+    fn test_conditions() {
+        let mut bus = TestBus::new(
+            "
+            loop:
+                inc a
+                jr loop
+            ",
+        );
+        let mut cpu = Z80::new();
+        cpu.registers.h = 0x01;
+        let mut debugger = Debugger::new();
+        assert_eq!(0, debugger.add_condition("a == $3f && (hl) != 0").unwrap());
+        assert_eq!(1, debugger.add_condition("a >= 0x40").unwrap());
+        assert_eq!("a == $3f && (hl) != 0", debugger.conditions()[0].to_string());
+        assert_eq!(Break::Condition(1), debugger.run_until_break(&mut cpu, &mut bus));
+        assert_eq!(0x40, cpu.registers.a);
+
+        bus.memory[0x100] = 1;
+        cpu.registers.a = 0;
+        assert_eq!(Break::Condition(0), debugger.run_until_break(&mut cpu, &mut bus));
+        assert_eq!((0x3f, 1), (cpu.registers.a, cpu.pc));
+
+        let holds = |text: &str| Condition::parse(text).unwrap().evaluate(&cpu, &bus);
+        assert!(holds("pc - 1 == 0 && (pc) == $18 && hl == $100 && bc' == 0"));
+        assert!(holds("!(0) == 0 && !0 && ~0 != 0 && -1 == $ffffffff && 0ffh == %11111111"));
+        assert!(holds("(hl - $100) == $3c | 0 && A < 2 + $3f"));
+
+        let error = |text: &str| Condition::parse(text).unwrap_err();
+        assert_eq!(ConditionError { position: 0 }, error("q == 1"));
+        assert_eq!(ConditionError { position: 5 }, error("a ==\t"));
+        assert_eq!(ConditionError { position: 5 }, error("a == $"));
+        assert_eq!(ConditionError { position: 4 }, error("(hl b"));
+        assert_eq!(ConditionError { position: 2 }, error("a b"));
+    }
+}
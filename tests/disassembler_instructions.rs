@@ -2,14 +2,165 @@
 
 #[cfg(test)]
 mod test_disassembler_instructions {
+    use z80::bus::Bus;
     use z80::disassembler::instruction::*;
-    
+    use z80::cpu::Z80;
+    use z80::disassembler::{BusMemory, Disassembler, Image, TStates};
 
     use z80::registers::Reg8;
 
+    struct TestBus {
+        memory: Vec<u8>,
+    }
+
+    impl Bus for TestBus {
+        fn memory_read(&self, address: usize) -> u8 {
+            self.memory[address]
+        }
+
+        fn memory_read_word(&self, address: usize) -> u16 {
+            self.memory[address] as u16 | ((self.memory[address + 1] as u16) << 8)
+        }
+
+        fn memory_write(&mut self, address: usize, value: u8) {
+            self.memory[address] = value;
+        }
+
+        fn memory_write_word(&mut self, address: usize, value: u16) {
+            self.memory[address] = value as u8;
+            self.memory[address + 1] = (value >> 8) as u8;
+        }
+
+        #[allow(unused_variables)]
+        fn tick(&mut self, machine_cycles: u8, t_states: u8) {}
+    }
+
+    fn disassembler(mut prg: Vec<u8>) -> Disassembler<'static> {
+        prg.resize(0x10000, 0);
+        Disassembler::new(prg, 0)
+    }
+
+    fn disassemble(prg: Vec<u8>) -> String {
+        format!("{}", disassembler(prg).instruction())
+    }
+
+    fn t_states(prg: Vec<u8>) -> TStates {
+        disassembler(prg).disassemble(0).t_states
+    }
+
     #[test]
     fn test_output() {
         let instr = Instruction::ADD8(Arg8::Register(Reg8::A), Arg8::Register(Reg8::B));
         assert_eq!("add a,b", format!("{}", instr));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_ddcb_output() {
+        assert_eq!("rlc (ix+5),b", disassemble(vec![0xdd, 0xcb, 0x05, 0x00]));
+        assert_eq!("set 3,(iy-2),a", disassemble(vec![0xfd, 0xcb, 0xfe, 0xdf]));
+        assert_eq!("res 0,(ix+1)", disassemble(vec![0xdd, 0xcb, 0x01, 0x86]));
+        assert_eq!("bit 7,(ix+0)", disassemble(vec![0xdd, 0xcb, 0x00, 0x78]));
+    }
+
+    #[test]
+    fn test_index_and_relative_output() {
+        assert_eq!("ld (ix-123),$34", disassemble(vec![0xdd, 0x36, 0x85, 0x34]));
+        assert_eq!("inc (iy+0)", disassemble(vec![0xfd, 0x34, 0x00]));
+        assert_eq!("jr nz,$-2", disassemble(vec![0x20, 0xfc]));
+        assert_eq!("djnz $+0", disassemble(vec![0x10, 0xfe]));
+        assert_eq!("jp (ix)", disassemble(vec![0xdd, 0xe9]));
+        assert_eq!("ret po", disassemble(vec![0xe0]));
+        // the last of several prefixes wins
+        assert_eq!("ld iy,$1234", disassemble(vec![0xdd, 0xfd, 0x21, 0x34, 0x12]));
+    }
+
+    /// Every opcode of the seven tables against a listing from an independent
+    /// decoder, with the operand bytes each one takes
+    #[test]
+    fn test_all_opcodes_golden() {
+        let listing = include_str!("data/disassembly.txt");
+        assert_eq!(1792, listing.lines().count());
+        for line in listing.lines() {
+            let (bytes, expected) = line.split_at(13);
+            let prg: Vec<u8> = bytes.split_whitespace().map(|b| u8::from_str_radix(b, 16).unwrap()).collect();
+            let decoded = disassembler(prg.clone()).disassemble(0);
+            assert_eq!(expected, format!("{}", decoded.instruction), "{}", bytes.trim());
+            assert_eq!(prg, decoded.bytes);
+            assert_eq!(prg.len(), decoded.length);
+        }
+    }
+
+    #[test]
+    fn test_decoded_instruction() {
+        let decoded = disassembler(vec![0x00, 0xdd, 0x36, 0x85, 0x34]).disassemble(1);
+        assert_eq!(1, decoded.address);
+        assert_eq!(vec![0xdd, 0x36, 0x85, 0x34], decoded.bytes);
+        assert_eq!(4, decoded.length);
+        assert_eq!(TStates::Fixed(19), decoded.t_states);
+        assert_eq!("0001  dd 36 85 34  ld (ix-123),$34", format!("{}", decoded));
+    }
+
+    #[test]
+    fn test_t_states() {
+        assert_eq!(TStates::Fixed(4), t_states(vec![0x00]));
+        assert_eq!(TStates::Fixed(10), t_states(vec![0xc3, 0x00, 0x10]));
+        assert_eq!(TStates::Fixed(12), t_states(vec![0x18, 0x10]));
+        assert_eq!(TStates::Conditional { taken: 12, not_taken: 7 }, t_states(vec![0x38, 0x10]));
+        assert_eq!(TStates::Conditional { taken: 13, not_taken: 8 }, t_states(vec![0x10, 0x10]));
+        assert_eq!(TStates::Conditional { taken: 17, not_taken: 10 }, t_states(vec![0xe4, 0x00, 0x10]));
+        assert_eq!(TStates::Conditional { taken: 11, not_taken: 5 }, t_states(vec![0xf8]));
+        // jp cc takes 10 either way
+        assert_eq!(TStates::Fixed(10), t_states(vec![0xca, 0x00, 0x10]));
+        assert_eq!(TStates::Conditional { taken: 21, not_taken: 16 }, t_states(vec![0xed, 0xb0]));
+        assert_eq!(TStates::Conditional { taken: 21, not_taken: 16 }, t_states(vec![0xed, 0xb1]));
+        assert_eq!(TStates::Conditional { taken: 21, not_taken: 16 }, t_states(vec![0xed, 0xb2]));
+        assert_eq!(TStates::Conditional { taken: 21, not_taken: 16 }, t_states(vec![0xed, 0xbb]));
+        assert_eq!(TStates::Fixed(20), t_states(vec![0xdd, 0xcb, 0x05, 0x46]));
+        assert_eq!(TStates::Fixed(23), t_states(vec![0xfd, 0xcb, 0x05, 0xc6]));
+    }
+
+    #[test]
+    fn test_iter() {
+        // ld a,$01; jr $-2; ld bc,$1234
+        let prg = vec![0x3e, 0x01, 0x18, 0xfe, 0x01, 0x34, 0x12];
+        let disassembler = disassembler(prg);
+        let listing: Vec<String> = disassembler.iter(0..7).map(|d| format!("{}", d)).collect();
+        assert_eq!(
+            vec![
+                "0000  3e 01        ld a,$01",
+                "0002  18 fe        jr $+0",
+                "0004  01 34 12     ld bc,$1234",
+            ],
+            listing
+        );
+        // an instruction that starts inside the range is decoded whole
+        assert_eq!(vec![0, 2], disassembler.iter(..=2).map(|d| d.address).collect::<Vec<_>>());
+        assert_eq!(3 + 0x10000 - 7, disassembler.iter(..).count());
+    }
+
+    #[test]
+    fn test_image_at_base() {
+        let rom = [0xc3, 0x03, 0x80, 0xcd];
+        let disassembler = Disassembler::new(Image::new(0x8000, &rom), 0x8000);
+        assert_eq!("jp $8003", format!("{}", disassembler.instruction()));
+        // past the end reads as zero
+        assert_eq!("call $0000", format!("{}", disassembler.disassemble(0x8003).instruction));
+    }
+
+    #[test]
+    fn test_live_bus() {
+        let mut memory = vec![0; 0x10000];
+        // ld a,$12; inc a; jr $-1
+        memory[..5].copy_from_slice(&[0x3e, 0x12, 0x3c, 0x18, 0xfd]);
+        let mut bus = TestBus { memory };
+        let mut cpu = Z80::new();
+        let mut listing = vec![];
+        for _ in 0..4 {
+            let decoded = Disassembler::new(BusMemory(&bus), 0).disassemble(cpu.pc);
+            listing.push(format!("{}", decoded.instruction));
+            cpu.step(&mut bus);
+        }
+        assert_eq!(vec!["ld a,$12", "inc a", "jr $-1", "inc a"], listing);
+        assert_eq!(0x14, cpu.registers.a);
+    }
+}
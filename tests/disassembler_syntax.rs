@@ -0,0 +1,81 @@
+
+
+#[cfg(test)]
+mod test_disassembler_syntax {
+    use z80::disassembler::{Case, Disassembler, Hex, Image, Mnemonics, StaticDisassembler, Syntax};
+
+    fn format(syntax: &Syntax, prg: Vec<u8>) -> String {
+        let disassembler = Disassembler::new(Image::new(0x100, &prg), 0x100);
+        let decoded = disassembler.disassemble(0x100);
+        syntax.format(&decoded.instruction, decoded.address)
+    }
+
+    #[test]
+    fn test_case_and_hex() {
+        let upper = Syntax { case: Case::Upper, hex: Hex::Prefix, ..Syntax::default() };
+        assert_eq!("LD (IX-123),0x3A", format(&upper, vec![0xdd, 0x36, 0x85, 0x3a]));
+        assert_eq!("EX AF,AF'", format(&upper, vec![0x08]));
+
+        let suffix = Syntax { hex: Hex::Suffix, ..Syntax::default() };
+        assert_eq!("ld a,0ffh", format(&suffix, vec![0x3e, 0xff]));
+        assert_eq!("jp 1234h", format(&suffix, vec![0xc3, 0x34, 0x12]));
+        assert_eq!("call nz,0abcdh", format(&suffix, vec![0xc4, 0xcd, 0xab]));
+        assert_eq!("rst 38h", format(&suffix, vec![0xff]));
+    }
+
+    #[test]
+    fn test_displacement_and_relative() {
+        let unsigned = Syntax { signed_displacement: false, ..Syntax::default() };
+        assert_eq!("ld (ix+$85),$34", format(&unsigned, vec![0xdd, 0x36, 0x85, 0x34]));
+        assert_eq!("set 3,(iy+$fe),a", format(&unsigned, vec![0xfd, 0xcb, 0xfe, 0xdf]));
+
+        let absolute = Syntax { absolute_relative: true, ..Syntax::default() };
+        assert_eq!("jr nz,$00fe", format(&absolute, vec![0x20, 0xfc]));
+        assert_eq!("djnz $0100", format(&absolute, vec![0x10, 0xfe]));
+        assert_eq!("jr $+4", format(&Syntax::default(), vec![0x18, 0x02]));
+
+        let disassembler = Disassembler::new(Image::new(0x100, &[0x18, 0x02]), 0x100);
+        assert_eq!("0100  18 02        jr $0104", disassembler.disassemble(0x100).format(&absolute));
+    }
+
+    #[test]
+    fn test_intel_8080() {
+        let intel = Syntax { mnemonics: Mnemonics::Intel8080, ..Syntax::default() };
+        assert_eq!("mov b,m", format(&intel, vec![0x46]));
+        assert_eq!("mvi a,$12", format(&intel, vec![0x3e, 0x12]));
+        assert_eq!("lxi h,$1234", format(&intel, vec![0x21, 0x34, 0x12]));
+        assert_eq!("ldax d", format(&intel, vec![0x1a]));
+        assert_eq!("sta $1234", format(&intel, vec![0x32, 0x34, 0x12]));
+        assert_eq!("adi $01", format(&intel, vec![0xc6, 0x01]));
+        assert_eq!("sbb c", format(&intel, vec![0x99]));
+        assert_eq!("dad sp", format(&intel, vec![0x39]));
+        assert_eq!("jnz $1234", format(&intel, vec![0xc2, 0x34, 0x12]));
+        assert_eq!("cpe $1234", format(&intel, vec![0xec, 0x34, 0x12]));
+        assert_eq!("rm", format(&intel, vec![0xf8]));
+        assert_eq!("rst 7", format(&intel, vec![0xff]));
+        assert_eq!("push psw", format(&intel, vec![0xf5]));
+        assert_eq!("pchl", format(&intel, vec![0xe9]));
+        assert_eq!("xthl", format(&intel, vec![0xe3]));
+        assert_eq!("hlt", format(&intel, vec![0x76]));
+        // the 8080 has none of these
+        assert_eq!("djnz $+0", format(&intel, vec![0x10, 0xfe]));
+        assert_eq!("ld (ix+5),a", format(&intel, vec![0xdd, 0x77, 0x05]));
+        assert_eq!("ld b,ixh", format(&intel, vec![0xdd, 0x44]));
+    }
+
+    #[test]
+    fn test_listing_syntax() {
+        // loop: dcr b; jnz loop; ret
+        let prg = [0x05, 0xc2, 0x00, 0x80, 0xc9, 0xaa];
+        let mut disassembler = StaticDisassembler::new(Image::new(0x8000, &prg), 0x8000..=0x8005);
+        disassembler.add_entry(0x8000);
+        let mut listing = disassembler.run();
+        listing.syntax = Syntax { case: Case::Upper, hex: Hex::Suffix, mnemonics: Mnemonics::Intel8080, ..Syntax::default() };
+        let mut asm = vec![];
+        listing.write_asm(&mut asm).unwrap();
+        assert_eq!(
+            "\tORG 8000H\nL_8000:\n\tDCR B\n\tJNZ L_8000\n\tRET\n\tDB 0AAH\n",
+            String::from_utf8(asm).unwrap()
+        );
+    }
+}
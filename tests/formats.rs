@@ -0,0 +1,210 @@
+#[cfg(all(test, feature = "formats"))]
+mod test_formats {
+    use z80::cpu::Z80;
+    use z80::formats::z80::{compress, decompress, Version};
+    use z80::formats::{sna, z80 as dot_z80, Machine, Snapshot, SnapshotError};
+    use z80::state::Z80State;
+
+    fn state() -> Z80State {
+        Z80State {
+            af: 0x1234,
+            bc: 0x2345,
+            de: 0x3456,
+            hl: 0x4567,
+            alt_af: 0x5678,
+            alt_bc: 0x6789,
+            alt_de: 0x789a,
+            alt_hl: 0x89ab,
+            ix: 0x9abc,
+            iy: 0xabcd,
+            sp: 0xfff0,
+            pc: 0x8123,
+            i: 0x3f,
+            r: 0xa5,
+            iff1: true,
+            iff2: true,
+            interrupt_mode: 1,
+            ..Z80State::default()
+        }
+    }
+
+    /// Ram with long runs, single bytes and ED sequences for the compressor
+    fn synthetic(machine: Machine) -> Snapshot {
+        let mut snapshot = Snapshot::new(machine, &Z80::new());
+        snapshot.state = state();
+        snapshot.border = 5;
+        for (i, b) in snapshot.ram.iter_mut().enumerate() {
+            *b = match (i / 0x1000) % 4 {
+                0 => 0,
+                1 => (i * 7 / 3) as u8,
+                2 => if i % 3 == 0 { 0xed } else { (i >> 4) as u8 },
+                _ => 0xed,
+            };
+        }
+        // each 128K bank gets its number in the first byte
+        if machine == Machine::Spectrum128 {
+            for bank in 0..8 {
+                snapshot.bank_mut(bank)[0] = bank as u8;
+            }
+        }
+        snapshot
+    }
+
+    #[test]
+    fn test_sna_48k_round_trip() {
+        let mut snapshot = synthetic(Machine::Spectrum48);
+        let bytes = sna::save(&snapshot).unwrap();
+        assert_eq!(sna::LEN_48K, bytes.len());
+        // pc is pushed below sp
+        assert_eq!([0x23, 0x81], bytes[27 + 0xbfee..27 + 0xbff0]);
+
+        let loaded = sna::load(&bytes).unwrap();
+        snapshot.ram[0xbfee] = 0x23;
+        snapshot.ram[0xbfef] = 0x81;
+        assert_eq!(snapshot, loaded);
+    }
+
+    #[test]
+    fn test_sna_48k_header() {
+        let bytes = sna::save(&synthetic(Machine::Spectrum48)).unwrap();
+        assert_eq!(0x3f, bytes[0]);
+        assert_eq!([0xab, 0x89], bytes[1..3]);
+        assert_eq!([0x67, 0x45], bytes[9..11]);
+        assert_eq!(0x04, bytes[19]);
+        assert_eq!(0xa5, bytes[20]);
+        assert_eq!([0x34, 0x12], bytes[21..23]);
+        assert_eq!([0xee, 0xff], bytes[23..25]);
+        assert_eq!([1, 5], bytes[25..27]);
+    }
+
+    #[test]
+    fn test_sna_128k_round_trip() {
+        for port in [0x10, 0x13, 0x15, 0x02] {
+            let mut snapshot = synthetic(Machine::Spectrum128);
+            snapshot.port_7ffd = port;
+            let bytes = sna::save(&snapshot).unwrap();
+            let expected = if port & 7 == 2 || port & 7 == 5 { sna::LEN_128K_DUPLICATE } else { sna::LEN_128K };
+            assert_eq!(expected, bytes.len());
+            // banks 5, 2 and the paged one come first
+            assert_eq!(5, bytes[27]);
+            assert_eq!(2, bytes[27 + 0x4000]);
+            assert_eq!(port & 7, bytes[27 + 0x8000]);
+            assert_eq!(snapshot, sna::load(&bytes).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_sna_errors() {
+        assert_eq!(Err(SnapshotError::Truncated), sna::load(&[0; 10]));
+        assert_eq!(Err(SnapshotError::BadLength(1000)), sna::load(&[0; 1000]));
+
+        let mut snapshot = synthetic(Machine::Spectrum48);
+        snapshot.state.sp = 0x0001;
+        assert_eq!(Err(SnapshotError::StackInRom), sna::save(&snapshot));
+    }
+
+    #[test]
+    fn test_compress() {
+        // a single ED keeps the next byte out of a run
+        assert_eq!(vec![0xed, 0x00, 0xed, 0xed, 0x05, 0x00], compress(&[0xed, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(vec![0xed, 0xed, 0x02, 0xed], compress(&[0xed, 0xed]));
+        assert_eq!(vec![1, 1, 1, 1], compress(&[1, 1, 1, 1]));
+        assert_eq!(vec![0xed, 0xed, 0xff, 0x00, 0xed, 0xed, 0x05, 0x00], compress(&[0; 260]));
+    }
+
+    #[test]
+    fn test_decompress() {
+        assert_eq!(Ok(vec![1, 0, 0, 0, 0, 0, 2]), decompress(&[1, 0xed, 0xed, 5, 0, 2], 7));
+        assert_eq!(Err(SnapshotError::Truncated), decompress(&[1, 0xed, 0xed], 7));
+        let data: Vec<u8> = (0..5000).map(|i| if i % 7 == 0 { 0xed } else { (i / 50) as u8 }).collect();
+        assert_eq!(Ok(data.clone()), decompress(&compress(&data), data.len()));
+    }
+
+    #[test]
+    fn test_z80_v1_round_trip() {
+        let snapshot = synthetic(Machine::Spectrum48);
+        let bytes = dot_z80::save(&snapshot, Version::V1).unwrap();
+        assert_eq!([0x23, 0x81], bytes[6..8]);
+        // r bit 7, border 5 and compressed
+        assert_eq!(0x01 | 0x0a | 0x20, bytes[12]);
+        assert_eq!([0x00, 0xed, 0xed, 0x00], bytes[bytes.len() - 4..]);
+        assert!(bytes.len() < 0xc000);
+        assert_eq!(snapshot, dot_z80::load(&bytes).unwrap());
+
+        let mut snapshot = synthetic(Machine::Spectrum128);
+        snapshot.port_7ffd = 3;
+        assert_eq!(Err(SnapshotError::NotSpectrum48), dot_z80::save(&snapshot, Version::V1));
+    }
+
+    #[test]
+    fn test_z80_v1_uncompressed() {
+        let snapshot = synthetic(Machine::Spectrum48);
+        let mut bytes = dot_z80::save(&snapshot, Version::V1).unwrap();
+        bytes.truncate(30);
+        bytes[12] &= !0x20;
+        bytes.extend_from_slice(&snapshot.ram);
+        assert_eq!(snapshot, dot_z80::load(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_z80_v2_v3_round_trip() {
+        for version in [Version::V2, Version::V3] {
+            let snapshot = synthetic(Machine::Spectrum48);
+            let bytes = dot_z80::save(&snapshot, version).unwrap();
+            assert_eq!([0, 0], bytes[6..8]);
+            assert_eq!(0, bytes[34]);
+            assert_eq!(snapshot, dot_z80::load(&bytes).unwrap());
+
+            let mut snapshot = synthetic(Machine::Spectrum128);
+            snapshot.port_7ffd = 0x14;
+            let bytes = dot_z80::save(&snapshot, version).unwrap();
+            assert_eq!(if version == Version::V2 { 3 } else { 4 }, bytes[34]);
+            assert_eq!(0x14, bytes[35]);
+            assert_eq!(snapshot, dot_z80::load(&bytes).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_z80_v3_uncompressed_page() {
+        let mut snapshot = synthetic(Machine::Spectrum48);
+        // no runs at all, so the page is stored as it is
+        for (i, b) in snapshot.ram[..0x4000].iter_mut().enumerate() {
+            *b = (i % 251) as u8 ^ (i / 251) as u8 | 1;
+        }
+        let bytes = dot_z80::save(&snapshot, Version::V3).unwrap();
+        assert!(bytes.windows(3).any(|w| w == [0xff, 0xff, 8]));
+        assert_eq!(snapshot, dot_z80::load(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_z80_errors() {
+        let snapshot = synthetic(Machine::Spectrum48);
+        let bytes = dot_z80::save(&snapshot, Version::V3).unwrap();
+        assert_eq!(Err(SnapshotError::Truncated), dot_z80::load(&bytes[..20]));
+        assert_eq!(Err(SnapshotError::Truncated), dot_z80::load(&bytes[..bytes.len() - 10]));
+
+        let mut plus3 = bytes;
+        plus3[34] = 7;
+        assert_eq!(Err(SnapshotError::UnsupportedHardware(7)), dot_z80::load(&plus3));
+    }
+
+    #[test]
+    fn test_load_into_cpu() {
+        let mut snapshot = synthetic(Machine::Spectrum128);
+        snapshot.port_7ffd = 0x11;
+        let loaded = dot_z80::load(&dot_z80::save(&snapshot, Version::V3).unwrap()).unwrap();
+
+        let mut cpu = Z80::new();
+        loaded.restore(&mut cpu);
+        assert_eq!(0x8123, cpu.pc);
+        assert_eq!(0x9abc, cpu.registers.ix);
+        assert_eq!(0x5678, cpu.snapshot().alt_af);
+
+        let rom = vec![0xf3; 0x4000];
+        let memory = loaded.memory(&rom);
+        assert_eq!(0xf3, memory[0x0000]);
+        assert_eq!(5, memory[0x4000]);
+        assert_eq!(2, memory[0x8000]);
+        assert_eq!(1, memory[0xc000]);
+    }
+}
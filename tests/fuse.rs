@@ -0,0 +1,369 @@
+
+
+#[cfg(test)]
+mod test_fuse {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    use z80::bus::{Bus, Cycle};
+    use z80::cpu::Z80;
+    use z80::state::Z80State;
+
+    /// Logs bus activity like the FUSE core tests do: `MC` for every
+    /// T-state the address bus could be contended, `MR`/`MW` for memory and
+    /// `PR`/`PW` for ports. Port events are kept without their time as
+    /// FUSE's port contention pattern is a Spectrum ula detail.
+    struct RecordingBus {
+        memory: Vec<u8>,
+        t_states: u32,
+        /// When the current M-cycle began
+        cycle_start: u32,
+        events: RefCell<Vec<String>>,
+    }
+
+    impl RecordingBus {
+        fn event(&self, event: String) {
+            self.events.borrow_mut().push(event);
+        }
+    }
+
+    impl Bus for RecordingBus {
+        fn memory_read(&self, address: usize) -> u8 {
+            let value = self.memory[address];
+            self.event(format!("{} MR {:04x} {:02x}", self.cycle_start, address, value));
+            value
+        }
+
+        fn memory_read_word(&self, address: usize) -> u16 {
+            self.memory_read(address) as u16 | ((self.memory_read((address + 1) & 0xffff) as u16) << 8)
+        }
+
+        fn memory_write(&mut self, address: usize, value: u8) {
+            self.event(format!("{} MW {:04x} {:02x}", self.cycle_start, address, value));
+            self.memory[address] = value;
+        }
+
+        fn memory_write_word(&mut self, address: usize, value: u16) {
+            self.memory_write(address, value as u8);
+            self.memory_write((address + 1) & 0xffff, (value >> 8) as u8);
+        }
+
+        /// FUSE answers port reads with the upper byte of the port
+        fn io_read(&mut self, port: u16) -> u8 {
+            let value = (port >> 8) as u8;
+            self.event(format!("PR {:04x} {:02x}", port, value));
+            value
+        }
+
+        fn io_write(&mut self, port: u16, value: u8) {
+            self.event(format!("PW {:04x} {:02x}", port, value));
+        }
+
+        fn cycle(&mut self, cycle: Cycle) -> u8 {
+            self.cycle_start = self.t_states;
+            match cycle {
+                Cycle::M1 { addr } | Cycle::MemRead { addr } | Cycle::MemWrite { addr } => {
+                    self.event(format!("{} MC {:04x}", self.t_states, addr));
+                }
+                Cycle::Internal { addr, n } => {
+                    for i in 0..n as u32 {
+                        self.event(format!("{} MC {:04x}", self.t_states + i, addr));
+                    }
+                }
+                Cycle::IoRead { .. } | Cycle::IoWrite { .. } | Cycle::IntAck { .. } => {}
+            }
+            0
+        }
+
+        fn tick(&mut self, _: u8, t_states: u8) {
+            self.t_states += t_states as u32;
+        }
+    }
+
+    /// What a test starts from or ends with
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct Machine {
+        state: Z80State,
+        t_states: u32,
+        /// Blocks of memory as `(address, bytes)`
+        memory: Vec<(u16, Vec<u8>)>,
+    }
+
+    fn hex(word: &str) -> Result<u16, String> {
+        u16::from_str_radix(word, 16).map_err(|_| format!("bad hex number {}", word))
+    }
+
+    fn decimal(word: &str) -> Result<u32, String> {
+        word.parse().map_err(|_| format!("bad number {}", word))
+    }
+
+    /// The two register lines, then memory blocks of `address bytes... -1`
+    fn machine(lines: &[&str]) -> Result<Machine, String> {
+        if lines.len() < 2 {
+            return Err("missing registers".to_string());
+        }
+        let words: Vec<u16> = lines[0].split_whitespace().map(hex).collect::<Result<_, _>>()?;
+        let other: Vec<&str> = lines[1].split_whitespace().collect();
+        if words.len() != 13 || other.len() != 7 {
+            return Err(format!("bad registers {} / {}", lines[0], lines[1]));
+        }
+        let state = Z80State {
+            af: words[0],
+            bc: words[1],
+            de: words[2],
+            hl: words[3],
+            alt_af: words[4],
+            alt_bc: words[5],
+            alt_de: words[6],
+            alt_hl: words[7],
+            ix: words[8],
+            iy: words[9],
+            sp: words[10],
+            pc: words[11],
+            wz: words[12],
+            i: hex(other[0])? as u8,
+            r: hex(other[1])? as u8,
+            iff1: decimal(other[2])? != 0,
+            iff2: decimal(other[3])? != 0,
+            interrupt_mode: decimal(other[4])? as u8,
+            halted: decimal(other[5])? != 0,
+            ..Z80State::default()
+        };
+
+        let mut memory = vec![];
+        for line in lines[2..].iter().filter(|line| line.trim() != "-1") {
+            let mut words = line.split_whitespace();
+            let address = hex(words.next().unwrap_or(""))?;
+            let bytes = words.take_while(|word| *word != "-1").map(|byte| hex(byte).map(|b| b as u8)).collect::<Result<_, _>>()?;
+            memory.push((address, bytes));
+        }
+        Ok(Machine { state, t_states: decimal(other[6])?, memory })
+    }
+
+    /// Tests by name, each a list of lines. Tests are separated by blank lines.
+    fn tests(text: &str) -> Vec<(String, Vec<&str>)> {
+        let mut tests = vec![];
+        let mut lines = text.lines().peekable();
+        while lines.peek().is_some() {
+            let block: Vec<&str> = lines.by_ref().skip_while(|line| line.trim().is_empty()).take_while(|line| !line.trim().is_empty()).collect();
+            if let Some((name, rest)) = block.split_first() {
+                tests.push((name.trim().to_string(), rest.to_vec()));
+            }
+        }
+        tests
+    }
+
+    /// Memory filled with de ad be ef like FUSE's tests expect, with `blocks` on top
+    fn memory(blocks: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut memory: Vec<u8> = [0xde, 0xad, 0xbe, 0xef].iter().copied().cycle().take(0x10000).collect();
+        for (address, bytes) in blocks {
+            for (i, byte) in bytes.iter().enumerate() {
+                memory[(*address as usize + i) & 0xffff] = *byte;
+            }
+        }
+        memory
+    }
+
+    /// Runs one test and says what differs
+    fn run_test(input: &[&str], expected: &[&str]) -> Result<(), String> {
+        let start = machine(input)?;
+        let events_end = expected.iter().position(|line| !line.starts_with(' ')).unwrap_or(expected.len());
+        let want_events: Vec<String> = expected[..events_end]
+            .iter()
+            .filter_map(|line| {
+                let words: Vec<&str> = line.split_whitespace().collect();
+                match words.as_slice() {
+                    [_, "PC", ..] => None,
+                    [_, kind @ ("PR" | "PW"), rest @ ..] => Some(format!("{} {}", kind, rest.join(" "))),
+                    words => Some(words.join(" ")),
+                }
+            })
+            .collect();
+        let end = machine(&expected[events_end..])?;
+
+        let mut bus = RecordingBus { memory: memory(&start.memory), t_states: 0, cycle_start: 0, events: RefCell::new(vec![]) };
+        let mut cpu = Z80::new();
+        cpu.restore(&start.state);
+        while bus.t_states < start.t_states {
+            cpu.step(&mut bus);
+        }
+
+        let mut errors = vec![];
+        let (want, got) = (end.state, cpu.snapshot());
+        let registers = [
+            ("af", want.af, got.af),
+            ("bc", want.bc, got.bc),
+            ("de", want.de, got.de),
+            ("hl", want.hl, got.hl),
+            ("af'", want.alt_af, got.alt_af),
+            ("bc'", want.alt_bc, got.alt_bc),
+            ("de'", want.alt_de, got.alt_de),
+            ("hl'", want.alt_hl, got.alt_hl),
+            ("ix", want.ix, got.ix),
+            ("iy", want.iy, got.iy),
+            ("sp", want.sp, got.sp),
+            ("pc", want.pc, got.pc),
+            ("memptr", want.wz, got.wz),
+            ("i", want.i as u16, got.i as u16),
+            ("r", want.r as u16, got.r as u16),
+            ("iff1", want.iff1 as u16, got.iff1 as u16),
+            ("iff2", want.iff2 as u16, got.iff2 as u16),
+            ("im", want.interrupt_mode as u16, got.interrupt_mode as u16),
+            ("halted", want.halted as u16, got.halted as u16),
+        ];
+        for (name, want, got) in registers.iter() {
+            if want != got {
+                errors.push(format!("{} {:04x} != {:04x}", name, got, want));
+            }
+        }
+        if bus.t_states != end.t_states {
+            errors.push(format!("{} T-states != {}", bus.t_states, end.t_states));
+        }
+
+        // the expected blocks are what changed
+        let want_memory = memory(&[start.memory, end.memory].concat());
+        if let Some(address) = (0..0x10000).find(|&i| bus.memory[i] != want_memory[i]) {
+            errors.push(format!("({:04x}) {:02x} != {:02x}", address, bus.memory[address], want_memory[address]));
+        }
+
+        let got_events = bus.events.into_inner();
+        if got_events != want_events {
+            let i = got_events.iter().zip(want_events.iter()).take_while(|(got, want)| got == want).count();
+            errors.push(format!(
+                "event {}: {} != {}",
+                i,
+                got_events.get(i).map_or("nothing", String::as_str),
+                want_events.get(i).map_or("nothing", String::as_str)
+            ));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+
+    fn directory() -> PathBuf {
+        match std::env::var_os("Z80_FUSE_DIR") {
+            Some(dir) => PathBuf::from(dir),
+            None => Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/fuse"),
+        }
+    }
+
+    /// Runs every test in a `tests.in` against its `tests.expected` and
+    /// prints every mismatch. Returns how many ran and what failed.
+    fn run_tests(input: &str, expected: &str) -> (usize, Vec<String>) {
+        let expected: HashMap<String, Vec<&str>> = tests(expected).into_iter().collect();
+        let inputs = tests(input);
+        let mut failures = vec![];
+        for (name, input) in inputs.iter() {
+            let result = match expected.get(name) {
+                Some(expected) => run_test(input, expected),
+                None => Err("not in tests.expected".to_string()),
+            };
+            if let Err(err) = result {
+                failures.push(format!("{}: {}", name, err));
+            }
+        }
+
+        for failure in failures.iter() {
+            println!("{}", failure);
+        }
+        println!("{} of {} FUSE tests pass", inputs.len() - failures.len(), inputs.len());
+        (inputs.len(), failures)
+    }
+
+    /// A few hand written tests in FUSE's format, so the parser and the
+    /// comparison run without the files. 3e expects 8 T-states and 06 has
+    /// no expected result, both on purpose.
+    #[test]
+    fn test_inline() {
+        let input = "
+00
+0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000
+00 00 0 0 0 0 1
+0000 00 -1
+-1
+
+77
+5600 0000 0000 8000 0000 0000 0000 0000 0000 0000 0000 0000 0000
+00 00 0 0 0 0 1
+0000 77 -1
+-1
+
+d3
+1200 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000
+00 00 0 0 0 0 1
+0000 d3 34 -1
+-1
+
+3e
+0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000
+00 00 0 0 0 0 1
+0000 3e 12 -1
+-1
+
+06
+0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000
+00 00 0 0 0 0 1
+0000 06 12 -1
+-1
+";
+        let expected = "
+00
+    0 MC 0000
+    0 MR 0000 00
+0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0001 0000
+00 01 0 0 0 0 4
+
+77
+    0 MC 0000
+    0 MR 0000 77
+    4 MC 8000
+    4 MW 8000 56
+5600 0000 0000 8000 0000 0000 0000 0000 0000 0000 0000 0001 0000
+00 01 0 0 0 0 7
+8000 56 -1
+
+d3
+    0 MC 0000
+    0 MR 0000 d3
+    4 MC 0001
+    4 MR 0001 34
+    7 PC 1234
+    8 PW 1234 12
+1200 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0002 1235
+00 01 0 0 0 0 11
+
+3e
+    0 MC 0000
+    0 MR 0000 3e
+    4 MC 0001
+    4 MR 0001 12
+1200 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0002 0000
+00 01 0 0 0 0 8
+";
+        let (count, failures) = run_tests(input, expected);
+        assert_eq!(5, count);
+        assert_eq!(vec!["3e: 7 T-states != 8".to_string(), "06: not in tests.expected".to_string()], failures);
+    }
+
+    /// Runs FUSE's `tests.in` against `tests.expected`. The files are looked
+    /// for in `tests/data/fuse`, or in the directory `Z80_FUSE_DIR` names;
+    /// without them only `test_inline` checks the runner.
+    #[test]
+    fn test_fuse() {
+        let dir = directory();
+        let (input, expected) = match (std::fs::read_to_string(dir.join("tests.in")), std::fs::read_to_string(dir.join("tests.expected"))) {
+            (Ok(input), Ok(expected)) => (input, expected),
+            _ => {
+                println!("{} has no tests.in and tests.expected, skipping the FUSE tests", dir.display());
+                return;
+            }
+        };
+        let (_, failures) = run_tests(&input, &expected);
+        assert!(failures.is_empty(), "{} FUSE tests failed", failures.len());
+    }
+}
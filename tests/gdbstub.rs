@@ -0,0 +1,219 @@
+
+
+#[cfg(test)]
+mod test_gdbstub {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use z80::assembler::assemble_bytes;
+    use z80::bus::Bus;
+    use z80::cpu::Z80;
+    use z80::gdbstub::GdbStub;
+
+    struct TestBus {
+        memory: Vec<u8>,
+    }
+
+    impl TestBus {
+        fn new(source: &str) -> TestBus {
+            let mut memory = assemble_bytes(source).unwrap();
+            memory.resize(0x10000, 0);
+            TestBus { memory }
+        }
+    }
+
+    impl Bus for TestBus {
+        fn memory_read(&self, address: usize) -> u8 {
+            self.memory[address]
+        }
+
+        fn memory_read_word(&self, address: usize) -> u16 {
+            self.memory[address] as u16 | ((self.memory[address + 1] as u16) << 8)
+        }
+
+        fn memory_write(&mut self, address: usize, value: u8) {
+            self.memory[address] = value;
+        }
+
+        fn memory_write_word(&mut self, address: usize, value: u16) {
+            self.memory[address] = value as u8;
+            self.memory[address + 1] = (value >> 8) as u8;
+        }
+
+        #[allow(unused_variables)]
+        fn tick(&mut self, machine_cycles: u8, t_states: u8) {}
+    }
+
+    /// The gdb end of the connection
+    struct Client {
+        stream: TcpStream,
+        no_ack: bool,
+    }
+
+    impl Client {
+        fn send(&mut self, packet: &str) {
+            let checksum = packet.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+            write!(self.stream, "${}#{:02x}", packet, checksum).unwrap();
+            if !self.no_ack {
+                assert_eq!(b'+', self.byte());
+            }
+        }
+
+        fn receive(&mut self) -> String {
+            assert_eq!(b'$', self.byte());
+            let mut data = vec![];
+            loop {
+                match self.byte() {
+                    b'#' => break,
+                    b => data.push(b),
+                }
+            }
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum).unwrap();
+            let expected = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+            assert_eq!(format!("{:02x}", expected).as_bytes(), &checksum);
+            if !self.no_ack {
+                self.stream.write_all(b"+").unwrap();
+            }
+            String::from_utf8(data).unwrap()
+        }
+
+        fn command(&mut self, packet: &str) -> String {
+            self.send(packet);
+            self.receive()
+        }
+
+        fn byte(&mut self) -> u8 {
+            let mut byte = [0];
+            self.stream.read_exact(&mut byte).unwrap();
+            byte[0]
+        }
+    }
+
+    /// Runs `script` as gdb against a stub serving `cpu` and `bus`
+    fn session(cpu: &mut Z80, bus: &mut TestBus, script: impl FnOnce(&mut Client) + Send + 'static) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let stream = TcpStream::connect(address).unwrap();
+            stream.set_nodelay(true).unwrap();
+            script(&mut Client { stream, no_ack: false });
+        });
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_nodelay(true).unwrap();
+        GdbStub::new().serve(stream, cpu, bus).unwrap();
+        client.join().unwrap();
+    }
+
+    #[test]
+    fn test_session() {
+        let mut bus = TestBus::new(
+            "
+                ld hl,$200
+                ld b,3
+            loop:
+                inc (hl)    ; 5
+                djnz loop
+                exx
+                halt
+            ",
+        );
+        let mut cpu = Z80::new();
+        session(&mut cpu, &mut bus, |gdb| {
+            assert!(gdb.command("qSupported:swbreak+").contains("PacketSize="));
+            assert!(gdb.command("qXfer:features:read:target.xml:0,ffff").contains("<architecture>z80</architecture>"));
+            assert_eq!("S05", gdb.command("?"));
+            assert_eq!("", gdb.command("vMustReplyEmpty"));
+            // af, bc, de, hl, sp, pc, ix, iy, the shadow pairs and ir
+            let registers = gdb.command("g");
+            assert_eq!(13 * 4, registers.len());
+            assert_eq!("0000000000000000f0df0000", &registers[..24]);
+
+            assert_eq!("OK", gdb.command("Z0,5,1"));
+            assert_eq!("S05", gdb.command("c"));
+            assert_eq!("0500", gdb.command("p5"));
+            assert_eq!("0002", gdb.command("p3"));
+            assert_eq!("S05", gdb.command("s"));
+            assert_eq!("0600", gdb.command("p5"));
+            assert_eq!("01", gdb.command("m200,1"));
+            assert_eq!("OK", gdb.command("z0,5,1"));
+
+            assert_eq!("OK", gdb.command("M201,2:abcd"));
+            assert_eq!("01abcd00", gdb.command("m200,4"));
+            assert_eq!("OK", gdb.command("Z2,200,1"));
+            assert_eq!("T05watch:200;", gdb.command("c"));
+            assert_eq!("0600", gdb.command("p5"));
+            assert_eq!("02", gdb.command("m200,1"));
+            assert_eq!("OK", gdb.command("z2,200,1"));
+
+            // a watchpoint as long as memory, and a read longer than a packet
+            assert_eq!("OK", gdb.command("Z2,200,10000"));
+            assert_eq!("OK", gdb.command("z2,200,10000"));
+            assert_eq!(0x1000, gdb.command("m0,800").len());
+            assert_eq!("E01", gdb.command("m0,ffffffff"));
+
+            // set b so the loop ends, then run into the halt
+            assert_eq!("OK", gdb.command("P1=0001"));
+            assert_eq!("OK", gdb.command("P2=3412"));
+            assert_eq!("OK", gdb.command("Z0,9,1"));
+            assert_eq!("S05", gdb.command("c"));
+            assert_eq!("0900", gdb.command("p5"));
+            assert_eq!("0000", gdb.command("p1"));
+            assert_eq!("3412", gdb.command("pa"));
+            assert_eq!("E01", gdb.command("p20"));
+
+            gdb.send("QStartNoAckMode");
+            assert_eq!("OK", gdb.receive());
+            gdb.no_ack = true;
+            let mut registers = gdb.command("g");
+            registers.replace_range(0..4, "ff00");
+            assert_eq!("OK", gdb.command(&format!("G{}", registers)));
+            assert_eq!("OK", gdb.command("D"));
+        });
+        assert_eq!(0x00ff, cpu.snapshot().af);
+        assert_eq!(9, cpu.pc);
+        assert_eq!(0x1234, cpu.snapshot().alt_de);
+    }
+
+    /// Ctrl-C stops a cpu that would otherwise run forever
+    #[test]
+    fn test_interrupt() {
+        let mut bus = TestBus::new("loop: jr loop");
+        let mut cpu = Z80::new();
+        session(&mut cpu, &mut bus, |gdb| {
+            gdb.send("c");
+            gdb.stream.write_all(&[0x03]).unwrap();
+            assert_eq!("S02", gdb.receive());
+            assert_eq!("0000", gdb.command("p5"));
+            gdb.send("k");
+        });
+        assert!(cpu.t_cycles > 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_socket() {
+        use std::os::unix::net::{UnixListener, UnixStream};
+
+        let path = std::env::temp_dir().join(format!("z80-gdbstub-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let client = thread::spawn({
+            let path = path.clone();
+            move || {
+                let mut stream = UnixStream::connect(path).unwrap();
+                stream.write_all(b"$?#3f").unwrap();
+                let mut reply = [0; 8];
+                stream.read_exact(&mut reply).unwrap();
+                assert_eq!(b"+$S05#b8", &reply);
+                stream.write_all(b"+$k#6b").unwrap();
+            }
+        });
+        let mut bus = TestBus::new("nop");
+        let (stream, _) = listener.accept().unwrap();
+        GdbStub::new().serve(stream, &mut Z80::new(), &mut bus).unwrap();
+        client.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+#[cfg(test)]
+mod test_loader {
+    use z80::bus::Bus;
+    use z80::cpu::Z80;
+    use z80::loader::{LoadError, Program, Segment};
+
+    struct TestBus {
+        memory: Vec<u8>,
+    }
+
+    impl Bus for TestBus {
+        fn memory_read(&self, address: usize) -> u8 {
+            self.memory[address]
+        }
+
+        fn memory_read_word(&self, address: usize) -> u16 {
+            self.memory[address] as u16 | ((self.memory[address + 1] as u16) << 8)
+        }
+
+        fn memory_write(&mut self, address: usize, value: u8) {
+            self.memory[address] = value;
+        }
+
+        fn memory_write_word(&mut self, address: usize, value: u16) {
+            self.memory[address] = value as u8;
+            self.memory[address + 1] = (value >> 8) as u8;
+        }
+
+        fn tick(&mut self, _: u8, _: u8) {}
+    }
+
+    fn new_bus() -> TestBus {
+        TestBus { memory: vec![0; 0x10000] }
+    }
+
+    #[test]
+    fn test_ihex_data_and_eof() {
+        let text = ":030100003E427606\n:020103000000FA\n\n:00000001FF\n:01002000CC13\n";
+        let program = Program::from_ihex(text).unwrap();
+        // contiguous records are joined and nothing after the eof is read
+        assert_eq!(vec![Segment { address: 0x100, data: vec![0x3e, 0x42, 0x76, 0x00, 0x00] }], program.segments);
+        assert_eq!(None, program.entry);
+    }
+
+    #[test]
+    fn test_ihex_extended_addresses() {
+        let linear = ":020000040001F9\n:02001000AABB89\n:0400000500000100F6\n:00000001FF";
+        let program = Program::from_ihex(linear).unwrap();
+        assert_eq!(vec![Segment { address: 0x10010, data: vec![0xaa, 0xbb] }], program.segments);
+        assert_eq!(Some(0x100), program.entry);
+
+        let segment = ":020000021000EC\n:01002000CC13\n:0400000300100034B5\n:00000001FF";
+        let program = Program::from_ihex(segment).unwrap();
+        assert_eq!(vec![Segment { address: 0x10020, data: vec![0xcc] }], program.segments);
+        assert_eq!(Some(0x134), program.entry);
+    }
+
+    #[test]
+    fn test_ihex_errors() {
+        assert_eq!(
+            Err(LoadError::Checksum { line: 2, expected: 0x06, found: 0x07 }),
+            Program::from_ihex("\n:030100003E427607")
+        );
+        assert_eq!(Err(LoadError::MissingStartCode { line: 1 }), Program::from_ihex("030100003E427606"));
+        assert_eq!(Err(LoadError::InvalidHex { line: 1 }), Program::from_ihex(":0301000G3E427606"));
+        assert_eq!(Err(LoadError::InvalidHex { line: 1 }), Program::from_ihex(":030100003E42760"));
+        assert_eq!(Err(LoadError::BadLength { line: 1 }), Program::from_ihex(":040100003E427605"));
+        assert_eq!(
+            Err(LoadError::UnknownRecordType { line: 1, record_type: 6 }),
+            Program::from_ihex(":00000006FA")
+        );
+    }
+
+    #[test]
+    fn test_srec() {
+        let s19 = "S00600004844521B\nS106800001020373\nS10480030474\nS5030002FA\nS90380007C\n";
+        let program = Program::from_srec(s19).unwrap();
+        assert_eq!(vec![Segment { address: 0x8000, data: vec![1, 2, 3, 4] }], program.segments);
+        assert_eq!(Some(0x8000), program.entry);
+
+        let s28 = Program::from_srec("S2050123450988\nS80401234592").unwrap();
+        assert_eq!(vec![Segment { address: 0x12345, data: vec![9] }], s28.segments);
+        assert_eq!(Some(0x12345), s28.entry);
+
+        let s37 = Program::from_srec("S307000040000708A9\nS70500004000BA").unwrap();
+        assert_eq!(vec![Segment { address: 0x4000, data: vec![7, 8] }], s37.segments);
+        assert_eq!(Some(0x4000), s37.entry);
+    }
+
+    #[test]
+    fn test_srec_errors() {
+        assert_eq!(
+            Err(LoadError::Checksum { line: 2, expected: 0x73, found: 0x72 }),
+            Program::from_srec("S00600004844521B\nS106800001020372")
+        );
+        assert_eq!(
+            Err(LoadError::RecordCount { line: 2, expected: 3, found: 1 }),
+            Program::from_srec("S106800001020373\nS5030003F9")
+        );
+        assert_eq!(Err(LoadError::MissingStartCode { line: 1 }), Program::from_srec(":00000001FF"));
+        assert_eq!(
+            Err(LoadError::UnknownRecordType { line: 1, record_type: 4 }),
+            Program::from_srec("S4030000FC")
+        );
+    }
+
+    #[test]
+    fn test_load_sets_pc() {
+        let mut cpu = Z80::new();
+        let mut bus = new_bus();
+        Program::from_srec("S106800001020373\nS90380007C").unwrap().load(&mut cpu, &mut bus).unwrap();
+        assert_eq!([1, 2, 3], bus.memory[0x8000..0x8003]);
+        assert_eq!(0x8000, cpu.pc);
+
+        // ld a,0x42; halt
+        Program::binary(&[0x3e, 0x42, 0x76], 0x200).load(&mut cpu, &mut bus).unwrap();
+        assert_eq!(0x200, cpu.pc);
+        cpu.step(&mut bus);
+        assert_eq!(0x42, cpu.registers.a);
+
+        // no entry leaves pc alone
+        Program::from_ihex(":030100003E427606\n:00000001FF").unwrap().load(&mut cpu, &mut bus).unwrap();
+        assert_eq!(0x202, cpu.pc);
+        assert_eq!(0x76, bus.memory[0x102]);
+    }
+
+    #[test]
+    fn test_address_out_of_range() {
+        let mut cpu = Z80::new();
+        let mut bus = new_bus();
+        let linear = Program::from_ihex(":020000040001F9\n:02001000AABB89\n:00000001FF").unwrap();
+        assert_eq!(Err(LoadError::AddressOutOfRange { address: 0x10010 }), linear.write_to(&mut bus));
+
+        // the entry is checked before anything is written
+        let s28 = Program::from_srec("S2050123450988\nS80401234592").unwrap();
+        assert_eq!(Err(LoadError::AddressOutOfRange { address: 0x12345 }), s28.load(&mut cpu, &mut bus));
+
+        // a binary running past $ffff is not written at all
+        let binary = Program::binary(&[1, 2, 3], 0xfffe);
+        assert_eq!(Err(LoadError::AddressOutOfRange { address: 0x10000 }), binary.load(&mut cpu, &mut bus));
+        assert_eq!(0, bus.memory[0xfffe]);
+        assert_eq!(0, cpu.pc);
+    }
+}
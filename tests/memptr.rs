@@ -0,0 +1,378 @@
+#[cfg(test)]
+mod test_memptr {
+    use z80::bus::Bus;
+    use z80::cpu::Z80;
+
+    struct TestBus {
+        memory: Vec<u8>,
+    }
+
+    impl TestBus {
+        fn new(prg: Vec<u8>) -> TestBus {
+            TestBus { memory: prg }
+        }
+    }
+
+    impl Bus for TestBus {
+        fn memory_read(&self, address: usize) -> u8 {
+            self.memory[address]
+        }
+
+        fn memory_read_word(&self, address: usize) -> u16 {
+            self.memory[address] as u16 | ((self.memory[address + 1] as u16) << 8)
+        }
+
+        fn memory_write(&mut self, address: usize, value: u8) {
+            self.memory[address] = value;
+        }
+
+        fn memory_write_word(&mut self, address: usize, value: u16) {
+            self.memory[address] = value as u8;
+            self.memory[address + 1] = (value >> 8) as u8;
+        }
+
+        #[allow(unused_variables)]
+        fn port_write(&mut self, port: u8, byte: u8) {}
+
+        #[allow(unused_variables)]
+        fn port_read(&mut self, port: u8) -> u8 {
+            0xff
+        }
+
+        #[allow(unused_variables)]
+        fn tick(&mut self, machine_cycles: u8, t_states: u8) {}
+    }
+
+    const XY: u8 = 0b0010_1000;
+
+    fn new_cpu(mut prg: Vec<u8>) -> (Z80, TestBus) {
+        prg.resize(0x10000, 0);
+        let mut cpu = Z80::new();
+        cpu.sp = 0x8000;
+        (cpu, TestBus::new(prg))
+    }
+
+    fn run(prg: Vec<u8>, setup: impl FnOnce(&mut Z80, &mut TestBus)) -> (Z80, TestBus) {
+        let (mut cpu, mut bus) = new_cpu(prg);
+        setup(&mut cpu, &mut bus);
+        cpu.step(&mut bus);
+        (cpu, bus)
+    }
+
+    #[test]
+    fn ld_a_mem_rp() {
+        let (cpu, _) = run(vec![0x0a], |cpu, _| {
+            cpu.registers.b = 0x12;
+            cpu.registers.c = 0x34;
+        });
+        assert_eq!(0x1235, cpu.registers.wz);
+
+        let (cpu, _) = run(vec![0x1a], |cpu, _| {
+            cpu.registers.d = 0x12;
+            cpu.registers.e = 0xff;
+        });
+        assert_eq!(0x1300, cpu.registers.wz);
+    }
+
+    #[test]
+    fn ld_mem_rp_a() {
+        let (cpu, _) = run(vec![0x02], |cpu, _| {
+            cpu.registers.a = 0x56;
+            cpu.registers.b = 0x12;
+            cpu.registers.c = 0xff;
+        });
+        assert_eq!(0x5600, cpu.registers.wz);
+
+        let (cpu, _) = run(vec![0x12], |cpu, _| {
+            cpu.registers.a = 0x56;
+            cpu.registers.d = 0x12;
+            cpu.registers.e = 0x34;
+        });
+        assert_eq!(0x5635, cpu.registers.wz);
+    }
+
+    #[test]
+    fn ld_mem_hl_leaves_wz() {
+        let (cpu, _) = run(vec![0x7e], |cpu, _| {
+            cpu.registers.wz = 0xbeef;
+            cpu.registers.h = 0x12;
+            cpu.registers.l = 0x34;
+        });
+        assert_eq!(0xbeef, cpu.registers.wz);
+    }
+
+    #[test]
+    fn ld_a_mem_nn() {
+        let (cpu, _) = run(vec![0x3a, 0x34, 0x12], |_, _| {});
+        assert_eq!(0x1235, cpu.registers.wz);
+    }
+
+    #[test]
+    fn ld_mem_nn_a() {
+        let (cpu, bus) = run(vec![0x32, 0x34, 0x12], |cpu, _| cpu.registers.a = 0x56);
+        assert_eq!(0x56, bus.memory_read(0x1234));
+        assert_eq!(0x5635, cpu.registers.wz);
+    }
+
+    #[test]
+    fn ld_rp_mem_nn() {
+        let (cpu, _) = run(vec![0x2a, 0x34, 0x12], |_, _| {});
+        assert_eq!(0x1235, cpu.registers.wz);
+
+        let (cpu, _) = run(vec![0xed, 0x4b, 0xff, 0x12], |_, _| {});
+        assert_eq!(0x1300, cpu.registers.wz);
+
+        let (cpu, _) = run(vec![0xdd, 0x2a, 0x34, 0x12], |_, _| {});
+        assert_eq!(0x1235, cpu.registers.wz);
+    }
+
+    #[test]
+    fn ld_mem_nn_rp() {
+        let (cpu, _) = run(vec![0x22, 0x34, 0x12], |_, _| {});
+        assert_eq!(0x1235, cpu.registers.wz);
+
+        let (cpu, _) = run(vec![0xed, 0x73, 0x34, 0x12], |_, _| {});
+        assert_eq!(0x1235, cpu.registers.wz);
+
+        let (cpu, _) = run(vec![0xfd, 0x22, 0x34, 0x12], |_, _| {});
+        assert_eq!(0x1235, cpu.registers.wz);
+    }
+
+    #[test]
+    fn add_adc_sbc_16() {
+        let setup = |cpu: &mut Z80, _: &mut TestBus| {
+            cpu.registers.h = 0x10;
+            cpu.registers.l = 0xff;
+            cpu.registers.ix = 0x2000;
+            cpu.registers.iy = 0x3000;
+        };
+        let (cpu, _) = run(vec![0x09], setup);
+        assert_eq!(0x1100, cpu.registers.wz);
+        let (cpu, _) = run(vec![0xed, 0x5a], setup);
+        assert_eq!(0x1100, cpu.registers.wz);
+        let (cpu, _) = run(vec![0xed, 0x52], setup);
+        assert_eq!(0x1100, cpu.registers.wz);
+        let (cpu, _) = run(vec![0xdd, 0x09], setup);
+        assert_eq!(0x2001, cpu.registers.wz);
+        let (cpu, _) = run(vec![0xfd, 0x29], setup);
+        assert_eq!(0x3001, cpu.registers.wz);
+    }
+
+    #[test]
+    fn ex_mem_sp_hl() {
+        let (cpu, _) = run(vec![0xe3], |_, bus| bus.memory_write_word(0x8000, 0x1234));
+        assert_eq!(0x1234, cpu.registers.wz);
+
+        let (cpu, _) = run(vec![0xdd, 0xe3], |_, bus| bus.memory_write_word(0x8000, 0x4321));
+        assert_eq!(0x4321, cpu.registers.ix);
+        assert_eq!(0x4321, cpu.registers.wz);
+    }
+
+    #[test]
+    fn jp() {
+        let (cpu, _) = run(vec![0xc3, 0x34, 0x12], |_, _| {});
+        assert_eq!(0x1234, cpu.registers.wz);
+
+        // conditional jumps load wz whether taken or not
+        let (cpu, _) = run(vec![0xc2, 0x34, 0x12], |cpu, _| cpu.registers.f = 0x40);
+        assert_eq!(0x0003, cpu.pc);
+        assert_eq!(0x1234, cpu.registers.wz);
+
+        let (cpu, _) = run(vec![0xe9], |cpu, _| {
+            cpu.registers.wz = 0xbeef;
+            cpu.registers.h = 0x12;
+        });
+        assert_eq!(0x1200, cpu.pc);
+        assert_eq!(0xbeef, cpu.registers.wz);
+
+        // the operand wraps around to the start of memory
+        let (cpu, _) = run(vec![0x34, 0x12], |cpu, bus| {
+            bus.memory_write(0xffff, 0xc3);
+            cpu.pc = 0xffff;
+        });
+        assert_eq!(0x1234, cpu.pc);
+        assert_eq!(0x1234, cpu.registers.wz);
+    }
+
+    #[test]
+    fn jr_and_djnz() {
+        let (cpu, _) = run(vec![0x18, 0x10], |_, _| {});
+        assert_eq!(0x0012, cpu.registers.wz);
+
+        let (cpu, _) = run(vec![0x20, 0x10], |cpu, _| {
+            cpu.registers.wz = 0xbeef;
+            cpu.registers.f = 0x40;
+        });
+        assert_eq!(0xbeef, cpu.registers.wz);
+
+        let (cpu, _) = run(vec![0x10, 0xfe], |cpu, _| cpu.registers.b = 2);
+        assert_eq!(0x0000, cpu.pc);
+        assert_eq!(0x0000, cpu.registers.wz);
+    }
+
+    #[test]
+    fn call_ret_rst() {
+        let (cpu, _) = run(vec![0xcd, 0x34, 0x12], |_, _| {});
+        assert_eq!(0x1234, cpu.registers.wz);
+
+        let (cpu, _) = run(vec![0xdc, 0x34, 0x12], |_, _| {});
+        assert_eq!(0x0003, cpu.pc);
+        assert_eq!(0x1234, cpu.registers.wz);
+
+        let (cpu, _) = run(vec![0xc9], |_, bus| bus.memory_write_word(0x8000, 0x4321));
+        assert_eq!(0x4321, cpu.registers.wz);
+
+        let (cpu, _) = run(vec![0xed, 0x4d], |_, bus| bus.memory_write_word(0x8000, 0x4321));
+        assert_eq!(0x4321, cpu.registers.wz);
+
+        let (cpu, _) = run(vec![0xef], |_, _| {});
+        assert_eq!(0x0028, cpu.registers.wz);
+    }
+
+    #[test]
+    fn in_out() {
+        let (cpu, _) = run(vec![0xdb, 0x34], |cpu, _| cpu.registers.a = 0x12);
+        assert_eq!(0x1235, cpu.registers.wz);
+
+        let (cpu, _) = run(vec![0xd3, 0xff], |cpu, _| cpu.registers.a = 0x12);
+        assert_eq!(0x1200, cpu.registers.wz);
+
+        let bc = |cpu: &mut Z80, _: &mut TestBus| {
+            cpu.registers.b = 0x12;
+            cpu.registers.c = 0x34;
+        };
+        let (cpu, _) = run(vec![0xed, 0x78], bc);
+        assert_eq!(0x1235, cpu.registers.wz);
+        let (cpu, _) = run(vec![0xed, 0x79], bc);
+        assert_eq!(0x1235, cpu.registers.wz);
+    }
+
+    #[test]
+    fn block_io() {
+        let setup = |cpu: &mut Z80, _: &mut TestBus| {
+            cpu.registers.b = 0x12;
+            cpu.registers.c = 0x34;
+            cpu.registers.h = 0x40;
+        };
+        // ini/ind use bc before b is decremented
+        let (cpu, _) = run(vec![0xed, 0xa2], setup);
+        assert_eq!(0x1235, cpu.registers.wz);
+        let (cpu, _) = run(vec![0xed, 0xaa], setup);
+        assert_eq!(0x1233, cpu.registers.wz);
+        // outi/outd use bc after b is decremented
+        let (cpu, _) = run(vec![0xed, 0xa3], setup);
+        assert_eq!(0x1135, cpu.registers.wz);
+        let (cpu, _) = run(vec![0xed, 0xab], setup);
+        assert_eq!(0x1133, cpu.registers.wz);
+    }
+
+    #[test]
+    fn ldir() {
+        let setup = |cpu: &mut Z80, _: &mut TestBus| {
+            cpu.registers.wz = 0xbeef;
+            cpu.registers.c = 2;
+            cpu.registers.h = 0x40;
+            cpu.registers.d = 0x50;
+        };
+        let (mut cpu, mut bus) = run(vec![0x00, 0x00, 0xed, 0xb0], |cpu, bus| {
+            setup(cpu, bus);
+            cpu.pc = 2;
+        });
+        assert_eq!(0x0002, cpu.pc);
+        assert_eq!(0x0003, cpu.registers.wz);
+
+        cpu.registers.wz = 0xbeef;
+        cpu.step(&mut bus);
+        assert_eq!(0x0004, cpu.pc);
+        assert_eq!(0xbeef, cpu.registers.wz);
+    }
+
+    #[test]
+    fn cpi_cpd() {
+        let (cpu, _) = run(vec![0xed, 0xa1], |cpu, _| cpu.registers.wz = 0x1234);
+        assert_eq!(0x1235, cpu.registers.wz);
+        let (cpu, _) = run(vec![0xed, 0xa9], |cpu, _| cpu.registers.wz = 0x1234);
+        assert_eq!(0x1233, cpu.registers.wz);
+
+        // repeating cpir points wz at the instruction
+        let (cpu, _) = run(vec![0xed, 0xb1], |cpu, _| {
+            cpu.registers.a = 0xff;
+            cpu.registers.c = 2;
+        });
+        assert_eq!(0x0000, cpu.pc);
+        assert_eq!(0x0001, cpu.registers.wz);
+    }
+
+    #[test]
+    fn rld_rrd() {
+        let hl = |cpu: &mut Z80, _: &mut TestBus| {
+            cpu.registers.h = 0x12;
+            cpu.registers.l = 0x34;
+        };
+        let (cpu, _) = run(vec![0xed, 0x6f], hl);
+        assert_eq!(0x1235, cpu.registers.wz);
+        let (cpu, _) = run(vec![0xed, 0x67], hl);
+        assert_eq!(0x1235, cpu.registers.wz);
+    }
+
+    #[test]
+    fn indexed() {
+        let (cpu, _) = run(vec![0xdd, 0x7e, 0xfe], |cpu, _| cpu.registers.ix = 0x1000);
+        assert_eq!(0x0ffe, cpu.registers.wz);
+
+        let (cpu, _) = run(vec![0xfd, 0x34, 0x05], |cpu, _| cpu.registers.iy = 0x1000);
+        assert_eq!(0x1005, cpu.registers.wz);
+
+        let (cpu, _) = run(vec![0xdd, 0xcb, 0x05, 0x06], |cpu, _| cpu.registers.ix = 0x1000);
+        assert_eq!(0x1005, cpu.registers.wz);
+    }
+
+    #[test]
+    fn interrupt() {
+        let (mut cpu, mut bus) = new_cpu(vec![]);
+        cpu.trigger_nmi();
+        cpu.handle_interrupt(&mut bus);
+        assert_eq!(0x0066, cpu.registers.wz);
+    }
+
+    #[test]
+    fn bit_hl_takes_xy_from_wz() {
+        let (cpu, _) = run(vec![0xcb, 0x46], |cpu, _| {
+            cpu.registers.wz = 0x2800;
+            cpu.registers.f = 0;
+        });
+        assert_eq!(XY, cpu.registers.f & XY);
+
+        let (cpu, _) = run(vec![0xcb, 0x46], |cpu, bus| {
+            cpu.registers.wz = 0x0000;
+            cpu.registers.h = 0x40;
+            bus.memory_write(0x4000, 0xff);
+            cpu.registers.f = XY;
+        });
+        assert_eq!(0, cpu.registers.f & XY);
+    }
+
+    #[test]
+    fn bit_r_takes_xy_from_register() {
+        let (cpu, _) = run(vec![0xcb, 0x40], |cpu, _| {
+            cpu.registers.wz = 0x0000;
+            cpu.registers.b = 0x28;
+        });
+        assert_eq!(XY, cpu.registers.f & XY);
+    }
+
+    #[test]
+    fn bit_index_takes_xy_from_address() {
+        let (cpu, _) = run(vec![0xdd, 0xcb, 0x00, 0x46], |cpu, _| {
+            cpu.registers.wz = 0x0000;
+            cpu.registers.ix = 0x2800;
+        });
+        assert_eq!(XY, cpu.registers.f & XY);
+
+        let (cpu, _) = run(vec![0xfd, 0xcb, 0x01, 0x7e], |cpu, _| {
+            cpu.registers.wz = 0xffff;
+            cpu.registers.iy = 0x1000;
+        });
+        assert_eq!(0, cpu.registers.f & XY);
+    }
+}
@@ -13,7 +13,6 @@ mod test_z80 {
         memory: Vec<u8>,
         pub m_cycles: u8,
         pub t_states: u8,
-        pub ticks: u8,
     }
 
     impl TestBus {
@@ -22,7 +21,6 @@ mod test_z80 {
                 memory: prg,
                 m_cycles: 0,
                 t_states: 0,
-                ticks: 0,
             }
         }
     }
@@ -529,4 +527,155 @@ mod test_z80 {
        assert_eq!(SUBTRACT, cpu.registers.f & XYMASK);
 
    }
-}
\ No newline at end of file
+
+   const X: u8 = 0b0000_1000;
+   const Y: u8 = 0b0010_0000;
+
+   #[test]
+   fn test_daa_after_add() {
+       let (mut cpu, mut bus) = new_bus_and_cpu_with_prg(vec![0x3e, 0x15, 0xc6, 0x27, 0x27]);
+       cpu.execute_next_instruction(&mut bus);
+       cpu.execute_next_instruction(&mut bus);
+       cpu.execute_next_instruction(&mut bus);
+       assert_eq!(0x42, cpu.registers.a);
+       assert_eq!(HALFCARRY | PARITY, cpu.registers.f);
+
+       let (mut cpu, mut bus) = new_bus_and_cpu_with_prg(vec![0x3e, 0x99, 0xc6, 0x01, 0x27]);
+       cpu.execute_next_instruction(&mut bus);
+       cpu.execute_next_instruction(&mut bus);
+       cpu.execute_next_instruction(&mut bus);
+       assert_eq!(0x00, cpu.registers.a);
+       assert_eq!(ZERO | HALFCARRY | PARITY | CARRY, cpu.registers.f);
+   }
+
+   #[test]
+   fn test_daa_after_sub() {
+       let (mut cpu, mut bus) = new_bus_and_cpu_with_prg(vec![0x3e, 0x42, 0xd6, 0x15, 0x27]);
+       cpu.execute_next_instruction(&mut bus);
+       cpu.execute_next_instruction(&mut bus);
+       cpu.execute_next_instruction(&mut bus);
+       assert_eq!(0x27, cpu.registers.a);
+       assert_eq!(Y | PARITY | SUBTRACT, cpu.registers.f);
+   }
+
+   #[test]
+   fn test_cpl_xy() {
+       let (mut cpu, mut bus) = new_bus_and_cpu_with_prg(vec![0x2f]);
+       cpu.registers.a = 0x5a;
+       cpu.execute_next_instruction(&mut bus);
+       assert_eq!(0xa5, cpu.registers.a);
+       assert_eq!(Y | HALFCARRY | SUBTRACT, cpu.registers.f);
+   }
+
+   // scf after an instruction that left the flags alone (ld b,b) and after one
+   // that wrote them (and a), for each chip flavour
+   fn scf_flags(model: Model, prg: Vec<u8>, a: u8, f: u8) -> u8 {
+       let (mut cpu, mut bus) = new_bus_and_cpu_with_prg(prg);
+       cpu.model = model;
+       cpu.registers.a = a;
+       cpu.registers.f = f;
+       cpu.execute_next_instruction(&mut bus);
+       cpu.execute_next_instruction(&mut bus);
+       cpu.registers.f
+   }
+
+   #[test]
+   fn test_scf_zilog_nmos() {
+       assert_eq!(Y | X | CARRY, scf_flags(Model::ZilogNmos, vec![0x40, 0x37], 0x00, Y | X));
+       assert_eq!(Y | CARRY, scf_flags(Model::ZilogNmos, vec![0x40, 0x37], 0x20, 0));
+       assert_eq!(Y | X | PARITY | CARRY, scf_flags(Model::ZilogNmos, vec![0xa7, 0x37], 0x28, 0));
+       assert_eq!(ZERO | PARITY | CARRY, scf_flags(Model::ZilogNmos, vec![0xa7, 0x37], 0x00, Y | X));
+   }
+
+   #[test]
+   fn test_scf_zilog_cmos() {
+       // q clear after ld b,b, so X and Y are f | a
+       assert_eq!(Y | X | CARRY, scf_flags(Model::ZilogCmos, vec![0x40, 0x37], 0x00, Y | X));
+       assert_eq!(Y | CARRY, scf_flags(Model::ZilogCmos, vec![0x40, 0x37], 0x20, 0));
+       // q set after and a, so X and Y come from a only
+       assert_eq!(Y | X | PARITY | CARRY, scf_flags(Model::ZilogCmos, vec![0xa7, 0x37], 0x28, 0));
+       assert_eq!(ZERO | PARITY | CARRY, scf_flags(Model::ZilogCmos, vec![0xa7, 0x37], 0x00, Y | X));
+       assert_eq!(Y | X | HALFCARRY, scf_flags(Model::ZilogCmos, vec![0x40, 0x3f], 0x00, Y | X | CARRY));
+       assert_eq!(CARRY, scf_flags(Model::ZilogCmos, vec![0x3f, 0x3f], 0x00, Y | X | CARRY));
+   }
+
+   #[test]
+   fn test_scf_after_nmi() {
+       // cp $28 leaves Y and X set in f and a at 0, then the nmi handler runs scf
+       let mut prg = vec![0xfe, 0x28];
+       prg.resize(0x66, 0);
+       prg.push(0x37);
+       let (mut cpu, mut bus) = new_bus_and_cpu_with_prg(prg);
+       cpu.step(&mut bus);
+       cpu.trigger_nmi();
+       cpu.step(&mut bus);
+       assert_eq!(0x67, cpu.pc);
+       // q was cleared by the acknowledge, so X and Y come from f
+       assert_eq!(Y | X, cpu.registers.f & (Y | X));
+   }
+
+   #[test]
+   fn test_scf_nec_nmos() {
+       assert_eq!(CARRY, scf_flags(Model::NecNmos, vec![0x40, 0x37], 0x00, Y | X));
+       assert_eq!(X | CARRY, scf_flags(Model::NecNmos, vec![0x40, 0x37], 0x08, Y));
+       assert_eq!(ZERO | PARITY | CARRY, scf_flags(Model::NecNmos, vec![0xa7, 0x37], 0x00, Y | X));
+   }
+
+   #[test]
+   fn test_ccf_q() {
+       assert_eq!(HALFCARRY, scf_flags(Model::ZilogNmos, vec![0x40, 0x3f], 0x00, CARRY));
+       assert_eq!(Y | X | HALFCARRY, scf_flags(Model::ZilogNmos, vec![0x40, 0x3f], 0x00, Y | X | CARRY));
+       assert_eq!(HALFCARRY, scf_flags(Model::NecNmos, vec![0x40, 0x3f], 0x00, Y | X | CARRY));
+       // the second ccf sees the flags the first one wrote
+       assert_eq!(CARRY, scf_flags(Model::ZilogNmos, vec![0x3f, 0x3f], 0x00, Y | X | CARRY));
+   }
+
+   #[test]
+   fn test_ddcb_copy_to_register() {
+       // rlc (ix+5),b
+       let (mut cpu, mut bus) = new_cpu(vec![0xdd, 0xcb, 0x05, 0x00]);
+       cpu.registers.ix = 0x1000;
+       bus.memory_write(0x1005, 0x81);
+       cpu.execute_next_instruction(&mut bus);
+       assert_eq!(0x03, bus.memory_read(0x1005));
+       assert_eq!(0x03, cpu.registers.b);
+       assert_eq!(CARRY | PARITY, cpu.registers.f);
+       assert_eq!(23, bus.t_states);
+
+       // set 3,(iy-2),a
+       let (mut cpu, mut bus) = new_cpu(vec![0xfd, 0xcb, 0xfe, 0xdf]);
+       cpu.registers.iy = 0x1002;
+       cpu.execute_next_instruction(&mut bus);
+       assert_eq!(0x08, bus.memory_read(0x1000));
+       assert_eq!(0x08, cpu.registers.a);
+       assert_eq!(23, bus.t_states);
+   }
+
+   #[test]
+   fn test_ddcb_memory_only() {
+       // res 0,(ix+1) leaves every register alone
+       let (mut cpu, mut bus) = new_cpu(vec![0xdd, 0xcb, 0x01, 0x86]);
+       cpu.registers.ix = 0x1000;
+       cpu.registers.h = 0x12;
+       cpu.registers.l = 0x34;
+       bus.memory_write(0x1001, 0xff);
+       cpu.execute_next_instruction(&mut bus);
+       assert_eq!(0xfe, bus.memory_read(0x1001));
+       assert_eq!(0x12, cpu.registers.h);
+       assert_eq!(0x34, cpu.registers.l);
+       assert_eq!(23, bus.t_states);
+   }
+
+   #[test]
+   fn test_ddcb_bit_ignores_register() {
+       for op in 0x78..=0x7f {
+           let (mut cpu, mut bus) = new_cpu(vec![0xdd, 0xcb, 0x00, op]);
+           cpu.registers.ix = 0x1000;
+           bus.memory_write(0x1000, 0x80);
+           cpu.execute_next_instruction(&mut bus);
+           assert_eq!(0x00, cpu.registers.b);
+           assert_eq!(SIGN | HALFCARRY, cpu.registers.f & XYMASK);
+           assert_eq!(20, bus.t_states);
+       }
+   }
+}
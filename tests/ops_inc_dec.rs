@@ -70,7 +70,7 @@ mod test_z80 {
         ]);
 
         cpu.registers.b = 0x10;
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(0x11, cpu.registers.b);
         assert_eq!(1, bus.m_cycles);
         assert_eq!(4, bus.t_states);
@@ -84,7 +84,7 @@ mod test_z80 {
 
         cpu.registers.b = 0x14;
         cpu.registers.c = 0x7;
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(0x8, cpu.registers.c);
         assert_eq!(1, bus.m_cycles);
         assert_eq!(6, bus.t_states);
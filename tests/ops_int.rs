@@ -3,13 +3,21 @@
 #[cfg(test)]
 mod test_z80 {
     use z80::cpu::Z80;
-    use z80::bus::Bus;
+    use z80::bus::{Bus, Cycle};
 
 
     struct TestBus {
         memory: Vec<u8>,
         pub m_cycles: u8,
         pub t_states: u8,
+        /// Bytes the interrupting device puts on the data bus
+        pub data_bus: Vec<u8>,
+        pub acks: usize,
+        /// A slow device at this address holds /WAIT for `wait_states` when read
+        pub slow: Option<(u16, u8)>,
+        waiting: u8,
+        /// The cycles /WAIT stretched, once per wait state
+        pub waited: Vec<Cycle>,
     }
 
     impl TestBus {
@@ -18,6 +26,11 @@ mod test_z80 {
                 memory: prg,
                 m_cycles: 0,
                 t_states: 0,
+                data_bus: vec![],
+                acks: 0,
+                slow: None,
+                waiting: 0,
+                waited: vec![],
             }
         }
     }
@@ -48,6 +61,30 @@ mod test_z80 {
             0xff
         }
 
+        fn interrupt_acknowledge(&mut self) -> u8 {
+            let byte = self.data_bus.get(self.acks).copied().unwrap_or(0xff);
+            self.acks += 1;
+            byte
+        }
+
+        fn cycle(&mut self, cycle: Cycle) -> u8 {
+            if let (Cycle::MemRead { addr }, Some((slow, wait_states))) = (cycle, self.slow) {
+                if addr == slow {
+                    self.waiting = wait_states;
+                }
+            }
+            0
+        }
+
+        fn wait(&mut self, cycle: Cycle) -> bool {
+            if self.waiting == 0 {
+                return false;
+            }
+            self.waiting -= 1;
+            self.waited.push(cycle);
+            true
+        }
+
         fn tick(&mut self, machine_cycles: u8, t_states: u8) {
             self.m_cycles += machine_cycles;
             self.t_states += t_states;
@@ -70,8 +107,255 @@ mod test_z80 {
         ]);
 
 
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(1, bus.m_cycles);
         assert_eq!(4, bus.t_states);
     }
-}
\ No newline at end of file
+
+    /// Runs ei and a nop so interrupts are accepted, then acknowledges INT
+    fn acknowledge(im: u8, data_bus: Vec<u8>) -> (Z80, TestBus) {
+        let (mut cpu, mut bus) = new_cpu(vec![0xfb, 0x00]);
+        cpu.interrupt_mode = im;
+        cpu.sp = 0x2000;
+        bus.data_bus = data_bus;
+        cpu.step(&mut bus);
+        cpu.step(&mut bus);
+        bus.m_cycles = 0;
+        bus.t_states = 0;
+        cpu.set_int_line(true);
+        cpu.handle_interrupt(&mut bus);
+        (cpu, bus)
+    }
+
+    fn pushed(bus: &TestBus, sp: u16) -> u16 {
+        bus.memory_read(sp as usize) as u16 | ((bus.memory_read(sp as usize + 1) as u16) << 8)
+    }
+
+    #[test]
+    fn test_im1() {
+        let (cpu, bus) = acknowledge(1, vec![]);
+
+        assert_eq!(0x0038, cpu.pc);
+        assert_eq!(0x1ffe, cpu.sp);
+        assert_eq!(0x0002, pushed(&bus, cpu.sp));
+        assert_eq!(0x0038, cpu.registers.wz);
+        assert_eq!(13, bus.t_states);
+    }
+
+    #[test]
+    fn test_im2() {
+        let (mut cpu, mut bus) = new_cpu(vec![0xfb, 0x00]);
+        cpu.interrupt_mode = 2;
+        cpu.sp = 0x2000;
+        cpu.registers.i = 0x30;
+        // the low bit of the vector byte is ignored
+        bus.data_bus = vec![0x41];
+        bus.memory_write(0x3040, 0x34);
+        bus.memory_write(0x3041, 0x12);
+        cpu.step(&mut bus);
+        cpu.step(&mut bus);
+        bus.t_states = 0;
+
+        cpu.set_int_line(true);
+        cpu.handle_interrupt(&mut bus);
+        assert_eq!(0x1234, cpu.pc);
+        assert_eq!(0x0002, pushed(&bus, cpu.sp));
+        assert_eq!(1, bus.acks);
+        assert_eq!(19, bus.t_states);
+    }
+
+    #[test]
+    fn test_im0_rst() {
+        let (cpu, bus) = acknowledge(0, vec![0xcf]);
+
+        assert_eq!(0x0008, cpu.pc);
+        assert_eq!(0x0002, pushed(&bus, cpu.sp));
+        assert_eq!(13, bus.t_states);
+    }
+
+    #[test]
+    fn test_im0_multi_byte() {
+        let (cpu, bus) = acknowledge(0, vec![0xcd, 0x34, 0x12]);
+
+        assert_eq!(0x1234, cpu.pc);
+        assert_eq!(0x0002, pushed(&bus, cpu.sp));
+        assert_eq!(3, bus.acks);
+    }
+
+    #[test]
+    fn test_acknowledge_increments_r() {
+        let (mut cpu, mut bus) = new_cpu(vec![0xfb, 0x00]);
+        cpu.interrupt_mode = 1;
+        cpu.sp = 0x2000;
+        cpu.registers.r = 0xff;
+        cpu.step(&mut bus);
+        cpu.step(&mut bus);
+        assert_eq!(0x81, cpu.registers.r);
+
+        cpu.set_int_line(true);
+        cpu.handle_interrupt(&mut bus);
+        assert_eq!(0x82, cpu.registers.r);
+    }
+
+    #[test]
+    fn test_interrupt_releases_halt() {
+        let (mut cpu, mut bus) = new_cpu(vec![0xfb, 0x76]);
+        cpu.interrupt_mode = 1;
+        cpu.sp = 0x2000;
+        cpu.step(&mut bus);
+        cpu.step(&mut bus);
+        cpu.step(&mut bus);
+        assert_eq!(0x0002, cpu.pc);
+
+        cpu.set_int_line(true);
+        cpu.step(&mut bus);
+        // the nop at 0x38 has run as well
+        assert_eq!(0x0039, cpu.pc);
+        assert_eq!(0x0002, pushed(&bus, cpu.sp));
+    }
+
+    #[test]
+    fn test_interrupt_blocked() {
+        // di
+        let (mut cpu, mut bus) = new_cpu(vec![0xf3, 0x00]);
+        cpu.interrupt_mode = 1;
+        cpu.step(&mut bus);
+        cpu.set_int_line(true);
+        cpu.handle_interrupt(&mut bus);
+        assert_eq!(0x0001, cpu.pc);
+
+        // not right after ei
+        let (mut cpu, mut bus) = new_cpu(vec![0xfb, 0x00]);
+        cpu.interrupt_mode = 1;
+        cpu.step(&mut bus);
+        cpu.set_int_line(true);
+        cpu.handle_interrupt(&mut bus);
+        assert_eq!(0x0001, cpu.pc);
+
+        // and only once until interrupts are enabled again
+        let (mut cpu, mut bus) = acknowledge(1, vec![]);
+        cpu.set_int_line(true);
+        cpu.handle_interrupt(&mut bus);
+        assert_eq!(0x0038, cpu.pc);
+        assert_eq!(0, bus.acks);
+    }
+
+    #[test]
+    fn test_int_is_level_triggered() {
+        // ei, nop, then the handler at 0x38 is ei; reti
+        let (mut cpu, mut bus) = new_cpu(vec![0xfb, 0x00]);
+        bus.memory_write(0x38, 0xfb);
+        bus.memory_write(0x39, 0xed);
+        bus.memory_write(0x3a, 0x4d);
+        cpu.interrupt_mode = 1;
+        cpu.sp = 0x2000;
+        cpu.step(&mut bus);
+        cpu.step(&mut bus);
+
+        cpu.set_int_line(true);
+        cpu.step(&mut bus);
+        cpu.step(&mut bus);
+        // still asserted, so it is taken again once reti is done
+        cpu.step(&mut bus);
+        assert_eq!(0x0039, cpu.pc);
+
+        cpu.set_int_line(false);
+        cpu.step(&mut bus);
+        cpu.step(&mut bus);
+        assert_eq!(0x0003, cpu.pc);
+    }
+
+    #[test]
+    fn test_nmi_is_edge_triggered() {
+        let (mut cpu, mut bus) = new_cpu(vec![0xf3, 0x00]);
+        cpu.sp = 0x2000;
+        cpu.step(&mut bus);
+
+        bus.t_states = 0;
+        cpu.trigger_nmi();
+        cpu.handle_interrupt(&mut bus);
+        // taken even with interrupts disabled
+        assert_eq!(0x0066, cpu.pc);
+        assert_eq!(0x0001, pushed(&bus, cpu.sp));
+        assert_eq!(11, bus.t_states);
+        assert!(cpu.nmi);
+
+        cpu.handle_interrupt(&mut bus);
+        assert_eq!(0x0066, cpu.pc);
+    }
+
+    #[test]
+    fn test_nmi_before_int() {
+        let (mut cpu, mut bus) = new_cpu(vec![0xfb, 0x76]);
+        cpu.interrupt_mode = 1;
+        cpu.sp = 0x2000;
+        cpu.step(&mut bus);
+        cpu.step(&mut bus);
+
+        cpu.set_int_line(true);
+        cpu.trigger_nmi();
+        cpu.handle_interrupt(&mut bus);
+        assert_eq!(0x0066, cpu.pc);
+        assert_eq!(0x0002, pushed(&bus, cpu.sp));
+    }
+
+    #[test]
+    fn test_wait_line() {
+        // ld a,($1234) then nop, with the byte at $1234 two wait states slow
+        let (mut cpu, mut bus) = new_cpu(vec![0x3a, 0x34, 0x12, 0x00]);
+        bus.memory[0x1234] = 0x56;
+        bus.slow = Some((0x1234, 2));
+
+        assert_eq!(15, cpu.step(&mut bus));
+        assert_eq!(0x56, cpu.registers.a);
+        assert_eq!(4, bus.m_cycles);
+        assert_eq!(15, bus.t_states);
+        assert_eq!(vec![Cycle::MemRead { addr: 0x1234 }; 2], bus.waited);
+
+        // the next instruction runs at full speed
+        assert_eq!(4, cpu.step(&mut bus));
+        assert_eq!(0x0004, cpu.pc);
+    }
+
+    #[test]
+    fn test_busreq_line() {
+        let (mut cpu, mut bus) = new_cpu(vec![0xfb, 0x00]);
+        cpu.interrupt_mode = 1;
+        cpu.sp = 0x2000;
+        cpu.step(&mut bus);
+        cpu.step(&mut bus);
+
+        cpu.set_busreq_line(true);
+        cpu.set_int_line(true);
+        assert!(!cpu.busack());
+        cpu.step(&mut bus);
+        assert!(cpu.busack());
+        assert_eq!(0x0002, cpu.pc);
+
+        cpu.set_busreq_line(false);
+        assert!(!cpu.busack());
+        cpu.step(&mut bus);
+        assert_eq!(0x0039, cpu.pc);
+    }
+
+    #[test]
+    fn test_reset_line() {
+        let (mut cpu, mut bus) = new_cpu(vec![0x00]);
+        cpu.pc = 0x1234;
+        cpu.interrupt_mode = 2;
+        cpu.registers.i = 0x12;
+        cpu.registers.a = 0x55;
+
+        cpu.set_reset_line(true);
+        assert_eq!(0x0000, cpu.pc);
+        assert_eq!(0, cpu.interrupt_mode);
+        assert_eq!(0x00, cpu.registers.i);
+        assert_eq!(0x55, cpu.registers.a);
+        cpu.step(&mut bus);
+        assert_eq!(0x0000, cpu.pc);
+
+        cpu.set_reset_line(false);
+        cpu.step(&mut bus);
+        assert_eq!(0x0001, cpu.pc);
+    }
+}
@@ -2,11 +2,12 @@
 mod test_io {
     //    use z80::registers::Reg16;
     use z80::bus::Bus;
-    use z80::cpu::Z80;
+    use z80::cpu::{Model, Z80};
 
     struct TestBus {
         memory: Vec<u8>,
         pub port_data: Vec<u8>,
+        pub last_port: u16,
         pub m_cycles: u8,
         pub t_states: u8,
     }
@@ -17,7 +18,8 @@ mod test_io {
                 memory: prg,
                 m_cycles: 0,
                 t_states: 0,
-                port_data: vec![0; 0xff],
+                port_data: vec![0; 0x100],
+                last_port: 0,
             }
         }
     }
@@ -40,13 +42,13 @@ mod test_io {
             self.memory[address + 1] = (value >> 8) as u8;
         }
 
-        #[allow(unused_variables)]
-        fn port_write(&mut self, port: u8, byte: u8) {
-            self.port_data[port as usize] = byte;
+        fn io_write(&mut self, port: u16, byte: u8) {
+            self.last_port = port;
+            self.port_data[port as u8 as usize] = byte;
         }
 
-        #[allow(unused_variables)]
-        fn port_read(&mut self, port: u8) -> u8 {
+        fn io_read(&mut self, port: u16) -> u8 {
+            self.last_port = port;
             0xff
         }
 
@@ -68,7 +70,7 @@ mod test_io {
         let (mut cpu, mut bus) = new_cpu(vec![0xd3, 0x01]);
 
         cpu.registers.a = 0x23;
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         // assert_eq!(3, bus.m_cycles);
         // assert_eq!(11, bus.t_states);
         assert_eq!(0x23, bus.port_data[0x01]);
@@ -81,7 +83,7 @@ mod test_io {
         cpu.registers.c = 0x01;
         cpu.registers.d = 0x5a;
 
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(3, bus.m_cycles);
         assert_eq!(12, bus.t_states);
         assert_eq!(0x5a, bus.port_data[0x01]);
@@ -99,7 +101,7 @@ mod test_io {
 
         bus.memory_write(0x1000, 0x59);
 
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(0x0f, cpu.registers.b);
         assert_eq!(0x10, cpu.registers.h);
         assert_eq!(0x01, cpu.registers.l);
@@ -107,4 +109,246 @@ mod test_io {
         assert_eq!(4, bus.m_cycles);
         assert_eq!(16, bus.t_states);
     }
+
+    #[test]
+    fn test_in_a_n_address() {
+        let (mut cpu, mut bus) = new_cpu(vec![0xdb, 0xfe]);
+
+        cpu.registers.a = 0x7f;
+        cpu.step(&mut bus);
+        assert_eq!(0x7ffe, bus.last_port);
+        assert_eq!(0xff, cpu.registers.a);
+    }
+
+    #[test]
+    fn test_out_n_a_address() {
+        let (mut cpu, mut bus) = new_cpu(vec![0xd3, 0xfe]);
+
+        cpu.registers.a = 0x12;
+        cpu.step(&mut bus);
+        assert_eq!(0x12fe, bus.last_port);
+        assert_eq!(0x12, bus.port_data[0xfe]);
+    }
+
+    #[test]
+    fn test_in_r_c_address() {
+        let (mut cpu, mut bus) = new_cpu(vec![0xed, 0x78]);
+
+        cpu.registers.b = 0xbf;
+        cpu.registers.c = 0xfe;
+        cpu.step(&mut bus);
+        assert_eq!(0xbffe, bus.last_port);
+    }
+
+    #[test]
+    fn test_out_c_r_address() {
+        let (mut cpu, mut bus) = new_cpu(vec![0xed, 0x79]);
+
+        cpu.registers.a = 0x10;
+        cpu.registers.b = 0x7f;
+        cpu.registers.c = 0xfd;
+        cpu.step(&mut bus);
+        assert_eq!(0x7ffd, bus.last_port);
+        assert_eq!(0x10, bus.port_data[0xfd]);
+    }
+
+    #[test]
+    fn test_ini_ind_address_uses_b_before_decrement() {
+        for op in [0xa2, 0xaa] {
+            let (mut cpu, mut bus) = new_cpu(vec![0xed, op]);
+
+            cpu.registers.b = 0x10;
+            cpu.registers.c = 0x07;
+            cpu.registers.h = 0x20;
+            cpu.step(&mut bus);
+            assert_eq!(0x1007, bus.last_port);
+            assert_eq!(0x0f, cpu.registers.b);
+        }
+    }
+
+    #[test]
+    fn test_outi_outd_address_uses_b_after_decrement() {
+        for op in [0xa3, 0xab] {
+            let (mut cpu, mut bus) = new_cpu(vec![0xed, op]);
+
+            cpu.registers.b = 0x10;
+            cpu.registers.c = 0x07;
+            cpu.registers.h = 0x20;
+            cpu.step(&mut bus);
+            assert_eq!(0x0f07, bus.last_port);
+        }
+    }
+
+    /// Places a block instruction at 0x2800 so the repeat copies 0x28 into x/y
+    fn block_cpu(op: u8, b: u8, c: u8, hl: u16) -> (Z80, TestBus) {
+        let (mut cpu, mut bus) = new_cpu(vec![]);
+        bus.memory_write(0x2800, 0xed);
+        bus.memory_write(0x2801, op);
+        cpu.pc = 0x2800;
+        cpu.registers.b = b;
+        cpu.registers.c = c;
+        cpu.registers.h = (hl >> 8) as u8;
+        cpu.registers.l = hl as u8;
+        (cpu, bus)
+    }
+
+    #[test]
+    fn test_otir() {
+        let (mut cpu, mut bus) = block_cpu(0xb3, 0x02, 0x07, 0x1000);
+        bus.memory_write(0x1000, 0x11);
+        bus.memory_write(0x1001, 0x22);
+
+        cpu.step(&mut bus);
+        assert_eq!(0x2800, cpu.pc);
+        assert_eq!(0x01, cpu.registers.b);
+        assert_eq!(0x11, bus.port_data[0x07]);
+        assert_eq!(0x0107, bus.last_port);
+        assert_eq!(5, bus.m_cycles);
+        assert_eq!(21, bus.t_states);
+
+        bus.m_cycles = 0;
+        bus.t_states = 0;
+        cpu.step(&mut bus);
+        assert_eq!(0x2802, cpu.pc);
+        assert_eq!(0x00, cpu.registers.b);
+        assert_eq!(0x10, cpu.registers.h);
+        assert_eq!(0x02, cpu.registers.l);
+        assert_eq!(0x22, bus.port_data[0x07]);
+        assert_eq!(0x0007, bus.last_port);
+        assert_eq!(4, bus.m_cycles);
+        assert_eq!(16, bus.t_states);
+        assert_eq!(0x40, cpu.registers.f);
+    }
+
+    #[test]
+    fn test_otdr() {
+        let (mut cpu, mut bus) = block_cpu(0xbb, 0x02, 0x07, 0x1001);
+        bus.memory_write(0x1000, 0x11);
+        bus.memory_write(0x1001, 0x22);
+
+        cpu.step(&mut bus);
+        assert_eq!(0x2800, cpu.pc);
+        assert_eq!(0x22, bus.port_data[0x07]);
+        assert_eq!(21, bus.t_states);
+
+        bus.t_states = 0;
+        cpu.step(&mut bus);
+        assert_eq!(0x2802, cpu.pc);
+        assert_eq!(0x00, cpu.registers.b);
+        assert_eq!(0x0f, cpu.registers.h);
+        assert_eq!(0xff, cpu.registers.l);
+        assert_eq!(0x11, bus.port_data[0x07]);
+        assert_eq!(16, bus.t_states);
+    }
+
+    #[test]
+    fn test_inir() {
+        let (mut cpu, mut bus) = block_cpu(0xb2, 0x02, 0x10, 0x1000);
+
+        cpu.step(&mut bus);
+        assert_eq!(0x2800, cpu.pc);
+        assert_eq!(0x0210, bus.last_port);
+        assert_eq!(0xff, bus.memory_read(0x1000));
+        assert_eq!(5, bus.m_cycles);
+        assert_eq!(21, bus.t_states);
+
+        bus.m_cycles = 0;
+        bus.t_states = 0;
+        cpu.step(&mut bus);
+        assert_eq!(0x2802, cpu.pc);
+        assert_eq!(0x0110, bus.last_port);
+        assert_eq!(0xff, bus.memory_read(0x1001));
+        assert_eq!(0x10, cpu.registers.h);
+        assert_eq!(0x02, cpu.registers.l);
+        assert_eq!(0x00, cpu.registers.b);
+        assert_eq!(4, bus.m_cycles);
+        assert_eq!(16, bus.t_states);
+    }
+
+    #[test]
+    fn test_indr() {
+        let (mut cpu, mut bus) = block_cpu(0xba, 0x02, 0x10, 0x1001);
+
+        cpu.step(&mut bus);
+        assert_eq!(0x2800, cpu.pc);
+        assert_eq!(21, bus.t_states);
+
+        bus.t_states = 0;
+        cpu.step(&mut bus);
+        assert_eq!(0x2802, cpu.pc);
+        assert_eq!(0x0f, cpu.registers.h);
+        assert_eq!(0xff, cpu.registers.l);
+        assert_eq!(0xff, bus.memory_read(0x1000));
+        assert_eq!(0xff, bus.memory_read(0x1001));
+        assert_eq!(16, bus.t_states);
+    }
+
+    #[test]
+    fn test_ini_flags() {
+        // 0xff + (c + 1) carries, n is bit 7 of the byte read
+        let (mut cpu, mut bus) = block_cpu(0xa2, 0x81, 0x10, 0x1000);
+
+        cpu.step(&mut bus);
+        assert_eq!(0x80, cpu.registers.b);
+        assert_eq!(0x93, cpu.registers.f);
+        assert_eq!(0x8111, cpu.registers.wz);
+    }
+
+    #[test]
+    fn test_otir_repeat_flags_no_carry() {
+        let (mut cpu, mut bus) = block_cpu(0xb3, 0x05, 0x07, 0x1000);
+        bus.memory_write(0x1000, 0x01);
+
+        cpu.step(&mut bus);
+        // p from b and x/y from the high byte of pc
+        assert_eq!(0x28, cpu.registers.f);
+    }
+
+    #[test]
+    fn test_otdr_repeat_flags_carry() {
+        let (mut cpu, mut bus) = block_cpu(0xbb, 0x10, 0x07, 0x10ff);
+        bus.memory_write(0x10ff, 0x7f);
+
+        cpu.step(&mut bus);
+        // h from the low nibble of b, p from b + 1
+        assert_eq!(0x3d, cpu.registers.f);
+    }
+
+    #[test]
+    fn test_indr_repeat_flags_carry_negative() {
+        let (mut cpu, mut bus) = block_cpu(0xba, 0x10, 0x10, 0x1000);
+
+        cpu.step(&mut bus);
+        // p from b - 1
+        assert_eq!(0x2f, cpu.registers.f);
+    }
+
+    #[test]
+    fn test_in_f_c() {
+        let (mut cpu, mut bus) = new_cpu(vec![0xed, 0x70]);
+
+        cpu.registers.a = 0x12;
+        cpu.registers.f = 0x01;
+        cpu.step(&mut bus);
+        // only the flags change, carry is kept
+        assert_eq!(0x12, cpu.registers.a);
+        assert_eq!(0xad, cpu.registers.f);
+        assert_eq!(12, bus.t_states);
+    }
+
+    #[test]
+    fn test_out_c_0() {
+        let (mut cpu, mut bus) = new_cpu(vec![0xed, 0x71]);
+
+        cpu.registers.c = 0x01;
+        bus.port_data[0x01] = 0x55;
+        cpu.step(&mut bus);
+        assert_eq!(0x00, bus.port_data[0x01]);
+
+        let (mut cpu, mut bus) = new_cpu(vec![0xed, 0x71]);
+        cpu.model = Model::ZilogCmos;
+        cpu.registers.c = 0x01;
+        cpu.step(&mut bus);
+        assert_eq!(0xff, bus.port_data[0x01]);
+    }
 }
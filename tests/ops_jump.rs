@@ -64,7 +64,7 @@ mod test_z80 {
         let (mut cpu, mut bus) = new_cpu(vec![0xe9]);
 
         cpu.registers.l = 0xff;
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(0xff, cpu.pc);
         assert_eq!(1, bus.m_cycles);
         assert_eq!(4, bus.t_states);
@@ -73,7 +73,7 @@ mod test_z80 {
     fn test_jp_nn() {
         let (mut cpu, mut bus) = new_cpu(vec![0xc3, 0xff, 0x00]);
 
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(0xff, cpu.pc);
         assert_eq!(3, bus.m_cycles);
         assert_eq!(10, bus.t_states);
@@ -85,11 +85,11 @@ mod test_z80 {
 
         cpu.sp = 0x2000;
         bus.memory_write_word(0x00ff, 0xc9);
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(0xff, cpu.pc);
         assert_eq!(5, bus.m_cycles);
         assert_eq!(17, bus.t_states);
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(0x03, cpu.pc);
         assert_eq!(0x2000, cpu.sp);
     }
@@ -100,7 +100,7 @@ mod test_z80 {
 
         cpu.sp = 0x2000;
 
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(0x38, cpu.pc);
         // assert_eq!(3, bus.m_cycles);
         // assert_eq!(11, bus.t_states);
@@ -58,7 +58,7 @@ mod test_z80 {
         let (mut cpu, mut bus) = new_cpu(vec![0x41]);
 
         cpu.registers.c = 0x10;
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(0x10, cpu.registers.b);
         assert_eq!(1, bus.m_cycles);
         assert_eq!(4, bus.t_states);
@@ -71,7 +71,7 @@ mod test_z80 {
         bus.memory_write(0xff, 0x20);
         cpu.registers.l = 0xff;
         cpu.registers.c = 0x10;
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(0x20, cpu.registers.c);
         assert_eq!(2, bus.m_cycles);
         assert_eq!(7, bus.t_states);
@@ -86,7 +86,7 @@ mod test_z80 {
         cpu.registers.b = 0x15;
 
         bus.memory_read(0xff);
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(0x15, bus.memory_read(0xff));
         assert_eq!(7, bus.t_states);
         assert_eq!(2, bus.m_cycles);
@@ -99,7 +99,7 @@ mod test_z80 {
         bus.memory_write(0x0011, 0x1);
         cpu.registers.a = 0;
 
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(0x1, cpu.registers.a);
         // assert_eq!(7, bus.t_states);
         // assert_eq!(2, bus.m_cycles);
@@ -111,7 +111,7 @@ mod test_z80 {
 
         cpu.registers.b = 0x06;
 
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(0x11, cpu.registers.b);
         assert_eq!(2, bus.m_cycles);
         assert_eq!(7, bus.t_states);
@@ -123,7 +123,7 @@ mod test_z80 {
 
         cpu.registers.l = 0xff;
 
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(0x11, bus.memory_read(0xff));
         assert_eq!(3, bus.m_cycles);
         assert_eq!(10, bus.t_states);
@@ -136,7 +136,7 @@ mod test_z80 {
         cpu.registers.d = 0x1;
         cpu.registers.e = 0x2;
 
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(0x34, cpu.registers.d);
         assert_eq!(0x12, cpu.registers.e);
 
@@ -155,7 +155,7 @@ mod test_z80 {
 
         bus.memory_write(0x1234, 0x78);
         bus.memory_write(0x1235, 0x56);
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(0x78, cpu.registers.l);
         assert_eq!(0x56, cpu.registers.h);
         assert_eq!(16, bus.t_states);
@@ -171,7 +171,7 @@ mod test_z80 {
 
         bus.memory_write(0x1234, 0x78);
         bus.memory_write(0x1235, 0x56);
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
 
         assert_eq!(0x1, bus.memory_read(0x1234));
         assert_eq!(0x1, bus.memory_read(0x1235));
@@ -0,0 +1,353 @@
+
+
+#[cfg(test)]
+mod test_single_step {
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+    use std::path::{Path, PathBuf};
+
+    use serde_json::Value;
+    use z80::bus::Bus;
+    use z80::cpu::Z80;
+    use z80::state::Z80State;
+
+    /// One memory or port access: read or write, port or memory, address and
+    /// the byte, which the json leaves out for some accesses
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    struct Access {
+        write: bool,
+        io: bool,
+        address: u16,
+        value: Option<u8>,
+    }
+
+    struct TestBus {
+        memory: Vec<u8>,
+        /// Bytes for port reads, in the order the case has them
+        port_reads: Vec<(u16, u8)>,
+        activity: RefCell<Vec<Access>>,
+        t_states: u32,
+    }
+
+    impl TestBus {
+        fn record(&self, write: bool, io: bool, address: u16, value: u8) {
+            self.activity.borrow_mut().push(Access { write, io, address, value: Some(value) });
+        }
+    }
+
+    impl Bus for TestBus {
+        fn memory_read(&self, address: usize) -> u8 {
+            let value = self.memory[address];
+            self.record(false, false, address as u16, value);
+            value
+        }
+
+        fn memory_read_word(&self, address: usize) -> u16 {
+            self.memory_read(address) as u16 | ((self.memory_read((address + 1) & 0xffff) as u16) << 8)
+        }
+
+        fn memory_write(&mut self, address: usize, value: u8) {
+            self.record(true, false, address as u16, value);
+            self.memory[address] = value;
+        }
+
+        fn memory_write_word(&mut self, address: usize, value: u16) {
+            self.memory_write(address, value as u8);
+            self.memory_write((address + 1) & 0xffff, (value >> 8) as u8);
+        }
+
+        fn io_read(&mut self, port: u16) -> u8 {
+            let value = match self.port_reads.iter().position(|(address, _)| *address == port) {
+                Some(i) => self.port_reads.remove(i).1,
+                None => 0xff,
+            };
+            self.record(false, true, port, value);
+            value
+        }
+
+        fn io_write(&mut self, port: u16, value: u8) {
+            self.record(true, true, port, value);
+        }
+
+        fn tick(&mut self, _: u8, t_states: u8) {
+            self.t_states += t_states as u32;
+        }
+    }
+
+    fn directory() -> PathBuf {
+        match std::env::var_os("Z80_SINGLESTEP_DIR") {
+            Some(dir) => PathBuf::from(dir),
+            None => Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/singlestep"),
+        }
+    }
+
+    fn number(json: &Value, name: &str) -> u64 {
+        json[name].as_u64().unwrap_or(0)
+    }
+
+    fn state(json: &Value) -> Z80State {
+        let n = |name| number(json, name) as u16;
+        Z80State {
+            af: n("a") << 8 | n("f"),
+            bc: n("b") << 8 | n("c"),
+            de: n("d") << 8 | n("e"),
+            hl: n("h") << 8 | n("l"),
+            alt_af: n("af_"),
+            alt_bc: n("bc_"),
+            alt_de: n("de_"),
+            alt_hl: n("hl_"),
+            ix: n("ix"),
+            iy: n("iy"),
+            sp: n("sp"),
+            pc: n("pc"),
+            i: n("i") as u8,
+            r: n("r") as u8,
+            wz: n("wz"),
+            q: n("q") as u8,
+            iff1: n("iff1") != 0,
+            iff2: n("iff2") != 0,
+            interrupt_mode: n("im") as u8,
+            ei_delay: n("ei") != 0,
+            ..Z80State::default()
+        }
+    }
+
+    fn ram(json: &Value) -> Vec<(u16, u8)> {
+        json["ram"]
+            .as_array()
+            .map(|ram| ram.iter().map(|cell| (cell[0].as_u64().unwrap() as u16, cell[1].as_u64().unwrap() as u8)).collect())
+            .unwrap_or_default()
+    }
+
+    /// The accesses in the per T-state `cycles` list. An access spans the
+    /// consecutive T-states that have its read or write pin active.
+    fn expected_activity(cycles: &[Value]) -> Vec<Access> {
+        let mut accesses: Vec<Access> = vec![];
+        let mut active = false;
+        for cycle in cycles {
+            let pins: Vec<char> = cycle[2].as_str().unwrap_or("----").chars().collect();
+            let (read, write) = (pins.first() == Some(&'r'), pins.get(1) == Some(&'w'));
+            if !read && !write {
+                active = false;
+                continue;
+            }
+            let access = Access {
+                write,
+                io: pins.get(3) == Some(&'i'),
+                address: cycle[0].as_u64().unwrap_or(0) as u16,
+                value: cycle[1].as_u64().map(|value| value as u8),
+            };
+            match accesses.last_mut() {
+                Some(last) if active && (last.write, last.io, last.address) == (access.write, access.io, access.address) => {
+                    last.value = access.value.or(last.value);
+                }
+                _ => accesses.push(access),
+            }
+            active = true;
+        }
+        accesses
+    }
+
+    /// Runs one case and says what differs
+    fn run_case(case: &Value) -> Result<(), String> {
+        let initial = &case["initial"];
+        let expected = &case["final"];
+        let mut bus = TestBus { memory: vec![0; 0x10000], port_reads: vec![], activity: RefCell::new(vec![]), t_states: 0 };
+        for (address, value) in ram(initial) {
+            bus.memory[address as usize] = value;
+        }
+        let ports = case["ports"].as_array().cloned().unwrap_or_default();
+        bus.port_reads = ports
+            .iter()
+            .filter(|port| port[2].as_str() == Some("r"))
+            .map(|port| (port[0].as_u64().unwrap() as u16, port[1].as_u64().unwrap() as u8))
+            .collect();
+
+        let mut cpu = Z80::new();
+        cpu.restore(&state(initial));
+        cpu.step(&mut bus);
+
+        let mut errors = vec![];
+        let (want, got) = (state(expected), cpu.snapshot());
+        let registers = [
+            ("af", want.af, got.af),
+            ("bc", want.bc, got.bc),
+            ("de", want.de, got.de),
+            ("hl", want.hl, got.hl),
+            ("af'", want.alt_af, got.alt_af),
+            ("bc'", want.alt_bc, got.alt_bc),
+            ("de'", want.alt_de, got.alt_de),
+            ("hl'", want.alt_hl, got.alt_hl),
+            ("ix", want.ix, got.ix),
+            ("iy", want.iy, got.iy),
+            ("sp", want.sp, got.sp),
+            ("pc", want.pc, got.pc),
+            ("i", want.i as u16, got.i as u16),
+            ("r", want.r as u16, got.r as u16),
+            ("wz", want.wz, got.wz),
+            ("q", want.q as u16, got.q as u16),
+            ("iff1", want.iff1 as u16, got.iff1 as u16),
+            ("iff2", want.iff2 as u16, got.iff2 as u16),
+            ("im", want.interrupt_mode as u16, got.interrupt_mode as u16),
+        ];
+        for (name, want, got) in registers.iter() {
+            if want != got {
+                errors.push(format!("{} {:04x} != {:04x}", name, got, want));
+            }
+        }
+        for (address, value) in ram(expected) {
+            if bus.memory[address as usize] != value {
+                errors.push(format!("({:04x}) {:02x} != {:02x}", address, bus.memory[address as usize], value));
+            }
+        }
+
+        let cycles = case["cycles"].as_array().cloned().unwrap_or_default();
+        if bus.t_states != cycles.len() as u32 {
+            errors.push(format!("{} T-states != {}", bus.t_states, cycles.len()));
+        }
+        let want = expected_activity(&cycles);
+        let got = bus.activity.into_inner();
+        let same = want.len() == got.len()
+            && want.iter().zip(got.iter()).all(|(want, got)| {
+                (want.write, want.io, want.address) == (got.write, got.io, got.address) && (want.value.is_none() || want.value == got.value)
+            });
+        if !same {
+            errors.push(format!("bus {:?} != {:?}", got, want));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+
+    /// Passing opcodes counted and failing ones listed by prefix, like "dd cb __"
+    type Report = BTreeMap<String, (usize, Vec<String>)>;
+
+    /// Runs every case of each opcode and prints which opcodes pass. Returns
+    /// the report and the first failure of each failing opcode.
+    fn run_opcodes(opcodes: &[(String, Vec<Value>)]) -> (Report, Vec<String>) {
+        let mut report = Report::new();
+        let mut failures = vec![];
+        for (name, cases) in opcodes {
+            let failure = cases.iter().find_map(|case| run_case(case).err().map(|err| format!("{}: {}", case["name"], err)));
+            let (prefix, opcode) = match name.rsplit_once(' ') {
+                Some((prefix, opcode)) => (prefix.to_string(), opcode.to_string()),
+                None => ("none".to_string(), name.clone()),
+            };
+            let entry = report.entry(prefix).or_default();
+            match failure {
+                None => entry.0 += 1,
+                Some(failure) => {
+                    entry.1.push(opcode);
+                    failures.push(failure);
+                }
+            }
+        }
+
+        println!("{:<10} {:>6} {:>6}  failing", "prefix", "passed", "failed");
+        for (prefix, (passed, failed)) in report.iter() {
+            println!("{:<10} {:>6} {:>6}  {}", prefix, passed, failed.len(), failed.join(" "));
+        }
+        for failure in failures.iter() {
+            println!("{}", failure);
+        }
+        (report, failures)
+    }
+
+    /// Hand written cases in the SingleStepTests format, so the runner is
+    /// checked without the test files. The ed 00 case expects the wrong pc
+    /// on purpose.
+    #[test]
+    fn test_inline_cases() {
+        let opcode = |name: &str, case: &str| (name.to_string(), vec![serde_json::from_str::<Value>(case).unwrap()]);
+        let opcodes = [
+            opcode(
+                "00",
+                r#"{
+                    "name": "00 0000",
+                    "initial": {"pc": 4096, "sp": 0, "a": 1, "f": 2, "r": 16, "ram": [[4096, 0]]},
+                    "final": {"pc": 4097, "sp": 0, "a": 1, "f": 2, "r": 17, "ram": [[4096, 0]]},
+                    "cycles": [[4096, 0, "r-m-"], [4096, null, "----"], [16, null, "----"], [16, null, "----"]]
+                }"#,
+            ),
+            opcode(
+                "db",
+                r#"{
+                    "name": "db 0000",
+                    "initial": {"pc": 4096, "a": 52, "r": 16, "ram": [[4096, 219], [4097, 18]]},
+                    "final": {"pc": 4098, "a": 86, "r": 17, "wz": 13331, "ram": [[4096, 219], [4097, 18]]},
+                    "cycles": [
+                        [4096, 219, "r-m-"], [4096, null, "----"], [16, null, "----"], [16, null, "----"],
+                        [4097, 18, "r-m-"], [4097, null, "----"], [4097, null, "----"],
+                        [13330, null, "----"], [13330, 86, "r--i"], [13330, null, "----"], [13330, null, "----"]
+                    ],
+                    "ports": [[13330, 86, "r"]]
+                }"#,
+            ),
+            opcode(
+                "dd cb __ 06",
+                r#"{
+                    "name": "dd cb __ 06 0000",
+                    "initial": {"pc": 4096, "ix": 8192, "r": 16, "ram": [[4096, 221], [4097, 203], [4098, 5], [4099, 6], [8197, 129]]},
+                    "final": {"pc": 4100, "ix": 8192, "f": 5, "r": 18, "wz": 8197, "q": 5,
+                              "ram": [[4096, 221], [4097, 203], [4098, 5], [4099, 6], [8197, 3]]},
+                    "cycles": [
+                        [4096, 221, "r-m-"], [4096, null, "----"], [16, null, "----"], [16, null, "----"],
+                        [4097, 203, "r-m-"], [4097, null, "----"], [17, null, "----"], [17, null, "----"],
+                        [4098, 5, "r-m-"], [4098, null, "----"], [4098, null, "----"],
+                        [4099, 6, "r-m-"], [4099, null, "----"], [4099, null, "----"], [4099, null, "----"], [4099, null, "----"],
+                        [8197, 129, "r-m-"], [8197, null, "----"], [8197, null, "----"], [8197, null, "----"],
+                        [8197, 3, "-wm-"], [8197, null, "----"], [8197, null, "----"]
+                    ]
+                }"#,
+            ),
+            opcode(
+                "ed 00",
+                r#"{
+                    "name": "ed 00 0000",
+                    "initial": {"pc": 4096, "r": 16, "ram": [[4096, 237], [4097, 0]]},
+                    "final": {"pc": 4099, "r": 18, "ram": [[4096, 237], [4097, 0]]},
+                    "cycles": [
+                        [4096, 237, "r-m-"], [4096, null, "----"], [16, null, "----"], [16, null, "----"],
+                        [4097, 0, "r-m-"], [4097, null, "----"], [17, null, "----"], [17, null, "----"]
+                    ]
+                }"#,
+            ),
+        ];
+        let (report, failures) = run_opcodes(&opcodes);
+        assert_eq!(Some(&(2, vec![])), report.get("none"));
+        assert_eq!(Some(&(1, vec![])), report.get("dd cb __"));
+        assert_eq!(Some(&(0, vec!["00".to_string()])), report.get("ed"));
+        assert_eq!(vec!["\"ed 00 0000\": pc 1002 != 1003".to_string()], failures);
+    }
+
+    /// Runs the SingleStepTests z80 json files, one file per opcode with a
+    /// thousand single instruction cases each. They are looked for in
+    /// `tests/data/singlestep`, or in the directory `Z80_SINGLESTEP_DIR`
+    /// names; without them only `test_inline_cases` checks the runner.
+    #[test]
+    fn test_single_step() {
+        let dir = directory();
+        let mut files: Vec<PathBuf> = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries.filter_map(|entry| Some(entry.ok()?.path())).filter(|path| path.extension().is_some_and(|e| e == "json")).collect(),
+            Err(_) => {
+                println!("{} not found, skipping the SingleStepTests", dir.display());
+                return;
+            }
+        };
+        files.sort();
+
+        let opcodes: Vec<(String, Vec<Value>)> = files
+            .iter()
+            .map(|file| {
+                let text = std::fs::read_to_string(file).unwrap();
+                let cases = serde_json::from_str(&text).unwrap_or_else(|err| panic!("{}: {}", file.display(), err));
+                (file.file_stem().unwrap().to_string_lossy().to_string(), cases)
+            })
+            .collect();
+        let (_, failures) = run_opcodes(&opcodes);
+        assert!(failures.is_empty(), "{} opcodes failed", failures.len());
+    }
+}
@@ -0,0 +1,168 @@
+#[cfg(test)]
+mod test_state {
+    use z80::bus::Bus;
+    use z80::cpu::Z80;
+    use z80::state::{StateError, Z80State};
+
+    struct TestBus {
+        memory: Vec<u8>,
+    }
+
+    impl Bus for TestBus {
+        fn memory_read(&self, address: usize) -> u8 {
+            self.memory[address]
+        }
+
+        fn memory_read_word(&self, address: usize) -> u16 {
+            self.memory[address] as u16 | ((self.memory[address + 1] as u16) << 8)
+        }
+
+        fn memory_write(&mut self, address: usize, value: u8) {
+            self.memory[address] = value;
+        }
+
+        fn memory_write_word(&mut self, address: usize, value: u16) {
+            self.memory[address] = value as u8;
+            self.memory[address + 1] = (value >> 8) as u8;
+        }
+
+        fn tick(&mut self, _: u8, _: u8) {}
+    }
+
+    fn new_bus(prg: &[u8]) -> TestBus {
+        let mut memory = prg.to_vec();
+        memory.resize(0x10000, 0);
+        TestBus { memory }
+    }
+
+    fn sample() -> Z80State {
+        Z80State {
+            af: 0x0102,
+            bc: 0x0304,
+            de: 0x0506,
+            hl: 0x0708,
+            alt_af: 0x090a,
+            alt_bc: 0x0b0c,
+            alt_de: 0x0d0e,
+            alt_hl: 0x0f10,
+            ix: 0x1112,
+            iy: 0x1314,
+            sp: 0x1516,
+            pc: 0x1718,
+            i: 0x19,
+            r: 0x1a,
+            wz: 0x1b1c,
+            q: 0x1d,
+            iff1: true,
+            iff2: false,
+            interrupt_mode: 2,
+            halted: true,
+            ei_delay: false,
+            nmi_pending: true,
+            nmi: false,
+        }
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut cpu = Z80::new();
+        cpu.restore(&sample());
+        assert_eq!(sample(), cpu.snapshot());
+        assert_eq!(0x01, cpu.registers.a);
+        assert_eq!(0x1112, cpu.registers.ix);
+        assert_eq!(0x1718, cpu.pc);
+    }
+
+    #[test]
+    fn test_snapshot_shadow_registers() {
+        // exx; ex af,af'
+        let mut bus = new_bus(&[0xd9, 0x08]);
+        let mut cpu = Z80::new();
+        cpu.registers.b = 0x12;
+        cpu.registers.a = 0x34;
+        cpu.step(&mut bus);
+        cpu.step(&mut bus);
+        let state = cpu.snapshot();
+        assert_eq!(0x1200, state.alt_bc);
+        assert_eq!(0x34, state.alt_af >> 8);
+        assert_eq!(0, state.bc);
+    }
+
+    #[test]
+    fn test_snapshot_ei_delay_and_halt() {
+        // ei; halt
+        let mut bus = new_bus(&[0xfb, 0x76]);
+        let mut cpu = Z80::new();
+        cpu.step(&mut bus);
+        let state = cpu.snapshot();
+        assert!(state.iff1 && state.iff2 && state.ei_delay);
+
+        cpu.step(&mut bus);
+        let state = cpu.snapshot();
+        assert!(state.halted && !state.ei_delay);
+    }
+
+    #[test]
+    fn test_restore_replays_deterministically() {
+        // ld a,7; add a,a; ld (0x8000),a; dec a; jr nz,-4
+        let prg = [0x3e, 0x07, 0x87, 0x32, 0x00, 0x80, 0x3d, 0x20, 0xfa];
+        let mut bus = new_bus(&prg);
+        let mut cpu = Z80::new();
+        for _ in 0..5 {
+            cpu.step(&mut bus);
+        }
+        let saved = cpu.snapshot();
+        let saved_bus = new_bus(&bus.memory);
+        for _ in 0..20 {
+            cpu.step(&mut bus);
+        }
+
+        let mut other = Z80::new();
+        let mut other_bus = saved_bus;
+        other.restore(&saved);
+        for _ in 0..20 {
+            other.step(&mut other_bus);
+        }
+        assert_eq!(cpu.snapshot(), other.snapshot());
+        assert_eq!(bus.memory, other_bus.memory);
+    }
+
+    #[test]
+    fn test_encoding_layout() {
+        let bytes = sample().to_bytes();
+        assert_eq!(Z80State::LEN, bytes.len());
+        assert_eq!(b"Z80S", &bytes[..4]);
+        assert_eq!(1, bytes[4]);
+        // af little endian, then pc and wz
+        assert_eq!([0x02, 0x01], bytes[5..7]);
+        assert_eq!([0x18, 0x17], bytes[27..29]);
+        assert_eq!([0x1c, 0x1b], bytes[29..31]);
+        assert_eq!([0x19, 0x1a, 0x1d, 0x02], bytes[31..35]);
+        // iff1, halted and nmi_pending
+        assert_eq!(0b0001_0101, bytes[35]);
+    }
+
+    #[test]
+    fn test_encoding_round_trip() {
+        let state = sample();
+        assert_eq!(Ok(state), Z80State::from_bytes(&state.to_bytes()));
+        let state = Z80State::default();
+        assert_eq!(Ok(state), Z80State::from_bytes(&state.to_bytes()));
+    }
+
+    #[test]
+    fn test_decoding_errors() {
+        let bytes = sample().to_bytes();
+        assert_eq!(Err(StateError::Truncated), Z80State::from_bytes(&bytes[..20]));
+        assert_eq!(Err(StateError::Truncated), Z80State::from_bytes(&bytes[..3]));
+        assert_eq!(Err(StateError::BadMagic), Z80State::from_bytes(b"SNA"));
+
+        let mut bad = bytes.clone();
+        bad[0] = b'X';
+        assert_eq!(Err(StateError::BadMagic), Z80State::from_bytes(&bad));
+
+        let mut newer = bytes;
+        newer[4] = 2;
+        assert_eq!(Err(StateError::UnsupportedVersion(2)), Z80State::from_bytes(&newer));
+    }
+}
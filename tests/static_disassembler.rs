@@ -0,0 +1,127 @@
+
+
+#[cfg(test)]
+mod test_static_disassembler {
+    use z80::assembler::assemble_bytes;
+    use z80::disassembler::{parse_symbols, Image, StaticDisassembler, SymbolError};
+
+    // a CP/M style program that prints through the BDOS, with its string
+    // between two routines
+    const PROGRAM: [u8; 27] = [
+        0x31, 0x00, 0xf0, // ld sp,$f000
+        0xcd, 0x0c, 0x01, // call $010c
+        0xc3, 0x00, 0x00, // jp $0000
+        0x48, 0x69, 0x24, // "Hi$"
+        0x11, 0x09, 0x01, // ld de,$0109
+        0x0e, 0x09, // ld c,$09
+        0xcd, 0x05, 0x00, // call $0005
+        0x06, 0x03, // ld b,$03
+        0x10, 0xfe, // djnz $+0
+        0xdd, 0x00, // nop with an unused prefix
+        0xc9, // ret
+    ];
+
+    fn listing(symbols: &str) -> z80::disassembler::Listing {
+        let mut disassembler = StaticDisassembler::new(Image::new(0x0100, &PROGRAM), 0x0100..=0x011a);
+        disassembler.add_entry(0x0100);
+        disassembler.symbols = parse_symbols(symbols).unwrap();
+        disassembler.run()
+    }
+
+    #[test]
+    fn test_code_and_data() {
+        let listing = listing("");
+        assert!(listing.is_code(0x0100));
+        assert!(listing.is_code(0x0108));
+        assert!(!listing.is_code(0x0109));
+        assert!(!listing.is_code(0x010b));
+        assert!(listing.is_code(0x010c));
+        assert!(listing.is_code(0x011a));
+        assert_eq!(vec![0x0000, 0x0005, 0x0100, 0x010c, 0x0116], listing.labels.keys().copied().collect::<Vec<_>>());
+        assert_eq!(Some("L_010c"), listing.label(0x010c));
+    }
+
+    #[test]
+    fn test_asm_with_symbols() {
+        let listing = listing("WBOOT equ $0000\nbdos = 5 ; entry\n\nprint: equ 010ch\n");
+        let mut asm = vec![];
+        listing.write_asm(&mut asm).unwrap();
+        assert_eq!(
+            "WBOOT equ $0000\n\
+             bdos equ $0005\n\
+             \torg $0100\n\
+             L_0100:\n\
+             \tld sp,$f000\n\
+             \tcall print\n\
+             \tjp WBOOT\n\
+             \tdb $48,$69,$24\n\
+             print:\n\
+             \tld de,$0109\n\
+             \tld c,$09\n\
+             \tcall bdos\n\
+             \tld b,$03\n\
+             L_0116:\n\
+             \tdjnz L_0116\n\
+             \tdb $dd,$00\t; nop\n\
+             \tret\n",
+            String::from_utf8(asm).unwrap()
+        );
+    }
+
+    /// The `.asm` for `program` at $0100 disassembled from `entries`,
+    /// checked to assemble back to the same bytes
+    fn reassembled(program: &[u8], entries: &[u16]) -> String {
+        let end = 0x0100 + program.len() as u16 - 1;
+        let mut disassembler = StaticDisassembler::new(Image::new(0x0100, program), 0x0100..=end);
+        for entry in entries {
+            disassembler.add_entry(*entry);
+        }
+        let mut asm = vec![];
+        disassembler.run().write_asm(&mut asm).unwrap();
+        let asm = String::from_utf8(asm).unwrap();
+        assert_eq!(program.to_vec(), assemble_bytes(&asm).unwrap(), "{}", asm);
+        asm
+    }
+
+    /// Instructions that overlap, or run past the end, are written as the
+    /// bytes up to the next one, whatever order the entries are followed in
+    #[test]
+    fn test_overlapping_code() {
+        // entered at $0100 the ld skips the xor a at $0101
+        let program = [0x3e, 0xaf, 0xc9, 0x00, 0x3e];
+        let expected = "\torg $0100\n\
+                        L_0100:\n\
+                        \tdb $3e\t; ld a,$af\n\
+                        L_0101:\n\
+                        \txor a\n\
+                        \tret\n\
+                        \tdb $00\n\
+                        L_0104:\n\
+                        \tdb $3e\t; ld a,$00\n";
+        assert_eq!(expected, reassembled(&program, &[0x0100, 0x0101, 0x0104]));
+        assert_eq!(expected, reassembled(&program, &[0x0101, 0x0104, 0x0100]));
+    }
+
+    /// A jump into the operand of an instruction already decoded
+    #[test]
+    fn test_jump_into_operand() {
+        // ld a,$af; jp $0101
+        let program = [0x3e, 0xaf, 0xc3, 0x01, 0x01];
+        assert_eq!(
+            "\torg $0100\n\
+             L_0100:\n\
+             \tdb $3e\t; ld a,$af\n\
+             L_0101:\n\
+             \txor a\n\
+             \tjp L_0101\n",
+            reassembled(&program, &[0x0100])
+        );
+    }
+
+    #[test]
+    fn test_symbol_errors() {
+        assert_eq!(Err(SymbolError { line: 2 }), parse_symbols("a equ 1\nb equ\n"));
+        assert_eq!(Err(SymbolError { line: 1 }), parse_symbols("c = $10000"));
+        assert_eq!(Some(&"d".to_string()), parse_symbols("d = 0x1f").unwrap().get(&0x1f));
+    }
+}
@@ -12,7 +12,6 @@ mod test_z80 {
         memory: Vec<u8>,
         pub m_cycles: u8,
         pub t_states: u8,
-        pub ticks: u8,
     }
 
     impl TestBus {
@@ -21,7 +20,6 @@ mod test_z80 {
                 memory: prg,
                 m_cycles: 0,
                 t_states: 0,
-                ticks: 0,
             }
         }
     }
@@ -55,8 +53,8 @@ mod test_z80 {
         }
 
         fn tick(&mut self, machine_cycles: u8, t_states: u8) {
-            self.m_cycles += machine_cycles;
-            self.t_states += t_states;
+            self.m_cycles = self.m_cycles.wrapping_add(machine_cycles);
+            self.t_states = self.t_states.wrapping_add(t_states);
         }
     }
 
@@ -72,7 +70,7 @@ mod test_z80 {
     #[test]
     fn ld_b_b() {
         let (mut cpu, mut bus) = new_cpu(vec![0x40]);
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(1, bus.m_cycles);
         assert_eq!(4, bus.t_states);
     }
@@ -80,7 +78,7 @@ mod test_z80 {
     #[test]
     fn ld_a_n() {
         let (mut cpu, mut bus) = new_cpu(vec![0x3e]);
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(2, bus.m_cycles);
         assert_eq!(7, bus.t_states);
     }
@@ -88,7 +86,7 @@ mod test_z80 {
     #[test]
     fn ld_a_mem_hl() {
         let (mut cpu, mut bus) = new_cpu(vec![0x7e]);
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(2, bus.m_cycles);
         assert_eq!(7, bus.t_states);
     }
@@ -96,7 +94,7 @@ mod test_z80 {
     #[test]
     fn ld_mem_hl_b() {
         let (mut cpu, mut bus) = new_cpu(vec![0x70]);
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(2, bus.m_cycles);
         assert_eq!(7, bus.t_states);
     }
@@ -107,7 +105,7 @@ mod test_z80 {
         cpu.registers.a = 0x11;
         cpu.registers.ix = 0x1000;
         bus.memory_write(0x1005, 0x22);
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(0x33, cpu.registers.a);
         assert_eq!(5, bus.m_cycles);
         assert_eq!(19, bus.t_states);
@@ -116,7 +114,7 @@ mod test_z80 {
     #[test]
     fn test_jr() {
         let (mut cpu, mut bus) = new_cpu(vec![0x18]);
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(3, bus.m_cycles);
         assert_eq!(12, bus.t_states);
     }
@@ -125,7 +123,7 @@ mod test_z80 {
     fn test_jr_cond_true() {
         let (mut cpu, mut bus) = new_cpu(vec![0x28]);
         cpu.registers.set_flag(Flag::Zero, true);
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(3, bus.m_cycles);
         assert_eq!(12, bus.t_states); 
     }
@@ -134,7 +132,7 @@ mod test_z80 {
     fn test_jr_cond_false() {
         let (mut cpu, mut bus) = new_cpu(vec![0x28]);
         cpu.registers.set_flag(Flag::Zero, false);
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(2, bus.m_cycles);
         assert_eq!(7, bus.t_states); 
     }
@@ -143,7 +141,7 @@ mod test_z80 {
     fn test_ret_cond_true() {
         let (mut cpu, mut bus) = new_cpu(vec![0xc8]);
         cpu.registers.set_flag(Flag::Zero, true);
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(3, bus.m_cycles);
         assert_eq!(11, bus.t_states); 
     }
@@ -152,7 +150,7 @@ mod test_z80 {
     fn test_ret_cond_false() {
         let (mut cpu, mut bus) = new_cpu(vec![0xc8]);
         cpu.registers.set_flag(Flag::Zero, false);
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(1, bus.m_cycles);
         assert_eq!(5, bus.t_states); 
     }
@@ -161,7 +159,7 @@ mod test_z80 {
     fn test_ret() {
         let (mut cpu, mut bus) = new_cpu(vec![0xc9]);
         cpu.registers.set_flag(Flag::Zero, false);
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(3, bus.m_cycles);
         assert_eq!(10, bus.t_states); 
     }
@@ -170,9 +168,50 @@ mod test_z80 {
      #[test]
     fn test_nop() {
         let (mut cpu, mut bus) = new_cpu(vec![0x00]);
-        cpu.step(&mut bus, 0);
+        cpu.step(&mut bus);
         assert_eq!(1, cpu.pc);
         assert_eq!(1, bus.m_cycles);
         assert_eq!(4, bus.t_states); 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_step_returns_t_states() {
+        // nop, ld a,n, ld a,(ix+d)
+        let (mut cpu, mut bus) = new_cpu(vec![0x00, 0x3e, 0x01, 0xdd, 0x7e, 0x05]);
+        assert_eq!(4, cpu.step(&mut bus));
+        assert_eq!(7, cpu.step(&mut bus));
+        assert_eq!(19, cpu.step(&mut bus));
+        assert_eq!(30, cpu.t_cycles);
+        assert_eq!(8, cpu.m_cycles);
+    }
+
+    #[test]
+    fn test_step_counts_interrupt_acknowledge() {
+        // ei, nop, with a nop at 0x38
+        let (mut cpu, mut bus) = new_cpu(vec![0xfb, 0x00]);
+        cpu.interrupt_mode = 1;
+        cpu.sp = 0x8000;
+        cpu.step(&mut bus);
+        cpu.step(&mut bus);
+        cpu.set_int_line(true);
+        assert_eq!(13 + 4, cpu.step(&mut bus));
+    }
+
+    #[test]
+    fn test_run_for_overshoot() {
+        // jp 0 takes 10 T-states, a 69888 T-state frame ends 2 T-states into the next one
+        let (mut cpu, mut bus) = new_cpu(vec![0xc3, 0x00, 0x00]);
+        assert_eq!(2, cpu.run_for(&mut bus, 69888));
+        assert_eq!(69890, cpu.t_cycles);
+        // the next frame is shortened by the overshoot, 2 * 69888 + 4 in total
+        assert_eq!(4, cpu.run_for(&mut bus, 69888 - 2));
+        assert_eq!(139780, cpu.t_cycles);
+    }
+
+    #[test]
+    fn test_run_until() {
+        let (mut cpu, mut bus) = new_cpu(vec![0x00, 0x00, 0x00, 0x76]);
+        assert_eq!(12, cpu.run_until(&mut bus, |cpu| cpu.pc == 3));
+        assert_eq!(0, cpu.run_until(&mut bus, |cpu| cpu.pc == 3));
+    }
+}
@@ -0,0 +1,142 @@
+
+
+#[cfg(test)]
+mod test_tracer {
+    use z80::assembler::assemble_bytes;
+    use z80::bus::Bus;
+    use z80::cpu::Z80;
+    use z80::tracer::{Tracer, Trigger};
+
+    struct TestBus {
+        memory: Vec<u8>,
+    }
+
+    impl TestBus {
+        fn new(source: &str) -> TestBus {
+            let mut memory = assemble_bytes(source).unwrap();
+            memory.resize(0x10000, 0);
+            TestBus { memory }
+        }
+    }
+
+    impl Bus for TestBus {
+        fn memory_read(&self, address: usize) -> u8 {
+            self.memory[address]
+        }
+
+        fn memory_read_word(&self, address: usize) -> u16 {
+            self.memory[address] as u16 | ((self.memory[address + 1] as u16) << 8)
+        }
+
+        fn memory_write(&mut self, address: usize, value: u8) {
+            self.memory[address] = value;
+        }
+
+        fn memory_write_word(&mut self, address: usize, value: u16) {
+            self.memory[address] = value as u8;
+            self.memory[address + 1] = (value >> 8) as u8;
+        }
+
+        fn io_read(&mut self, port: u16) -> u8 {
+            (port >> 8) as u8
+        }
+
+        #[allow(unused_variables)]
+        fn tick(&mut self, machine_cycles: u8, t_states: u8) {}
+    }
+
+    fn trace(tracer: Tracer<Vec<u8>>) -> Vec<String> {
+        String::from_utf8(tracer.into_inner()).unwrap().lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_format() {
+        let mut bus = TestBus::new(
+            "
+                ld hl,$200
+                ld a,$41
+                ld (hl),a
+                add a,(hl)
+                ld bc,$12fe
+                out (c),a
+                in e,(c)
+                push bc
+                halt
+            ",
+        );
+        let mut cpu = Z80::new();
+        let mut tracer = Tracer::new(vec![]);
+        for _ in 0..10 {
+            tracer.step(&mut cpu, &mut bus).unwrap();
+        }
+        let lines = trace(tracer);
+        assert_eq!(10, lines.len());
+        assert_eq!(
+            "0005  77           ld (hl),a              AF=4100 BC=0000 DE=0000 HL=0200 IX=0000 IY=0000 SP=dff0 \
+             AF'=0000 BC'=0000 DE'=0000 HL'=0000 IR=008e F=-------- T=17 wr 0200=41",
+            lines[2]
+        );
+        assert!(lines[3].starts_with("0006  86           add a,(hl)  "));
+        assert!(lines[3].ends_with(" F=-------- T=24 rd 0200=41"));
+        assert!(lines[4].contains(" AF=8284 ") && lines[4].ends_with(" F=S----P-- T=31"));
+        assert!(lines[5].ends_with(" out 12fe=82"));
+        assert!(lines[6].ends_with(" in 12fe=12"));
+        assert!(lines[7].ends_with(" wr dfef=12 wr dfee=fe"));
+        assert!(lines[9].starts_with("0010               (halted)  "));
+    }
+
+    #[test]
+    fn test_triggers() {
+        let mut bus = TestBus::new(
+            "
+                ld b,4
+            loop:
+                nop         ; 2
+                djnz loop
+                halt        ; 5
+            ",
+        );
+        let mut cpu = Z80::new();
+        let mut tracer = Tracer::new(vec![]);
+        tracer.start_at(Trigger::Pc(5));
+        assert!(!tracer.is_active());
+        tracer.run_for(&mut cpu, &mut bus, 100).unwrap();
+        let lines = trace(tracer);
+        assert!(lines[0].starts_with("0005  76           halt"));
+        assert!(lines[1..].iter().all(|line| line.starts_with("0006               (halted)")));
+
+        // the first djnz ends at 7 + 4 + 13
+        let mut cpu = Z80::new();
+        let mut tracer = Tracer::new(vec![]);
+        tracer.start_at(Trigger::Cycle(24));
+        tracer.stop_at(Trigger::Pc(5));
+        tracer.run_for(&mut cpu, &mut bus, 100).unwrap();
+        let addresses: Vec<String> = trace(tracer).iter().map(|line| line[..4].to_string()).collect();
+        assert_eq!(vec!["0002", "0003", "0002", "0003", "0002", "0003"], addresses);
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut bus = TestBus::new(
+            "
+                im 1
+                ei
+            loop:
+                jr loop
+                org $38
+                reti
+            ",
+        );
+        let mut cpu = Z80::new();
+        // past the instruction after ei
+        cpu.run_until(&mut bus, |cpu| cpu.pc == 3 && cpu.t_cycles > 12);
+        cpu.set_int_line(true);
+        let mut tracer = Tracer::new(vec![]);
+        tracer.step(&mut cpu, &mut bus).unwrap();
+        let lines = trace(tracer);
+        assert_eq!(2, lines.len());
+        assert!(lines[0].starts_with("0003               int   "));
+        assert!(lines[0].ends_with(" wr dfef=00 wr dfee=03"));
+        assert!(lines[1].starts_with("0038  ed 4d        reti  "));
+    }
+}